@@ -0,0 +1,52 @@
+// Vertex shader
+
+struct Uniforms {
+    projection: mat4x4<f32>,
+    ambient: vec4<f32>, // light of the map, multiplies every color
+    blend: f32, // from the positions before the last tick to the current ones
+}
+
+@group(0) @binding(0) var<uniform> uniforms: Uniforms;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+}
+
+struct LinkInput {
+    @location(2) color: vec4<f32>,
+    @location(3) start: vec2<f32>,
+    @location(4) end: vec2<f32>,
+    @location(5) width: f32,
+    @location(6) tick_start: vec2<f32>,
+    @location(7) tick_end: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    vertex: VertexInput,
+    link: LinkInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.color = link.color;
+    // the quad is stretched from the start to the end of the link
+    let start = mix(link.tick_start, link.start, uniforms.blend);
+    let end = mix(link.tick_end, link.end, uniforms.blend);
+    let along = end - start;
+    let across = normalize(vec2<f32>(-along.y, along.x) + vec2<f32>(0.0, 1e-6)) * link.width / 2.0;
+    let position = start + along * (vertex.position.x + 1.0) / 2.0 + across * vertex.position.y;
+    out.clip_position = uniforms.projection * vec4<f32>(position, 0.0, 1.0);
+    return out;
+}
+
+// Fragment shader
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color * uniforms.ambient;
+}
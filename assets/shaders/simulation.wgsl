@@ -1,7 +1,11 @@
 // Vertex shader
 
+const FLUID_SCALE: f32 = 1.8; // fluid blobs overlap their neighbours and blend into one surface
+
 struct Uniforms {
     projection: mat4x4<f32>,
+    ambient: vec4<f32>, // light of the map, multiplies every color
+    blend: f32, // from the positions before the last tick to the current ones
 }
 
 @group(0) @binding(0) var<uniform> uniforms: Uniforms;
@@ -16,6 +20,7 @@ struct ParticleInput {
     @location(3) position: vec2<f32>,
     @location(4) texture: u32,
     @location(5) color: vec4<f32>,
+    @location(6) tick_position: vec2<f32>,
 }
 
 struct VertexOutput {
@@ -23,6 +28,7 @@ struct VertexOutput {
     @location(0) uv: vec2<f32>,
     @location(1) texture: u32,
     @location(2) color: vec4<f32>,
+    @location(3) fluid: u32,
 }
 
 @vertex
@@ -34,24 +40,50 @@ fn vs_main(
     out.uv = vertex.uv;
     out.texture = particle.texture;
     out.color = particle.color;
-    let world_position = vec4<f32>(vertex.position*particle.size + particle.position, 0.0, 1.0);
+    let fluid = particle.size < 0.0;
+    out.fluid = u32(fluid);
+    let size = select(particle.size, -particle.size * FLUID_SCALE, fluid);
+    let position = mix(particle.tick_position, particle.position, uniforms.blend);
+    let world_position = vec4<f32>(vertex.position*size + position, 0.0, 1.0);
     out.clip_position = uniforms.projection * world_position;
     return out;
 }
 
 // Fragment shader
 
+#ifdef TEXTURE_ATLAS
+// every texture in its rect of one image, min in xy and max in zw
+@group(1) @binding(0)
+var texture_atlas: texture_2d<f32>;
+@group(1) @binding(2)
+var<uniform> atlas_rects: array<vec4<f32>, #{MAX_ATLAS_RECTS}>;
+#else
 @group(1) @binding(0)
 var texture_array: binding_array<texture_2d<f32>>;
+#endif
 @group(1) @binding(1)
 var texture_sampler: sampler;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+#ifdef TEXTURE_ATLAS
+    // half a texel in from the edges of the rect, so the neighbours don't bleed in
+    let rect = atlas_rects[min(in.texture, #{MAX_ATLAS_RECTS} - 1u)];
+    let half_texel = 0.5 / vec2<f32>(textureDimensions(texture_atlas));
+    let uv = clamp(mix(rect.xy, rect.zw, in.uv), rect.xy + half_texel, rect.zw - half_texel);
+    let color = textureSample(texture_atlas, texture_sampler, uv);
+#else
     let color = textureSample(
         texture_array[in.texture], 
         texture_sampler, 
         in.uv);
+#endif
+
+    // metaball-like: the alpha fades out from the center, overlapping blobs add up
+    if in.fluid == 1u {
+        let falloff = 1.0 - smoothstep(0.3, 1.0, length(in.uv * 2.0 - 1.0));
+        return vec4<f32>(in.color.rgb * uniforms.ambient.rgb, in.color.a * falloff);
+    }
      
-    return color*in.color;
+    return color*in.color*uniforms.ambient;
 }
\ No newline at end of file
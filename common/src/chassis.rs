@@ -0,0 +1,86 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Build of the tank a player drives, chosen in the lobby. Every client places the same model for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Chassis {
+    /// The tank everyone drove before the others came.
+    #[default]
+    Tank,
+    /// Light and quick, with fewer parts to lose.
+    Scout,
+    /// Heavy and slow, its long muzzle throws the shells further.
+    Artillery,
+    /// Built in the map editor, its model is sent to everyone in the lobby. Not in [`Chassis::ALL`].
+    Custom,
+}
+
+impl Chassis {
+    pub const ALL: [Self; 3] = [Self::Tank, Self::Scout, Self::Artillery];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Tank => "tank",
+            Self::Scout => "scout",
+            Self::Artillery => "artillery",
+            Self::Custom => "custom",
+        }
+    }
+
+    /// The one after it in the lobby's list, the last one goes back to the first.
+    pub fn next(&self) -> Self {
+        let i = Self::ALL.iter().position(|chassis| chassis == self).map_or(0, |i| i + 1);
+        Self::ALL[i % Self::ALL.len()]
+    }
+}
+
+/// What the players drive in a match: the chassis they picked and the models of their custom vehicles.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Garage {
+    pub chassis: Vec<(u8, Chassis)>,   // the players missing drive the tank
+    pub vehicles: Vec<(u8, Vec<u8>)>, // exported vehicles of the players who picked a custom one
+}
+
+impl Garage {
+    pub fn chassis(&self, id: u8) -> Chassis {
+        self.chassis.iter().find(|(i, _)| *i == id).map_or(Chassis::default(), |(_, chassis)| *chassis)
+    }
+
+    pub fn vehicle(&self, id: u8) -> Option<&[u8]> {
+        self.vehicles.iter().find(|(i, _)| *i == id).map(|(_, vehicle)| vehicle.as_slice())
+    }
+}
+
+impl FromStr for Chassis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|chassis| chassis.name() == s)
+            .ok_or(format!("unknown chassis \"{s}\" (expected tank, scout or artillery)"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chassis_test() {
+        for chassis in Chassis::ALL {
+            assert_eq!(chassis.name().parse(), Ok(chassis));
+        }
+        assert!("truck".parse::<Chassis>().is_err());
+        assert_eq!(Chassis::Tank.next(), Chassis::Scout);
+        assert_eq!(Chassis::Artillery.next(), Chassis::Tank);
+        assert_eq!(Chassis::Custom.next(), Chassis::Tank);
+
+        let garage = Garage { chassis: vec![(1, Chassis::Custom)], vehicles: vec![(1, vec![1, 2])] };
+        assert_eq!(garage.chassis(0), Chassis::Tank);
+        assert_eq!(garage.chassis(1), Chassis::Custom);
+        assert_eq!(garage.vehicle(1), Some([1, 2].as_slice()));
+        assert_eq!(garage.vehicle(0), None);
+    }
+}
@@ -0,0 +1,112 @@
+use std::path::{Path, PathBuf};
+
+use directories::ProjectDirs;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::ASSETS_PATH;
+
+pub const ASSETS_ENV: &str = "SMOG_ASSETS";
+pub const MAPS_ENV: &str = "SMOG_MAPS";
+pub const SAVES_ENV: &str = "SMOG_SAVES";
+pub const REPLAYS_ENV: &str = "SMOG_REPLAYS";
+pub const VEHICLES_ENV: &str = "SMOG_VEHICLES";
+
+const MAPS_DIR: &str = "maps";
+const REPLAYS_DIR: &str = "replays";
+const VEHICLES_DIR: &str = "vehicles";
+
+fn env_dir(var: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+fn project_dirs() -> Option<ProjectDirs> {
+    ProjectDirs::from("", "", "SMOG")
+}
+
+/// Places an installed build may keep its assets in, the working directory goes first for development.
+fn asset_candidates() -> Vec<PathBuf> {
+    let mut candidates = vec![PathBuf::from(ASSETS_PATH)];
+    if let Some(exe_dir) = std::env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf)) {
+        candidates.push(exe_dir.join(ASSETS_PATH));
+        candidates.push(exe_dir.join("../Resources").join(ASSETS_PATH)); // macOS app bundle
+        candidates.push(exe_dir.join("../share/smog").join(ASSETS_PATH)); // unix prefix install
+    }
+    if let Some(dirs) = project_dirs() {
+        candidates.push(dirs.data_dir().join(ASSETS_PATH));
+    }
+    candidates
+}
+
+/// Directory with the game assets, `SMOG_ASSETS` overrides the search.
+pub fn assets_dir() -> PathBuf {
+    if let Some(dir) = env_dir(ASSETS_ENV) {
+        return dir;
+    }
+    asset_candidates()
+        .into_iter()
+        .find(|dir| dir.is_dir())
+        .map(|dir| dir.canonicalize().unwrap_or(dir))
+        .unwrap_or(PathBuf::from(ASSETS_PATH))
+}
+
+/// Directory with one subdirectory per map, `SMOG_MAPS` overrides it.
+pub fn maps_dir() -> PathBuf {
+    env_dir(MAPS_ENV).unwrap_or_else(|| assets_dir().join(MAPS_DIR))
+}
+
+/// Per user data of the client, `SMOG_SAVES` overrides it.
+pub fn saves_dir() -> PathBuf {
+    env_dir(SAVES_ENV)
+        .or_else(|| project_dirs().map(|dirs| dirs.data_dir().to_path_buf()))
+        .unwrap_or(PathBuf::from("saves"))
+}
+
+/// Recorded matches, `SMOG_REPLAYS` overrides it.
+pub fn replays_dir() -> PathBuf {
+    env_dir(REPLAYS_ENV).unwrap_or_else(|| saves_dir().join(REPLAYS_DIR))
+}
+
+/// Vehicles exported by the editor, `SMOG_VEHICLES` overrides it.
+pub fn vehicles_dir() -> PathBuf {
+    env_dir(VEHICLES_ENV).unwrap_or_else(|| saves_dir().join(VEHICLES_DIR))
+}
+
+/// Client settings saved as `file` in the platform config directory, shared by the game and the editor.
+/// Falls back to the defaults if the file is missing or broken.
+pub fn load<T: DeserializeOwned + Default>(file: &str) -> T {
+    project_dirs()
+        .and_then(|dirs| std::fs::read_to_string(dirs.config_dir().join(file)).ok())
+        .and_then(|contents| ron::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+pub fn save<T: Serialize>(file: &str, value: &T) -> anyhow::Result<()> {
+    let Some(dirs) = project_dirs() else {
+        return Ok(());
+    };
+    std::fs::create_dir_all(dirs.config_dir())?;
+    std::fs::write(dirs.config_dir().join(file), ron::ser::to_string_pretty(value, Default::default())?)?;
+    Ok(())
+}
+
+/// Path to load through the asset server, relative to the assets directory when it's inside of it.
+pub fn asset_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    let assets = assets_dir();
+    match path.strip_prefix(&assets) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => path.canonicalize().unwrap_or(path.to_path_buf()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn asset_path_test() {
+        assert_eq!(asset_path(assets_dir().join(MAPS_DIR).join("test")), PathBuf::from("maps/test"));
+    }
+}
@@ -1,7 +1,22 @@
-pub const ASSETS_PATH : &str = "assets";
-pub const RELATIVE_MAPS_PATH : &str = "assets/maps";
-pub const ASSETS_MAPS_PATH: &str = "maps/";
+pub const ASSETS_PATH : &str = "assets"; // default name of the assets directory, see config::assets_dir
 pub const MAP_FILE: &str = "map.smog";
 pub const BACKGROUND_FILE: &str = "background.png";
+pub const ATLAS_FILE: &str = "atlas.png"; // particle textures of a map packed into one image
+pub const THUMBNAIL_FILE: &str = "thumbnail.png"; // preview of the baked map, written by the editor
+pub const REPLAY_EXTENSION: &str = "smogr";
+pub const VEHICLE_EXTENSION: &str = "smogv"; // vehicle built in the editor, see config::vehicles_dir
 
 pub const MAX_TEAMS: usize = 8;
+
+pub const PHYSICS_TICK: std::time::Duration = std::time::Duration::from_nanos(2300000); // 2.3ms per PHYSICS TICK ~ 55 fps client
+pub const DEFAULT_ROUND_TIME: u32 = 300; // seconds until sudden death, 0 disables it
+pub const DEFAULT_FRIENDLY_FIRE: bool = true;
+pub const DEFAULT_RESPAWNS: u32 = 0;
+pub const DEFAULT_PROJECTILES: u8 = 0b11_1111; // bit i allows projectile i, every one of them
+pub const DISCOVERY_PORT: u16 = 7878; // UDP port servers answer LAN queries on
+
+pub mod chassis;
+pub mod config;
+pub mod locale;
+pub mod mode;
+pub mod trace;
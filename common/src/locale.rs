@@ -0,0 +1,97 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use crate::config;
+
+pub const LOCALES_PATH: &str = "locales";
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+/// UI strings of one language, read from `assets/locales/<language>.json`.
+/// Missing keys fall back to the default language and then to the key itself.
+#[derive(Debug, Clone, Default)]
+pub struct Locale {
+    pub language: String,
+    strings: HashMap<String, String>,
+    fallback: HashMap<String, String>,
+}
+
+impl Locale {
+    fn path(language: &str) -> PathBuf {
+        config::assets_dir()
+            .join(LOCALES_PATH)
+            .join(format!("{language}.json"))
+    }
+
+    fn read(language: &str) -> Option<HashMap<String, String>> {
+        let contents = std::fs::read_to_string(Self::path(language)).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    pub fn load(language: &str) -> Self {
+        let fallback = Self::read(DEFAULT_LANGUAGE).unwrap_or_default();
+        match Self::read(language) {
+            Some(strings) => Self {
+                language: language.to_string(),
+                strings,
+                fallback,
+            },
+            None => Self {
+                language: DEFAULT_LANGUAGE.to_string(),
+                strings: fallback.clone(),
+                fallback,
+            },
+        }
+    }
+
+    /// Languages that have a locale file, sorted by their code.
+    pub fn languages() -> Vec<String> {
+        let Ok(entries) = std::fs::read_dir(config::assets_dir().join(LOCALES_PATH)) else {
+            return vec![DEFAULT_LANGUAGE.to_string()];
+        };
+        let mut languages: Vec<_> = entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                (path.extension()? == "json").then_some(path.file_stem()?.to_str()?.to_string())
+            })
+            .collect();
+        languages.sort();
+        languages
+    }
+
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        self.strings
+            .get(key)
+            .or_else(|| self.fallback.get(key))
+            .map_or(key, |s| s.as_str())
+    }
+
+    /// Replaces every `{name}` in the string with its value.
+    pub fn format(&self, key: &str, args: &[(&str, &str)]) -> String {
+        let mut string = self.get(key).to_string();
+        for (name, value) in args {
+            string = string.replace(&format!("{{{name}}}"), value);
+        }
+        string
+    }
+
+    /// Font with the glyphs of the language, relative to the assets folder.
+    pub fn font(&self) -> Option<&str> {
+        self.strings.get("font").map(|s| s.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_test() {
+        let locale = Locale {
+            language: "test".to_string(),
+            strings: HashMap::from([("round".to_string(), "{time} until {event}".to_string())]),
+            fallback: HashMap::from([("fallback".to_string(), "used".to_string())]),
+        };
+        assert_eq!(locale.format("round", &[("time", "1:00"), ("event", "sudden death")]), "1:00 until sudden death");
+        assert_eq!(locale.get("fallback"), "used");
+        assert_eq!(locale.get("missing"), "missing");
+    }
+}
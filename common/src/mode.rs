@@ -0,0 +1,77 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{DEFAULT_FRIENDLY_FIRE, DEFAULT_PROJECTILES, DEFAULT_RESPAWNS, DEFAULT_ROUND_TIME};
+
+/// Rules that decide how a match is scored and won.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum GameMode {
+    /// Last team with a living tank wins.
+    #[default]
+    TeamDeathmatch,
+    /// Every player for themselves, last living tank wins.
+    Deathmatch,
+    /// Teams score while they hold the map's hill alone, first to `score_limit` wins.
+    KingOfTheHill { score_limit: u32 },
+    /// Teams score by bringing the enemy flag to their own one, first to `score_limit` wins.
+    CaptureTheFlag { score_limit: u32 },
+    /// Nobody wins, the match goes on until the players leave. Made for trying maps out.
+    Sandbox,
+}
+
+impl GameMode {
+    pub const HILL_SCORE_LIMIT: u32 = 60;
+    pub const FLAG_SCORE_LIMIT: u32 = 3;
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::TeamDeathmatch => "tdm",
+            Self::Deathmatch => "dm",
+            Self::KingOfTheHill { .. } => "koth",
+            Self::CaptureTheFlag { .. } => "ctf",
+            Self::Sandbox => "sandbox",
+        }
+    }
+}
+
+/// Rules of a match chosen by the host before it starts.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MatchSettings {
+    pub mode: GameMode,
+    pub round_time: u32, // seconds until sudden death, 0 disables it
+    pub friendly_fire: bool,
+    pub respawns: u32, // extra tanks every player gets
+    pub projectiles: u8, // projectiles the tanks can fire, bit i allows projectile i
+}
+
+impl Default for MatchSettings {
+    fn default() -> Self {
+        Self {
+            mode: GameMode::default(),
+            round_time: DEFAULT_ROUND_TIME,
+            friendly_fire: DEFAULT_FRIENDLY_FIRE,
+            respawns: DEFAULT_RESPAWNS,
+            projectiles: DEFAULT_PROJECTILES,
+        }
+    }
+}
+
+impl FromStr for GameMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tdm" => Ok(Self::TeamDeathmatch),
+            "dm" => Ok(Self::Deathmatch),
+            "koth" => Ok(Self::KingOfTheHill {
+                score_limit: Self::HILL_SCORE_LIMIT,
+            }),
+            "ctf" => Ok(Self::CaptureTheFlag {
+                score_limit: Self::FLAG_SCORE_LIMIT,
+            }),
+            "sandbox" => Ok(Self::Sandbox),
+            _ => Err(format!("unknown game mode \"{s}\" (expected tdm, dm, koth, ctf or sandbox)")),
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    fmt::Debug,
+    fs::File,
+    io::{LineWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use serde_json::{Map, Value};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, EnvFilter, Layer};
+
+pub const TRACE_ENV: &str = "SMOG_TRACE";
+
+/// Writes spans and events in the chrome trace format, the file opens in Perfetto or chrome://tracing.
+pub struct ChromeLayer {
+    out: Mutex<LineWriter<File>>,
+    start: Instant,
+}
+
+impl ChromeLayer {
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let mut out = LineWriter::new(File::create(path)?);
+        // the viewers accept the array without the closing bracket, so a killed process still leaves a valid trace
+        writeln!(out, "[")?;
+        Ok(Self {
+            out: Mutex::new(out),
+            start: Instant::now(),
+        })
+    }
+
+    /// Layer writing to the file named by `SMOG_TRACE`, if it's set.
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os(TRACE_ENV)?;
+        match Self::create(&path) {
+            Ok(layer) => Some(layer),
+            Err(e) => {
+                // there's no subscriber to report this to yet
+                eprintln!("Failed to create the trace file {path:?}: {e}");
+                None
+            }
+        }
+    }
+
+    fn write(&self, phase: &str, name: &str, target: &str, args: Map<String, Value>) {
+        let mut event = Map::new();
+        event.insert("name".into(), name.into());
+        event.insert("cat".into(), target.into());
+        event.insert("ph".into(), phase.into());
+        event.insert("ts".into(), (self.start.elapsed().as_secs_f64() * 1e6).into());
+        event.insert("pid".into(), std::process::id().into());
+        event.insert("tid".into(), thread_id().into());
+        if phase == "i" {
+            event.insert("s".into(), "t".into());
+        }
+        if !args.is_empty() {
+            event.insert("args".into(), Value::Object(args));
+        }
+        if let Ok(mut out) = self.out.lock() {
+            let _ = writeln!(out, "{},", Value::Object(event));
+        }
+    }
+}
+
+/// Small sequential ids read better in the viewers than the os ones.
+fn thread_id() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    thread_local! {
+        static ID: u64 = NEXT.fetch_add(1, Ordering::Relaxed);
+    }
+    ID.with(|id| *id)
+}
+
+#[derive(Default)]
+struct Fields(Map<String, Value>);
+
+impl Visit for Fields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.insert(field.name().into(), format!("{value:?}").into());
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for ChromeLayer {
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let args = span.extensions().get::<Fields>().map(|fields| fields.0.clone()).unwrap_or_default();
+        self.write("B", span.name(), span.metadata().target(), args);
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            self.write("E", span.name(), span.metadata().target(), Map::new());
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        self.write("i", event.metadata().name(), event.metadata().target(), fields.0);
+    }
+}
+
+/// Logs to the terminal for the executables without bevy, `RUST_LOG` overrides the filter.
+pub fn init(default_filter: &str) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_filter));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .with(ChromeLayer::from_env())
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::{info, info_span};
+
+    use super::*;
+
+    #[test]
+    fn chrome_trace_test() {
+        let path = std::env::temp_dir().join(format!("smog-trace-{}.json", std::process::id()));
+        let layer = ChromeLayer::create(&path).unwrap();
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            info_span!("tick", tick = 1).in_scope(|| info!("inside"));
+        });
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let events: Vec<Value> = serde_json::from_str(&format!("{}{{}}]", contents)).unwrap();
+        let phases: Vec<_> = events.iter().filter_map(|event| event["ph"].as_str()).collect();
+        assert_eq!(phases, ["B", "i", "E"]);
+        assert_eq!(events[0]["args"]["tick"], 1);
+    }
+}
@@ -0,0 +1,221 @@
+//! Whole matches played in one process: a real server, headless clients and the shared session runner.
+
+use std::{
+    net::SocketAddr,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use bevy::math::vec2;
+use common::{config, mode::MatchSettings, MAP_FILE, PHYSICS_TICK};
+use image::Rgba;
+use map_editor::{
+    constructor::MapConstructor,
+    map::{Map, Spawn},
+};
+use packet_tools::{
+    client_packets::ClientPacket,
+    game_packets::{GamePacket, PACKET_SIZE},
+    server_packets::ServerPacket,
+    udp::Transport,
+    IndexedPacket, UnsizedPacketRead, UnsizedPacketWrite,
+};
+use render::palette::TeamColors;
+use server::server::{GameServer, LobbyServer};
+use session::{GameSession, SUB_TICKS};
+use smog::network::client::GameClient;
+use solver::{particle::GROUND, Constraint};
+use tokio::{io::AsyncReadExt, net::TcpStream, runtime::Runtime};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+const SLOTS_STORED: usize = 16; // same as the server executable
+
+/// Flat ground with the spawns spread evenly above it.
+pub fn test_map(name: &str, spawns: usize) -> Map {
+    let mut constructor = MapConstructor::new(
+        name.to_string(),
+        Constraint::Box(vec2(-100., -20.), vec2(100., 60.)),
+    );
+    constructor.add_layer();
+    let layer = constructor.layers.last_mut().unwrap();
+    layer.base_particle = GROUND;
+    layer.init_from_fn(|pos| (pos.y < -10.).then_some(Rgba([194, 178, 128, 255])));
+    constructor.spawns = (0..spawns)
+        .map(|i| Spawn {
+            pos: vec2(-60. + 120. * i as f32 / (spawns.max(2) - 1) as f32, 5.),
+            team: i,
+        })
+        .collect();
+    constructor.map()
+}
+
+/// Writes the map where both the server and the clients look for it.
+pub fn install_map<P: AsRef<Path>>(map: &Map, maps_dir: P) -> Result<()> {
+    let dir = maps_dir.as_ref().join(&map.name);
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join(MAP_FILE), map.serialize())?;
+    Ok(())
+}
+
+/// Client that simulates the match without rendering it.
+pub struct HeadlessClient {
+    pub client: GameClient<GamePacket, PACKET_SIZE>,
+    pub session: GameSession,
+    last_input: Option<u128>, // tick the last scripted input was sent on
+    pub synced: usize, // state syncs applied
+}
+
+pub struct Match {
+    pub clients: Vec<HeadlessClient>,
+    addr: SocketAddr,
+    _server: GameServer,
+    runtime: Runtime,
+}
+
+impl Match {
+    /// Hosts the map on a free local port and plays it with `players` clients, followed by `spectators` ones.
+    /// With a `sync_interval` the server simulates the match too and sends its state to the clients.
+    pub fn start(
+        map: &Map,
+        players: usize,
+        spectators: usize,
+        settings: MatchSettings,
+        transport: Transport,
+        sync_interval: Option<u128>,
+    ) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+        let lobby = runtime.block_on(LobbyServer::new(
+            "127.0.0.1:0",
+            "integration".to_string(),
+            map.clone(),
+            settings,
+            None,
+        ))?;
+        let addr = lobby.local_addr();
+
+        let mut clients = (0..players)
+            .map(|i| GameClient::new(addr, format!("player {i}")))
+            .chain((0..spectators).map(|i| GameClient::spectate(addr, format!("spectator {i}"))))
+            .collect::<Result<Vec<_>>>()?;
+
+        let (lobby, incoming) = runtime.block_on(lobby.close());
+        if lobby.len() != players + spectators {
+            bail!("{} of {} clients joined the lobby", lobby.len(), players + spectators);
+        }
+        let server = runtime.block_on(async {
+            let mut server = GameServer::new(lobby, PHYSICS_TICK, SLOTS_STORED)
+                .await
+                .with_transport(transport)
+                .with_reconnects(incoming);
+            if let Some(sync_interval) = sync_interval {
+                server = server.with_simulation(map.clone(), settings, sync_interval);
+            }
+            server.run::<GamePacket, PACKET_SIZE>().await;
+            server
+        });
+
+        let start = Instant::now();
+        while !clients.iter().all(|client| client.game_started()) {
+            if start.elapsed() > TIMEOUT {
+                bail!("the game didn't start");
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut headless = vec![];
+        for client in clients.iter_mut() {
+            client.run()?;
+        }
+        for client in clients {
+            let lobby = &client.lobby;
+            let map = Map::init_from_file(&lobby.map, config::maps_dir())?;
+            let session = GameSession::new(
+                lobby.id,
+                client.name.clone(),
+                &map,
+                &lobby.players,
+                &lobby.garage,
+                &lobby.settings,
+                &TeamColors::default(),
+            );
+            headless.push(HeadlessClient {
+                client,
+                session,
+                last_input: None,
+                synced: 0,
+            });
+        }
+
+        Ok(Self {
+            clients: headless,
+            addr,
+            _server: server,
+            runtime,
+        })
+    }
+
+    /// Simulates `ticks` ticks on every client, `script` gives the input of a player on a tick.
+    pub fn play<F: Fn(u8, u128) -> Option<GamePacket>>(&mut self, ticks: u128, script: F) -> Result<()> {
+        let start = Instant::now();
+        while self.clients.iter().any(|client| client.session.controller.tick < ticks) {
+            if start.elapsed() > TIMEOUT {
+                bail!("the server stopped sending ticks");
+            }
+            for headless in self.clients.iter_mut() {
+                let tick = headless.session.controller.tick;
+                if headless.last_input != Some(tick) {
+                    headless.last_input = Some(tick);
+                    if let Some(packet) = script(headless.client.lobby.id, tick) {
+                        headless.client.send_packet(packet)?;
+                    }
+                }
+                for packets in headless.client.get_packets(SUB_TICKS) {
+                    if headless.session.controller.tick >= ticks {
+                        break;
+                    }
+                    headless.session.tick(&packets);
+                }
+                if let Some(state) = headless.client.take_state_sync() {
+                    let session = &mut headless.session;
+                    if GameSession::apply_state_sync(&mut session.controller, &mut session.solver, &state) {
+                        headless.synced += 1;
+                    }
+                }
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        Ok(())
+    }
+
+    /// Reconnects with the session like a client that received `received` ticks, returns the first `ticks` resent.
+    /// The player's client stops getting ticks, as the server moves it to the new stream.
+    pub fn reconnect(&self, session: u64, received: u64, ticks: usize) -> Result<Vec<Vec<IndexedPacket<GamePacket, PACKET_SIZE>>>> {
+        self.runtime.block_on(async {
+            let mut stream = TcpStream::connect(self.addr).await?;
+            stream.write_packet(&ClientPacket::Reconnect { session, received }).await?;
+            let ServerPacket::SetSession(_) = stream.read_packet().await? else {
+                bail!("the server didn't accept the session");
+            };
+            let mut resent = vec![];
+            let mut bytes = vec![];
+            while resent.len() < ticks {
+                let mut buf = [0; 4096];
+                let n = tokio::time::timeout(TIMEOUT, stream.read(&mut buf)).await??;
+                if n == 0 {
+                    bail!("the server closed the stream");
+                }
+                bytes.extend_from_slice(&buf[..n]);
+                let (packets, partial) = packet_tools::deserialize_queue(&mut bytes)?;
+                bytes.truncate(partial);
+                resent.extend(packets);
+            }
+            resent.truncate(ticks);
+            Ok(resent)
+        })
+    }
+
+    pub fn state_hashes(&self) -> Vec<u64> {
+        self.clients.iter().map(|client| client.session.state_hash()).collect()
+    }
+}
@@ -0,0 +1,68 @@
+use bevy::math::vec2;
+use common::{
+    config,
+    mode::{GameMode, MatchSettings},
+};
+use integration::{install_map, test_map, Match};
+use packet_tools::{game_packets::GamePacket, udp::Transport};
+
+const PLAYERS: usize = 3;
+const TICKS: u128 = 600;
+const SYNC_INTERVAL: u128 = 100;
+
+/// Every player drives, aims, shoots and dashes at their own moments.
+fn scripted_input(id: u8, tick: u128) -> Option<GamePacket> {
+    match (tick + 13 * id as u128) % 120 {
+        0 => Some(GamePacket::Thrust(1., 1.)),
+        30 => Some(GamePacket::Muzzle(vec2(10. * id as f32, 20.))),
+        45 => Some(GamePacket::Fire(0)),
+        60 => Some(GamePacket::Thrust(-1., -0.5)),
+        90 => Some(GamePacket::Dash(1.)),
+        _ => None,
+    }
+}
+
+#[test]
+fn lockstep_test() {
+    // the env variable is shared by the server and the clients, so they read the same map
+    let maps_dir = std::env::temp_dir().join(format!("smog-lockstep-{}", std::process::id()));
+    std::env::set_var(config::MAPS_ENV, &maps_dir);
+    let map = test_map("lockstep", PLAYERS);
+    install_map(&map, &maps_dir).unwrap();
+
+    let settings = MatchSettings {
+        mode: GameMode::Deathmatch,
+        ..Default::default()
+    };
+    for transport in [Transport::Tcp, Transport::Udp] {
+        // the spectator sends inputs too, the server has to drop them
+        let mut game = Match::start(&map, PLAYERS, 1, settings, transport, None).unwrap();
+        let initial = game.state_hashes();
+        game.play(TICKS, scripted_input).unwrap();
+        let hashes = game.state_hashes();
+
+        assert!(hashes.windows(2).all(|pair| pair[0] == pair[1]), "clients desynced over {transport:?}: {hashes:?}");
+        assert_ne!(initial[0], hashes[0]);
+
+        // a dropped player gets the ticks it missed, strangers get nothing
+        let session = game.clients[0].client.lobby.session.unwrap();
+        let resent = game.reconnect(session, TICKS as u64 - 100, 100).unwrap();
+        assert_eq!(resent.len(), 100, "ticks weren't recorded for reconnects over {transport:?}");
+        assert!(game.reconnect(!session, 0, 1).is_err());
+    }
+
+    // the server's state arrives between the ticks and keeps the clients in step
+    for transport in [Transport::Tcp, Transport::Udp] {
+        let mut game = Match::start(&map, PLAYERS, 1, settings, transport, Some(SYNC_INTERVAL)).unwrap();
+        game.play(TICKS, scripted_input).unwrap();
+        let hashes = game.state_hashes();
+        assert!(
+            hashes.windows(2).all(|pair| pair[0] == pair[1]),
+            "clients desynced with the server's state over {transport:?}: {hashes:?}"
+        );
+        // the last state may come after the clients stopped
+        let synced = game.clients.iter().all(|client| client.synced >= (TICKS / SYNC_INTERVAL - 1) as usize);
+        assert!(synced, "the server's state didn't arrive over {transport:?}");
+    }
+    let _ = std::fs::remove_dir_all(&maps_dir);
+}
@@ -8,11 +8,12 @@ pub mod constructor {
         prelude::Image,
     };
     use image::{Rgba, RgbaImage};
-    use rand::Rng;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use render::{ambient::Ambient, ParallaxLayer};
     use serde::{Deserialize, Serialize};
-    use solver::{particle::Particle, Connection, Constraint, Link, Solver, PARTICLE_RADIUS};
+    use solver::{particle::Particle, Connection, Constraint, ForceField, Forces, Hazard, Link, Solver, PARTICLE_RADIUS};
 
-    use crate::map::{Map, Spawn};
+    use crate::map::{Flag, Hill, Map, Pickup, Spawn};
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct TriangularGrid<T> {
@@ -29,7 +30,7 @@ pub mod constructor {
         const X_SHIFT: f32 = PARTICLE_RADIUS * 2.;
         const Y_SHIFT: f32 = 1.7320508075688772935274463415059 * PARTICLE_RADIUS; // sqrt(3) * radius
 
-        pub fn new(constraint: Constraint) -> Self {
+        pub fn new(constraint: &Constraint) -> Self {
             let (bl, tr) = constraint.bounds();
             let width = ((tr.x - bl.x) / Self::X_SHIFT) as usize + 3;
             let height = ((tr.y - bl.y) / Self::Y_SHIFT) as usize + 3;
@@ -104,6 +105,61 @@ pub mod constructor {
             }
         }
 
+        /// Cell closest to the position, none if it's outside the grid.
+        pub fn cell_at(&self, pos: Vec2) -> Option<(usize, usize)> {
+            let (bl, tr) = self.bounds;
+            let j = ((pos.y - bl.y - PARTICLE_RADIUS) / Self::Y_SHIFT).round() as i64 + 1;
+            let i = match j % 2 {
+                1 => ((pos.x - bl.x - PARTICLE_RADIUS) / Self::X_SHIFT).round() as i64 + 1,
+                _ => ((pos.x - bl.x) / Self::X_SHIFT).round() as i64,
+            };
+            if i < 1 || j < 1 || i >= self.width as i64 - 1 || j >= self.height as i64 - 1 {
+                return None;
+            }
+            let cell = (i as usize, j as usize);
+            let pos = self.get_position(cell);
+            (pos.x <= tr.x - PARTICLE_RADIUS && pos.y <= tr.y - PARTICLE_RADIUS).then_some(cell)
+        }
+
+        /// Cells whose positions satisfy `f`.
+        pub fn region<F: Fn(Vec2, &T) -> bool>(&self, f: F) -> Vec<(usize, usize)> {
+            let mut cells = vec![];
+            let (_bl, tr) = self.bounds;
+            for i in 1..self.width - 1 {
+                for j in 1..self.height - 1 {
+                    let pos = self.get_position((i, j));
+                    if pos.x <= tr.x - PARTICLE_RADIUS && pos.y <= tr.y - PARTICLE_RADIUS && f(pos, self.get((i, j))) {
+                        cells.push((i, j));
+                    }
+                }
+            }
+            cells
+        }
+
+        /// Empties the cells, returning their positions and what they held.
+        pub fn take(&mut self, cells: &[(usize, usize)]) -> Vec<(Vec2, T)> {
+            cells
+                .iter()
+                .map(|cell| (self.get_position(*cell), std::mem::take(self.get_mut(*cell))))
+                .collect()
+        }
+
+        /// Puts the values into the cells closest to their positions, the ones that land outside
+        /// the grid or where `allowed` is false are dropped. Returns the cells that were filled.
+        pub fn place<F: Fn(Vec2) -> bool>(&mut self, values: Vec<(Vec2, T)>, allowed: F) -> Vec<(usize, usize)> {
+            let mut cells = vec![];
+            for (pos, value) in values {
+                let Some(cell) = self.cell_at(pos).filter(|cell| allowed(self.get_position(*cell))) else {
+                    continue;
+                };
+                *self.get_mut(cell) = value;
+                cells.push(cell);
+            }
+            cells.sort_unstable();
+            cells.dedup();
+            cells
+        }
+
         pub fn for_each_mut<F: FnMut(Vec2, &mut T)>(&mut self, mut f: F) {
             let (_bl, tr) = self.bounds;
             for i in 1..self.width - 1 {
@@ -117,12 +173,26 @@ pub mod constructor {
         }
     }
 
+    /// How the particles of a layer are linked when it's baked.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+    pub enum ConnectionMode {
+        /// Links between random particles, `strength` times as many as there are pairs of neighbours.
+        #[default]
+        Random,
+        /// Every particle is linked to its neighbours on the grid, rigid and the same every time.
+        Triangulated,
+    }
+
     pub struct Layer {
         pub(crate) constraint: Constraint,
         pub(crate) grid: TriangularGrid<Option<(usize, Rgba<u8>)>>,
+        pub name: String,
+        pub visible: bool, // hidden layers stay in the layout but are left out of the baked map
         pub base_particle: Particle,
         pub link: Option<Link>,
         pub strength: f32,
+        pub mode: ConnectionMode,
+        pub seed: u64, // of the random connections, the same layer bakes into the same structure
         pub particles: Option<Vec<Particle>>,
         pub connections: Option<Vec<Connection>>,
     }
@@ -134,13 +204,17 @@ pub mod constructor {
             link: Option<Link>,
             strength: f32,
         ) -> Self {
-            let grid = TriangularGrid::new(constraint);
+            let grid = TriangularGrid::new(&constraint);
             Self {
                 constraint,
                 grid,
+                name: String::new(),
+                visible: true,
                 base_particle,
                 link,
                 strength,
+                mode: ConnectionMode::default(),
+                seed: rand::random(),
                 particles: None,
                 connections: None,
             }
@@ -154,9 +228,13 @@ pub mod constructor {
             );
             let (scale_x, scale_y) = (image.width() as f32 / width, image.height() as f32 / height);
             let bl = self.grid.bounds.0;
+            let constraint = &self.constraint;
 
             let mut ind = 0;
             self.grid.for_each_mut(|pos, v| {
+                if !constraint.contains(pos, PARTICLE_RADIUS) {
+                    return;
+                }
                 let offset_pos = pos - bl; // get position of the particle as if the bl = (0, 0)
                 let (i, j) = (
                     (offset_pos.x * scale_x) as u32,
@@ -172,6 +250,71 @@ pub mod constructor {
             });
         }
 
+        /// Fills the layer wherever `color` returns a color, used for generated maps.
+        pub fn init_from_fn<F: Fn(Vec2) -> Option<Rgba<u8>>>(&mut self, color: F) {
+            let constraint = &self.constraint;
+            let mut ind = 0;
+            self.grid.for_each_mut(|pos, v| {
+                if !constraint.contains(pos, PARTICLE_RADIUS) {
+                    return;
+                }
+                if let Some(color) = color(pos) {
+                    *v = Some((ind, color));
+                    ind += 1;
+                }
+            });
+        }
+
+        pub fn cell_position(&self, cell: (usize, usize)) -> Vec2 {
+            self.grid.get_position(cell)
+        }
+
+        /// Position of the cell closest to `pos`, transforms around it keep the cells on the grid.
+        pub fn snap(&self, pos: Vec2) -> Option<Vec2> {
+            self.grid.cell_at(pos).map(|cell| self.grid.get_position(cell))
+        }
+
+        /// Filled cells whose positions satisfy `f`.
+        pub fn select<F: Fn(Vec2) -> bool>(&self, f: F) -> Vec<(usize, usize)> {
+            self.grid.region(|pos, v| v.is_some() && f(pos))
+        }
+
+        /// Moves the particles of the cells to the positions given by `f`, overwriting what was there.
+        /// With `keep` the original cells stay, which duplicates them. Returns the new cells.
+        pub fn transform<F: Fn(Vec2) -> Vec2>(&mut self, cells: &[(usize, usize)], f: F, keep: bool) -> Vec<(usize, usize)> {
+            let values = match keep {
+                true => cells.iter().map(|cell| (self.grid.get_position(*cell), *self.grid.get(*cell))).collect(),
+                false => self.grid.take(cells),
+            };
+            let values = values
+                .into_iter()
+                .filter(|(_, v)| v.is_some())
+                .map(|(pos, v)| (f(pos), v))
+                .collect();
+            let constraint = &self.constraint;
+            let cells = self.grid.place(values, |pos| constraint.contains(pos, PARTICLE_RADIUS));
+            self.changed();
+            cells
+        }
+
+        pub fn clear(&mut self, cells: &[(usize, usize)]) {
+            self.grid.take(cells);
+            self.changed();
+        }
+
+        /// Numbers the particles again after cells were moved and drops the baked ones.
+        fn changed(&mut self) {
+            let mut ind = 0;
+            self.grid.for_each_mut(|_, v| {
+                if let Some((i, _)) = v {
+                    *i = ind;
+                    ind += 1;
+                }
+            });
+            self.particles = None;
+            self.connections = None;
+        }
+
         pub fn get_particles(&self) -> Vec<Particle> {
             let mut particles = vec![];
             self.grid.for_each(|pos, v| {
@@ -186,11 +329,12 @@ pub mod constructor {
         }
 
         pub fn get_connections(&self) -> Vec<Connection> {
-            let mut connections_num = 0;
             let Some(link) = self.link else {
                 return vec![];
             };
 
+            // pairs of neighbours on the grid
+            let mut adjacent = vec![];
             for i in 1..self.grid.width - 1 {
                 for j in 1..self.grid.height - 1 {
                     let pos = (i, j);
@@ -198,8 +342,7 @@ pub mod constructor {
                         self.grid.for_adjacent(pos, |p| {
                             if let Some((p_ind, _)) = p {
                                 if p_ind > ind {
-                                    //connections.push((*ind, *p_ind, link));
-                                    connections_num += 1;
+                                    adjacent.push((*ind, *p_ind));
                                 }
                             }
                         })
@@ -207,18 +350,24 @@ pub mod constructor {
                 }
             }
 
-            let mut connections = vec![];
             let particles = self.get_particles();
-            let mut rng = rand::thread_rng();
-            for _ in 0..(connections_num as f32 * self.strength) as usize {
-                let i = rng.gen_range(0..particles.len());
-                let j = rng.gen_range(0..particles.len());
+            let pairs = match self.mode {
+                ConnectionMode::Triangulated => adjacent,
+                ConnectionMode::Random => {
+                    let mut rng = StdRng::seed_from_u64(self.seed);
+                    (0..(adjacent.len() as f32 * self.strength) as usize)
+                        .map(|_| (rng.gen_range(0..particles.len()), rng.gen_range(0..particles.len())))
+                        .collect()
+                }
+            };
+
+            let mut connections = vec![];
+            for (i, j) in pairs {
                 let dist = (particles[i].pos - particles[j].pos).length();
                 if dist > 0. {
                     connections.push((i, j, link.with_length(dist)));
                 }
             }
-
             connections
         }
 
@@ -233,16 +382,37 @@ pub mod constructor {
             }
             let particles = self.particles.as_ref().unwrap();
             let connections = self.connections.as_ref().unwrap();
-            Solver::new(self.constraint, particles, connections)
+            Solver::new(self.constraint.clone(), particles, connections)
         }
     }
+    /// Even-odd test, works for the self-intersecting outlines a lasso can draw.
+    pub fn polygon_contains(points: &[Vec2], pos: Vec2) -> bool {
+        let mut inside = false;
+        for (i, a) in points.iter().enumerate() {
+            let b = points[(i + 1) % points.len()];
+            if (a.y > pos.y) != (b.y > pos.y) && pos.x < a.x + (pos.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+                inside = !inside;
+            }
+        }
+        inside
+    }
+
     pub struct MapConstructor {
         pub name: String,
         pub constraint: Constraint,
         pub layers: Vec<Layer>,
         pub spawns: Vec<Spawn>,
+        pub hill: Option<Hill>,
+        pub flags: Vec<Flag>,
+        pub pickups: Vec<Pickup>,
         pub textures: Vec<Handle<Image>>,
         pub background: Option<Handle<Image>>,
+        pub parallax: Vec<ParallaxLayer>,
+        pub ambient: Ambient,
+        pub forces: Forces,
+        pub fields: Vec<ForceField>,
+        pub hazards: Vec<Hazard>,
+        pub pack_atlas: bool, // the saved map gets its textures packed into an atlas
 
         pub particles: Option<Vec<Particle>>,
         pub connections: Option<Vec<Connection>>,
@@ -255,29 +425,79 @@ pub mod constructor {
                 constraint,
                 layers: vec![],
                 spawns: vec![],
+                hill: None,
+                flags: vec![],
+                pickups: vec![],
                 textures: vec![],
                 background: None,
+                parallax: vec![],
+                ambient: Ambient::default(),
+                forces: Forces::default(),
+                fields: vec![],
+                hazards: vec![],
+                pack_atlas: false,
                 particles: None,
                 connections: None,
             }
         }
 
         pub fn add_layer(&mut self) {
-            self.layers
-                .push(Layer::new(self.constraint, Particle::default(), None, 1.))
+            let mut layer = Layer::new(self.constraint.clone(), Particle::default(), None, 1.);
+            layer.name = format!("layer {}", self.layers.len());
+            self.layers.push(layer)
+        }
+
+        /// Moves the layer to `to`, shifting the ones in between. Returns false if either index is out of range.
+        pub fn move_layer(&mut self, from: usize, to: usize) -> bool {
+            if from >= self.layers.len() || to >= self.layers.len() {
+                return false;
+            }
+            let layer = self.layers.remove(from);
+            self.layers.insert(to, layer);
+            self.clear_baked();
+            true
+        }
+
+        /// Returns whether the layer is visible now.
+        pub fn toggle_layer(&mut self, ind: usize) -> bool {
+            let Some(layer) = self.layers.get_mut(ind) else {
+                return false;
+            };
+            layer.visible = !layer.visible;
+            let visible = layer.visible;
+            self.clear_baked();
+            visible
+        }
+
+        pub fn rename_layer(&mut self, ind: usize, name: String) {
+            if let Some(layer) = self.layers.get_mut(ind) {
+                layer.name = name;
+            }
+        }
+
+        pub fn remove_layer(&mut self, ind: usize) {
+            if ind < self.layers.len() {
+                self.layers.remove(ind);
+                self.clear_baked();
+            }
+        }
+
+        fn clear_baked(&mut self) {
+            self.particles = None;
+            self.connections = None;
         }
 
         pub fn bake_layers(&mut self) {
             let mut particles = vec![];
             let mut connections = vec![];
             let mut offset = 0;
-            for layer in self.layers.iter_mut() {
+            for layer in self.layers.iter_mut().filter(|layer| layer.visible) {
                 layer.bake();
                 particles.append(&mut layer.particles.as_mut().unwrap().clone());
 
                 let layer_connections = layer.connections.as_ref().unwrap();
                 for (i, j, link) in layer_connections.iter() {
-                    connections.push((*i + offset, *j + offset, *link));
+                    connections.push((*i + offset, *j + offset, link.move_pivot(|k| k + offset)));
                 }
 
                 offset = particles.len();
@@ -292,7 +512,11 @@ pub mod constructor {
             }
             let particles = self.particles.as_ref().unwrap();
             let connections = self.connections.as_ref().unwrap();
-            Solver::new(self.constraint, particles, connections)
+            let mut solver = Solver::new(self.constraint.clone(), particles, connections);
+            solver.forces = self.forces;
+            solver.fields = self.fields.clone();
+            solver.hazards = self.hazards.clone();
+            solver
         }
 
         pub fn map(&mut self) -> Map {
@@ -303,31 +527,156 @@ pub mod constructor {
             let connections = self.connections.as_ref().unwrap().clone();
             Map {
                 name: self.name.clone(),
-                constraint: self.constraint,
+                constraint: self.constraint.clone(),
                 particles,
                 connections,
                 spawns: self.spawns.clone(),
+                hill: self.hill.clone(),
+                flags: self.flags.clone(),
+                pickups: self.pickups.clone(),
                 textures_num: self.textures.len(),
                 background: self.background.is_some(),
+                parallax: self.parallax.iter().map(|layer| layer.factor).collect(),
+                ambient: self.ambient,
+                atlas: None,
+                forces: self.forces,
+                fields: self.fields.clone(),
+                hazards: self.hazards.clone(),
             }
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use bevy::math::{vec2, Mat2};
+        use image::Rgba;
+        use solver::Constraint;
+
+        use super::*;
+
+        #[test]
+        fn layer_order_test() {
+            let mut constructor = MapConstructor::new("test".to_string(), Constraint::Box(vec2(-20., -20.), vec2(20., 20.)));
+            for _ in 0..3 {
+                constructor.add_layer();
+            }
+            constructor.layers[0].init_from_fn(|pos| (pos.x < 0.).then_some(Rgba([255, 0, 0, 255])));
+            constructor.layers[2].init_from_fn(|_| Some(Rgba([0, 0, 255, 255])));
+            constructor.rename_layer(2, "ground".to_string());
+
+            assert!(constructor.move_layer(2, 0));
+            assert!(!constructor.move_layer(0, 3));
+            let names: Vec<_> = constructor.layers.iter().map(|layer| layer.name.as_str()).collect();
+            assert_eq!(names, ["ground", "layer 0", "layer 1"]);
+
+            constructor.bake_layers();
+            let all = constructor.particles.as_ref().unwrap().len();
+            assert!(!constructor.toggle_layer(0));
+            assert!(constructor.particles.is_none());
+            constructor.bake_layers();
+            let visible = constructor.particles.as_ref().unwrap().len();
+            assert_eq!(visible, constructor.layers[1].get_particles().len());
+            assert!(visible > 0 && visible < all);
+        }
+
+        #[test]
+        fn selection_transform_test() {
+            let mut layer = Layer::new(Constraint::Box(vec2(-20., -20.), vec2(20., 20.)), Particle::default(), None, 1.);
+            layer.init_from_fn(|pos| (pos.x > 0. && pos.y > 0. && pos.length() < 5.).then_some(Rgba([255, 0, 0, 255])));
+            let all = layer.get_particles().len();
+            assert!(layer.select(|pos| pos.x > 2.).len() < all);
+
+            let center = layer.snap(vec2(0.3, -0.2)).unwrap();
+            assert_eq!(layer.grid.cell_at(center).map(|cell| layer.cell_position(cell)), Some(center));
+            let mut cells = layer.select(|_| true);
+            let selected = cells.len();
+            let original = cells.clone();
+            assert_eq!(selected, all);
+
+            // six turns by 60 degrees around a cell come back to the same cells
+            let rotation = Mat2::from_angle(std::f32::consts::PI / 3.);
+            for _ in 0..6 {
+                cells = layer.transform(&cells, |pos| center + rotation * (pos - center), false);
+                assert_eq!(cells.len(), selected);
+            }
+            assert_eq!(cells, original);
+            assert_eq!(layer.get_particles().len(), all);
+
+            let mirrored = layer.transform(&cells, |pos| vec2(2. * center.x - pos.x, pos.y), true);
+            assert_eq!(mirrored.len(), selected);
+            assert!(layer.particles.is_none());
+
+            let polygon = [vec2(-30., -30.), vec2(30., -30.), vec2(30., 30.), vec2(-30., 30.)];
+            let everything = layer.select(|pos| polygon_contains(&polygon, pos));
+            layer.clear(&everything);
+            assert!(layer.get_particles().is_empty());
+        }
+
+        #[test]
+        fn seed_test() {
+            let link = Link::Rigid { length: 1., durability: 1., elasticity: 10. };
+            let mut layer = Layer::new(Constraint::Box(vec2(-20., -20.), vec2(20., 20.)), Particle::default(), Some(link), 1.);
+            layer.init_from_fn(|pos| (pos.length() < 10.).then_some(Rgba([255, 0, 0, 255])));
+            let pairs = |layer: &Layer| -> Vec<(usize, usize)> {
+                layer.get_connections().iter().map(|(i, j, _)| (*i, *j)).collect()
+            };
+
+            // baking again gives the same structure, another seed a different one
+            let baked = pairs(&layer);
+            assert!(!baked.is_empty());
+            assert_eq!(pairs(&layer), baked);
+            let saved = crate::serde::SerdeLayer::from_layer(&layer).to_layer();
+            assert_eq!(pairs(&saved), baked);
+            layer.seed = layer.seed.wrapping_add(1);
+            assert_ne!(pairs(&layer), baked);
+        }
+
+        #[test]
+        fn triangulated_test() {
+            let link = Link::Rigid { length: 1., durability: 1., elasticity: 10. };
+            let mut layer = Layer::new(Constraint::Box(vec2(-20., -20.), vec2(20., 20.)), Particle::default(), Some(link), 1.);
+            layer.init_from_fn(|pos| (pos.length() < 10.).then_some(Rgba([255, 0, 0, 255])));
+            layer.mode = ConnectionMode::Triangulated;
+
+            // every link joins two neighbours, and every particle inside has six of them
+            let particles = layer.get_particles();
+            let connections = layer.get_connections();
+            assert!(connections
+                .iter()
+                .all(|(i, j, _)| (particles[*i].pos.distance(particles[*j].pos) - 2. * PARTICLE_RADIUS).abs() < 1e-3));
+            let center = particles.iter().position(|p| p.pos.length() < 1.).unwrap();
+            assert_eq!(connections.iter().filter(|(i, j, _)| *i == center || *j == center).count(), 6);
+
+            // and the seed doesn't change them
+            layer.seed = layer.seed.wrapping_add(1);
+            assert_eq!(layer.get_connections().len(), connections.len());
+        }
+    }
 }
 
 pub mod map {
-    use std::path::{Path, PathBuf};
+    use std::{
+        io::ErrorKind,
+        path::{Path, PathBuf},
+    };
 
-    use anyhow::Result;
     use bevy::{
         asset::{AssetServer, Handle},
-        math::Vec2,
+        color::Color,
+        math::{Rect, Vec2},
         prelude::Image,
     };
-    use common::{ASSETS_MAPS_PATH, BACKGROUND_FILE, MAP_FILE, RELATIVE_MAPS_PATH};
+    use common::{config, ATLAS_FILE, BACKGROUND_FILE, MAP_FILE, THUMBNAIL_FILE};
+    use render::{ambient::Ambient, atlas::AtlasTextures, ParallaxLayer};
+    use image::{Rgba, RgbaImage};
     use serde::{Deserialize, Serialize};
-    use solver::{particle::Particle, Connection, Constraint, Solver};
+    use solver::{
+        particle::{Item, Particle},
+        Connection, Constraint, ForceField, Forces, Hazard, Solver, PARTICLE_RADIUS,
+    };
+    use tracing::instrument;
 
-    
+    use crate::format::{self, MapV0};
 
     #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
     pub struct Spawn {
@@ -335,6 +684,63 @@ pub mod map {
         pub team: usize,
     }
 
+    /// Zone contested in the king of the hill mode.
+    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+    pub struct Hill {
+        pub pos: Vec2,
+        pub radius: f32,
+    }
+
+    /// Flag of a team in the capture the flag mode, placed at its base.
+    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+    pub struct Flag {
+        pub pos: Vec2,
+        pub team: usize,
+    }
+
+    /// Pickup placed on the map, respawns some time after it's taken.
+    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+    pub struct Pickup {
+        pub pos: Vec2,
+        pub item: Item,
+    }
+
+    /// Why a map couldn't be loaded.
+    #[derive(Debug)]
+    pub enum MapError {
+        NotFound(String),
+        Io(std::io::Error),
+        Corrupt(String),
+        MissingFile(PathBuf),
+    }
+
+    impl std::fmt::Display for MapError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::NotFound(name) => write!(f, "Map \"{name}\" not found"),
+                Self::Io(error) => write!(f, "Failed to read the map: {error}"),
+                Self::Corrupt(reason) => write!(f, "Map is corrupt: {reason}"),
+                Self::MissingFile(path) => write!(f, "Map file {path:?} is missing"),
+            }
+        }
+    }
+
+    impl std::error::Error for MapError {}
+
+    impl From<std::io::Error> for MapError {
+        fn from(error: std::io::Error) -> Self {
+            Self::Io(error)
+        }
+    }
+
+    impl Hill {
+        pub const DEFAULT_RADIUS: f32 = 15.;
+
+        pub fn contains(&self, pos: Vec2) -> bool {
+            self.pos.distance(pos) <= self.radius
+        }
+    }
+
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct Map {
         pub name: String,
@@ -342,13 +748,26 @@ pub mod map {
         pub particles: Vec<Particle>,
         pub connections: Vec<Connection>,
         pub spawns: Vec<Spawn>,
+        pub hill: Option<Hill>,
+        pub flags: Vec<Flag>,
+        pub pickups: Vec<Pickup>,
         pub textures_num: usize,
         pub background: bool,
+        pub parallax: Vec<f32>, // factors of the parallax images, from the furthest one
+        pub ambient: Ambient,
+        pub atlas: Option<Vec<Rect>>, // rects of the particle textures in the atlas image, in place of the textures
+        pub forces: Forces,
+        pub fields: Vec<ForceField>,
+        pub hazards: Vec<Hazard>,
     }
 
     impl Map {
         pub fn solver(&self) -> Solver {
-            Solver::new(self.constraint, &self.particles, &self.connections)
+            let mut solver = Solver::new(self.constraint.clone(), &self.particles, &self.connections);
+            solver.forces = self.forces;
+            solver.fields = self.fields.clone();
+            solver.hazards = self.hazards.clone();
+            solver
         }
 
         pub fn texture_paths<P: AsRef<Path>>(&self, base_path: P) -> Vec<PathBuf> {
@@ -383,21 +802,113 @@ pub mod map {
             Some(path)
         }
 
-        pub fn init_from_file<P: AsRef<Path>>(name: &str, base_path: P) -> Result<Self> {
+        pub fn parallax_paths<P: AsRef<Path>>(&self, base_path: P) -> Vec<PathBuf> {
+            Self::get_parallax_paths(&self.name, self.parallax.len(), base_path)
+        }
+
+        pub fn get_parallax_paths<P: AsRef<Path>>(name: &str, num: usize, base_path: P) -> Vec<PathBuf> {
+            (0..num).map(|i| base_path.as_ref().join(name).join(format!("parallax_{i}.png"))).collect()
+        }
+
+        pub fn atlas_path<P: AsRef<Path>>(&self, base_path: P) -> Option<PathBuf> {
+            self.atlas.as_ref().map(|_| base_path.as_ref().join(&self.name).join(ATLAS_FILE))
+        }
+
+        pub fn get_thumbnail_path<P: AsRef<Path>>(name: &str, base_path: P) -> PathBuf {
+            base_path.as_ref().join(name).join(THUMBNAIL_FILE)
+        }
+
+        /// Draws the particles with their colors over a dark background, `width` pixels wide and as tall as the
+        /// boundary is. Textures aren't applied, at thumbnail size the colors are what tells the maps apart.
+        pub fn render_thumbnail(&self, width: u32) -> RgbaImage {
+            const BACKGROUND: Rgba<u8> = Rgba([20, 20, 24, 255]);
+            let (bl, tr) = self.constraint.bounds();
+            let size = tr - bl;
+            let scale = width as f32 / size.x;
+            let height = ((size.y * scale).round() as u32).max(1);
+            let mut image = RgbaImage::from_pixel(width, height, BACKGROUND);
+            let radius = (PARTICLE_RADIUS * scale).max(0.5);
+            for particle in self.particles.iter() {
+                // the particle colors are linear, the image is sRGB
+                let color = Color::linear_rgba(particle.color.x, particle.color.y, particle.color.z, 1.).to_srgba();
+                let color = Rgba([color.red, color.green, color.blue, 1.].map(|c| (c.clamp(0., 1.) * 255.) as u8));
+                let center = (particle.pos - bl) * scale;
+                let center = Vec2::new(center.x, height as f32 - center.y);
+                let (min, max) = (center - radius, center + radius);
+                for x in min.x.floor().max(0.) as u32..(max.x.ceil() as u32).min(width) {
+                    for y in min.y.floor().max(0.) as u32..(max.y.ceil() as u32).min(height) {
+                        let pixel = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                        if pixel.distance(center) <= radius + 0.5 {
+                            image.put_pixel(x, y, color);
+                        }
+                    }
+                }
+            }
+            image
+        }
+
+        /// Names of the maps in the directory, the ones without a map file are left out.
+        pub fn list<P: AsRef<Path>>(base_path: P) -> Vec<String> {
+            let Ok(entries) = std::fs::read_dir(base_path) else {
+                return vec![];
+            };
+            let mut names: Vec<String> = entries
+                .flatten()
+                .filter(|entry| entry.path().join(MAP_FILE).is_file())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .collect();
+            names.sort();
+            names
+        }
+
+        #[instrument(level = "debug", skip(base_path))]
+        pub fn init_from_file<P: AsRef<Path>>(name: &str, base_path: P) -> Result<Self, MapError> {
             let mut map_path = PathBuf::from(base_path.as_ref());
             map_path.push(name);
             map_path.push(MAP_FILE);
-            let map_bytes =
-                std::fs::read(&map_path)?;
+            let map_bytes = std::fs::read(&map_path).map_err(|e| match e.kind() {
+                ErrorKind::NotFound => MapError::NotFound(name.to_string()),
+                _ => MapError::Io(e),
+            })?;
             Map::deserialize(&map_bytes)
         }
 
+        /// Makes sure the textures, the atlas and the backgrounds of the map are on the disk.
+        pub fn check_files<P: AsRef<Path>>(&self, base_path: P) -> Result<(), MapError> {
+            let background = self.background_path(&base_path);
+            let atlas = self.atlas_path(&base_path);
+            let parallax = self.parallax_paths(&base_path);
+            match self
+                .texture_paths(&base_path)
+                .into_iter()
+                .chain(atlas)
+                .chain(background)
+                .chain(parallax)
+                .find(|path| !path.is_file())
+            {
+                Some(path) => Err(MapError::MissingFile(path)),
+                None => Ok(()),
+            }
+        }
+
         pub fn serialize(&self) -> Vec<u8> {
-            postcard::to_stdvec(&self).unwrap()
+            format::encode(self)
         }
 
-        pub fn deserialize(bytes: &[u8]) -> Result<Self> {
-            anyhow::Ok(postcard::from_bytes(bytes)?)
+        /// Rejects maps that would make the solver index out of bounds.
+        pub fn deserialize(bytes: &[u8]) -> Result<Self, MapError> {
+            let map: Map = format::decode::<Map, MapV0>(bytes).map_err(MapError::Corrupt)?;
+            if map.spawns.is_empty() {
+                return Err(MapError::Corrupt("no spawns".to_string()));
+            }
+            if !map.constraint.is_valid() {
+                return Err(MapError::Corrupt("degenerate boundary".to_string()));
+            }
+            let particles = map.particles.len();
+            if map.connections.iter().any(|(i, j, _)| *i >= particles || *j >= particles) {
+                return Err(MapError::Corrupt("connection to a missing particle".to_string()));
+            }
+            Ok(map)
         }
     }
 
@@ -405,26 +916,46 @@ pub mod map {
         pub map: Map,
         pub textures: Vec<Handle<Image>>,
         pub background: Option<Handle<Image>>,
+        pub parallax: Vec<ParallaxLayer>,
+        pub atlas: Option<AtlasTextures>,
     }
 
     impl MapLoader {
+        #[instrument(level = "debug", skip(asset_server))]
         pub fn init_from_file(
             name: &str,
             asset_server: &AssetServer,
-        ) -> Result<Self> {
-            let mut map_path = PathBuf::from(RELATIVE_MAPS_PATH);
-            map_path.push(name);
-            map_path.push(MAP_FILE);
-            let map_bytes = std::fs::read(&map_path)?;
-            let map = Map::deserialize(&map_bytes)?;
+        ) -> Result<Self, MapError> {
+            let maps_dir = config::maps_dir();
+            let map = Map::init_from_file(name, &maps_dir)?;
+            map.check_files(&maps_dir)?;
+            let assets_maps_dir = config::asset_path(&maps_dir);
             let textures = map
-                .texture_paths(ASSETS_MAPS_PATH)
+                .texture_paths(&assets_maps_dir)
                 .into_iter()
                 .map(|path| asset_server.load(path))
                 .collect();
-            let background = map.background_path(ASSETS_MAPS_PATH)
+            let background = map.background_path(&assets_maps_dir)
                 .map(|path| asset_server.load(path));
-            anyhow::Ok(Self { map, textures, background })
+            let parallax = map
+                .parallax_paths(&assets_maps_dir)
+                .into_iter()
+                .zip(map.parallax.iter())
+                .map(|(path, factor)| ParallaxLayer { image: asset_server.load(path), factor: *factor })
+                .collect();
+            let atlas = map.atlas_path(&assets_maps_dir).zip(map.atlas.clone()).map(|(path, rects)| AtlasTextures {
+                image: asset_server.load(path),
+                rects,
+            });
+            Ok(Self { map, textures, background, parallax, atlas })
+        }
+
+        /// Thumbnail of an installed map, none if the map was saved without one.
+        pub fn thumbnail(name: &str, asset_server: &AssetServer) -> Option<Handle<Image>> {
+            let maps_dir = config::maps_dir();
+            Map::get_thumbnail_path(name, &maps_dir)
+                .is_file()
+                .then(|| asset_server.load(Map::get_thumbnail_path(name, config::asset_path(&maps_dir))))
         }
 
         pub fn map_exists<P: AsRef<Path>>(name: &str, base_path: P) -> bool { // TODO: change this function to try to construct a map
@@ -434,6 +965,66 @@ pub mod map {
             map_path.exists() 
         }
     }
+    #[cfg(test)]
+    mod tests {
+        use bevy::math::vec2;
+        use solver::{Constraint, Link};
+
+        use super::*;
+
+        #[test]
+        fn corrupt_map_test() {
+            let mut map = Map {
+                name: "test".to_string(),
+                constraint: Constraint::Box(vec2(-10., -10.), vec2(10., 10.)),
+                particles: vec![],
+                connections: vec![],
+                spawns: vec![Spawn { pos: vec2(0., 0.), team: 0 }],
+                hill: None,
+                flags: vec![],
+                pickups: vec![],
+                textures_num: 0,
+                background: false,
+                parallax: vec![],
+                ambient: Ambient::default(),
+                atlas: None,
+                forces: Forces::default(),
+                fields: vec![],
+                hazards: vec![],
+            };
+            assert!(Map::deserialize(&map.serialize()).is_ok());
+            assert!(matches!(Map::deserialize(&[1, 2, 3]), Err(MapError::Corrupt(_))));
+            map.connections.push((0, 1, Link::Force(1.)));
+            assert!(matches!(Map::deserialize(&map.serialize()), Err(MapError::Corrupt(_))));
+        }
+
+        #[test]
+        fn thumbnail_test() {
+            let red = Particle::default().with_position(vec2(-5., 0.)).with_color(bevy::math::Vec4::new(1., 0., 0., 1.));
+            let map = Map {
+                name: "test".to_string(),
+                constraint: Constraint::Box(vec2(-10., -5.), vec2(10., 5.)),
+                particles: vec![red],
+                connections: vec![],
+                spawns: vec![],
+                hill: None,
+                flags: vec![],
+                pickups: vec![],
+                textures_num: 0,
+                background: false,
+                parallax: vec![],
+                ambient: Ambient::default(),
+                atlas: None,
+                forces: Forces::default(),
+                fields: vec![],
+                hazards: vec![],
+            };
+            let thumbnail = map.render_thumbnail(200);
+            assert_eq!(thumbnail.dimensions(), (200, 100));
+            assert_eq!(thumbnail.get_pixel(50, 50).0, [255, 0, 0, 255]);
+            assert_ne!(thumbnail.get_pixel(150, 50).0, [255, 0, 0, 255]);
+        }
+    }
 }
 
 pub mod serde {
@@ -443,9 +1034,13 @@ pub mod serde {
     use bevy::asset::AssetServer;
     use image::Rgba;
     use serde::{Deserialize, Serialize};
-    use solver::{particle::Particle, Connection, Constraint, Link};
+    use render::{ambient::Ambient, ParallaxLayer};
+    use solver::{particle::Particle, Connection, Constraint, ForceField, Forces, Hazard, Link};
 
-    use crate::map::{Map, Spawn};
+    use crate::{
+        format::{self, SerdeMapConstructorV0},
+        map::{Flag, Hill, Map, Pickup, Spawn},
+    };
 
     use super::constructor::*;
 
@@ -453,9 +1048,13 @@ pub mod serde {
     pub struct SerdeLayer {
         pub(crate) constraint: Constraint,
         pub(crate) grid: TriangularGrid<Option<(usize, [u8; 4])>>,
+        pub name: String,
+        pub visible: bool,
         pub base_particle: Particle,
         pub link: Option<Link>,
         pub strength: f32,
+        pub mode: ConnectionMode,
+        pub seed: u64,
         pub particles: Option<Vec<Particle>>,
         pub connections: Option<Vec<Connection>>,
     }
@@ -476,11 +1075,15 @@ pub mod serde {
                 .collect();
             grid.grid = grid_particles;
             Layer {
-                constraint: self.constraint,
+                constraint: self.constraint.clone(),
                 grid,
+                name: self.name,
+                visible: self.visible,
                 base_particle: self.base_particle,
                 link: self.link,
                 strength: self.strength,
+                mode: self.mode,
+                seed: self.seed,
                 particles: self.particles,
                 connections: self.connections,
             }
@@ -501,11 +1104,15 @@ pub mod serde {
                 .collect();
             grid.grid = grid_particles;
             Self {
-                constraint: layer.constraint,
+                constraint: layer.constraint.clone(),
                 grid,
+                name: layer.name.clone(),
+                visible: layer.visible,
                 base_particle: layer.base_particle,
                 link: layer.link,
                 strength: layer.strength,
+                mode: layer.mode,
+                seed: layer.seed,
                 particles: layer.particles.clone(),
                 connections: layer.connections.clone(),
             }
@@ -518,8 +1125,17 @@ pub mod serde {
         pub constraint: Constraint,
         pub layers: Vec<SerdeLayer>,
         pub spawns: Vec<Spawn>,
+        pub hill: Option<Hill>,
+        pub flags: Vec<Flag>,
+        pub pickups: Vec<Pickup>,
         pub textures_num: usize,
         pub background: bool,
+        pub parallax: Vec<f32>,
+        pub ambient: Ambient,
+        pub forces: Forces,
+        pub fields: Vec<ForceField>,
+        pub hazards: Vec<Hazard>,
+        pub pack_atlas: bool,
         pub particles: Option<Vec<Particle>>,
         pub connections: Option<Vec<Connection>>,
     }
@@ -546,14 +1162,28 @@ pub mod serde {
             let background = 
                 Map::get_background_path(&self.name, self.background, &textures_base_path)
                 .map(|path| asset_server.load(path));
+            let parallax = Map::get_parallax_paths(&self.name, self.parallax.len(), &textures_base_path)
+                .into_iter()
+                .zip(self.parallax)
+                .map(|(path, factor)| ParallaxLayer { image: asset_server.load(path), factor })
+                .collect();
 
             MapConstructor {
                 name: self.name,
-                constraint: self.constraint,
+                constraint: self.constraint.clone(),
                 layers,
                 spawns: self.spawns,
+                hill: self.hill,
+                flags: self.flags,
+                pickups: self.pickups,
                 textures,
                 background,
+                parallax,
+                ambient: self.ambient,
+                forces: self.forces,
+                fields: self.fields,
+                hazards: self.hazards,
+                pack_atlas: self.pack_atlas,
                 particles: self.particles,
                 connections: self.connections,
             }
@@ -568,22 +1198,639 @@ pub mod serde {
 
             Self {
                 name: constructor.name.clone(),
-                constraint: constructor.constraint,
+                constraint: constructor.constraint.clone(),
                 layers,
                 spawns: constructor.spawns.clone(),
+                hill: constructor.hill.clone(),
+                flags: constructor.flags.clone(),
+                pickups: constructor.pickups.clone(),
                 textures_num: constructor.textures.len(),
                 background: constructor.background.is_some(),
+                parallax: constructor.parallax.iter().map(|layer| layer.factor).collect(),
+                ambient: constructor.ambient,
+                forces: constructor.forces,
+                fields: constructor.fields.clone(),
+                hazards: constructor.hazards.clone(),
+                pack_atlas: constructor.pack_atlas,
                 particles: constructor.particles.clone(),
                 connections: constructor.connections.clone(),
             }
         }
 
         pub fn serialize(&self) -> Vec<u8> {
-            postcard::to_stdvec(&self).unwrap()
+            format::encode(self)
         }
 
         pub fn deserialize(bytes: &[u8]) -> Result<Self> {
-            anyhow::Ok(postcard::from_bytes(bytes)?)
+            format::decode::<Self, SerdeMapConstructorV0>(bytes).map_err(anyhow::Error::msg)
+        }
+    }
+}
+
+pub mod format {
+    //! Header in front of the saved maps, editor files and vehicles. Files saved before the header
+    //! existed are read with the first layouts below and migrated to the current ones.
+
+    use bevy::math::{Vec2, Vec4};
+    use render::ambient::Ambient;
+    use serde::{de::DeserializeOwned, Deserialize, Serialize};
+    use solver::{
+        particle::{Kind, Particle},
+        Connection, Constraint, Forces, Link,
+    };
+
+    use crate::{
+        constructor::{ConnectionMode, TriangularGrid},
+        map::{Map, Spawn},
+        serde::{SerdeLayer, SerdeMapConstructor},
+    };
+
+    const MAGIC: &[u8; 4] = b"SMOG";
+    pub const VERSION: u16 = 1; // bump with a migration whenever a saved struct changes
+
+    pub fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        postcard::to_extend(value, bytes).unwrap()
+    }
+
+    /// Reads the current format, or the headerless one `L` and migrates it.
+    pub fn decode<T, L>(bytes: &[u8]) -> Result<T, String>
+    where
+        T: DeserializeOwned,
+        L: DeserializeOwned + Into<T>,
+    {
+        let Some(rest) = bytes.strip_prefix(MAGIC.as_slice()) else {
+            return postcard::from_bytes::<L>(bytes).map(Into::into).map_err(|e| e.to_string());
+        };
+        if rest.len() < 2 {
+            return Err("truncated header".to_string());
+        }
+        match u16::from_le_bytes([rest[0], rest[1]]) {
+            VERSION => postcard::from_bytes(&rest[2..]).map_err(|e| e.to_string()),
+            version => Err(format!("format {version} is newer than this build")),
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub(crate) struct ParticleV0 {
+        pub radius: f32,
+        pub mass: f32,
+        pub pos: Vec2,
+        pub pos_old: Vec2,
+        pub acc: Vec2,
+        pub texture: u32,
+        pub kind: Kind,
+        pub color: Vec4,
+    }
+
+    impl From<ParticleV0> for Particle {
+        fn from(p: ParticleV0) -> Self {
+            Particle {
+                radius: p.radius,
+                mass: p.mass,
+                pos: p.pos,
+                pos_old: p.pos_old,
+                acc: p.acc,
+                texture: p.texture,
+                kind: p.kind,
+                color: p.color,
+                ..Particle::null()
+            }
+        }
+    }
+
+    fn particles(particles: Vec<ParticleV0>) -> Vec<Particle> {
+        particles.into_iter().map(Particle::from).collect()
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub(crate) struct MapV0 {
+        pub name: String,
+        pub constraint: Constraint,
+        pub particles: Vec<ParticleV0>,
+        pub connections: Vec<Connection>,
+        pub spawns: Vec<Spawn>,
+        pub textures_num: usize,
+        pub background: bool,
+    }
+
+    impl From<MapV0> for Map {
+        fn from(map: MapV0) -> Self {
+            Map {
+                name: map.name,
+                constraint: map.constraint,
+                particles: particles(map.particles),
+                connections: map.connections,
+                spawns: map.spawns,
+                hill: None,
+                flags: vec![],
+                pickups: vec![],
+                textures_num: map.textures_num,
+                background: map.background,
+                parallax: vec![],
+                ambient: Ambient::default(),
+                atlas: None,
+                forces: Forces::default(),
+                fields: vec![],
+                hazards: vec![],
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub(crate) struct SerdeLayerV0 {
+        pub constraint: Constraint,
+        pub grid: TriangularGrid<Option<(usize, [u8; 4])>>,
+        pub base_particle: ParticleV0,
+        pub link: Option<Link>,
+        pub strength: f32,
+        pub particles: Option<Vec<ParticleV0>>,
+        pub connections: Option<Vec<Connection>>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub(crate) struct SerdeMapConstructorV0 {
+        pub name: String,
+        pub constraint: Constraint,
+        pub layers: Vec<SerdeLayerV0>,
+        pub spawns: Vec<Spawn>,
+        pub textures_num: usize,
+        pub background: bool,
+        pub particles: Option<Vec<ParticleV0>>,
+        pub connections: Option<Vec<Connection>>,
+    }
+
+    impl From<SerdeMapConstructorV0> for SerdeMapConstructor {
+        fn from(constructor: SerdeMapConstructorV0) -> Self {
+            let layers = constructor
+                .layers
+                .into_iter()
+                .enumerate()
+                .map(|(i, layer)| SerdeLayer {
+                    constraint: layer.constraint,
+                    grid: layer.grid,
+                    name: format!("layer {i}"),
+                    visible: true,
+                    base_particle: layer.base_particle.into(),
+                    link: layer.link,
+                    strength: layer.strength,
+                    mode: ConnectionMode::default(),
+                    seed: rand::random(),
+                    particles: layer.particles.map(particles),
+                    connections: layer.connections,
+                })
+                .collect();
+            SerdeMapConstructor {
+                name: constructor.name,
+                constraint: constructor.constraint,
+                layers,
+                spawns: constructor.spawns,
+                hill: None,
+                flags: vec![],
+                pickups: vec![],
+                textures_num: constructor.textures_num,
+                background: constructor.background,
+                parallax: vec![],
+                ambient: Ambient::default(),
+                forces: Forces::default(),
+                fields: vec![],
+                hazards: vec![],
+                pack_atlas: false,
+                particles: constructor.particles.map(particles),
+                connections: constructor.connections,
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use bevy::math::vec2;
+
+        use crate::map::MapError;
+
+        use super::*;
+
+        fn push_f32s(bytes: &mut Vec<u8>, values: &[f32]) {
+            for value in values {
+                bytes.extend(value.to_le_bytes());
+            }
+        }
+
+        /// A map as the first release saved it, byte by byte.
+        fn baseline_map() -> Vec<u8> {
+            let mut bytes = vec![3, b'o', b'l', b'd']; // name
+            bytes.push(0); // Constraint::Box
+            push_f32s(&mut bytes, &[-10., -10., 10., 10.]);
+            bytes.push(1); // one particle
+            push_f32s(&mut bytes, &[1., 1., 0., 0., 0., 0., 0., 0.]); // radius, mass, pos, pos_old, acc
+            bytes.push(2); // texture
+            bytes.push(0); // Kind::None
+            push_f32s(&mut bytes, &[1., 0., 0., 1.]); // color
+            bytes.push(0); // no connections
+            bytes.push(1); // one spawn
+            push_f32s(&mut bytes, &[0., 5.]);
+            bytes.push(1); // team
+            bytes.push(3); // textures_num
+            bytes.push(1); // background
+            bytes
+        }
+
+        #[test]
+        fn baseline_map_test() {
+            let map = Map::deserialize(&baseline_map()).unwrap();
+            assert_eq!(map.name, "old");
+            assert_eq!(map.spawns, vec![Spawn { pos: vec2(0., 5.), team: 1 }]);
+            assert_eq!((map.textures_num, map.background), (3, true));
+            let particle = map.particles[0];
+            assert_eq!((particle.texture, particle.color), (2, Vec4::new(1., 0., 0., 1.)));
+            assert_eq!((particle.owner, particle.collision_mask), (None, u16::MAX));
+            assert!(map.hill.is_none() && map.atlas.is_none());
+
+            let bytes = map.serialize();
+            assert!(bytes.starts_with(MAGIC));
+            let resaved = Map::deserialize(&bytes).unwrap();
+            assert_eq!(resaved.serialize(), bytes);
+
+            let mut newer = bytes.clone();
+            newer[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&(VERSION + 1).to_le_bytes());
+            assert!(matches!(Map::deserialize(&newer), Err(MapError::Corrupt(_))));
+        }
+
+        #[test]
+        fn baseline_constructor_test() {
+            let constraint = Constraint::Box(vec2(-10., -10.), vec2(10., 10.));
+            let particle = || ParticleV0 {
+                radius: 1.,
+                mass: 1.,
+                pos: Vec2::ZERO,
+                pos_old: Vec2::ZERO,
+                acc: Vec2::ZERO,
+                texture: 1,
+                kind: Kind::None,
+                color: Vec4::ONE,
+            };
+            let layer = SerdeLayerV0 {
+                constraint: constraint.clone(),
+                grid: TriangularGrid::new(&constraint),
+                base_particle: particle(),
+                link: Some(Link::Force(1.)),
+                strength: 0.5,
+                particles: Some(vec![particle()]),
+                connections: None,
+            };
+            let old = SerdeMapConstructorV0 {
+                name: "old".to_string(),
+                constraint,
+                layers: vec![layer],
+                spawns: vec![],
+                textures_num: 1,
+                background: false,
+                particles: None,
+                connections: None,
+            };
+            let constructor = SerdeMapConstructor::deserialize(&postcard::to_stdvec(&old).unwrap()).unwrap();
+            let layer = &constructor.layers[0];
+            assert_eq!((layer.name.as_str(), layer.visible, layer.strength), ("layer 0", true, 0.5));
+            assert_eq!(layer.base_particle.texture, 1);
+            assert_eq!(layer.particles.as_ref().unwrap().len(), 1);
+
+            let bytes = constructor.serialize();
+            assert!(bytes.starts_with(MAGIC));
+            let resaved = SerdeMapConstructor::deserialize(&bytes).unwrap();
+            assert_eq!(resaved.serialize(), bytes);
+        }
+    }
+}
+
+pub mod vehicle {
+    use std::{ops::Range, path::Path};
+
+    use bevy::math::Vec2;
+    use common::VEHICLE_EXTENSION;
+    use serde::{Deserialize, Serialize};
+    use solver::{
+        particle::{Kind, Particle},
+        Connection, Link, PARTICLE_RADIUS,
+    };
+
+    use crate::{constructor::MapConstructor, format};
+
+    // a vehicle is drawn in the editor with its parts on layers of these names, the other layers are loose parts
+    pub const BASE_LAYER: &str = "base"; // the other parts are joined to it where they touch it
+    pub const MUZZLE_LAYER: &str = "muzzle";
+    pub const LEFT_MOTORS_LAYER: &str = "left motors";
+    pub const RIGHT_MOTORS_LAYER: &str = "right motors";
+
+    pub const MAX_PARTICLES: usize = 150; // about twice the tank
+    pub const MAX_MASS: f32 = 400.;
+    pub const MAX_DURABILITY: f32 = 20.; // of a single link, the artillery's base has 16
+    pub const MIN_RADIUS: f32 = PARTICLE_RADIUS / 2.; // of a spike, the smallest particle the editor draws with
+    pub const MAX_MOTOR: f32 = 512.; // acceleration of a motor, the tank's in the top gear
+
+    const JOINT_DISTANCE: f32 = 2. * PARTICLE_RADIUS * 1.1; // neighbours on the grid are one diameter apart
+
+    /// Player model built in the editor, the game places it in place of a chassis.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Vehicle {
+        pub name: String,
+        pub particles: Vec<Particle>,
+        pub connections: Vec<Connection>,
+        pub base_connections: Vec<usize>, // their durability is the hp
+        pub left_motors: Vec<usize>,
+        pub right_motors: Vec<usize>,
+        pub pistols: Vec<usize>,      // links aiming the muzzle
+        pub center: usize,            // pivot of the muzzle
+        pub muzzle: usize,            // end of the muzzle, the projectiles come out of it
+        pub center_connection: usize, // from the base to the center
+    }
+
+    /// Why a vehicle couldn't be exported or loaded.
+    #[derive(Debug)]
+    pub enum VehicleError {
+        Io(std::io::Error),
+        Corrupt(String),
+        MissingLayer(&'static str),
+        TooBig(usize),  // particles
+        TooHeavy(f32), // mass
+    }
+
+    impl std::fmt::Display for VehicleError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::Io(error) => write!(f, "Failed to read the vehicle: {error}"),
+                Self::Corrupt(reason) => write!(f, "Vehicle is corrupt: {reason}"),
+                Self::MissingLayer(name) => write!(f, "Vehicle has no \"{name}\" layer"),
+                Self::TooBig(particles) => write!(f, "Vehicle has {particles} particles, at most {MAX_PARTICLES} are allowed"),
+                Self::TooHeavy(mass) => write!(f, "Vehicle weighs {mass}, at most {MAX_MASS} is allowed"),
+            }
+        }
+    }
+
+    impl std::error::Error for VehicleError {}
+
+    impl From<std::io::Error> for VehicleError {
+        fn from(error: std::io::Error) -> Self {
+            Self::Io(error)
+        }
+    }
+
+    impl Vehicle {
+        /// Builds the vehicle from the visible layers, the parts are found by the names of their layers.
+        /// The muzzle turns around its particle closest to the base and is aimed from the ends of the base.
+        pub fn from_constructor(constructor: &mut MapConstructor) -> Result<Self, VehicleError> {
+            constructor.bake_layers();
+            let mut particles = constructor.particles.clone().unwrap_or_default();
+            let mut connections = constructor.connections.clone().unwrap_or_default();
+
+            let mut layers: Vec<(&str, Range<usize>, Option<Link>)> = vec![];
+            let mut start = 0;
+            for layer in constructor.layers.iter().filter(|layer| layer.visible) {
+                let len = layer.particles.as_ref().map_or(0, Vec::len);
+                layers.push((layer.name.as_str(), start..start + len, layer.link));
+                start += len;
+            }
+            let find = |name: &'static str| {
+                layers
+                    .iter()
+                    .find(|(layer, range, _)| *layer == name && !range.is_empty())
+                    .map(|(_, range, link)| (range.clone(), *link))
+                    .ok_or(VehicleError::MissingLayer(name))
+            };
+            let (base, base_link) = find(BASE_LAYER)?;
+            let (muzzle, muzzle_link) = find(MUZZLE_LAYER)?;
+            let (left_motors, _) = find(LEFT_MOTORS_LAYER)?;
+            let (right_motors, _) = find(RIGHT_MOTORS_LAYER)?;
+            let base_link = base_link.ok_or(VehicleError::Corrupt("the base has no links".to_string()))?;
+            let muzzle_link = muzzle_link.unwrap_or(base_link);
+
+            for i in left_motors.clone().chain(right_motors.clone()) {
+                if !particles[i].kind.is_motor() {
+                    particles[i].set_kind(Kind::Motor(0.));
+                }
+            }
+
+            // the links of the loose parts don't count for the hp, like the treads of the tank
+            let part = |i: usize| [&base, &muzzle, &left_motors, &right_motors].iter().any(|range| range.contains(&i));
+            let mut base_connections: Vec<usize> =
+                (0..connections.len()).filter(|i| part(connections[*i].0) || part(connections[*i].1)).collect();
+            let mut join = |connections: &mut Vec<Connection>, i: usize, j: usize, link: Link| {
+                let length = particles[i].pos.distance(particles[j].pos);
+                base_connections.push(connections.len());
+                connections.push((i, j, link.with_length(length)));
+                connections.len() - 1
+            };
+
+            for i in (0..particles.len()).filter(|i| !base.contains(i) && !muzzle.contains(i)) {
+                for j in base.clone() {
+                    let distance = particles[i].pos.distance(particles[j].pos);
+                    if distance > 0. && distance <= JOINT_DISTANCE {
+                        join(&mut connections, j, i, base_link);
+                    }
+                }
+            }
+
+            let middle = base.clone().map(|i| particles[i].pos).sum::<Vec2>() / base.len() as f32;
+            let closest = |range: Range<usize>, pos: Vec2| {
+                range.min_by(|i, j| particles[*i].pos.distance(pos).total_cmp(&particles[*j].pos.distance(pos))).unwrap()
+            };
+            let center = closest(muzzle.clone(), middle);
+            let center_pos = particles[center].pos;
+            let end = muzzle.clone().max_by(|i, j| particles[*i].pos.distance(center_pos).total_cmp(&particles[*j].pos.distance(center_pos))).unwrap();
+            let center_base = closest(base.clone(), center_pos);
+            let left = base.clone().min_by(|i, j| particles[*i].pos.x.total_cmp(&particles[*j].pos.x)).unwrap();
+            let right = base.clone().max_by(|i, j| particles[*i].pos.x.total_cmp(&particles[*j].pos.x)).unwrap();
+
+            join(&mut connections, left, center, muzzle_link);
+            join(&mut connections, right, center, muzzle_link);
+            let pistols = vec![join(&mut connections, left, end, muzzle_link), join(&mut connections, right, end, muzzle_link)];
+            let center_connection = join(&mut connections, center_base, center, muzzle_link);
+
+            let vehicle = Self {
+                name: constructor.name.clone(),
+                particles,
+                connections,
+                base_connections,
+                left_motors: left_motors.collect(),
+                right_motors: right_motors.collect(),
+                pistols,
+                center,
+                muzzle: end,
+                center_connection,
+            };
+            vehicle.validate()?;
+            Ok(vehicle)
+        }
+
+        /// Rejects vehicles that would make the controller index out of bounds or that are too strong to be fair.
+        pub fn validate(&self) -> Result<(), VehicleError> {
+            let corrupt = |reason: &str| Err(VehicleError::Corrupt(reason.to_string()));
+            let particles = self.particles.len();
+            if particles > MAX_PARTICLES {
+                return Err(VehicleError::TooBig(particles));
+            }
+            if self.particles.iter().any(|p| !(p.mass > 0. && p.mass.is_finite())) {
+                return corrupt("particle without mass");
+            }
+            let mass = self.particles.iter().map(|p| p.mass).sum::<f32>();
+            if mass > MAX_MASS {
+                return Err(VehicleError::TooHeavy(mass));
+            }
+            if self.particles.iter().any(|p| !matches!(p.kind, Kind::None | Kind::Spike | Kind::Motor(_))) {
+                return corrupt("particle of a kind vehicles can't have");
+            }
+            // vehicles come from other players, every peer would take a NaN or a huge force into its solver
+            if self.particles.iter().any(|p| !(p.pos.is_finite() && p.pos_old.is_finite() && p.acc.is_finite())) {
+                return corrupt("particle out of the map");
+            }
+            if self.particles.iter().any(|p| !(MIN_RADIUS..=PARTICLE_RADIUS).contains(&p.radius)) {
+                return corrupt("particle of a size the editor can't draw");
+            }
+            if self.particles.iter().any(|p| matches!(p.kind, Kind::Motor(acc) if !(acc.abs() <= MAX_MOTOR))) {
+                return corrupt("motor stronger than allowed");
+            }
+            if self.particles.iter().any(|p| {
+                !(p.temperature.is_finite() && (0. ..=1.).contains(&p.friction) && (0. ..=1.).contains(&p.restitution))
+            }) {
+                return corrupt("particle of a material the editor can't draw");
+            }
+            if self.connections.iter().any(|(i, j, link)| {
+                *i >= particles || *j >= particles || matches!(link, Link::Angle { pivot, .. } if *pivot >= particles)
+            }) {
+                return corrupt("link to a missing particle");
+            }
+            if self.connections.iter().any(|(_, _, link)| !(link.durability() <= MAX_DURABILITY)) {
+                return corrupt("link stronger than allowed");
+            }
+            if self.connections.iter().any(|(_, _, link)| !Self::link_finite(link)) {
+                return corrupt("link out of the map");
+            }
+            let connections = self.connections.len();
+            if self.base_connections.iter().chain(&self.pistols).chain([&self.center_connection]).any(|i| *i >= connections) {
+                return corrupt("missing link");
+            }
+            if self.left_motors.iter().chain(&self.right_motors).chain([&self.center, &self.muzzle]).any(|i| *i >= particles) {
+                return corrupt("missing particle");
+            }
+            if self.right_motors.is_empty() || self.pistols.is_empty() {
+                return corrupt("no right motors or nothing aims the muzzle");
+            }
+            if self.connections[self.center_connection].1 != self.center {
+                return corrupt("the center link doesn't hold the center");
+            }
+            if self.base_connections.iter().map(|i| self.connections[*i].2.durability()).sum::<f32>() <= 0. {
+                return corrupt("no hp");
+            }
+            Ok(())
+        }
+
+        fn link_finite(link: &Link) -> bool {
+            match *link {
+                Link::Force(force) => force.is_finite(),
+                Link::Rigid { length, durability, elasticity } => {
+                    length.is_finite() && durability.is_finite() && elasticity.is_finite()
+                }
+                Link::Rope { length, durability } => length.is_finite() && durability.is_finite(),
+                Link::Angle { target, stiffness, .. } => target.is_finite() && (0. ..=1.).contains(&stiffness),
+            }
+        }
+
+        pub fn serialize(&self) -> Vec<u8> {
+            format::encode(self)
+        }
+
+        pub fn deserialize(bytes: &[u8]) -> Result<Self, VehicleError> {
+            let vehicle = format::decode::<Vehicle, Vehicle>(bytes).map_err(VehicleError::Corrupt)?;
+            vehicle.validate()?;
+            Ok(vehicle)
+        }
+
+        pub fn get_path<P: AsRef<Path>>(name: &str, base_path: P) -> std::path::PathBuf {
+            base_path.as_ref().join(format!("{name}.{VEHICLE_EXTENSION}"))
+        }
+
+        /// Names of the vehicles in the directory.
+        pub fn list<P: AsRef<Path>>(base_path: P) -> Vec<String> {
+            let Ok(entries) = std::fs::read_dir(base_path) else {
+                return vec![];
+            };
+            let mut names: Vec<String> = entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == VEHICLE_EXTENSION))
+                .filter_map(|path| path.file_stem().and_then(|name| name.to_str()).map(str::to_string))
+                .collect();
+            names.sort();
+            names
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use bevy::math::vec2;
+        use image::Rgba;
+        use solver::{particle::METAL, Constraint};
+
+        use super::*;
+        use crate::constructor::ConnectionMode;
+
+        /// Hull with a muzzle on top and a motor under each end.
+        fn test_constructor() -> MapConstructor {
+            let mut constructor = MapConstructor::new("test".to_string(), Constraint::Box(vec2(-20., -20.), vec2(20., 20.)));
+            let link = Link::Rigid { length: 1., durability: 10., elasticity: 10. };
+            let parts: [(&str, fn(Vec2) -> bool); 4] = [
+                (BASE_LAYER, |pos| pos.x.abs() < 4. && pos.y.abs() < 1.),
+                (MUZZLE_LAYER, |pos| pos.x.abs() < 1. && pos.y > 1.5 && pos.y < 6.),
+                (LEFT_MOTORS_LAYER, |pos| (pos.x + 3.).abs() < 1. && pos.y > -2.5 && pos.y < -1.),
+                (RIGHT_MOTORS_LAYER, |pos| (pos.x - 3.).abs() < 1. && pos.y > -2.5 && pos.y < -1.),
+            ];
+            for (i, (name, shape)) in parts.into_iter().enumerate() {
+                constructor.add_layer();
+                let layer = &mut constructor.layers[i];
+                layer.name = name.to_string();
+                layer.base_particle = METAL;
+                layer.link = Some(link);
+                layer.mode = ConnectionMode::Triangulated;
+                layer.init_from_fn(|pos| shape(pos).then_some(Rgba([255, 255, 255, 255])));
+            }
+            constructor
+        }
+
+        #[test]
+        fn export_vehicle_test() {
+            let mut constructor = test_constructor();
+            let vehicle = Vehicle::from_constructor(&mut constructor).unwrap();
+            assert!(vehicle.particles[vehicle.center].pos.y < vehicle.particles[vehicle.muzzle].pos.y);
+            assert!(vehicle.right_motors.iter().all(|i| vehicle.particles[*i].kind.is_motor()));
+            // the motors hang on the base
+            let joined = |i: &usize| vehicle.connections.iter().any(|(a, b, _)| b == i && vehicle.particles[*a].pos.y > -1.);
+            assert!(vehicle.left_motors.iter().any(joined));
+            assert!(Vehicle::deserialize(&vehicle.serialize()).is_ok());
+
+            constructor.rename_layer(1, "barrel".to_string());
+            assert!(matches!(Vehicle::from_constructor(&mut constructor), Err(VehicleError::MissingLayer(MUZZLE_LAYER))));
+        }
+
+        #[test]
+        fn malicious_vehicle_test() {
+            let vehicle = Vehicle::from_constructor(&mut test_constructor()).unwrap();
+
+            // every change is one a crafted upload could make, none of them gets past the peers
+            let tampered: [fn(&mut Vehicle); 8] = [
+                |v| v.particles[0].pos.x = f32::NAN,
+                |v| v.particles[0].pos_old.y = f32::INFINITY,
+                |v| v.particles[0].radius = 1000.,
+                |v| v.particles[0].radius = 0.,
+                |v| v.particles[v.left_motors[0]].set_kind(Kind::Motor(1e30)),
+                |v| v.particles[v.right_motors[0]].set_kind(Kind::Motor(f32::NAN)),
+                |v| v.particles[0].friction = -5.,
+                |v| v.connections[0].2 = v.connections[0].2.with_length(f32::NAN),
+            ];
+            for tamper in tampered {
+                let mut bad = vehicle.clone();
+                tamper(&mut bad);
+                assert!(matches!(Vehicle::deserialize(&bad.serialize()), Err(VehicleError::Corrupt(_))));
+            }
         }
     }
 }
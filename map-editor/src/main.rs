@@ -1,11 +1,13 @@
+use std::f32::consts::PI;
 use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::Result;
-use bevy::asset::AssetPath;
-use bevy::input::mouse::MouseWheel;
-use bevy::math::vec2;
+use bevy::asset::{AssetPath, LoadState};
+use bevy::log::{BoxedLayer, LogPlugin};
+use bevy::math::{vec2, vec3};
 use bevy::prelude::*;
 
 use bevy::render::camera::ScalingMode;
@@ -18,16 +20,24 @@ use bevy::{
     ui::{JustifyContent, Style},
     DefaultPlugins,
 };
+use bevy_simple_text_input::{
+    TextInputBundle, TextInputInactive, TextInputPlugin, TextInputSettings, TextInputSubmitEvent, TextInputSystem,
+    TextInputValue,
+};
 
-use common::{MAX_TEAMS, RELATIVE_MAPS_PATH};
+use common::locale::{Locale, DEFAULT_LANGUAGE};
+use common::{config, trace::ChromeLayer, MAP_FILE, THUMBNAIL_FILE};
 use image::RgbaImage;
-use map_editor::map::{Map, Spawn};
+use map_editor::map::{Flag, Hill, Map, Pickup, Spawn};
 use map_editor::serde::SerdeMapConstructor;
-use text_io::{read, try_read};
+use map_editor::vehicle::Vehicle;
 
-use map_editor::constructor::MapConstructor;
-use render::{RenderSimulationPlugin, RenderedSimulation, SimulationCamera, SimulationTextures};
-use solver::{Link, Solver};
+use map_editor::constructor::{polygon_contains, ConnectionMode, MapConstructor};
+use render::{ambient::Ambient, atlas, camera::ObserverCamera, debug::SolverDebugPlugin, palette::TeamColors, settings::Settings, ParallaxLayer, RenderSimulationPlugin, RenderedSimulation, SimulationCamera, SimulationTextures};
+use solver::{
+    particle::{Item, Kind, GROUND, WATER, WOOD},
+    Constraint, ForceField, Hazard, Link, Polygon, Push, Region, Solver, PARTICLE_RADIUS,
+};
 
 const DURABILITY_DEFAULT: f32 = 1.;
 const ELASTICITY_DEFAULT: f32 = 5.;
@@ -39,19 +49,98 @@ struct TextureColumn;
 enum ButtonAction {
     AddTexture,
     AddBackground,
+    AddParallax,
+    ToggleAtlas,
     RemoveTexture(Entity, Handle<Image>),
+    RestoreAutosave(PathBuf),
+    DiscardAutosave(PathBuf),
 }
 
 #[derive(Component)]
 enum TextMarker {
+    Fixed,
+    Fluid,
+    Atlas,
+}
+
+/// Inspector input, the value is applied to the map or the current layer when ENTER is pressed.
+#[derive(Component, Clone, Copy, Debug)]
+enum Field {
     Mass,
     Texture,
     Strength,
     Durability,
     Elasticity,
+    Friction,
+    Restitution,
+    Group,
+    Mask,
+    Gravity,
+    Wind,
+    Push,
+    Parallax,
+    Ambient,
+    Name,
+    LayerName,
+}
+
+/// Panel listing the layers, rebuilt whenever they change.
+#[derive(Component)]
+struct LayerList;
+
+#[derive(Component, Clone, Copy)]
+enum LayerAction {
+    Select(usize),
+    Toggle(usize),
+    Up(usize),
+    Down(usize),
+}
+
+/// Cells of the current layer picked with the rectangle or the lasso.
+#[derive(Resource, Default)]
+struct Selection {
+    layer: Option<(usize, usize)>, // index of the layer and the number of layers when it was made
+    cells: Vec<(usize, usize)>,
+    drag: Option<SelectionDrag>,
+}
+
+/// First corner of the force field or the hazard being placed, or the center of a radial field.
+#[derive(Resource, Default)]
+struct RegionStart(Option<Vec2>);
+
+const FIELD_FORCE: f32 = 50.; // push of the first field, an updraft lifting light particles
+const PARALLAX_DEFAULT: f32 = 0.5; // factor of a new parallax layer, it follows the camera halfway
+
+enum SelectionDrag {
+    Rect(Vec2, Vec2),
+    Lasso(Vec<Vec2>),
+}
+
+/// Text style and strings for the parts of the UI built after the startup.
+#[derive(Resource)]
+struct EditorStyle {
+    text: TextStyle,
+    shown: String,
+    hidden: String,
+    restore: String,
+    restore_yes: String,
+    restore_no: String,
+}
+
+fn text_input(text_style: &TextStyle) -> TextInputBundle {
+    TextInputBundle::default()
+        .with_text_style(text_style.clone())
+        .with_placeholder("---", None)
+        .with_settings(TextInputSettings {
+            retain_on_submit: true,
+            ..default()
+        })
+        .with_inactive(true)
 }
 
-fn setup_ui(mut commands: Commands, textures: Res<SimulationTextures>) {
+fn setup_ui(mut commands: Commands, textures: Res<SimulationTextures>, asset_server: Res<AssetServer>) {
+    let locale = Locale::load(&std::env::var("SMOG_LANG").unwrap_or(DEFAULT_LANGUAGE.to_string()));
+
     let style = Style {
         width: Val::Px(160.0),
         height: Val::Px(30.0),
@@ -70,11 +159,27 @@ fn setup_ui(mut commands: Commands, textures: Res<SimulationTextures>) {
     };
 
     let text_style = TextStyle {
-        font: Default::default(),
+        font: locale
+            .font()
+            .map(|font| asset_server.load(font.to_string()))
+            .unwrap_or_default(),
         font_size: 20.0,
         color: Color::WHITE,
     };
 
+    let input_node = NodeBundle {
+        style: Style {
+            width: Val::Px(140.0),
+            border: UiRect::all(Val::Px(2.)),
+            padding: UiRect::horizontal(Val::Px(5.)),
+            ..default()
+        },
+        border_color: Color::WHITE.into(),
+        background_color: Color::BLACK.into(),
+        border_radius: BorderRadius::all(Val::Px(5.)),
+        ..default()
+    };
+
     let text_node = NodeBundle {
         style: Style {
             width: Val::Px(160.0),
@@ -123,50 +228,93 @@ fn setup_ui(mut commands: Commands, textures: Res<SimulationTextures>) {
                     // mass
                     parent.spawn(text_node.clone()).with_children(|parent| {
                         parent.spawn(TextBundle {
-                            text: Text::from_section("[M]ass:", text_style.clone()),
+                            text: Text::from_section(locale.get("editor.mass"), text_style.clone()),
                             ..default()
                         });
 
-                        parent
-                            .spawn(TextBundle {
-                                text: Text::from_section("---", text_style.clone()),
-                                ..default()
-                            })
-                            .insert(TextMarker::Mass);
+                        parent.spawn((input_node.clone(), text_input(&text_style), Field::Mass));
                     });
                     // texture
                     parent.spawn(text_node.clone()).with_children(|parent| {
                         parent.spawn(TextBundle {
-                            text: Text::from_section("[T]exture:", text_style.clone()),
+                            text: Text::from_section(locale.get("editor.texture"), text_style.clone()),
                             ..default()
                         });
 
-                        parent
-                            .spawn(TextBundle {
-                                text: Text::from_section("---", text_style.clone()),
-                                ..default()
-                            })
-                            .insert(TextMarker::Texture);
+                        parent.spawn((input_node.clone(), text_input(&text_style), Field::Texture));
                     });
                     // strength
                     parent.spawn(text_node.clone()).with_children(|parent| {
                         parent.spawn(TextBundle {
-                            text: Text::from_section("[S]trength:", text_style.clone()),
+                            text: Text::from_section(locale.get("editor.strength"), text_style.clone()),
                             ..default()
                         });
 
-                        parent
-                            .spawn(TextBundle {
-                                text: Text::from_section("---", text_style.clone()),
-                                ..default()
-                            })
-                            .insert(TextMarker::Strength);
+                        parent.spawn((input_node.clone(), text_input(&text_style), Field::Strength));
                     });
 
                     // durability
                     parent.spawn(text_node.clone()).with_children(|parent| {
                         parent.spawn(TextBundle {
-                            text: Text::from_section("[D]urability:", text_style.clone()),
+                            text: Text::from_section(locale.get("editor.durability"), text_style.clone()),
+                            ..default()
+                        });
+
+                        parent.spawn((input_node.clone(), text_input(&text_style), Field::Durability));
+                    });
+
+                    // elasticity
+                    parent.spawn(text_node.clone()).with_children(|parent| {
+                        parent.spawn(TextBundle {
+                            text: Text::from_section(locale.get("editor.elasticity"), text_style.clone()),
+                            ..default()
+                        });
+
+                        parent.spawn((input_node.clone(), text_input(&text_style), Field::Elasticity));
+                    });
+
+                    // friction
+                    parent.spawn(text_node.clone()).with_children(|parent| {
+                        parent.spawn(TextBundle {
+                            text: Text::from_section(locale.get("editor.friction"), text_style.clone()),
+                            ..default()
+                        });
+
+                        parent.spawn((input_node.clone(), text_input(&text_style), Field::Friction));
+                    });
+
+                    // restitution
+                    parent.spawn(text_node.clone()).with_children(|parent| {
+                        parent.spawn(TextBundle {
+                            text: Text::from_section(locale.get("editor.restitution"), text_style.clone()),
+                            ..default()
+                        });
+
+                        parent.spawn((input_node.clone(), text_input(&text_style), Field::Restitution));
+                    });
+
+                    // collision group and mask, in hex
+                    parent.spawn(text_node.clone()).with_children(|parent| {
+                        parent.spawn(TextBundle {
+                            text: Text::from_section(locale.get("editor.group"), text_style.clone()),
+                            ..default()
+                        });
+
+                        parent.spawn((input_node.clone(), text_input(&text_style), Field::Group));
+                    });
+                    parent.spawn(text_node.clone()).with_children(|parent| {
+                        parent.spawn(TextBundle {
+                            text: Text::from_section(locale.get("editor.mask"), text_style.clone()),
+                            ..default()
+                        });
+
+                        parent.spawn((input_node.clone(), text_input(&text_style), Field::Mask));
+                    });
+
+                    // fixed
+                    parent.spawn(text_node.clone()).with_children(|parent| {
+                        parent.spawn(TextBundle {
+                            text: Text::from_section(locale.get("editor.fixed"), text_style.clone()),
                             ..default()
                         });
 
@@ -175,13 +323,13 @@ fn setup_ui(mut commands: Commands, textures: Res<SimulationTextures>) {
                                 text: Text::from_section("---", text_style.clone()),
                                 ..default()
                             })
-                            .insert(TextMarker::Durability);
+                            .insert(TextMarker::Fixed);
                     });
 
-                    // elasticity
+                    // fluid
                     parent.spawn(text_node.clone()).with_children(|parent| {
                         parent.spawn(TextBundle {
-                            text: Text::from_section("[E]lasticity:", text_style.clone()),
+                            text: Text::from_section(locale.get("editor.fluid"), text_style.clone()),
                             ..default()
                         });
 
@@ -190,7 +338,7 @@ fn setup_ui(mut commands: Commands, textures: Res<SimulationTextures>) {
                                 text: Text::from_section("---", text_style.clone()),
                                 ..default()
                             })
-                            .insert(TextMarker::Elasticity);
+                            .insert(TextMarker::Fluid);
                     });
                 });
             // Right column
@@ -209,28 +357,107 @@ fn setup_ui(mut commands: Commands, textures: Res<SimulationTextures>) {
                     ..default()
                 })
                 .with_children(|parent| {
+                    // map name, saved under it
+                    parent.spawn((
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Px(160.0),
+                                height: Val::Px(30.0),
+                                ..input_node.style.clone()
+                            },
+                            ..input_node.clone()
+                        },
+                        text_input(&text_style).with_placeholder(locale.get("editor.name"), None),
+                        Field::Name,
+                    ));
+                    parent.spawn((
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Px(160.0),
+                                height: Val::Px(30.0),
+                                ..input_node.style.clone()
+                            },
+                            ..input_node.clone()
+                        },
+                        text_input(&text_style).with_placeholder(locale.get("editor.layer_name"), None),
+                        Field::LayerName,
+                    ));
+
+                    // global forces
+                    for (label, field) in [
+                        ("editor.gravity", Field::Gravity),
+                        ("editor.wind", Field::Wind),
+                        ("editor.push", Field::Push),
+                        ("editor.parallax", Field::Parallax),
+                        ("editor.ambient", Field::Ambient),
+                    ] {
+                        parent
+                            .spawn(NodeBundle {
+                                style: Style {
+                                    height: Val::Auto,
+                                    ..text_node.style.clone()
+                                },
+                                ..text_node.clone()
+                            })
+                            .with_children(|parent| {
+                                parent.spawn(TextBundle {
+                                    text: Text::from_section(locale.get(label), text_style.clone()),
+                                    ..default()
+                                });
+                                parent.spawn((input_node.clone(), text_input(&text_style), field));
+                            });
+                    }
+
                     // Add background button
                     parent
                         .spawn(button.clone())
                         .with_children(|parent| {
                             parent.spawn(TextBundle {
-                                text: Text::from_section("Add background", text_style.clone()),
+                                text: Text::from_section(locale.get("editor.add_background"), text_style.clone()),
                                 ..default()
                             });
                         })
                         .insert(ButtonAction::AddBackground);
 
+                    // Add parallax button
+                    parent
+                        .spawn(button.clone())
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle {
+                                text: Text::from_section(locale.get("editor.add_parallax"), text_style.clone()),
+                                ..default()
+                            });
+                        })
+                        .insert(ButtonAction::AddParallax);
+
                     // Add texture button
                     parent
                         .spawn(button.clone())
                         .with_children(|parent| {
                             parent.spawn(TextBundle {
-                                text: Text::from_section("Add texture", text_style.clone()),
+                                text: Text::from_section(locale.get("editor.add_texture"), text_style.clone()),
                                 ..default()
                             });
                         })
                         .insert(ButtonAction::AddTexture);
 
+                    // Atlas toggle
+                    parent
+                        .spawn(button.clone())
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle {
+                                text: Text::from_section(locale.get("editor.atlas"), text_style.clone()),
+                                ..default()
+                            });
+                            parent
+                                .spawn(TextBundle {
+                                    text: Text::from_section("---", text_style.clone()),
+                                    ..default()
+                                })
+                                .insert(TextMarker::Atlas);
+                        })
+                        .insert(ButtonAction::ToggleAtlas);
+
                     // Default textures
                     for handle in textures.textures.iter() {
                         parent.spawn(ButtonBundle {
@@ -241,45 +468,554 @@ fn setup_ui(mut commands: Commands, textures: Res<SimulationTextures>) {
                 })
                 .insert(TextureColumn);
         });
+
+    commands.spawn((
+        TextBundle {
+            text: Text::from_section("", TextStyle {
+                color: Color::srgb(1., 0.3, 0.3),
+                ..text_style.clone()
+            }),
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.),
+                left: Val::Px(10.),
+                ..default()
+            },
+            ..default()
+        },
+        StatusText,
+    ));
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(40.),
+                left: Val::Px(10.),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(2.),
+                ..default()
+            },
+            ..default()
+        },
+        LayerList,
+    ));
+    commands.insert_resource(EditorStyle {
+        text: text_style,
+        shown: locale.get("editor.shown").to_string(),
+        hidden: locale.get("editor.hidden").to_string(),
+        restore: locale.get("editor.restore").to_string(),
+        restore_yes: locale.get("editor.restore_yes").to_string(),
+        restore_no: locale.get("editor.restore_no").to_string(),
+    });
 }
 
 fn update_ui_system(mut query: Query<(&mut Text, &TextMarker)>, constructor: Query<&Constructor>) {
     let constructor = constructor.single();
-    if constructor.0.layers.len() > 0 {
-        let layer = &constructor.0.layers[constructor.1];
-        for (mut text, marker) in &mut query {
-            match marker {
-                TextMarker::Mass => text.sections[0].value = layer.base_particle.mass.to_string(),
-                TextMarker::Texture => {
-                    text.sections[0].value = layer.base_particle.texture.to_string()
-                }
-                TextMarker::Strength if layer.link.is_some() => {
-                    text.sections[0].value = layer.strength.to_string()
+    let layer = constructor.0.layers.get(constructor.1);
+    for (mut text, marker) in &mut query {
+        match (marker, layer) {
+            (TextMarker::Fixed, Some(layer)) => {
+                text.sections[0].value = layer.base_particle.is_static().to_string()
+            }
+            (TextMarker::Fluid, Some(layer)) => {
+                text.sections[0].value = layer.base_particle.is_fluid().to_string()
+            }
+            (TextMarker::Atlas, _) => text.sections[0].value = constructor.0.pack_atlas.to_string(),
+            _ => (),
+        }
+    }
+}
+
+fn field_value(constructor: &Constructor, field: Field) -> String {
+    let forces = &constructor.0.forces;
+    let layer = constructor.0.layers.get(constructor.1);
+    match (field, layer) {
+        (Field::Name, _) => constructor.0.name.clone(),
+        (Field::Gravity, _) => (-forces.gravity.y).to_string(),
+        (Field::Wind, _) => forces.wind.x.to_string(),
+        (Field::Push, _) => match constructor.0.fields.last().map(|field| field.push) {
+            Some(Push::Direction(force)) => format!("{} {}", force.x, force.y),
+            Some(Push::Radial(strength)) => strength.to_string(),
+            None => String::new(),
+        },
+        (Field::Ambient, _) => {
+            let ambient = constructor.0.ambient;
+            format!("{} {} {} {}", ambient.color.x, ambient.color.y, ambient.color.z, ambient.brightness)
+        }
+        (Field::Parallax, _) => constructor.0.parallax.last().map_or(String::new(), |layer| layer.factor.to_string()),
+        (Field::LayerName, Some(layer)) => layer.name.clone(),
+        (Field::Mass, Some(layer)) => layer.base_particle.mass.to_string(),
+        (Field::Texture, Some(layer)) => layer.base_particle.texture.to_string(),
+        (Field::Friction, Some(layer)) => layer.base_particle.friction.to_string(),
+        (Field::Restitution, Some(layer)) => layer.base_particle.restitution.to_string(),
+        (Field::Group, Some(layer)) => format!("{:#06x}", layer.base_particle.collision_group),
+        (Field::Mask, Some(layer)) => format!("{:#06x}", layer.base_particle.collision_mask),
+        (Field::Strength, Some(layer)) if layer.link.is_some() => layer.strength.to_string(),
+        (Field::Durability, Some(layer)) => layer.link.map_or(String::new(), |l| l.durability().to_string()),
+        (Field::Elasticity, Some(layer)) => layer.link.map_or(String::new(), |l| l.elasticity().to_string()),
+        _ => String::new(),
+    }
+}
+
+/// Shows the values of the map and the current layer in the inputs that aren't being edited.
+fn inspector_sync_system(
+    mut inputs: Query<(&mut TextInputValue, &TextInputInactive, &Field)>,
+    constructor: Query<&Constructor>,
+) {
+    let constructor = constructor.single();
+    for (mut value, inactive, field) in &mut inputs {
+        if !inactive.0 {
+            continue;
+        }
+        let current = field_value(constructor, *field);
+        if value.0 != current {
+            value.0 = current;
+        }
+    }
+}
+
+/// Lights the simulation with the ambient of the map, whether it was edited or came with a loaded layout.
+fn ambient_system(constructor: Query<&Constructor>, mut ambient: Query<&mut Ambient, With<RenderedSimulation>>) {
+    let constructor = constructor.single();
+    for mut ambient in &mut ambient {
+        if *ambient != constructor.0.ambient {
+            *ambient = constructor.0.ambient;
+        }
+    }
+}
+
+fn apply_field(constructor: &mut Constructor, simulation: &mut Solver, field: Field, value: &str) -> Result<()> {
+    let value = value.trim();
+    let float = || value.parse::<f32>().map_err(|_| anyhow::anyhow!("Incorrect {field:?} \"{value}\""));
+    let bits = || {
+        u16::from_str_radix(value.trim_start_matches("0x"), 16)
+            .map_err(|_| anyhow::anyhow!("Incorrect {field:?} \"{value}\", expected hex bits like 0x0003"))
+    };
+    match field {
+        Field::Name => {
+            if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '/' || c == '\\') {
+                anyhow::bail!("Map name must be a single word");
+            }
+            constructor.0.name = value.to_string();
+            return Ok(());
+        }
+        Field::Gravity => {
+            constructor.0.forces.gravity = vec2(0., -float()?);
+            simulation.forces = constructor.0.forces;
+            return Ok(());
+        }
+        Field::Wind => {
+            constructor.0.forces.wind = vec2(float()?, 0.);
+            simulation.forces = constructor.0.forces;
+            return Ok(());
+        }
+        Field::Push => {
+            // "x y" pushes in a direction, a single number away from the center
+            let numbers = value
+                .split_whitespace()
+                .map(|number| number.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| anyhow::anyhow!("Incorrect {field:?} \"{value}\""))?;
+            let push = match numbers[..] {
+                [strength] => Push::Radial(strength),
+                [x, y] => Push::Direction(vec2(x, y)),
+                _ => anyhow::bail!("Incorrect {field:?} \"{value}\", expected \"x y\" or a single number"),
+            };
+            let Some(last) = constructor.0.fields.last_mut() else {
+                anyhow::bail!("Place a force field first");
+            };
+            last.push = push;
+            simulation.fields = constructor.0.fields.clone();
+            return Ok(());
+        }
+        Field::Ambient => {
+            // "r g b brightness" lights the map, a single number only changes the brightness
+            let numbers = value
+                .split_whitespace()
+                .map(|number| number.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| anyhow::anyhow!("Incorrect {field:?} \"{value}\""))?;
+            let ambient = &mut constructor.0.ambient;
+            match numbers[..] {
+                [brightness] => ambient.brightness = brightness.max(0.),
+                [r, g, b, brightness] => {
+                    ambient.color = vec3(r, g, b).clamp(Vec3::ZERO, Vec3::ONE);
+                    ambient.brightness = brightness.max(0.);
                 }
-                TextMarker::Durability if layer.link.is_some() => {
-                    text.sections[0].value = layer.link.unwrap().durability().to_string();
+                _ => anyhow::bail!("Incorrect {field:?} \"{value}\", expected \"r g b brightness\" or a single number"),
+            }
+            return Ok(());
+        }
+        Field::Parallax => {
+            // 0 stays in place like the background, 1 moves with the camera like the sky
+            let Some(last) = constructor.0.parallax.last_mut() else {
+                anyhow::bail!("Add a parallax layer first");
+            };
+            last.factor = float()?.clamp(0., 1.);
+            return Ok(());
+        }
+        _ => (),
+    }
+
+    let layer_ind = constructor.1;
+    let Some(layer) = constructor.0.layers.get_mut(layer_ind) else {
+        anyhow::bail!("Add a layer first");
+    };
+    match field {
+        Field::LayerName => {
+            if value.is_empty() {
+                anyhow::bail!("Layer name can't be empty");
+            }
+            layer.name = value.to_string();
+            return Ok(());
+        }
+        Field::Mass => layer.base_particle.mass = float()?,
+        Field::Texture => {
+            layer.base_particle.texture = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Incorrect {field:?} \"{value}\""))?
+        }
+        Field::Strength => layer.strength = float()?,
+        Field::Durability => {
+            let elasticity = layer.link.map_or(ELASTICITY_DEFAULT, |l| l.elasticity());
+            layer.link = Some(Link::Rigid {
+                length: 1.,
+                durability: float()?,
+                elasticity,
+            });
+        }
+        Field::Elasticity => {
+            let durability = layer.link.map_or(DURABILITY_DEFAULT, |l| l.durability());
+            layer.link = Some(Link::Rigid {
+                length: 1.,
+                durability,
+                elasticity: float()?,
+            });
+        }
+        Field::Friction => layer.base_particle.friction = float()?.clamp(0., 1.),
+        Field::Restitution => layer.base_particle.restitution = float()?.clamp(0., 1.),
+        Field::Group => layer.base_particle.collision_group = bits()?,
+        Field::Mask => layer.base_particle.collision_mask = bits()?,
+        Field::Name | Field::Gravity | Field::Wind | Field::Push | Field::Parallax | Field::Ambient => unreachable!(),
+    }
+    layer.bake();
+    Ok(())
+}
+
+fn inspector_system(
+    mut commands: Commands,
+    mut events: EventReader<TextInputSubmitEvent>,
+    fields: Query<&Field>,
+    mut constructor: Query<&mut Constructor>,
+    mut simulation: Query<&mut RenderedSimulation>,
+    mut status: ResMut<Status>,
+    mut edited: EventWriter<Edited>,
+) {
+    let mut constructor = constructor.single_mut();
+    let mut simulation = simulation.single_mut();
+    for event in events.read() {
+        let Ok(field) = fields.get(event.entity) else {
+            continue;
+        };
+        match apply_field(&mut constructor, &mut simulation.0, *field, &event.value) {
+            Ok(()) => {
+                info!("{field:?} updated!");
+                status.0.clear();
+                edited.send(Edited);
+                // the renderer picks up the new factor along with the textures
+                if let Field::Parallax = field {
+                    commands.insert_resource(SimulationTextures {
+                        textures: constructor.0.textures.clone(),
+                        background: constructor.0.background.clone(),
+                        parallax: constructor.0.parallax.clone(),
+                        atlas: None,
+                    });
                 }
-                TextMarker::Elasticity if layer.link.is_some() => {
-                    text.sections[0].value =
-                        format!("{} %", layer.link.unwrap().elasticity().to_string());
+            }
+            Err(e) => status.error(e),
+        }
+    }
+}
+
+fn selection_system(
+    mouse: Res<ButtonInput<MouseButton>>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    settings: Res<Settings>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<(&Camera, &Transform), With<SimulationCamera>>,
+    mut constructor: Query<&mut Constructor>,
+    mut simulation: Query<&mut RenderedSimulation>,
+    mut selection: ResMut<Selection>,
+    mut edited: EventWriter<Edited>,
+) {
+    let (camera, camera_transform) = camera.single();
+    let mut constructor = constructor.single_mut();
+    let cursor = windows
+        .single()
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world_2d(&GlobalTransform::from(*camera_transform), cursor));
+
+    let current = (constructor.1, constructor.0.layers.len());
+    if selection.layer.is_some_and(|layer| layer != current) || keyboard.just_pressed(KeyCode::Escape) {
+        selection.layer = None;
+        selection.cells.clear();
+    }
+    let layer_ind = constructor.1;
+    let Some(layer) = constructor.0.layers.get_mut(layer_ind) else {
+        return;
+    };
+
+    // drawing the selection
+    if let Some(cursor) = cursor {
+        if mouse.just_pressed(MouseButton::Left) && keyboard.pressed(KeyCode::ControlLeft) {
+            selection.drag = Some(SelectionDrag::Rect(cursor, cursor));
+        } else if mouse.just_pressed(MouseButton::Left) && keyboard.pressed(KeyCode::ShiftLeft) {
+            selection.drag = Some(SelectionDrag::Lasso(vec![cursor]));
+        }
+        match &mut selection.drag {
+            Some(SelectionDrag::Rect(_, end)) => *end = cursor,
+            Some(SelectionDrag::Lasso(points)) if points.last().is_some_and(|last| last.distance(cursor) > 1.) => {
+                points.push(cursor)
+            }
+            _ => (),
+        }
+    }
+    if mouse.just_released(MouseButton::Left) {
+        if let Some(drag) = selection.drag.take() {
+            selection.cells = match drag {
+                SelectionDrag::Rect(start, end) => {
+                    let rect = Rect::from_corners(start, end);
+                    layer.select(|pos| rect.contains(pos))
                 }
-                _ => text.sections[0].value = "---".to_string(),
+                SelectionDrag::Lasso(points) => layer.select(|pos| polygon_contains(&points, pos)),
+            };
+            selection.layer = Some(current);
+            info!("{} particles selected", selection.cells.len());
+        }
+    }
+
+    if selection.cells.is_empty() {
+        return;
+    }
+
+    // transforms, around the cell closest to the middle of the selection so the particles stay on the grid
+    let sum: Vec2 = selection.cells.iter().map(|cell| layer.cell_position(*cell)).sum();
+    let Some(center) = layer.snap(sum / selection.cells.len() as f32) else {
+        return;
+    };
+    let keys = &settings.editor;
+    let shift = keyboard.pressed(KeyCode::ShiftLeft);
+    let delta = cursor.and_then(|cursor| layer.snap(cursor)).map(|target| target - center);
+    let cells = if keyboard.just_pressed(keys.rotate) {
+        let rotation = Mat2::from_angle(if shift { -PI / 3. } else { PI / 3. });
+        info!("Selection rotated!");
+        layer.transform(&selection.cells, |pos| center + rotation * (pos - center), false)
+    } else if keyboard.just_pressed(keys.mirror) && !keyboard.pressed(KeyCode::AltLeft) {
+        info!("Selection mirrored!");
+        match shift {
+            true => layer.transform(&selection.cells, |pos| vec2(pos.x, 2. * center.y - pos.y), false),
+            false => layer.transform(&selection.cells, |pos| vec2(2. * center.x - pos.x, pos.y), false),
+        }
+    } else if let (true, Some(delta)) = (keyboard.just_pressed(keys.move_selection), delta) {
+        info!("Selection moved!");
+        layer.transform(&selection.cells, |pos| pos + delta, false)
+    } else if let (true, Some(delta)) = (keyboard.just_pressed(keys.duplicate), delta) {
+        info!("Selection duplicated!");
+        layer.transform(&selection.cells, |pos| pos + delta, true)
+    } else if keyboard.just_pressed(keys.delete) {
+        layer.clear(&selection.cells);
+        info!("Selection deleted!");
+        vec![]
+    } else {
+        return;
+    };
+    selection.cells = cells;
+    simulation.single_mut().0 = layer.solver();
+    edited.send(Edited);
+}
+
+fn selection_gizmo_system(mut gizmos: Gizmos, constructor: Query<&Constructor>, selection: Res<Selection>) {
+    let color = Color::srgb(1., 1., 0.3);
+    match &selection.drag {
+        Some(SelectionDrag::Rect(start, end)) => gizmos.rect_2d((*start + *end) / 2., 0., *end - *start, color),
+        Some(SelectionDrag::Lasso(points)) => gizmos.linestrip_2d(points.iter().chain(points.first()).copied(), color),
+        None => (),
+    }
+    let constructor = constructor.single();
+    let Some(layer) = selection.layer.and_then(|(ind, _)| constructor.0.layers.get(ind)) else {
+        return;
+    };
+    for cell in selection.cells.iter() {
+        gizmos.circle_2d(layer.cell_position(*cell), PARTICLE_RADIUS, color);
+    }
+}
+
+fn layer_list_system(
+    mut commands: Commands,
+    constructor: Query<&Constructor>,
+    list: Query<Entity, With<LayerList>>,
+    style: Res<EditorStyle>,
+    mut shown: Local<Vec<(String, bool, bool)>>,
+) {
+    let constructor = constructor.single();
+    let rows: Vec<_> = constructor
+        .0
+        .layers
+        .iter()
+        .enumerate()
+        .map(|(i, layer)| (layer.name.clone(), layer.visible, i == constructor.1))
+        .collect();
+    if *shown == rows {
+        return;
+    }
+
+    let list = list.single();
+    commands.entity(list).despawn_descendants();
+    let button = |width: f32, border: Color| ButtonBundle {
+        style: Style {
+            width: Val::Px(width),
+            height: Val::Px(30.0),
+            border: UiRect::all(Val::Px(2.)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        border_color: border.into(),
+        background_color: NORMAL_BUTTON.into(),
+        border_radius: BorderRadius::all(Val::Px(5.)),
+        ..default()
+    };
+    commands.entity(list).with_children(|parent| {
+        for (i, (name, visible, current)) in rows.iter().enumerate() {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        column_gap: Val::Px(2.),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    let border = if *current { PRESSED_BUTTON } else { Color::WHITE };
+                    let label = if *visible { &style.shown } else { &style.hidden };
+                    let buttons = [
+                        (160., border, name.as_str(), LayerAction::Select(i)),
+                        (80., Color::WHITE, label.as_str(), LayerAction::Toggle(i)),
+                        (30., Color::WHITE, "^", LayerAction::Up(i)),
+                        (30., Color::WHITE, "v", LayerAction::Down(i)),
+                    ];
+                    for (width, border, text, action) in buttons {
+                        parent.spawn((button(width, border), action)).with_children(|parent| {
+                            parent.spawn(TextBundle {
+                                text: Text::from_section(text, style.text.clone()),
+                                ..default()
+                            });
+                        });
+                    }
+                });
+        }
+    });
+    *shown = rows;
+}
+
+fn layer_button_system(
+    buttons: Query<(&Interaction, &LayerAction), (Changed<Interaction>, With<Button>)>,
+    mut constructor: Query<&mut Constructor>,
+    mut simulation: Query<&mut RenderedSimulation>,
+    mut edited: EventWriter<Edited>,
+) {
+    let mut constructor = constructor.single_mut();
+    let mut simulation = simulation.single_mut();
+    for (interaction, action) in &buttons {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        // the selection follows the layer it was on
+        let (from, to) = match *action {
+            LayerAction::Select(ind) => {
+                constructor.1 = ind;
+                simulation.0 = constructor.0.layers[ind].solver();
+                info!("Switching to layer: {ind}");
+                continue;
             }
+            LayerAction::Toggle(ind) => {
+                let visible = constructor.0.toggle_layer(ind);
+                edited.send(Edited);
+                info!("Layer {ind} is {}!", if visible { "shown" } else { "hidden" });
+                continue;
+            }
+            LayerAction::Up(ind) => (ind, ind.saturating_sub(1)),
+            LayerAction::Down(ind) => (ind, ind + 1),
+        };
+        if from != to && constructor.0.move_layer(from, to) {
+            let current = constructor.1;
+            constructor.1 = match current {
+                _ if current == from => to,
+                _ if current == to => from,
+                _ => current,
+            };
+            edited.send(Edited);
+            info!("Layer {from} moved to {to}");
         }
     }
 }
 
+/// Clicking an input starts editing it, clicking anywhere else or ESC stops.
+fn focus_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    query: Query<(Entity, &Interaction), Changed<Interaction>>,
+    mut inputs: Query<(Entity, &mut TextInputInactive, &mut BorderColor)>,
+) {
+    if keyboard.just_pressed(KeyCode::Escape) {
+        for (_, mut inactive, mut border_color) in &mut inputs {
+            inactive.0 = true;
+            *border_color = Color::WHITE.into();
+        }
+    }
+    for (interaction_entity, interaction) in &query {
+        if *interaction == Interaction::Pressed {
+            for (entity, mut inactive, mut border_color) in &mut inputs {
+                inactive.0 = entity != interaction_entity;
+                *border_color = if inactive.0 { Color::WHITE } else { PRESSED_BUTTON }.into();
+            }
+        }
+    }
+}
+
+/// The editor shortcuts are off while an input is being edited.
+fn typing(inputs: Query<&TextInputInactive>) -> bool {
+    inputs.iter().any(|inactive| !inactive.0)
+}
+
 #[derive(Component)]
 struct Constructor(MapConstructor, usize);
 
 #[derive(Component)]
 struct ConstructorUpdate(Task<Result<MapConstructor>>);
 
+#[derive(Component)]
+struct MapSave(Task<Result<String>>);
+
+/// Last error of loading or saving, shown at the top of the screen until the next success.
+#[derive(Resource, Default)]
+struct Status(String);
+
+impl Status {
+    fn error(&mut self, error: impl std::fmt::Display) {
+        error!("{error}");
+        self.0 = error.to_string();
+    }
+}
+
+#[derive(Component)]
+struct StatusText;
+
 fn setup(mut commands: Commands, textures: Res<SimulationTextures>) {
     // create constructor entity
     let mut constructor = MapConstructor::new(
         "map".to_string(),
-        solver::Constraint::Box(vec2(-300., -50.), vec2(300., 150.)),
+        Constraint::Box(vec2(-300., -50.), vec2(300., 150.)),
     );
     constructor.textures = textures.textures.to_vec();
 
@@ -295,13 +1031,13 @@ fn setup(mut commands: Commands, textures: Res<SimulationTextures>) {
             projection: projection.into(),
             ..Default::default()
         })
-        .insert(SimulationCamera);
+        .insert(SimulationCamera)
+        .insert(ObserverCamera::default());
 
-    commands.spawn(RenderedSimulation(Solver::new(
-        constructor.constraint,
-        &[],
-        &[],
-    )));
+    commands.spawn((
+        RenderedSimulation(Solver::new(constructor.constraint.clone(), &[], &[])),
+        constructor.ambient,
+    ));
 
     // spawn constructor
     commands.spawn(Constructor(constructor, 0));
@@ -320,6 +1056,8 @@ fn button_system(
     state: Res<State<AppState>>,
     mut next_state: ResMut<NextState<AppState>>,
     mut constructor: Query<&mut Constructor>,
+    prompt: Query<Entity, With<AutosavePrompt>>,
+    asset_server: Res<AssetServer>,
 ) {
     let mut constructor = constructor.single_mut();
     for (interaction, button_action, mut background_color) in &mut interaction_query {
@@ -343,6 +1081,8 @@ fn button_system(
                     commands.insert_resource(SimulationTextures {
                         textures: constructor.0.textures.clone(),
                         background: constructor.0.background.clone(),
+                        parallax: constructor.0.parallax.clone(),
+                        atlas: None,
                     });
                     info!("Texture removed!");
                 }
@@ -352,6 +1092,8 @@ fn button_system(
                         commands.insert_resource(SimulationTextures {
                             textures: constructor.0.textures.clone(),
                             background: constructor.0.background.clone(),
+                            parallax: constructor.0.parallax.clone(),
+                            atlas: None,
                         });
                         *background_color = NORMAL_BUTTON.into();
                         next_state.set(AppState::Main);
@@ -360,6 +1102,38 @@ fn button_system(
                         next_state.set(AppState::PendingBackground(None));
                     }
                 }
+                ButtonAction::AddParallax => {
+                    // pressed again it removes the last layer instead
+                    if let AppState::PendingParallax(_) = state.get() {
+                        constructor.0.parallax.pop();
+                        commands.insert_resource(SimulationTextures {
+                            textures: constructor.0.textures.clone(),
+                            background: constructor.0.background.clone(),
+                            parallax: constructor.0.parallax.clone(),
+                            atlas: None,
+                        });
+                        *background_color = NORMAL_BUTTON.into();
+                        next_state.set(AppState::Main);
+                    } else if let AppState::Main = state.get() {
+                        *background_color = PRESSED_BUTTON.into();
+                        next_state.set(AppState::PendingParallax(None));
+                    }
+                }
+                ButtonAction::ToggleAtlas => {
+                    constructor.0.pack_atlas = !constructor.0.pack_atlas;
+                    info!("Textures are {} on saving!", if constructor.0.pack_atlas { "packed" } else { "kept apart" });
+                }
+                ButtonAction::RestoreAutosave(path) => {
+                    commands.spawn(ConstructorUpdate(load_layout(path.clone(), asset_server.clone())));
+                    prompt.iter().for_each(|entity| commands.entity(entity).despawn_recursive());
+                    info!("Restoring {path:?}");
+                }
+                ButtonAction::DiscardAutosave(path) => {
+                    if let Err(e) = fs::remove_file(path) {
+                        warn!("Failed to remove {path:?}: {e}");
+                    }
+                    prompt.iter().for_each(|entity| commands.entity(entity).despawn_recursive());
+                }
             }
         }
     }
@@ -372,6 +1146,7 @@ fn spawn_sprites_system(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     constructor: Query<&Constructor>,
+    team_colors: Res<TeamColors>,
     mut query: Query<(Entity, &mut Transform, &mut SpawnIndex, &mut Sprite)>,
 ) {
     let spawn_image = asset_server.load("textures/spawn.png");
@@ -387,7 +1162,7 @@ fn spawn_sprites_system(
         *spawn_ind = SpawnIndex(i);
         let spawn = &constructor.0.spawns[i];
         *transform = Transform::from_translation(spawn.pos.extend(-0.1));
-        sprite.color = Color::hsl(360. * spawn.team as f32 / MAX_TEAMS as f32, 0.95, 0.7);
+        sprite.color = team_colors.color(spawn.team);
         last_sprite = Some(i);
     }
     let start = last_sprite.map_or(0, |ind| ind + 1);
@@ -405,6 +1180,108 @@ fn spawn_sprites_system(
     }
 }
 
+/// Box -> circle -> hexagon -> box, each fitted into the bounds of the previous one.
+fn next_boundary(constraint: &Constraint) -> Constraint {
+    let (bl, tr) = constraint.bounds();
+    let (center, half) = ((bl + tr) / 2., (tr - bl) / 2.);
+    match constraint {
+        Constraint::Box(..) => Constraint::Circle {
+            center,
+            radius: half.min_element(),
+        },
+        Constraint::Circle { .. } => Constraint::Polygon(Polygon::new(
+            (0..6)
+                .map(|i| {
+                    let angle = i as f32 * PI / 3.;
+                    center + half * vec2(angle.cos(), angle.sin())
+                })
+                .collect(),
+        )),
+        Constraint::Polygon(_) => Constraint::Box(bl, tr),
+    }
+}
+
+fn mode_gizmo_system(
+    mut gizmos: Gizmos,
+    constructor: Query<&Constructor>,
+    team_colors: Res<TeamColors>,
+    region_start: Res<RegionStart>,
+) {
+    let constructor = constructor.single();
+    let boundary_color = Color::srgb(0.5, 0.5, 0.5);
+    match &constructor.0.constraint {
+        Constraint::Box(bl, tr) => gizmos.rect_2d((*bl + *tr) / 2., 0., *tr - *bl, boundary_color),
+        Constraint::Circle { center, radius } => {
+            gizmos.circle_2d(*center, *radius, boundary_color);
+        }
+        Constraint::Polygon(polygon) => gizmos.linestrip_2d(
+            polygon.vertices().iter().chain(polygon.vertices().first()).copied(),
+            boundary_color,
+        ),
+    }
+    if let Some(hill) = &constructor.0.hill {
+        gizmos.circle_2d(hill.pos, hill.radius, Color::srgb(1., 0.85, 0.));
+    }
+    let field_color = Color::srgb(0.3, 0.8, 1.);
+    for field in constructor.0.fields.iter() {
+        let center = field.region.center();
+        let size = match field.region {
+            Region::Rect { min, max } => {
+                gizmos.rect_2d(center, 0., max - min, field_color);
+                (max - min).min_element() / 2.
+            }
+            Region::Circle { center, radius } => {
+                gizmos.circle_2d(center, radius, field_color);
+                radius
+            }
+        };
+        // arrows along the push, half as long as the region is wide
+        match field.push {
+            Push::Direction(force) => {
+                gizmos.arrow_2d(center, center + force.normalize_or_zero() * size, field_color);
+            }
+            Push::Radial(strength) => {
+                for dir in [Vec2::X, Vec2::Y, Vec2::NEG_X, Vec2::NEG_Y] {
+                    let (from, to) = (center + dir * size / 4., center + dir * size * 3. / 4.);
+                    match strength >= 0. {
+                        true => gizmos.arrow_2d(from, to, field_color),
+                        false => gizmos.arrow_2d(to, from, field_color),
+                    };
+                }
+            }
+        }
+    }
+    let hazard_color = Color::srgb(1., 0.3, 0.1);
+    for hazard in constructor.0.hazards.iter() {
+        match hazard.region {
+            Region::Rect { min, max } => gizmos.rect_2d(hazard.region.center(), 0., max - min, hazard_color),
+            Region::Circle { center, radius } => {
+                gizmos.circle_2d(center, radius, hazard_color);
+            }
+        }
+    }
+    if let Some(start) = region_start.0 {
+        gizmos.circle_2d(start, 1., field_color);
+    }
+    for flag in constructor.0.flags.iter() {
+        let color = team_colors.color(flag.team);
+        gizmos.rect_2d(flag.pos, 0., vec2(3., 3.), color);
+    }
+    for pickup in constructor.0.pickups.iter() {
+        let color = pickup.item.color();
+        gizmos.circle_2d(pickup.pos, 1.5, Color::srgb(color.x, color.y, color.z));
+    }
+}
+
+/// Reads a *.smoge* layout in the background, the textures are looked up next to it.
+fn load_layout(path: PathBuf, asset_server: AssetServer) -> Task<Result<MapConstructor>> {
+    IoTaskPool::get().spawn(async move {
+        let bytes = fs::read(&path)?;
+        let constructor = SerdeMapConstructor::deserialize(&bytes)?;
+        anyhow::Ok(constructor.to_constructor(path, &asset_server))
+    })
+}
+
 fn drag_and_drop_system(
     mut commands: Commands,
     mut events: EventReader<FileDragAndDrop>,
@@ -423,14 +1300,7 @@ fn drag_and_drop_system(
 
         if let Some(ext) = path_buf.extension() {
             if ext == "smoge" {
-                let base_path = path_buf.clone();
-                let asset_server = asset_server.clone();
-                let task = IoTaskPool::get().spawn(async move {
-                    let bytes = fs::read(&base_path)?;
-                    let constructor = SerdeMapConstructor::deserialize(&bytes)?;
-                    anyhow::Ok(constructor.to_constructor(base_path, &asset_server))
-                });
-                commands.spawn(ConstructorUpdate(task));
+                commands.spawn(ConstructorUpdate(load_layout(path_buf.clone(), asset_server.clone())));
                 return;
             }
         }
@@ -451,6 +1321,11 @@ fn drag_and_drop_system(
                 info!("Loading background: {:?}", img.path());
                 next_state.set(AppState::PendingBackground(Some(img)));
             }
+            AppState::PendingParallax(None) => {
+                let img: Handle<Image> = asset_server.load(AssetPath::from_path(path_buf));
+                info!("Loading parallax layer: {:?}", img.path());
+                next_state.set(AppState::PendingParallax(Some(img)));
+            }
             _ => (),
         }
     }
@@ -463,6 +1338,7 @@ fn handle_constructor_update(
     mut update_task: Query<(Entity, &mut ConstructorUpdate)>,
     //column: Query<Entity, With<TextureColumn>>,
     buttons: Query<(Entity, &ButtonAction), With<Button>>,
+    mut status: ResMut<Status>,
 ) {
     let mut constructor = constructor.single_mut();
     //let column = column.single();
@@ -490,8 +1366,12 @@ fn handle_constructor_update(
                             .to_vec(),
                     ));
                     info!("Map loaded!");
+                    status.0.clear();
+                }
+                Err(e) => {
+                    commands.entity(entity).despawn();
+                    status.error(e);
                 }
-                Err(e) => error!("{e}"),
             }
         }
     }
@@ -519,6 +1399,7 @@ fn check_assets_system(
     mut next_state: ResMut<NextState<AppState>>,
     mut constructor: Query<&mut Constructor>,
     texture_column: Query<Entity, With<TextureColumn>>,
+    mut edited: EventWriter<Edited>,
 ) {
     let mut constructor = constructor.get_single_mut().unwrap();
     let column = texture_column.single();
@@ -528,6 +1409,7 @@ fn check_assets_system(
                 return;
             };
             add_layer_from_image(&mut constructor, img);
+            edited.send(Edited);
             next_state.set(AppState::Main);
         }
         AppState::PendingTexture(Some(handle)) => {
@@ -539,6 +1421,8 @@ fn check_assets_system(
             commands.insert_resource(SimulationTextures {
                 textures: constructor.0.textures.clone(),
                 background: constructor.0.background.clone(),
+                parallax: constructor.0.parallax.clone(),
+                atlas: None,
             });
             info!("Texture added!");
 
@@ -553,6 +1437,8 @@ fn check_assets_system(
                 commands.insert_resource(SimulationTextures {
                     textures: constructor.0.textures.clone(),
                     background: constructor.0.background.clone(),
+                    parallax: constructor.0.parallax.clone(),
+                    atlas: None,
                 });
                 for handle in textures {
                     add_texture_button(&mut commands, handle, column);
@@ -568,14 +1454,57 @@ fn check_assets_system(
             commands.insert_resource(SimulationTextures {
                 textures: constructor.0.textures.clone(),
                 background: constructor.0.background.clone(),
+                parallax: constructor.0.parallax.clone(),
+                atlas: None,
             });
             next_state.set(AppState::Main);
             info!("Background added!");
         }
+        AppState::PendingParallax(Some(handle)) => {
+            let Some(_) = image_assets.get(handle) else {
+                return;
+            };
+            constructor.0.parallax.push(ParallaxLayer {
+                image: handle.clone(),
+                factor: PARALLAX_DEFAULT,
+            });
+            commands.insert_resource(SimulationTextures {
+                textures: constructor.0.textures.clone(),
+                background: constructor.0.background.clone(),
+                parallax: constructor.0.parallax.clone(),
+                atlas: None,
+            });
+            next_state.set(AppState::Main);
+            info!("Parallax layer added!");
+        }
         _ => (),
     }
 }
 
+/// Images that failed to load would keep the editor waiting for them forever.
+fn failed_assets_system(
+    asset_server: Res<AssetServer>,
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut status: ResMut<Status>,
+) {
+    let pending = match state.get() {
+        AppState::PendingImage(Some(handle))
+        | AppState::PendingTexture(Some(handle))
+        | AppState::PendingBackground(Some(handle))
+        | AppState::PendingParallax(Some(handle)) => vec![handle],
+        AppState::PendingTextures(handles) => handles.iter().collect(),
+        _ => return,
+    };
+    for handle in pending {
+        if let Some(LoadState::Failed(e)) = asset_server.get_load_state(handle) {
+            status.error(e);
+            next_state.set(AppState::Main);
+            return;
+        }
+    }
+}
+
 fn add_texture_button(commands: &mut Commands, handle: &Handle<Image>, column: Entity) {
     let style = Style {
         width: Val::Px(160.0),
@@ -608,123 +1537,83 @@ fn add_texture_button(commands: &mut Commands, handle: &Handle<Image>, column: E
 }
 
 fn control_system(
-    mut evr_scroll: EventReader<MouseWheel>,
     mouse: Res<ButtonInput<MouseButton>>,
     keyboard: Res<ButtonInput<KeyCode>>,
+    settings: Res<Settings>,
     windows: Query<&Window, With<PrimaryWindow>>,
     mut simulation: Query<&mut RenderedSimulation>,
     mut constructor: Query<&mut Constructor>,
-    mut camera: Query<(&Camera, &mut OrthographicProjection, &mut Transform)>,
-    image_assets: Res<Assets<Image>>,
+    camera: Query<(&Camera, &Transform)>,
+    mut region_start: ResMut<RegionStart>,
+    mut edited: EventWriter<Edited>,
 ) {
-    let (camera, mut projection, mut camera_transform) = camera.single_mut();
+    let (camera, camera_transform) = camera.single();
     let window = windows.single();
     let mut simulation = simulation.single_mut();
     let mut constructor = constructor.single_mut();
-
-    // camera controls
-    for ev in evr_scroll.read() {
-        projection.scale *= f32::powf(1.25, ev.y);
-    }
-
-    let mut factor: f32 = 1.;
-    if keyboard.pressed(KeyCode::ShiftLeft) {
-        factor = 5.;
-    }
-    if keyboard.pressed(KeyCode::KeyA) {
-        camera_transform.translation.x -= 0.1 * factor;
-    }
-    if keyboard.pressed(KeyCode::KeyD) {
-        camera_transform.translation.x += 0.1 * factor;
-    }
-    if keyboard.pressed(KeyCode::KeyS) {
-        camera_transform.translation.y -= 0.1 * factor;
-    }
-    if keyboard.pressed(KeyCode::KeyW) {
-        camera_transform.translation.y += 0.1 * factor;
-    }
+    let keys = &settings.editor;
 
     // layer controls
     let layers_num = constructor.0.layers.len(); // TODO: make this code readable
     if layers_num > 0 {
-        if keyboard.just_pressed(KeyCode::ArrowLeft) {
+        if keyboard.just_pressed(keys.previous_layer) {
             let ind = (constructor.1 + (layers_num - 1)) % layers_num;
             constructor.1 = ind;
             simulation.0 = constructor.0.layers[ind].solver();
             info!("Switching to layer: {ind}");
         }
-        if keyboard.just_pressed(KeyCode::ArrowRight) {
+        if keyboard.just_pressed(keys.next_layer) {
             let ind = (constructor.1 + 1) % layers_num;
             constructor.1 = ind;
             simulation.0 = constructor.0.layers[ind].solver();
             info!("Switching to layer: {ind}");
         }
 
-        // FIXME: repeating code
         let layer_ind = constructor.1;
         let layer = &mut constructor.0.layers[layer_ind];
         if keyboard.pressed(KeyCode::AltLeft) {
-            if keyboard.just_pressed(KeyCode::KeyM) {
-                print!("mass << ");
-                let read: Result<f32, _> = try_read!();
-                let Ok(read) = read else {
-                    error!("Incorrect input!");
-                    return;
+            if keyboard.just_pressed(keys.fixed) {
+                let kind = match layer.base_particle.is_static() {
+                    true => Kind::None,
+                    false => Kind::Static,
                 };
-                layer.base_particle.mass = read;
-                info!("Mass updated!");
-            }
-            if keyboard.just_pressed(KeyCode::KeyT) {
-                print!("texture << ");
-                let read: Result<u32, _> = try_read!();
-                let Ok(read) = read else {
-                    error!("Incorrect input!");
-                    return;
-                };
-                layer.base_particle.texture = read;
-                info!("Texture updated!");
-            }
-            if keyboard.just_pressed(KeyCode::KeyS) {
-                print!("strength << ");
-                let read: Result<f32, _> = try_read!();
-                let Ok(read) = read else {
-                    error!("Incorrect input!");
-                    return;
+                layer.base_particle.set_kind(kind);
+                info!("Layer is {}!", if kind.is_static() { "fixed" } else { "movable" });
+            }
+            if keyboard.just_pressed(keys.water) {
+                // water preset, fluids don't hold together so the links go too
+                let preset = match layer.base_particle.is_fluid() {
+                    true => GROUND,
+                    false => WATER,
                 };
-                layer.strength = read;
-                info!("Strength updated!");
-            }
-            if keyboard.just_pressed(KeyCode::KeyD) {
-                print!("durability << ");
-                let read: Result<f32, _> = try_read!();
-                let Ok(read) = read else {
-                    error!("Incorrect input!");
-                    return;
+                layer.base_particle.set_kind(preset.kind);
+                layer.base_particle.mass = preset.mass;
+                if preset.is_fluid() {
+                    layer.link = None;
+                }
+                info!("Layer is {}!", if preset.is_fluid() { "water" } else { "solid" });
+            }
+            if keyboard.just_pressed(keys.wood) {
+                // wood preset, it burns down and its links with it
+                let preset = match layer.base_particle.kind {
+                    Kind::Flammable(_) => GROUND,
+                    _ => WOOD,
                 };
-                let elasticity = layer.link.map_or(ELASTICITY_DEFAULT, |l| l.elasticity());
-                layer.link = Some(Link::Rigid {
-                    length: 1.,
-                    durability: read,
-                    elasticity,
-                });
-                info!("Durability updated!");
-            }
-            if keyboard.just_pressed(KeyCode::KeyE) {
-                print!("elasticity << ");
-                let read: Result<f32, _> = try_read!();
-                let Ok(read) = read else {
-                    error!("Incorrect input!");
-                    return;
+                layer.base_particle.set_kind(preset.kind);
+                layer.base_particle.mass = preset.mass;
+                info!("Layer is {}!", if preset.kind.none() { "solid" } else { "wooden" });
+            }
+            if keyboard.just_pressed(keys.triangulate) {
+                layer.mode = match layer.mode {
+                    ConnectionMode::Random => ConnectionMode::Triangulated,
+                    ConnectionMode::Triangulated => ConnectionMode::Random,
                 };
-                let durability = layer.link.map_or(DURABILITY_DEFAULT, |l| l.durability());
-                layer.link = Some(Link::Rigid {
-                    length: 1.,
-                    durability,
-                    elasticity: read,
+                info!("Layer links {}!", match layer.mode {
+                    ConnectionMode::Random => "random particles",
+                    ConnectionMode::Triangulated => "neighbours",
                 });
-                info!("Elasticity updated!");
             }
-            if keyboard.just_pressed(KeyCode::Backspace) {
+            if keyboard.just_pressed(keys.unlink) {
                 layer.link = None;
                 info!("All connections removed!");
             }
@@ -734,19 +1623,20 @@ fn control_system(
             layer.bake();
         }
 
-        if keyboard.just_pressed(KeyCode::ArrowDown) {
+        if keyboard.just_pressed(keys.show_layer) {
             simulation.0 = constructor.0.layers[layer_ind].solver();
             info!("Showing layer: {layer_ind}");
         }
-        if keyboard.just_released(KeyCode::Delete) {
-            constructor.0.layers.remove(layer_ind);
+        if keyboard.just_released(keys.remove_layer) {
+            constructor.0.remove_layer(layer_ind);
             constructor.1 = usize::max(1, layer_ind) - 1;
+            edited.send(Edited);
             info!("Layer {layer_ind} removed");
         }
     }
 
     // simulation controls
-    if keyboard.just_pressed(KeyCode::Enter) {
+    if keyboard.just_pressed(keys.bake) {
         constructor.0.bake_layers();
         simulation.0 = constructor.0.solver();
         info!(
@@ -755,11 +1645,23 @@ fn control_system(
             constructor.0.connections.as_ref().map_or(0, |p| p.len())
         );
     }
-    if keyboard.just_pressed(KeyCode::Tab) {
+    if keyboard.just_pressed(keys.preview) {
         simulation.0 = constructor.0.solver();
     }
 
-    if keyboard.pressed(KeyCode::Space) {
+    // boundary controls, layers are cut to the boundary so it can only change on an empty map
+    if keyboard.pressed(KeyCode::ControlLeft) && keyboard.just_pressed(keys.boundary) {
+        if layers_num == 0 {
+            constructor.0.constraint = next_boundary(&constructor.0.constraint);
+            simulation.0 = Solver::new(constructor.0.constraint.clone(), &[], &[]);
+            info!("Boundary changed!");
+        } else {
+            warn!("Remove all layers before changing the boundary!");
+        }
+    }
+
+
+    if keyboard.pressed(keys.simulate) {
         let sub_ticks = 8;
         let dt = 1. / 60. / sub_ticks as f32;
         for _ in 0..sub_ticks {
@@ -775,18 +1677,23 @@ fn control_system(
         })
         .map(|ray| ray.origin.truncate())
     {
-        let digits = vec![
-            KeyCode::Digit1,
-            KeyCode::Digit2,
-            KeyCode::Digit3,
-            KeyCode::Digit4,
-            KeyCode::Digit5,
-            KeyCode::Digit6,
-            KeyCode::Digit7,
-            KeyCode::Digit8,
-        ];
-        for (team, key) in digits.into_iter().enumerate() {
-            if keyboard.just_pressed(key) {
+        for (team, &key) in keys.teams.iter().enumerate() {
+            if keyboard.just_pressed(key) && keyboard.pressed(KeyCode::ControlLeft) {
+                if let Some(item) = Item::ALL.get(team) {
+                    constructor.0.pickups.push(Pickup {
+                        pos: cursor_world_position,
+                        item: *item,
+                    });
+                    info!("Pickup added!");
+                }
+            } else if keyboard.just_pressed(key) && keyboard.pressed(KeyCode::ShiftLeft) {
+                constructor.0.flags.retain(|flag| flag.team != team);
+                constructor.0.flags.push(Flag {
+                    pos: cursor_world_position,
+                    team,
+                });
+                info!("Flag placed!");
+            } else if keyboard.just_pressed(key) {
                 constructor.0.spawns.push(Spawn {
                     pos: cursor_world_position,
                     team,
@@ -795,7 +1702,112 @@ fn control_system(
             }
         }
 
+        if keyboard.just_pressed(keys.hill) {
+            constructor.0.hill = Some(Hill {
+                pos: cursor_world_position,
+                radius: Hill::DEFAULT_RADIUS,
+            });
+            info!("Hill placed!");
+        }
+
+        // force fields take two presses, the corners of a directional one or the center and the edge of a radial one
+        if keyboard.just_pressed(keys.field) {
+            match region_start.0.take() {
+                None => region_start.0 = Some(cursor_world_position),
+                Some(start) => {
+                    let push = constructor
+                        .0
+                        .fields
+                        .last()
+                        .map_or(Push::Direction(vec2(0., FIELD_FORCE)), |field| field.push);
+                    let region = match push {
+                        Push::Direction(_) => Region::Rect {
+                            min: start.min(cursor_world_position),
+                            max: start.max(cursor_world_position),
+                        },
+                        Push::Radial(_) => Region::Circle {
+                            center: start,
+                            radius: start.distance(cursor_world_position),
+                        },
+                    };
+                    constructor.0.fields.push(ForceField { region, push });
+                    simulation.0.fields = constructor.0.fields.clone();
+                    edited.send(Edited);
+                    info!("Force field placed!");
+                }
+            }
+        }
+
+        // hazards are placed the same way, between two corners
+        if keyboard.just_pressed(keys.hazard) {
+            match region_start.0.take() {
+                None => region_start.0 = Some(cursor_world_position),
+                Some(start) => {
+                    constructor.0.hazards.push(Hazard {
+                        region: Region::Rect {
+                            min: start.min(cursor_world_position),
+                            max: start.max(cursor_world_position),
+                        },
+                        damage: Hazard::DEFAULT_DAMAGE,
+                    });
+                    simulation.0.hazards = constructor.0.hazards.clone();
+                    edited.send(Edited);
+                    info!("Hazard placed!");
+                }
+            }
+        }
+
         if mouse.just_pressed(MouseButton::Right) {
+            if constructor
+                .0
+                .hill
+                .as_ref()
+                .is_some_and(|hill| hill.contains(cursor_world_position))
+            {
+                constructor.0.hill = None;
+                info!("Hill removed!");
+            }
+
+            let old_len = constructor.0.fields.len();
+            constructor
+                .0
+                .fields
+                .retain(|field| !field.region.contains(cursor_world_position));
+            if constructor.0.fields.len() != old_len {
+                simulation.0.fields = constructor.0.fields.clone();
+                edited.send(Edited);
+                info!("Force field removed!");
+            }
+
+            let old_len = constructor.0.hazards.len();
+            constructor
+                .0
+                .hazards
+                .retain(|hazard| !hazard.region.contains(cursor_world_position));
+            if constructor.0.hazards.len() != old_len {
+                simulation.0.hazards = constructor.0.hazards.clone();
+                edited.send(Edited);
+                info!("Hazard removed!");
+            }
+
+            let old_len = constructor.0.pickups.len();
+            constructor
+                .0
+                .pickups
+                .retain(|pickup| pickup.pos.distance(cursor_world_position) > 5.);
+            if constructor.0.pickups.len() != old_len {
+                info!("Pickup removed!");
+            }
+
+            let old_len = constructor.0.flags.len();
+            constructor
+                .0
+                .flags
+                .retain(|flag| flag.pos.distance(cursor_world_position) > 5.);
+            if constructor.0.flags.len() != old_len {
+                info!("Flag removed!");
+            }
+
             let old_len = constructor.0.spawns.len();
             constructor
                 .0
@@ -807,88 +1819,362 @@ fn control_system(
         }
     }
 
-    if keyboard.pressed(KeyCode::ControlLeft) && keyboard.just_pressed(KeyCode::KeyS) {
-        print!("name (without spaces) << ");
-        let name: String = read!();
-        constructor.0.name = name;
-        let _ = save_map(&mut constructor.0, &image_assets);
-    }
 }
 
+fn save_image(image: Image, path: &Path) -> Result<()> {
+    let image: RgbaImage = image.try_into_dynamic()?.to_rgba8();
+    image.save(path)?;
+    Ok(())
+}
+
+/// The layout keeps using the textures one by one, so they're saved even when the map has an atlas.
 fn save_textures(map: &Map, textures: Vec<Image>) -> Result<()> {
-    let texture_paths = map.texture_paths(RELATIVE_MAPS_PATH);
-    for (i, texture) in textures.into_iter().enumerate() {
-        let image: RgbaImage = texture.try_into_dynamic().unwrap().to_rgba8();
-        image.save(&texture_paths[i])?;
+    let texture_paths = Map::get_texture_paths(&map.name, textures.len(), config::maps_dir());
+    for (texture, path) in textures.into_iter().zip(texture_paths.iter()) {
+        save_image(texture, path)?;
     }
     Ok(())
 }
 
 fn save_background(map: &Map, background: Option<Image>) -> Result<()> {
-    let Some(background_path) = map.background_path(RELATIVE_MAPS_PATH) else {
-        return Ok(());
-    };
-    background.map_or(anyhow::Ok(()), |background| {
-        let image: RgbaImage = background.try_into_dynamic().unwrap().to_rgba8();
-        image.save(&background_path)?;
-        Ok(())
-    })
+    match (map.background_path(config::maps_dir()), background) {
+        (Some(path), Some(background)) => save_image(background, &path),
+        _ => Ok(()),
+    }
 }
 
-fn save_map(constructor: &mut MapConstructor, image_assets: &Assets<Image>) -> Result<()> {
-    let serde_constructor = SerdeMapConstructor::from_constructor(&constructor);
-    let map = constructor.map();
-    let textures: Vec<Image> = constructor
-        .textures
+fn save_parallax(map: &Map, parallax: Vec<Image>) -> Result<()> {
+    let paths = map.parallax_paths(config::maps_dir());
+    for (image, path) in parallax.into_iter().zip(paths.iter()) {
+        save_image(image, path)?;
+    }
+    Ok(())
+}
+
+/// Packs the textures into one image, the particles keep their texture indices as the rects are in the same order.
+fn pack_atlas(textures: &[Image]) -> Result<(RgbaImage, Vec<Rect>)> {
+    let images = textures
         .iter()
-        .map(|handle| image_assets.get(handle).unwrap().clone()) // TODO: error handling
+        .map(|texture| Ok(texture.clone().try_into_dynamic()?.to_rgba8()))
+        .collect::<Result<Vec<_>>>()?;
+    let raw: Vec<(UVec2, &[u8])> = images
+        .iter()
+        .map(|image| (UVec2::new(image.width(), image.height()), image.as_raw().as_slice()))
         .collect();
-    let background: Option<Image> = constructor
+    let (size, data, rects) = atlas::pack(&raw);
+    let atlas = RgbaImage::from_raw(size.x, size.y, data).ok_or(anyhow::anyhow!("Atlas of {size} doesn't fit its pixels"))?;
+    Ok((atlas, rects))
+}
+
+fn get_image(image_assets: &Assets<Image>, handle: &Handle<Image>) -> Result<Image> {
+    image_assets
+        .get(handle)
+        .cloned()
+        .ok_or(anyhow::anyhow!("Image {:?} isn't loaded", handle.path()))
+}
+
+/// Writes the map in the background, the task returns its name once everything is saved.
+fn save_map(constructor: &mut MapConstructor, image_assets: &Assets<Image>) -> Result<Task<Result<String>>> {
+    let serde_constructor = SerdeMapConstructor::from_constructor(constructor);
+    let mut map = constructor.map();
+    let textures = constructor
+        .textures
+        .iter()
+        .map(|handle| get_image(image_assets, handle))
+        .collect::<Result<Vec<_>>>()?;
+    // the game loads the atlas in place of the textures
+    let atlas = match constructor.pack_atlas {
+        true => {
+            let (atlas, rects) = pack_atlas(&textures)?;
+            map.atlas = Some(rects);
+            map.textures_num = 0;
+            Some(atlas)
+        }
+        false => None,
+    };
+    let background = constructor
         .background
         .as_ref()
-        .map(|handle| image_assets.get(handle).unwrap().clone()); // TODO: error handling
+        .map(|handle| get_image(image_assets, handle))
+        .transpose()?;
+    let parallax = constructor
+        .parallax
+        .iter()
+        .map(|layer| get_image(image_assets, &layer.image))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(IoTaskPool::get().spawn(async move {
+        let mut base_path = config::maps_dir();
+        base_path.push(&map.name);
+        fs::create_dir_all(&base_path)?;
+
+        save_textures(&map, textures)?;
+        info!("Textures saved!");
+
+        save_background(&map, background)?;
+        info!("Background saved!");
+
+        save_parallax(&map, parallax)?;
+        info!("Parallax layers saved!");
+
+        if let (Some(atlas), Some(path)) = (atlas, map.atlas_path(config::maps_dir())) {
+            atlas.save(path)?;
+            info!("Atlas saved!");
+        }
+
+        map.render_thumbnail(THUMBNAIL_WIDTH).save(base_path.join(THUMBNAIL_FILE))?;
+        info!("Thumbnail saved!");
+
+        base_path.push(MAP_FILE);
+        File::create(&base_path).and_then(|mut file| file.write_all(&map.serialize()))?;
+        info!("Map \"{}\" saved!", map.name);
+
+        base_path.pop();
+        base_path.push(LAYOUT_FILE);
+        File::create(&base_path).and_then(|mut file| file.write_all(&serde_constructor.serialize()))?;
+        info!("Map layout \"{}\" saved!", map.name);
+        anyhow::Ok(map.name)
+    }))
+}
+
+fn save_system(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    settings: Res<Settings>,
+    mut constructor: Query<&mut Constructor>,
+    image_assets: Res<Assets<Image>>,
+    mut status: ResMut<Status>,
+) {
+    if !(keyboard.pressed(KeyCode::ControlLeft) && keyboard.just_pressed(settings.editor.save)) {
+        return;
+    }
+    let mut constructor = constructor.single_mut();
+    match save_map(&mut constructor.0, &image_assets) {
+        Ok(task) => {
+            commands.spawn(MapSave(task));
+        }
+        Err(e) => status.error(e),
+    }
+}
+
+/// Writes the layout as a vehicle the game can load, the parts are found by the names of the layers.
+fn export_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    settings: Res<Settings>,
+    mut constructor: Query<&mut Constructor>,
+    mut status: ResMut<Status>,
+) {
+    if !(keyboard.pressed(KeyCode::ControlLeft) && keyboard.just_pressed(settings.editor.export)) {
+        return;
+    }
+    let mut constructor = constructor.single_mut();
+    let mut export = || -> Result<PathBuf> {
+        let vehicle = Vehicle::from_constructor(&mut constructor.0)?;
+        let dir = config::vehicles_dir();
+        fs::create_dir_all(&dir)?;
+        let path = Vehicle::get_path(&vehicle.name, &dir);
+        fs::write(&path, vehicle.serialize())?;
+        Ok(path)
+    };
+    match export() {
+        Ok(path) => {
+            info!("Vehicle exported to {path:?}");
+            status.0.clear();
+        }
+        Err(e) => status.error(e),
+    }
+}
+
+const LAYOUT_FILE: &str = "map.smoge";
+const THUMBNAIL_WIDTH: u32 = 320;
+const AUTOSAVE_FILE: &str = "autosave.smoge";
+const AUTOSAVE_MINUTES: u64 = 2;
+const AUTOSAVE_COOLDOWN: Duration = Duration::from_secs(10); // edits in a row are saved together
+
+/// Layer, selection and inspector changes, they are autosaved right away.
+#[derive(Event)]
+struct Edited;
+
+/// Writes the layout to the autosave file of the map every few minutes and shortly after edits,
+/// `SMOG_AUTOSAVE` sets the minutes and 0 turns it off.
+#[derive(Resource)]
+struct Autosave {
+    interval: Option<Duration>,
+    last: Instant,
+    pending: bool,
+}
+
+impl Default for Autosave {
+    fn default() -> Self {
+        let minutes = std::env::var("SMOG_AUTOSAVE")
+            .ok()
+            .and_then(|minutes| minutes.parse().ok())
+            .unwrap_or(AUTOSAVE_MINUTES);
+        Self {
+            interval: (minutes > 0).then(|| Duration::from_secs(minutes * 60)),
+            last: Instant::now(),
+            pending: false,
+        }
+    }
+}
+
+#[derive(Component)]
+struct AutosavePrompt;
+
+fn autosave_system(
+    mut events: EventReader<Edited>,
+    mut autosave: ResMut<Autosave>,
+    constructor: Query<&Constructor>,
+    prompt: Query<(), With<AutosavePrompt>>,
+) {
+    if events.read().count() > 0 {
+        autosave.pending = true;
+    }
+    let Some(interval) = autosave.interval else {
+        return;
+    };
+    let elapsed = autosave.last.elapsed();
+    // the autosave waiting to be restored mustn't be overwritten
+    if !prompt.is_empty() || !(elapsed >= interval || autosave.pending && elapsed >= AUTOSAVE_COOLDOWN) {
+        return;
+    }
+    autosave.last = Instant::now();
+    autosave.pending = false;
 
+    let constructor = constructor.single();
+    if constructor.0.layers.is_empty() {
+        return;
+    }
+    let layout = SerdeMapConstructor::from_constructor(&constructor.0);
     IoTaskPool::get()
         .spawn(async move {
-            let mut base_path = PathBuf::from(RELATIVE_MAPS_PATH);
-            base_path.push(&map.name);
-            fs::create_dir_all(&base_path)?;
-
-            save_textures(&map, textures).map_err(|e| {
-                error! {"{e}"};
-                e
-            })?;
-            info!("Textures saved!");
-
-            save_background(&map, background).map_err(|e| {
-                error! {"{e}"};
-                e
-            })?;
-            info!("Background saved!");
-
-            base_path.push("map.smog");
-            File::create(&base_path)
-                .and_then(|mut file| file.write(&map.serialize()))
-                .map_err(|e| {
-                    error! {"{e}"};
-                    e
-                })?;
-            info!("Map \"{}\" saved!", map.name);
-
-            base_path.pop();
-            base_path.push("map.smoge");
-            File::create(&base_path)
-                .and_then(|mut file| file.write(&serde_constructor.serialize()))
-                .map_err(|e| {
-                    error! {"{e}"};
-                    e
-                })?;
-
-            info!("Map layout \"{}\" saved!", map.name);
-            anyhow::Ok(())
+            let mut path = config::maps_dir();
+            path.push(&layout.name);
+            path.push(AUTOSAVE_FILE);
+            let result = fs::create_dir_all(path.parent().unwrap())
+                .and_then(|_| File::create(&path))
+                .and_then(|mut file| file.write_all(&layout.serialize()));
+            match result {
+                Ok(()) => debug!("Autosaved to {path:?}"),
+                Err(e) => warn!("Failed to autosave to {path:?}: {e}"),
+            }
         })
         .detach();
-    anyhow::Ok(())
+}
+
+/// Most recent autosave that is newer than the map's own layout, it's left from a crash or an unsaved session.
+fn find_autosave() -> Option<PathBuf> {
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    fs::read_dir(config::maps_dir())
+        .ok()?
+        .flatten()
+        .filter_map(|entry| {
+            let dir = entry.path();
+            let autosave = modified(&dir.join(AUTOSAVE_FILE))?;
+            let saved = modified(&dir.join(LAYOUT_FILE)).unwrap_or(SystemTime::UNIX_EPOCH);
+            (autosave > saved).then(|| (autosave, dir.join(AUTOSAVE_FILE)))
+        })
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, path)| path)
+}
+
+fn autosave_prompt(mut commands: Commands, style: Res<EditorStyle>) {
+    let Some(path) = find_autosave() else {
+        return;
+    };
+    let name = path
+        .parent()
+        .and_then(|dir| dir.file_name())
+        .map_or(String::new(), |name| name.to_string_lossy().to_string());
+    let button = ButtonBundle {
+        style: Style {
+            width: Val::Px(160.0),
+            height: Val::Px(30.0),
+            border: UiRect::all(Val::Px(2.)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        border_color: BorderColor(Color::WHITE),
+        background_color: BackgroundColor(Color::BLACK),
+        border_radius: BorderRadius::all(Val::Px(10.)),
+        ..default()
+    };
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Percent(40.),
+                    left: Val::Percent(35.),
+                    width: Val::Percent(30.),
+                    padding: UiRect::all(Val::Px(10.)),
+                    border: UiRect::all(Val::Px(2.)),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(10.),
+                    ..default()
+                },
+                border_color: Color::WHITE.into(),
+                background_color: Color::BLACK.into(),
+                border_radius: BorderRadius::all(Val::Px(10.)),
+                ..default()
+            },
+            AutosavePrompt,
+        ))
+        .with_children(|parent| {
+            let text = style.restore.replace("{map}", &name);
+            parent.spawn(TextBundle {
+                text: Text::from_section(text, style.text.clone()),
+                ..default()
+            });
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        column_gap: Val::Px(10.),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for (label, action) in [
+                        (&style.restore_yes, ButtonAction::RestoreAutosave(path.clone())),
+                        (&style.restore_no, ButtonAction::DiscardAutosave(path.clone())),
+                    ] {
+                        parent.spawn((button.clone(), action)).with_children(|parent| {
+                            parent.spawn(TextBundle {
+                                text: Text::from_section(label.clone(), style.text.clone()),
+                                ..default()
+                            });
+                        });
+                    }
+                });
+        });
+}
+
+fn save_status_system(mut commands: Commands, mut saves: Query<(Entity, &mut MapSave)>, mut status: ResMut<Status>) {
+    for (entity, mut task) in &mut saves {
+        let Some(result) = block_on(poll_once(&mut task.0)) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        match result {
+            Ok(name) => {
+                info!("Map \"{name}\" is ready to play");
+                status.0.clear();
+            }
+            Err(e) => status.error(e),
+        }
+    }
+}
+
+fn status_system(status: Res<Status>, mut text: Query<&mut Text, With<StatusText>>) {
+    if !status.is_changed() {
+        return;
+    }
+    for mut text in &mut text {
+        text.sections[0].value = status.0.clone();
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash, States)]
@@ -898,28 +2184,59 @@ enum AppState {
     PendingImage(Option<Handle<Image>>),
     PendingTextures(Vec<Handle<Image>>),
     PendingBackground(Option<Handle<Image>>),
+    PendingParallax(Option<Handle<Image>>),
+}
+
+fn chrome_layer(_: &mut App) -> Option<BoxedLayer> {
+    ChromeLayer::from_env().map(|layer| Box::new(layer) as BoxedLayer)
 }
 
 fn main() {
     App::new()
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                title: "SMOG Editor".to_string(),
-                ..default()
-            }),
-            ..default()
-        }))
-        .add_plugins(RenderSimulationPlugin)
+        .add_plugins(
+            DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: Some(Window {
+                        title: "SMOG Editor".to_string(),
+                        ..default()
+                    }),
+                    ..default()
+                })
+                .set(AssetPlugin {
+                    file_path: config::assets_dir().to_string_lossy().to_string(),
+                    ..default()
+                })
+                .set(LogPlugin {
+                    custom_layer: chrome_layer,
+                    ..default()
+                }),
+        )
+        .add_plugins((RenderSimulationPlugin, SolverDebugPlugin))
+        .add_plugins(TextInputPlugin)
+        .insert_resource(Settings::load())
         .insert_state(AppState::Main)
         .init_resource::<SimulationTextures>()
+        .init_resource::<Status>()
+        .init_resource::<Selection>()
+        .init_resource::<RegionStart>()
+        .init_resource::<Autosave>()
+        .add_event::<Edited>()
         .add_systems(Startup, setup)
-        .add_systems(Startup, setup_ui)
+        .add_systems(Startup, (setup_ui, autosave_prompt).chain())
         .add_systems(Update, drag_and_drop_system)
         .add_systems(Update, handle_constructor_update)
         .add_systems(Update, check_assets_system)
-        .add_systems(Update, update_ui_system)
+        .add_systems(Update, failed_assets_system)
+        .add_systems(Update, (save_system.run_if(not(typing)), save_status_system, status_system).chain())
+        .add_systems(Update, export_system.run_if(not(typing)))
+        .add_systems(Update, autosave_system)
+        .add_systems(Update, (update_ui_system, inspector_sync_system, ambient_system))
+        .add_systems(Update, (focus_system.before(TextInputSystem), inspector_system.after(TextInputSystem)))
         .add_systems(Update, spawn_sprites_system)
+        .add_systems(Update, mode_gizmo_system)
         .add_systems(Update, button_system)
-        .add_systems(Update, control_system)
+        .add_systems(Update, (layer_button_system, layer_list_system).chain())
+        .add_systems(Update, control_system.run_if(not(typing)))
+        .add_systems(Update, (selection_system.run_if(not(typing)), selection_gizmo_system).chain())
         .run();
 }
@@ -1,3 +1,4 @@
+use common::chassis::Chassis;
 use serde::{Deserialize, Serialize};
 
 use crate::UnsizedPacket;
@@ -5,8 +6,16 @@ use crate::UnsizedPacket;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ClientPacket {
     SetName(String),
-    RequestMap,
+    RequestFiles(Vec<(String, u64)>), // map files that are missing or differ, with the bytes of each received before a download broke off
     Ok,
+    Reconnect { session: u64, received: u64 }, // first packet on a new stream, `received` ticks are skipped
+    JoinAsSpectator(String), // instead of `SetName`, the server ignores the inputs of spectators
+    SelectLobby(String), // first packet sent to a hub, the code names the lobby the rest of the stream goes to
+    VoteMap(String), // answer to `MapVote`
+    Password(String), // sent before `SetName` or `JoinAsSpectator` to join a protected lobby
+    AcceptCompression, // sent before `SetName` or `JoinAsSpectator`, the client decompresses the map files and the game stream
+    SelectChassis(Chassis), // sent in the lobby once the map is answered, the last one counts when the game starts
+    UploadVehicle(Vec<u8>), // exported vehicle driven with `Chassis::Custom`, sent like `SelectChassis`
 }
 
 impl UnsizedPacket for ClientPacket {}
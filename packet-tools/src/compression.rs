@@ -0,0 +1,42 @@
+use lz4_flex::block::{self, DecompressError};
+
+use crate::error::PacketError;
+
+/// Runs of the game stream shorter than this are sent as they are, compressing them saves next to nothing.
+pub const COMPRESSION_THRESHOLD: usize = 512;
+/// What compressed bytes may expand to, so a peer can't make the other side run out of memory.
+pub const MAX_DECOMPRESSED: usize = 64 * 1024 * 1024;
+
+/// Compresses the bytes into an LZ4 block behind their size, LZ4 is fast enough to run between the ticks.
+pub fn compress(bytes: &[u8]) -> Vec<u8> {
+    block::compress_prepend_size(bytes)
+}
+
+pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>, PacketError> {
+    let malformed = |e: DecompressError| PacketError::Malformed(e.to_string());
+    let (size, compressed) = block::uncompressed_size(bytes).map_err(malformed)?;
+    // the size comes from the peer, it's checked before anything is allocated for it
+    if size > MAX_DECOMPRESSED {
+        return Err(PacketError::Malformed(format!("expands to more than {MAX_DECOMPRESSED} bytes")));
+    }
+    block::decompress(compressed, size).map_err(malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compression_test() {
+        let bytes: Vec<u8> = (0..4096).map(|i| (i % 7) as u8).collect();
+        let compressed = compress(&bytes);
+        assert!(compressed.len() < bytes.len());
+        assert_eq!(decompress(&compressed).unwrap(), bytes);
+        assert!(decompress(&[0xff; 16]).is_err());
+
+        // a block that expands past the size in front of it
+        let mut lying = compressed.clone();
+        lying[..4].copy_from_slice(&16u32.to_le_bytes());
+        assert!(decompress(&lying).is_err());
+    }
+}
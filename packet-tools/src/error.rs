@@ -0,0 +1,32 @@
+/// Bytes that don't make a packet, either corrupted or sent by someone who isn't playing by the rules.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PacketError {
+    UnknownKind(u8),   // first byte of a game packet that names no packet
+    Truncated(usize),  // bytes the packet is missing
+    Malformed(String), // unsized packet that doesn't deserialize
+}
+
+impl std::fmt::Display for PacketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownKind(kind) => write!(f, "Unknown packet kind {kind}"),
+            Self::Truncated(missing) => write!(f, "Packet is missing {missing} bytes"),
+            Self::Malformed(error) => write!(f, "Malformed packet: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for PacketError {}
+
+impl From<postcard::Error> for PacketError {
+    fn from(error: postcard::Error) -> Self {
+        Self::Malformed(error.to_string())
+    }
+}
+
+// packets that don't parse most likely come from another version of the game
+impl From<PacketError> for std::io::Error {
+    fn from(error: PacketError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+    }
+}
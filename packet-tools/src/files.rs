@@ -0,0 +1,24 @@
+use std::path::Path;
+
+/// Checksum of a map file, the client downloads the files whose checksums differ from the server's.
+pub fn checksum(contents: &[u8]) -> u32 {
+    let mut crc = flate2::Crc::new();
+    crc.update(contents);
+    crc.sum()
+}
+
+/// `None` if the file can't be read, e.g. it wasn't downloaded yet.
+pub async fn checksum_file<P: AsRef<Path>>(path: P) -> Option<u32> {
+    tokio::fs::read(path).await.ok().map(|contents| checksum(&contents))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_test() {
+        assert_eq!(checksum(b"123456789"), 0xCBF43926);
+        assert_ne!(checksum(b"map v1"), checksum(b"map v2"));
+    }
+}
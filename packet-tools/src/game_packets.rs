@@ -1,6 +1,6 @@
-use bevy::{log::error, math::{vec2, Vec2}};
+use bevy::math::{vec2, Vec2};
 
-use crate::{IndexedPacket, Packet};
+use crate::{error::PacketError, IndexedPacket, Packet};
 
 pub const PACKET_SIZE: usize = 9;
 pub type IndexedGamePacket = IndexedPacket<GamePacket, {PACKET_SIZE}>;
@@ -15,6 +15,8 @@ pub enum GamePacket {
     Fire(u8),
     Thrust(f32, f32),
     Dash(f32),
+    Ping(u16), // round trip of the sender's inputs in milliseconds
+    Shield,
 }
 
 impl Packet<{PACKET_SIZE}> for GamePacket {
@@ -55,15 +57,25 @@ impl Packet<{PACKET_SIZE}> for GamePacket {
                 bytes.push(7);
                 bytes.extend(&[0;8]);
             }
+            Self::Ping(ms) => {
+                bytes.push(8);
+                bytes.extend(ms.to_be_bytes());
+                bytes.extend(&[0;6]);
+            }
+            Self::Shield => {
+                bytes.push(9);
+                bytes.extend(&[0;8]);
+            }
             Self::None => bytes = vec![0u8; 9]
         }
 
         bytes.try_into().unwrap()
     }
 
-    fn from_bytes(value: &[u8; PACKET_SIZE]) -> Self {
+    fn from_bytes(value: &[u8; PACKET_SIZE]) -> Result<Self, PacketError> {
         let kind = value[0];
-        match kind {
+        let packet = match kind {
+            0 => Self::None,
             1 => {
                 let x = f32::from_be_bytes(value[1..5].try_into().unwrap());
                 let y = f32::from_be_bytes(value[5..9].try_into().unwrap());
@@ -95,11 +107,16 @@ impl Packet<{PACKET_SIZE}> for GamePacket {
             7 => {
                 Self::ResetMuzzle
             }
-            _ => {
-                error!("receive damaged packet from server");
-                Self::None
+            8 => {
+                let ms = u16::from_be_bytes(value[1..3].try_into().unwrap());
+                Self::Ping(ms)
             }
-        }
+            9 => {
+                Self::Shield
+            }
+            _ => return Err(PacketError::UnknownKind(kind)),
+        };
+        Ok(packet)
     }
 }
 
@@ -117,9 +134,13 @@ mod tests{
             GamePacket::Thrust(3., -1.),
             GamePacket::ResetMuzzle,
             GamePacket::Dash(210.), 
+            GamePacket::Ping(120),
+            GamePacket::Shield,
         ];
         for p in v {
-            assert_eq!(p, GamePacket::from_bytes(&p.to_bytes()));
+            assert_eq!(p, GamePacket::from_bytes(&p.to_bytes()).unwrap());
         }
+        assert_eq!(GamePacket::from_bytes(&[0; PACKET_SIZE]), Ok(GamePacket::None));
+        assert_eq!(GamePacket::from_bytes(&[42; PACKET_SIZE]), Err(PacketError::UnknownKind(42)));
     }
 }
\ No newline at end of file
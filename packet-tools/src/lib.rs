@@ -4,20 +4,28 @@ use std::time::{Duration, Instant};
 use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
+use tracing::{trace_span, Instrument};
 
+use error::PacketError;
+use server_packets::ServerPacket;
+
+pub mod compression;
+pub mod error;
+pub mod files;
 pub mod game_packets;
 pub mod client_packets;
 pub mod server_packets;
+pub mod query_packets;
+pub mod udp;
 
 pub trait Packet<const SIZE: usize>: Clone + Copy + Send + Sync + 'static + std::fmt::Debug {
     fn to_bytes(&self) -> [u8; SIZE];
-    // FIXME: why does this method return `Self` and not `Result<Self>` ???
-    fn from_bytes(value: &[u8; SIZE]) -> Self;
+    fn from_bytes(value: &[u8; SIZE]) -> Result<Self, PacketError>;
 }
 
 impl<const SIZE: usize> Packet<SIZE> for [u8; SIZE] {
-    fn from_bytes(value: &[u8; SIZE]) -> Self {
-        value.clone()
+    fn from_bytes(value: &[u8; SIZE]) -> Result<Self, PacketError> {
+        Ok(value.clone())
     }
 
     fn to_bytes(&self) -> [u8; SIZE] {
@@ -42,14 +50,34 @@ impl<P: Packet<SIZE>, const SIZE: usize> IndexedPacket<P, SIZE> {
         bytes
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Self {
-        Self {
-            id: bytes[0],
-            contents: P::from_bytes(bytes[1..].try_into().unwrap())
-        }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        let contents = <&[u8; SIZE]>::try_from(bytes.get(1..).unwrap_or_default());
+        let (Some(&id), Ok(contents)) = (bytes.first(), contents) else {
+            return Err(PacketError::Truncated((SIZE + 1).saturating_sub(bytes.len())));
+        };
+        Ok(Self {
+            id,
+            contents: P::from_bytes(contents)?,
+        })
     }
 }
 
+/// Length byte that marks a server packet in the game stream instead of a tick, no tick holds that many packets.
+pub const SERVER_PACKET_MARKER: u8 = u8::MAX;
+
+/// Length byte that marks a compressed run of the game stream, a tick holds at most 253 packets.
+pub const COMPRESSED_MARKER: u8 = u8::MAX - 1;
+
+/// Kind byte of a game packet that ends the player's inputs, the lobby packets follow it on the stream.
+pub const INPUTS_END_MARKER: u8 = u8::MAX;
+
+/// What the game stream carries: the ticks and, from a server simulating the game, its own packets.
+#[derive(Debug, Clone)]
+pub enum StreamItem<P: Packet<SIZE>, const SIZE: usize> {
+    Tick(Vec<IndexedPacket<P, SIZE>>),
+    Server(ServerPacket),
+}
+
 pub fn serialize_queue<P: Packet<SIZE>, const SIZE: usize>(
     packets: &Vec<Vec<IndexedPacket<P, SIZE>>>,
 ) -> Vec<u8> {
@@ -61,42 +89,99 @@ pub fn serialize_queue<P: Packet<SIZE>, const SIZE: usize>(
     bytes
 }
 
+/// Puts the packet into the game stream between the ticks.
+pub fn serialize_server_packet(packet: &ServerPacket) -> Vec<u8> {
+    let mut bytes = vec![SERVER_PACKET_MARKER];
+    bytes.extend(packet.as_packet());
+    bytes
+}
+
+/// Compresses a serialized run of the game stream for a player that accepts it, short runs are kept as they are.
+pub fn compress_stream(bytes: &[u8]) -> Vec<u8> {
+    if bytes.len() < compression::COMPRESSION_THRESHOLD {
+        return bytes.to_vec();
+    }
+    let compressed = compression::compress(bytes);
+    if compressed.len() + 5 >= bytes.len() {
+        return bytes.to_vec();
+    }
+    let mut stream = vec![COMPRESSED_MARKER];
+    stream.extend(u32::to_be_bytes(compressed.len() as u32));
+    stream.extend(compressed);
+    stream
+}
+
+/// Ticks of the game stream, the server packets in between are skipped.
 pub fn deserialize_queue<P: Packet<SIZE>, const SIZE: usize>(
     bytes: &mut [u8],
-) -> (Vec<Vec<IndexedPacket<P, SIZE>>>, usize) {
+) -> Result<(Vec<Vec<IndexedPacket<P, SIZE>>>, usize), PacketError> {
+    let (items, res_len) = deserialize_stream(bytes)?;
+    let ticks = items
+        .into_iter()
+        .filter_map(|item| match item {
+            StreamItem::Tick(packets) => Some(packets),
+            StreamItem::Server(_) => None,
+        })
+        .collect();
+    Ok((ticks, res_len))
+}
+
+/// Items of the game stream in the order they were sent.
+/// The incomplete last item is moved to the start of `bytes`, its length is returned along with the items.
+/// A corrupted item fails the whole stream, as the items after it can't be told apart.
+pub fn deserialize_stream<P: Packet<SIZE>, const SIZE: usize>(
+    bytes: &mut [u8],
+) -> Result<(Vec<StreamItem<P, SIZE>>, usize), PacketError> {
     let mut result = Vec::new();
     let mut ind = 0;
 
     let mut res_len = 0;
     while ind < bytes.len() {
-        let len = bytes[ind] as usize;
-        ind += 1;
+        // bytes after the first one, unknown until the length of a server packet arrives
+        let len = match bytes[ind] {
+            SERVER_PACKET_MARKER | COMPRESSED_MARKER => bytes
+                .get(ind + 1..ind + 5)
+                .map(|len| 4 + u32::from_be_bytes(len.try_into().unwrap()) as usize),
+            packets => Some(packets as usize * (SIZE+1)),
+        };
 
-        if ind + len * (SIZE+1) <= bytes.len() {
-            let mut packets = Vec::new();
-            for packet_bytes in bytes[ind..].chunks(SIZE+1).take(len) {
-                packets.push(IndexedPacket::from_bytes(packet_bytes));
+        match len {
+            Some(len) if ind + 1 + len <= bytes.len() => {
+                let item = &bytes[ind + 1..ind + 1 + len];
+                match bytes[ind] {
+                    SERVER_PACKET_MARKER => result.push(StreamItem::Server(ServerPacket::from_bytes(&item[4..])?)),
+                    COMPRESSED_MARKER => {
+                        // a run is compressed whole, so it can't end in the middle of an item
+                        let mut run = compression::decompress(&item[4..])?;
+                        let (items, partial) = deserialize_stream(&mut run)?;
+                        if partial > 0 {
+                            return Err(PacketError::Malformed("compressed run ends mid-item".to_string()));
+                        }
+                        result.extend(items);
+                    }
+                    _ => {
+                        let packets = item.chunks(SIZE+1).map(IndexedPacket::from_bytes).collect::<Result<_, _>>()?;
+                        result.push(StreamItem::Tick(packets));
+                    }
+                }
+                ind += 1 + len;
+            }
+            _ => {
+                bytes.copy_within(ind.., 0);
+                res_len = bytes.len() - ind;
+                break;
             }
-            result.push(packets);
-
-            ind += (SIZE+1) * len;
-        }
-        else {
-            ind -= 1;
-            bytes.copy_within(ind.., 0);
-            res_len = bytes.len() - ind;
-            break;
         }
     }
-    (result, res_len)
+    Ok((result, res_len))
 }
 
 pub trait UnsizedPacket: Clone + Serialize + for<'a> Deserialize<'a> {
     fn to_bytes(&self) -> Vec<u8> {
         postcard::to_stdvec(self).unwrap()
     }
-    fn from_bytes(bytes: &[u8]) -> Self {
-        postcard::from_bytes(bytes).unwrap()
+    fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        Ok(postcard::from_bytes(bytes)?)
     }
 
     fn as_packet(&self) -> Vec<u8> {
@@ -107,9 +192,15 @@ pub trait UnsizedPacket: Clone + Serialize + for<'a> Deserialize<'a> {
         packet
     }
 
-    fn from_packet(bytes: &[u8]) -> Self {
-        let len = u32::from_be_bytes(bytes[0..4].try_into().unwrap()) as usize;
-        Self::from_bytes(&bytes[4..len])
+    fn from_packet(bytes: &[u8]) -> Result<Self, PacketError> {
+        let Some(len) = bytes.get(0..4) else {
+            return Err(PacketError::Truncated(4 - bytes.len()));
+        };
+        let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+        match bytes.get(4..4 + len) {
+            Some(contents) => Self::from_bytes(contents),
+            None => Err(PacketError::Truncated(4 + len - bytes.len())),
+        }
     }
 }
 
@@ -119,8 +210,9 @@ pub trait UnsizedPacketRead: AsyncReadExt + Unpin {
             let len = self.read_u32().await? as usize;
             let mut bytes = vec![0; len];
             self.read_exact(&mut bytes).await?;
-            Ok(P::from_bytes(&bytes))
+            Ok(P::from_bytes(&bytes)?)
         }
+        .instrument(trace_span!("read_packet"))
     }
 }
 
@@ -131,6 +223,7 @@ pub trait UnsizedPacketWrite: AsyncWriteExt + Unpin {
             self.write_all(&bytes).await?;
             Ok(())
         }
+        .instrument(trace_span!("write_packet"))
     }
 }
 
@@ -220,4 +313,42 @@ mod tests {
             v
         );
     }
+
+    #[test]
+    fn stream_test() {
+        let ticks = vec![vec![IndexedPacket::new(0, [1u8, 2])], vec![]];
+        let mut bytes = serialize_queue(&ticks);
+        bytes.extend(serialize_server_packet(&ServerPacket::StateSync(vec![7; 300])));
+        bytes.extend(serialize_queue(&ticks));
+
+        // the server packet arrives in two parts
+        let split = serialize_queue(&ticks).len() + 10;
+        let mut first = bytes[..split].to_vec();
+        let (items, res_len) = deserialize_stream::<[u8; 2], 2>(&mut first).unwrap();
+        assert_eq!(items.len(), 2);
+        let mut rest = first[..res_len].to_vec();
+        rest.extend(&bytes[split..]);
+        let (items, res_len) = deserialize_stream::<[u8; 2], 2>(&mut rest).unwrap();
+        assert_eq!(res_len, 0);
+        assert!(matches!(&items[..], [StreamItem::Server(ServerPacket::StateSync(state)), StreamItem::Tick(_), StreamItem::Tick(_)] if state.len() == 300));
+
+        let (ticks, _) = deserialize_queue::<[u8; 2], 2>(&mut bytes).unwrap();
+        assert_eq!(ticks.len(), 4);
+
+        // long runs are compressed and unpacked into their items, short ones are kept as they are
+        let run = serialize_queue(&vec![vec![IndexedPacket::new(0, [1u8, 2])]; 300]);
+        let mut compressed = compress_stream(&run);
+        assert!(compressed[0] == COMPRESSED_MARKER && compressed.len() < run.len());
+        let (ticks, res_len) = deserialize_queue::<[u8; 2], 2>(&mut compressed).unwrap();
+        assert_eq!((ticks.len(), res_len), (300, 0));
+        assert_eq!(compress_stream(&serialize_queue(&ticks[..2].to_vec())), serialize_queue(&ticks[..2].to_vec()));
+
+        // a server packet that doesn't parse fails the stream instead of panicking
+        let mut corrupted = vec![SERVER_PACKET_MARKER, 0, 0, 0, 2, 200, 200];
+        assert!(matches!(
+            deserialize_stream::<[u8; 2], 2>(&mut corrupted),
+            Err(PacketError::Malformed(_))
+        ));
+        assert_eq!(ServerPacket::from_packet(&[0, 0, 0, 9, 1]).unwrap_err(), PacketError::Truncated(8));
+    }
 }
@@ -0,0 +1,30 @@
+use common::mode::GameMode;
+use serde::{Deserialize, Serialize};
+
+use crate::UnsizedPacket;
+
+/// Sent over UDP as a single datagram, either to a known server or broadcasted to `common::DISCOVERY_PORT`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum QueryPacket {
+    Request,
+    Info(ServerInfo),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub name: String,
+    pub port: u16, // port of the game server, discovery answers come from a different one
+    pub map: String,
+    pub mode: GameMode,
+    pub players: u8,
+    pub max_players: u8,
+}
+
+impl QueryPacket {
+    // datagrams may come from anyone, so don't panic on garbage
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        postcard::from_bytes(bytes).ok()
+    }
+}
+
+impl UnsizedPacket for QueryPacket {}
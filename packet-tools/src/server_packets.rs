@@ -1,14 +1,26 @@
+use common::{chassis::Chassis, mode::MatchSettings};
 use serde::{Deserialize, Serialize};
 
 use crate::UnsizedPacket;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ServerPacket {
-    SetMap(String),
-    CreateFile { name: String, contents: Vec<u8> },
+    SetMap { name: String, files: Vec<(String, u32)> }, // the files of the map with their checksums
+    MatchSettings(MatchSettings),
+    FileChunk { name: String, offset: u64, total: u64, data: Vec<u8> }, // piece of a map file, done once it reaches `total`
     SetPlayers(Vec<(u8, String)>),
+    SetChassis(Vec<(u8, Chassis)>), // what every player chose in the lobby, sent right before `SetPlayers`
+    SetVehicles(Vec<(u8, Vec<u8>)>), // the custom vehicles of the players who drive one, sent right before `SetChassis`
     SetId(u8),
     StartGame,
+    UdpPort(u16), // the game goes over UDP on this port, sent right before `StartGame`
+    SetSession(u64), // token to reconnect with, echoed back when a reconnect is accepted
+    StateSync(Vec<u8>), // authoritative state from a server that simulates the game, sent between the ticks
+    Kicked(String), // reason the host removed the player from the lobby, the connection closes after it
+    MapVote(Vec<String>), // maps for the next round, ends the game stream and the lobby starts over on the stream
+    MapVoteResult(String), // map the players chose, it's set with `SetMap` right after
+    Rejected(String), // reason the lobby refused to let the player in, the connection closes after it
+    EnableCompression, // answer to `AcceptCompression`, the map files are LZ4 compressed from now on
 }
 
 impl UnsizedPacket for ServerPacket {}
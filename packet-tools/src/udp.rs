@@ -0,0 +1,190 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::UnsizedPacket;
+
+pub const MAX_DATAGRAM: usize = 65507;
+pub const STREAM_CHUNK: usize = 1200; // bytes of the game stream per datagram, under the MTU so IP doesn't split it
+const RESEND_AFTER: Duration = Duration::from_millis(50);
+const MAX_PENDING: usize = 4096; // unacknowledged packets after which a peer is given up
+
+/// Game transport, the lobby always goes over TCP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transport {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+/// Everything sent over the game UDP socket is a single datagram.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Datagram {
+    Hello(u8), // tells the server the address of the player with the id
+    Unreliable(Vec<u8>),
+    Reliable { seq: u32, bytes: Vec<u8> },
+    Ack(u32),
+}
+
+impl Datagram {
+    // datagrams may come from anyone, so don't panic on garbage
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        postcard::from_bytes(bytes).ok()
+    }
+}
+
+impl UnsizedPacket for Datagram {}
+
+/// The peer stopped acknowledging packets. Its receiver waits for the oldest one for good, so dropping it would
+/// stall the stream without a word, the peer has to reconnect instead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeerLost(pub usize); // packets it didn't acknowledge
+
+impl std::fmt::Display for PeerLost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The peer didn't acknowledge {} packets", self.0)
+    }
+}
+
+impl std::error::Error for PeerLost {}
+
+/// Sending half of the ack/retransmit layer, keeps packets until the peer acknowledges them.
+#[derive(Debug, Default)]
+pub struct ReliableSender {
+    next: u32,
+    pending: VecDeque<(u32, Vec<u8>, Instant)>,
+}
+
+impl ReliableSender {
+    /// Registers the bytes for retransmission and returns the datagram to send right away.
+    /// Fails without sending anything once the peer leaves too many packets unacknowledged.
+    pub fn send(&mut self, bytes: Vec<u8>) -> Result<Datagram, PeerLost> {
+        if self.pending.len() >= MAX_PENDING {
+            return Err(PeerLost(self.pending.len()));
+        }
+        let seq = self.next;
+        self.next = self.next.wrapping_add(1);
+        self.pending.push_back((seq, bytes.clone(), Instant::now()));
+        Ok(Datagram::Reliable { seq, bytes })
+    }
+
+    pub fn send_packet<P: UnsizedPacket>(&mut self, packet: &P) -> Result<Datagram, PeerLost> {
+        self.send(packet.to_bytes())
+    }
+
+    /// Splits a run of the game stream into datagrams, the receiver joins them back in order.
+    /// A state sync doesn't fit a single datagram.
+    pub fn send_stream(&mut self, bytes: &[u8]) -> Result<Vec<Datagram>, PeerLost> {
+        bytes.chunks(STREAM_CHUNK).map(|chunk| self.send(chunk.to_vec())).collect()
+    }
+
+    pub fn ack(&mut self, seq: u32) {
+        self.pending.retain(|(s, _, _)| *s != seq);
+    }
+
+    /// Datagrams that weren't acknowledged in time, in the order they were sent.
+    pub fn resend(&mut self, now: Instant) -> Vec<Datagram> {
+        let mut datagrams = vec![];
+        for (seq, bytes, sent) in self.pending.iter_mut() {
+            if now.duration_since(*sent) >= RESEND_AFTER {
+                *sent = now;
+                datagrams.push(Datagram::Reliable {
+                    seq: *seq,
+                    bytes: bytes.clone(),
+                });
+            }
+        }
+        datagrams
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// Receiving half of the ack/retransmit layer, delivers packets once and in order.
+#[derive(Debug, Default)]
+pub struct ReliableReceiver {
+    next: u32,
+    buffered: BTreeMap<u32, Vec<u8>>,
+}
+
+impl ReliableReceiver {
+    /// Returns the packets that can be delivered now, every received packet has to be acknowledged.
+    pub fn receive(&mut self, seq: u32, bytes: Vec<u8>) -> Vec<Vec<u8>> {
+        // packets before `next` are duplicates of the delivered ones
+        if seq.wrapping_sub(self.next) < u32::MAX / 2 {
+            self.buffered.insert(seq, bytes);
+        }
+        let mut delivered = vec![];
+        while let Some(bytes) = self.buffered.remove(&self.next) {
+            delivered.push(bytes);
+            self.next = self.next.wrapping_add(1);
+        }
+        delivered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reliable_test() {
+        let mut sender = ReliableSender::default();
+        let mut receiver = ReliableReceiver::default();
+        let datagrams: Vec<_> = (0..4u8).map(|i| sender.send(vec![i]).unwrap()).collect();
+        let receive = |receiver: &mut ReliableReceiver, datagram: &Datagram| {
+            let Datagram::Reliable { seq, bytes } = datagram else {
+                panic!("expected a reliable datagram");
+            };
+            receiver.receive(*seq, bytes.clone())
+        };
+
+        // the second datagram is lost, the rest wait for it
+        assert_eq!(receive(&mut receiver, &datagrams[0]), vec![vec![0]]);
+        assert!(receive(&mut receiver, &datagrams[2]).is_empty());
+        assert!(receive(&mut receiver, &datagrams[3]).is_empty());
+        for seq in [0, 2, 3] {
+            sender.ack(seq);
+        }
+
+        let resent = sender.resend(Instant::now() + RESEND_AFTER);
+        assert_eq!(resent, vec![datagrams[1].clone()]);
+        assert_eq!(receive(&mut receiver, &resent[0]), vec![vec![1], vec![2], vec![3]]);
+        assert!(receive(&mut receiver, &datagrams[2]).is_empty());
+
+        sender.ack(1);
+        assert_eq!(sender.pending(), 0);
+        assert_eq!(Datagram::parse(&Datagram::Ack(7).to_bytes()), Some(Datagram::Ack(7)));
+
+        // a long run goes out in chunks that arrive joined
+        let run: Vec<u8> = (0..3 * STREAM_CHUNK).map(|i| i as u8).collect();
+        let chunks = sender.send_stream(&run).unwrap();
+        assert_eq!(chunks.len(), 3);
+        let joined: Vec<u8> = chunks.iter().flat_map(|chunk| receive(&mut receiver, chunk)).flatten().collect();
+        assert_eq!(joined, run);
+        assert_eq!(Datagram::parse(&[255, 255]), None);
+    }
+
+    #[test]
+    fn overflow_test() {
+        let mut sender = ReliableSender::default();
+        for i in 0..MAX_PENDING {
+            assert!(sender.send(vec![i as u8]).is_ok());
+        }
+
+        // a silent peer is given up, none of the packets it waits for is dropped
+        assert_eq!(sender.send(vec![0]), Err(PeerLost(MAX_PENDING)));
+        assert_eq!(sender.send_stream(&[0; 3 * STREAM_CHUNK]), Err(PeerLost(MAX_PENDING)));
+        assert_eq!(sender.pending(), MAX_PENDING);
+        assert_eq!(sender.resend(Instant::now() + RESEND_AFTER)[0], Datagram::Reliable { seq: 0, bytes: vec![0] });
+
+        // acknowledgements make room again
+        sender.ack(0);
+        assert_eq!(sender.send(vec![1]), Ok(Datagram::Reliable { seq: MAX_PENDING as u32, bytes: vec![1] }));
+    }
+}
@@ -0,0 +1,60 @@
+use bevy::{ecs::query::QueryItem, prelude::*, render::extract_component::ExtractComponent};
+use serde::{Deserialize, Serialize};
+
+/// Light falling on the whole simulation, the colors of the particles and the links are multiplied by it.
+/// Maps set it for dusk or night, the game changes it at runtime for flashes.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Ambient {
+    pub color: Vec3, // linear rgb
+    pub brightness: f32,
+}
+
+impl Default for Ambient {
+    fn default() -> Self {
+        Self::DAY
+    }
+}
+
+impl Ambient {
+    pub const DAY: Self = Self { color: Vec3::ONE, brightness: 1. };
+    pub const DUSK: Self = Self { color: Vec3::new(1., 0.7, 0.5), brightness: 0.8 };
+    pub const NIGHT: Self = Self { color: Vec3::new(0.5, 0.6, 1.), brightness: 0.5 };
+
+    /// Multiplier of the colors as the shaders take it, the alpha is left as it is.
+    pub fn tint(&self) -> Vec4 {
+        (self.color * self.brightness).extend(1.)
+    }
+
+    /// The light `t` of the way to `other`, for fading between the two.
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            color: self.color.lerp(other.color, t),
+            brightness: self.brightness + (other.brightness - self.brightness) * t,
+        }
+    }
+}
+
+impl ExtractComponent for Ambient {
+    type QueryData = &'static Ambient;
+    type QueryFilter = ();
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::QueryData>) -> Option<Self> {
+        Some(*item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ambient_test() {
+        assert_eq!(Ambient::default().tint(), Vec4::ONE);
+        assert_eq!(Ambient::NIGHT.tint(), Vec4::new(0.25, 0.3, 0.5, 1.));
+
+        let half = Ambient::DAY.lerp(&Ambient { color: Vec3::ZERO, brightness: 0. }, 0.5);
+        assert_eq!(half, Ambient { color: Vec3::splat(0.5), brightness: 0.5 });
+        assert_eq!(Ambient::DAY.lerp(&Ambient::DUSK, 1.), Ambient::DUSK);
+    }
+}
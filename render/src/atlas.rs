@@ -0,0 +1,175 @@
+use bevy::{
+    math::uvec2,
+    prelude::*,
+    render::{
+        render_resource::{Buffer, BufferUsages, TextureView, TextureViewDescriptor},
+        renderer::{RenderDevice, RenderQueue},
+        Extract,
+    },
+};
+use wgpu::{util::TextureDataOrder, Extent3d, TextureDescriptor, TextureDimension, TextureUsages};
+
+use crate::{SimulationPipeline, SimulationTextures};
+
+const BYTES_PER_PIXEL: usize = 4;
+pub const MAX_ATLAS_RECTS: usize = 256; // textures an atlas can hold, their rects fill a uniform buffer
+
+/// Particle textures packed into one image, each one in its rect of the image from 0 to 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AtlasTextures {
+    pub image: Handle<Image>,
+    pub rects: Vec<Rect>,
+}
+
+/// The textures of the simulation packed by the renderer, for the GPUs that can't index an array of textures.
+#[derive(Resource)]
+pub(crate) struct PackedTextures {
+    pub textures: Vec<Handle<Image>>, // they're packed again once they change
+    pub view: TextureView,
+    pub rects: Vec<Rect>,
+}
+
+/// Packs RGBA images into a grid of tiles as large as the largest image, smaller ones are stretched to fit.
+/// Returns the size of the atlas, its pixels and the rect of every image.
+pub fn pack(images: &[(UVec2, &[u8])]) -> (UVec2, Vec<u8>, Vec<Rect>) {
+    let tile = images.iter().fold(UVec2::ONE, |tile, (size, _)| tile.max(*size));
+    // as square as it gets, a long row would outgrow the texture size limit
+    let columns = (images.len() as f32).sqrt().ceil().max(1.) as u32;
+    let rows = (images.len() as u32).div_ceil(columns).max(1);
+    let size = uvec2(tile.x * columns, tile.y * rows);
+
+    let mut data = vec![0; (size.x * size.y) as usize * BYTES_PER_PIXEL];
+    let mut rects = vec![];
+    for (i, (image_size, pixels)) in images.iter().enumerate() {
+        let corner = uvec2(i as u32 % columns, i as u32 / columns) * tile;
+        for y in 0..tile.y {
+            for x in 0..tile.x {
+                // nearest pixel of the image
+                let src = (y * image_size.y / tile.y * image_size.x + x * image_size.x / tile.x) as usize;
+                let dst = ((corner.y + y) * size.x + corner.x + x) as usize;
+                let Some(pixel) = pixels.get(src * BYTES_PER_PIXEL..(src + 1) * BYTES_PER_PIXEL) else {
+                    continue;
+                };
+                data[dst * BYTES_PER_PIXEL..(dst + 1) * BYTES_PER_PIXEL].copy_from_slice(pixel);
+            }
+        }
+        let min = corner.as_vec2() / size.as_vec2();
+        rects.push(Rect::from_corners(min, min + tile.as_vec2() / size.as_vec2()));
+    }
+    (size, data, rects)
+}
+
+/// Uniform buffer with the rects of the atlas, the shader picks the one of the particle's texture.
+pub(crate) fn rects_buffer(render_device: &RenderDevice, rects: &[Rect]) -> Buffer {
+    if rects.len() > MAX_ATLAS_RECTS {
+        warn!("The atlas has {} textures, only the first {MAX_ATLAS_RECTS} are used", rects.len());
+    }
+    let mut data = [[0f32; 4]; MAX_ATLAS_RECTS];
+    for (rect, data) in rects.iter().zip(data.iter_mut()) {
+        *data = [rect.min.x, rect.min.y, rect.max.x, rect.max.y];
+    }
+    render_device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
+        label: Some("simulation atlas rects buffer"),
+        contents: bytemuck::cast_slice(&data),
+        usage: BufferUsages::UNIFORM,
+    })
+}
+
+/// Packs the textures of the simulation once they're loaded, when the GPU can't index them and the map has no
+/// atlas of its own.
+pub(crate) fn extract_packed_textures(
+    mut commands: Commands,
+    pipeline: Res<SimulationPipeline>,
+    simulation_textures: Res<SimulationTextures>,
+    packed: Option<Res<PackedTextures>>,
+    image_assets: Extract<Res<Assets<Image>>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    if !pipeline.atlas || simulation_textures.atlas.is_some() {
+        return;
+    }
+    if packed.is_some_and(|packed| packed.textures == simulation_textures.textures) {
+        return;
+    }
+    let Some(images) = simulation_textures
+        .textures
+        .iter()
+        .map(|handle| image_assets.get(handle))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return;
+    };
+    let Some(format) = images.first().map(|image| image.texture_descriptor.format) else {
+        return;
+    };
+
+    // images of another pixel size are left blank
+    let images: Vec<(UVec2, &[u8])> = images
+        .iter()
+        .map(|image| {
+            let size = image.size();
+            match image.data.len() == (size.x * size.y) as usize * BYTES_PER_PIXEL {
+                true => (size, &image.data[..]),
+                false => {
+                    warn!("Texture of {:?} can't be packed into the atlas", image.texture_descriptor.format);
+                    (size, &[][..])
+                }
+            }
+        })
+        .collect();
+    let (size, data, rects) = pack(&images);
+
+    let texture = render_device.create_texture_with_data(
+        &render_queue,
+        &TextureDescriptor {
+            label: Some("simulation texture atlas"),
+            size: Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        },
+        TextureDataOrder::LayerMajor,
+        &data,
+    );
+    commands.insert_resource(PackedTextures {
+        textures: simulation_textures.textures.clone(),
+        view: texture.create_view(&TextureViewDescriptor::default()),
+        rects,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_test() {
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+        let small = red.repeat(1);
+        let large = blue.repeat(4);
+        let (size, data, rects) = pack(&[(UVec2::ONE, &small[..]), (uvec2(2, 2), &large[..]), (UVec2::ONE, &small[..])]);
+
+        // three tiles of the largest image in a 2x2 grid
+        assert_eq!(size, uvec2(4, 4));
+        let pixel = |x: u32, y: u32| &data[((y * size.x + x) as usize) * 4..][..4];
+        // the small image is stretched over its tile
+        assert_eq!(pixel(0, 0), red);
+        assert_eq!(pixel(1, 1), red);
+        assert_eq!(pixel(2, 0), blue);
+        assert_eq!(pixel(3, 1), blue);
+        assert_eq!(pixel(0, 2), red);
+        assert_eq!(pixel(3, 3), [0; 4]);
+
+        assert_eq!(rects[1], Rect::new(0.5, 0., 1., 0.5));
+        assert_eq!(rects[2], Rect::new(0., 0.5, 0.5, 1.));
+    }
+}
@@ -0,0 +1,91 @@
+use bevy::{input::mouse::MouseWheel, prelude::*, window::PrimaryWindow};
+
+use crate::{settings::Settings, RenderedSimulation};
+
+/// Free camera not bound to a tank: the camera keys of the settings pan (WASD by default), wheel zooms,
+/// left click on a player's particle follows it, click elsewhere to stop.
+#[derive(Component, Default)]
+pub struct ObserverCamera {
+    pub follow: Option<usize>, // index of the followed particle
+}
+
+impl ObserverCamera {
+    const PAN_SPEED: f32 = 0.1;
+    const FOLLOW_RADIUS: f32 = 3.; // how close the click has to be to a particle
+}
+
+fn observer_system(
+    mut evr_scroll: EventReader<MouseWheel>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    settings: Res<Settings>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    simulation: Query<&RenderedSimulation>,
+    mut camera: Query<(&Camera, &mut OrthographicProjection, &mut Transform, &mut ObserverCamera)>,
+) {
+    let Ok((camera, mut projection, mut transform, mut observer)) = camera.get_single_mut() else {
+        return;
+    };
+
+    // zoom
+    for ev in evr_scroll.read() {
+        projection.scale = settings.zoom(projection.scale, ev.y);
+    }
+
+    // pan
+    let keys = &settings.camera;
+    let mut factor = ObserverCamera::PAN_SPEED * projection.scale * settings.camera_sensitivity;
+    if keyboard.pressed(keys.fast) {
+        factor *= 5.;
+    }
+    let mut delta = Vec2::ZERO;
+    if keyboard.pressed(keys.left) {
+        delta.x -= factor;
+    }
+    if keyboard.pressed(keys.right) {
+        delta.x += factor;
+    }
+    if keyboard.pressed(keys.down) {
+        delta.y -= factor;
+    }
+    if keyboard.pressed(keys.up) {
+        delta.y += factor;
+    }
+    if delta != Vec2::ZERO {
+        observer.follow = None;
+        transform.translation += delta.extend(0.);
+    }
+
+    let Ok(simulation) = simulation.get_single() else {
+        return;
+    };
+    let particles = &simulation.0.particles;
+
+    // follow
+    if mouse.just_pressed(MouseButton::Left) {
+        let cursor = windows.single().cursor_position().and_then(|cursor| {
+            camera.viewport_to_world_2d(&GlobalTransform::from(*transform), cursor)
+        });
+        if let Some(cursor) = cursor {
+            observer.follow = particles
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| p.owner.is_some() && !p.projectile)
+                .map(|(i, p)| (i, p.pos.distance(cursor)))
+                .filter(|(_, dist)| *dist <= ObserverCamera::FOLLOW_RADIUS)
+                .min_by(|(_, d1), (_, d2)| d1.total_cmp(d2))
+                .map(|(i, _)| i);
+        }
+    }
+    if let Some(p) = observer.follow.and_then(|i| particles.get(i)) {
+        transform.translation = p.pos.extend(transform.translation.z);
+    }
+}
+
+pub struct ObserverCameraPlugin;
+
+impl Plugin for ObserverCameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, observer_system);
+    }
+}
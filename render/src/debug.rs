@@ -0,0 +1,150 @@
+use std::{collections::HashMap, f32::consts::PI};
+
+use bevy::{math::vec2, prelude::*};
+use solver::{Constraint, Link, Solver, CELL_MAX};
+
+use crate::{settings::Settings, RenderedSimulation};
+
+const VELOCITY_SCALE: f32 = 10.; // length of the velocity lines per world unit moved in a tick
+const MAX_STRAIN: f32 = 0.2; // stretch of a link against its length that shows fully red
+const BOUNDS_COLOR: Color = Color::srgb(1., 0., 1.);
+const VELOCITY_COLOR: Color = Color::srgb(0.3, 0.8, 1.);
+const SLEEP_COLOR: Color = Color::srgb(0.4, 0.4, 1.);
+
+/// What the solver overlay shows, the toggle key turns it on and off as a whole.
+#[derive(Resource, Debug, Clone)]
+pub struct SolverDebug {
+    pub enabled: bool,
+    pub grid: bool,       // occupied cells, red once they spill over
+    pub links: bool,      // green at rest, red when stretched
+    pub velocities: bool, // lines along the velocity of the moving particles
+    pub sleep: bool,      // circles around the sleeping particles
+    pub bounds: bool,     // the constraint
+}
+
+impl Default for SolverDebug {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            grid: true,
+            links: true,
+            velocities: true,
+            sleep: true,
+            bounds: true,
+        }
+    }
+}
+
+/// From green to red as `t` goes from 0 to 1.
+fn heat(t: f32) -> Color {
+    let t = t.clamp(0., 1.);
+    Color::srgb(t, 1. - t, 0.)
+}
+
+/// How far a link is from its length, ropes only count when they're pulled and angle links count the half turn.
+fn strain(solver: &Solver, (i, j, link): &(usize, usize, Link)) -> Option<f32> {
+    let (p1, p2) = (solver.particles[*i].pos, solver.particles[*j].pos);
+    let distance = p1.distance(p2);
+    match link {
+        Link::Force(_) => None,
+        Link::Rigid { length, .. } => Some((distance - length).abs() / length.max(f32::EPSILON)),
+        Link::Rope { length, .. } => Some((distance - length).max(0.) / length.max(f32::EPSILON)),
+        Link::Angle { pivot, target, .. } => {
+            let angle = Link::angle(p1, solver.particles[*pivot].pos, p2);
+            Some(Link::angle_error(angle, *target).abs() / PI)
+        }
+    }
+}
+
+fn toggle_system(keyboard: Res<ButtonInput<KeyCode>>, settings: Res<Settings>, mut debug: ResMut<SolverDebug>) {
+    if keyboard.just_pressed(settings.debug_overlay) {
+        debug.enabled = !debug.enabled;
+    }
+}
+
+fn draw_system(mut gizmos: Gizmos, debug: Res<SolverDebug>, simulations: Query<&RenderedSimulation>) {
+    if !debug.enabled {
+        return;
+    }
+    for simulation in &simulations {
+        let solver = &simulation.0;
+
+        if debug.grid {
+            let mut occupancy: HashMap<(usize, usize), usize> = HashMap::new();
+            for particle in &solver.particles {
+                *occupancy.entry(solver.get_cell(particle.pos)).or_default() += 1;
+            }
+            // the first column and row are off the bounds, see `Solver::get_cell`
+            let origin = solver.constraint.bounds().0 - Vec2::splat(solver.cell_size);
+            let size = Vec2::splat(solver.cell_size);
+            for ((col, row), count) in occupancy {
+                let center = origin + (vec2(col as f32, row as f32) + 0.5) * solver.cell_size;
+                gizmos.rect_2d(center, 0., size, heat(count as f32 / CELL_MAX as f32));
+            }
+        }
+
+        if debug.links {
+            for connection in &solver.connections {
+                let Some(strain) = strain(solver, connection) else {
+                    continue;
+                };
+                let (i, j, link) = connection;
+                let color = heat(strain / MAX_STRAIN);
+                // angle links show their arms
+                let points = [Some(*i), link.pivot(), Some(*j)].into_iter().flatten();
+                gizmos.linestrip_2d(points.map(|k| solver.particles[k].pos), color);
+            }
+        }
+
+        for particle in &solver.particles {
+            if debug.velocities && particle.velocity() != Vec2::ZERO {
+                gizmos.line_2d(particle.pos, particle.pos + particle.velocity() * VELOCITY_SCALE, VELOCITY_COLOR);
+            }
+            if debug.sleep && particle.asleep() && !particle.is_static() {
+                gizmos.circle_2d(particle.pos, particle.radius, SLEEP_COLOR);
+            }
+        }
+
+        if debug.bounds {
+            match &solver.constraint {
+                Constraint::Box(bl, tr) => gizmos.rect_2d((*bl + *tr) / 2., 0., *tr - *bl, BOUNDS_COLOR),
+                Constraint::Circle { center, radius } => {
+                    gizmos.circle_2d(*center, *radius, BOUNDS_COLOR);
+                }
+                Constraint::Polygon(polygon) => {
+                    let vertices = polygon.vertices();
+                    gizmos.linestrip_2d(vertices.iter().chain(vertices.first()).copied(), BOUNDS_COLOR)
+                }
+            }
+        }
+    }
+}
+
+/// Overlay of the solver's grid, links, velocities, sleeping particles and bounds, for tuning maps.
+pub struct SolverDebugPlugin;
+
+impl Plugin for SolverDebugPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SolverDebug>().add_systems(Update, (toggle_system, draw_system).chain());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solver::particle::Particle;
+
+    #[test]
+    fn strain_test() {
+        let mut particles = [Particle::default(), Particle::default()];
+        particles[1].pos = vec2(1.2, 0.);
+        let solver = Solver::new(Constraint::Box(vec2(-10., -10.), vec2(10., 10.)), &particles, &[]);
+
+        let rigid = Link::Rigid { length: 1., durability: 1., elasticity: 1. };
+        assert!((strain(&solver, &(0, 1, rigid)).unwrap() - 0.2).abs() < 1e-5);
+        // a slack rope isn't strained
+        let rope = Link::Rope { length: 2., durability: 1. };
+        assert_eq!(strain(&solver, &(0, 1, rope)), Some(0.));
+        assert_eq!(strain(&solver, &(0, 1, Link::Force(1.))), None);
+    }
+}
@@ -0,0 +1,70 @@
+use bevy::{ecs::query::QueryItem, prelude::*, render::{extract_component::ExtractComponent, Extract}};
+
+use crate::RenderedSimulation;
+
+const MAX_TICK_DISTANCE: f32 = 25.; // farther than a particle moves in a tick, it took the index of a removed one
+
+/// Positions of the particles before the last physics tick, the shader blends them with the current ones.
+///
+/// Only the simulations ticking in `FixedUpdate` have it, the others are drawn as they are.
+#[derive(Component, Debug, Clone, Default)]
+pub struct TickPositions(pub Vec<Vec2>);
+
+impl TickPositions {
+    /// Where the particle was before the tick, its current position if it's new or jumped too far.
+    pub fn get(&self, index: usize, pos: Vec2) -> Vec2 {
+        self.0
+            .get(index)
+            .copied()
+            .filter(|previous| previous.distance(pos) < MAX_TICK_DISTANCE)
+            .unwrap_or(pos)
+    }
+}
+
+impl ExtractComponent for TickPositions {
+    type QueryData = &'static TickPositions;
+    type QueryFilter = ();
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::QueryData>) -> Option<Self> {
+        Some(item.clone())
+    }
+}
+
+/// How far the frame is from the previous tick to the last one, from 0 to 1.
+#[derive(Resource, Debug, Clone, Copy)]
+pub(crate) struct TickBlend(pub f32);
+
+impl Default for TickBlend {
+    fn default() -> Self {
+        Self(1.)
+    }
+}
+
+/// Keeps the positions of the particles before every fixed step moves them.
+pub(crate) fn snapshot_system(mut simulations: Query<(&RenderedSimulation, &mut TickPositions)>) {
+    for (simulation, mut positions) in &mut simulations {
+        positions.0.clear();
+        positions.0.extend(simulation.0.particles.iter().map(|particle| particle.pos));
+    }
+}
+
+pub(crate) fn extract_tick_blend(mut commands: Commands, time: Extract<Res<Time<Fixed>>>) {
+    commands.insert_resource(TickBlend(time.overstep_fraction()));
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::vec2;
+
+    use super::*;
+
+    #[test]
+    fn tick_positions_test() {
+        let positions = TickPositions(vec![vec2(0., 0.), vec2(1., 1.)]);
+        assert_eq!(positions.get(0, vec2(2., 0.)), vec2(0., 0.));
+        // a new particle and one that jumped start where they are
+        assert_eq!(positions.get(2, vec2(5., 5.)), vec2(5., 5.));
+        assert_eq!(positions.get(1, vec2(100., 1.)), vec2(100., 1.));
+    }
+}
@@ -1,35 +1,49 @@
-use std::num::NonZeroU32;
+use std::{num::NonZeroU32, ops::Range};
 
 use bevy::{
     core_pipeline::core_2d::Transparent2d,
     ecs::{
+        entity::EntityHashMap,
         query::{QueryItem, ROQueryItem},
         system::{
-            lifetimeless::Read,
+            lifetimeless::SRes,
             SystemParamItem,
         },
     },
-    math::{vec2, FloatOrd},
+    math::{vec2, FloatOrd, Mat4},
     prelude::*,
     render::{
         extract_component::{ExtractComponent, ExtractComponentPlugin}, render_asset::RenderAssets, render_phase::{
             AddRenderCommand, DrawFunctions, PhaseItem, PhaseItemExtraIndex,
             RenderCommand, RenderCommandResult, SetItemPipeline, TrackedRenderPass, ViewSortedRenderPhases,
         }, render_resource::{
-            binding_types::{sampler, texture_2d},
+            binding_types::{sampler, texture_2d, uniform_buffer_sized},
             BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, Buffer,
             BufferUsages, ColorTargetState, ColorWrites,
             FragmentState, MultisampleState, PipelineCache, PrimitiveState,
-            RawBufferVec, RenderPipelineDescriptor, SpecializedRenderPipeline,
+            RenderPipelineDescriptor, ShaderDefVal, SpecializedRenderPipeline,
             SpecializedRenderPipelines, TextureFormat, VertexState,
-        }, renderer::{RenderDevice, RenderQueue}, texture::{BevyDefault as _, GpuImage}, view::ExtractedView, MainWorld, Render, RenderApp, RenderSet
+        }, renderer::{RenderDevice, RenderQueue}, texture::GpuImage, view::{ExtractedView, ViewTarget}, MainWorld, Render, RenderApp, RenderSet
     },
 };
 
+pub mod ambient;
+pub mod atlas;
+pub mod camera;
+pub mod debug;
+pub mod interpolation;
+pub mod link;
+pub mod palette;
 pub mod particle;
+pub mod settings;
+pub mod trail;
 mod vertex;
 
-use solver::Solver;
+use ambient::Ambient;
+use atlas::{AtlasTextures, PackedTextures};
+use solver::{particle::Particle, Solver};
+use interpolation::{TickBlend, TickPositions};
+use trail::Trails;
 use vertex::Vertex;
 use wgpu::{SamplerBindingType, ShaderStages, TextureSampleType};
 
@@ -50,91 +64,523 @@ pub struct SimulationCamera;
 #[derive(Resource)]
 struct SimulationPipeline {
     shader: Handle<Shader>,
+    links_shader: Handle<Shader>,
     uniforms_bind_group_layout: BindGroupLayout,
     textures_bind_group_layout: BindGroupLayout,
+    atlas: bool, // the textures come in an atlas, the map's own or packed for a GPU that can't index an array of them
+}
+
+/// What a pipeline draws, particles are textured and links are plain colored quads.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Primitive {
+    Particles,
+    Links,
+}
+
+/// Everything a simulation pipeline depends on, the target of the view decides the format of its output.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct SimulationPipelineKey {
+    primitive: Primitive,
+    msaa: Msaa,
+    hdr: bool, // the view is tonemapped after the pass, so it's drawn in linear colors
+    format: TextureFormat,
 }
 
 /// A [`RenderCommand`] that binds the vertex and index buffers and issues the
 /// draw command for our custom phase item.
 struct DrawSimulation;
 
+/// Same as [`DrawSimulation`], but for the links between the particles.
+struct DrawLinks;
+
+/// Same as [`DrawLinks`], but for the trails of the fast particles.
+struct DrawTrails;
+
 impl<P> RenderCommand<P> for DrawSimulation
 where
     P: PhaseItem,
 {
-    type Param = ();
+    type Param = SRes<PreparedSimulations>;
 
-    type ViewQuery = Read<ExtractedView>;
+    type ViewQuery = Entity;
 
-    type ItemQuery = Read<SimulationBuffers>;
+    type ItemQuery = ();
 
     fn render<'w>(
-        _: &P,
-        _extracted_view: ROQueryItem<'w, Self::ViewQuery>,
-        simulation_buffers: Option<&'w SimulationBuffers>,
-        _: SystemParamItem<'w, '_, Self::Param>,
+        item: &P,
+        view: ROQueryItem<'w, Self::ViewQuery>,
+        _: Option<()>,
+        prepared: SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
-        let Some(simulation_buffers) = simulation_buffers else {
+        let Some(simulation_buffers) = prepared.into_inner().0.get(&item.entity()) else {
             return RenderCommandResult::Failure;
         };
 
-        if simulation_buffers.particles.len() == 0 {
+        let Some(uniforms) = simulation_buffers.uniforms.get(&view) else {
+            return RenderCommandResult::Failure;
+        };
+
+        if simulation_buffers.particles.instances.is_empty() {
             return RenderCommandResult::Success;
         }
 
-        pass.set_bind_group(0, &simulation_buffers.uniforms_bind_group, &[]);
+        pass.set_bind_group(0, &uniforms.bind_group, &[]);
         pass.set_bind_group(1, &simulation_buffers.textures_bind_group, &[]);
         pass.set_vertex_buffer(0, simulation_buffers.vertices.slice(..));
-        pass.set_vertex_buffer(1, simulation_buffers.particles.buffer().unwrap().slice(..));
+        pass.set_vertex_buffer(1, simulation_buffers.particles.buffer.slice(..));
+        pass.set_index_buffer(
+            simulation_buffers.indices.slice(..),
+            0,
+            wgpu::IndexFormat::Uint32,
+        );
+        pass.draw_indexed(0..6, 0, 0..simulation_buffers.particles.instances.len() as u32);
+
+        RenderCommandResult::Success
+    }
+}
+
+impl<P> RenderCommand<P> for DrawLinks
+where
+    P: PhaseItem,
+{
+    type Param = SRes<PreparedSimulations>;
+
+    type ViewQuery = Entity;
+
+    type ItemQuery = ();
+
+    fn render<'w>(
+        item: &P,
+        view: Entity,
+        _: Option<()>,
+        prepared: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(simulation_buffers) = prepared.into_inner().0.get(&item.entity()) else {
+            return RenderCommandResult::Failure;
+        };
+
+        let Some(uniforms) = simulation_buffers.uniforms.get(&view) else {
+            return RenderCommandResult::Failure;
+        };
+
+        if simulation_buffers.links.instances.is_empty() {
+            return RenderCommandResult::Success;
+        }
+
+        pass.set_bind_group(0, &uniforms.bind_group, &[]);
+        pass.set_vertex_buffer(0, simulation_buffers.vertices.slice(..));
+        pass.set_vertex_buffer(1, simulation_buffers.links.buffer.slice(..));
         pass.set_index_buffer(
             simulation_buffers.indices.slice(..),
             0,
             wgpu::IndexFormat::Uint32,
         );
-        pass.draw_indexed(0..6, 0, 0..simulation_buffers.particles.len() as u32);
+        pass.draw_indexed(0..6, 0, 0..simulation_buffers.links.instances.len() as u32);
 
         RenderCommandResult::Success
     }
 }
 
-/// The GPU vertex and index buffers for our custom phase item.
+impl<P> RenderCommand<P> for DrawTrails
+where
+    P: PhaseItem,
+{
+    type Param = SRes<PreparedSimulations>;
+
+    type ViewQuery = Entity;
+
+    type ItemQuery = ();
+
+    fn render<'w>(
+        item: &P,
+        view: Entity,
+        _: Option<()>,
+        prepared: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(simulation_buffers) = prepared.into_inner().0.get(&item.entity()) else {
+            return RenderCommandResult::Failure;
+        };
+
+        let Some(uniforms) = simulation_buffers.uniforms.get(&view) else {
+            return RenderCommandResult::Failure;
+        };
+
+        if simulation_buffers.trails.instances.is_empty() {
+            return RenderCommandResult::Success;
+        }
+
+        pass.set_bind_group(0, &uniforms.bind_group, &[]);
+        pass.set_vertex_buffer(0, simulation_buffers.vertices.slice(..));
+        pass.set_vertex_buffer(1, simulation_buffers.trails.buffer.slice(..));
+        pass.set_index_buffer(
+            simulation_buffers.indices.slice(..),
+            0,
+            wgpu::IndexFormat::Uint32,
+        );
+        pass.draw_indexed(0..6, 0, 0..simulation_buffers.trails.instances.len() as u32);
+
+        RenderCommandResult::Success
+    }
+}
+
+/// Uniforms shared by the particles and the links, as laid out in the shaders.
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct Uniforms {
+    clip_from_simulation: Mat4,
+    ambient: Vec4, // see [`Ambient`]
+    blend: f32,    // see [`TickBlend`]
+    _padding: [f32; 3],
+}
+
+/// The GPU buffers of a simulation, allocated once per simulation entity.
 ///
-/// As the custom phase item is a single triangle, these are uploaded once and
-/// then left alone.
-#[derive(Component)]
+/// Only the instance buffers are reallocated, when the particles or the links outgrow them.
 struct SimulationBuffers {
-    // particles vertex buffer
+    // quad vertex buffer, shared by the particles, the links and the trails
     vertices: Buffer,
 
-    // particles instance buffer
-    particles: RawBufferVec<particle::Raw>,
+    // instance buffers
+    particles: InstanceBuffer<particle::Raw>,
+    links: InstanceBuffer<link::Raw>,
+    trails: InstanceBuffer<link::Raw>,
 
-    // particles index buffer
+    // quad index buffer
     indices: Buffer,
 
-    // uniform bind group
-    uniforms_bind_group: BindGroup,
-    _uniforms: Buffer,
+    // uniform bind group of every view
+    uniforms: EntityHashMap<SimulationUniforms>,
 
     // textures bind group
     textures_bind_group: BindGroup,
 }
 
+/// The uniforms of a simulation as seen through one view.
+struct SimulationUniforms {
+    buffer: Buffer,
+    bind_group: BindGroup,
+}
+
+/// Buffers of every rendered simulation, kept between frames by the main world entity.
+#[derive(Resource, Default)]
+struct PreparedSimulations(EntityHashMap<SimulationBuffers>);
+
+/// An instance of the simulation drawn from an instance buffer.
+trait Instance: bytemuck::Pod {
+    /// Whether the instance looks the same as the uploaded one, so it doesn't have to be uploaded again.
+    fn same(&self, uploaded: &Self) -> bool;
+}
+
+impl Instance for particle::Raw {
+    fn same(&self, uploaded: &Self) -> bool {
+        self.same(uploaded)
+    }
+}
+
+impl Instance for link::Raw {
+    fn same(&self, uploaded: &Self) -> bool {
+        self.same(uploaded)
+    }
+}
+
+/// An instance buffer along with the instances it currently holds.
+struct InstanceBuffer<T> {
+    buffer: Buffer,
+    capacity: usize,
+    instances: Vec<T>,
+}
+
+impl<T: Instance> InstanceBuffer<T> {
+    const MIN_CAPACITY: usize = 1024;
+
+    fn new(render_device: &RenderDevice) -> Self {
+        Self {
+            buffer: Self::create_buffer(render_device, Self::MIN_CAPACITY),
+            capacity: Self::MIN_CAPACITY,
+            instances: vec![],
+        }
+    }
+
+    fn create_buffer(render_device: &RenderDevice, capacity: usize) -> Buffer {
+        render_device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("simulation instance buffer"),
+            size: (capacity * std::mem::size_of::<T>()) as u64,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Uploads the ranges of instances that changed since the last frame, growing the buffer if needed.
+    /// The unchanged instances are kept as they were uploaded, so the small moves add up until they show.
+    fn write(&mut self, render_device: &RenderDevice, render_queue: &RenderQueue, instances: Vec<T>) {
+        if instances.len() > self.capacity {
+            self.capacity = instances.len().next_power_of_two();
+            self.buffer = Self::create_buffer(render_device, self.capacity);
+            self.instances.clear();
+        }
+
+        let changed = changed_ranges(&self.instances, &instances);
+        self.instances.resize(instances.len(), T::zeroed());
+        for range in changed {
+            let offset = range.start * std::mem::size_of::<T>();
+            render_queue.write_buffer(
+                &self.buffer,
+                offset as u64,
+                bytemuck::cast_slice(&instances[range.clone()]),
+            );
+            self.instances[range.clone()].copy_from_slice(&instances[range]);
+        }
+    }
+}
+
+impl SimulationBuffers {
+    /// Returns `None` while some of the textures are not loaded.
+    fn new(
+        render_device: &RenderDevice,
+        image_assets: &RenderAssets<GpuImage>,
+        simulation_textures: &SimulationTextures,
+        packed: Option<&PackedTextures>,
+        pipeline: &SimulationPipeline,
+    ) -> Option<Self> {
+        let images = simulation_textures
+            .textures
+            .iter()
+            .map(|handle| image_assets.get(handle))
+            .collect::<Option<Vec<_>>>()?;
+
+        let textures_bind_group = match (pipeline.atlas, &simulation_textures.atlas) {
+            (false, _) => {
+                let sampler = &images.first()?.sampler;
+                let textures: Vec<&wgpu::TextureView> = images
+                    .iter()
+                    .map(|image| &*image.texture_view)
+                    .collect();
+                render_device.create_bind_group(
+                    "simulation textures bind group",
+                    &pipeline.textures_bind_group_layout,
+                    &BindGroupEntries::sequential((&textures[..], sampler)),
+                )
+            }
+            (true, map_atlas) => {
+                // the map's atlas, or the textures packed into one
+                let (view, sampler, rects) = match map_atlas {
+                    Some(map_atlas) => {
+                        let image = image_assets.get(&map_atlas.image)?;
+                        (&image.texture_view, &image.sampler, &map_atlas.rects)
+                    }
+                    None => {
+                        let packed = packed?;
+                        (&packed.view, &images.first()?.sampler, &packed.rects)
+                    }
+                };
+                let rects = atlas::rects_buffer(render_device, rects);
+                render_device.create_bind_group(
+                    "simulation atlas bind group",
+                    &pipeline.textures_bind_group_layout,
+                    &BindGroupEntries::sequential((view, sampler, rects.as_entire_binding())),
+                )
+            }
+        };
+
+        let vertices = render_device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
+            label: Some("simulation vertex buffer"),
+            contents: bytemuck::cast_slice(&particle::Raw::vertices()),
+            usage: BufferUsages::VERTEX,
+        });
+
+        let indices = render_device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
+            label: Some("simulation index buffer"),
+            contents: bytemuck::cast_slice(&particle::Raw::indices()),
+            usage: BufferUsages::INDEX,
+        });
+
+        Some(Self {
+            vertices,
+            particles: InstanceBuffer::new(render_device),
+            links: InstanceBuffer::new(render_device),
+            trails: InstanceBuffer::new(render_device),
+            indices,
+            uniforms: EntityHashMap::default(),
+            textures_bind_group,
+        })
+    }
+
+    /// Writes the uniforms of the view, creating them the first time the view sees the simulation.
+    fn write_uniforms(
+        &mut self,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        pipeline: &SimulationPipeline,
+        view: Entity,
+        clip_from_simulation: &Mat4,
+        ambient: Vec4,
+        blend: f32,
+    ) {
+        let uniforms = Uniforms {
+            clip_from_simulation: *clip_from_simulation,
+            ambient,
+            blend,
+            _padding: [0.; 3],
+        };
+        match self.uniforms.get(&view) {
+            Some(view_uniforms) => render_queue.write_buffer(&view_uniforms.buffer, 0, bytemuck::bytes_of(&uniforms)),
+            None => {
+                let buffer = render_device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
+                    label: Some("simulation uniform buffer"),
+                    contents: bytemuck::bytes_of(&uniforms),
+                    usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                });
+                let bind_group = render_device.create_bind_group(
+                    Some("simulation uniform bind group"),
+                    &pipeline.uniforms_bind_group_layout,
+                    &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                );
+                self.uniforms.insert(view, SimulationUniforms { buffer, bind_group });
+            }
+        }
+    }
+
+    /// Fills the instance buffers with what's in the `view`, or with everything without one.
+    fn write_simulation(
+        &mut self,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        solver: &Solver,
+        tick_positions: Option<&TickPositions>,
+        trails: Option<&Trails>,
+        view: Option<Rect>,
+    ) {
+        let cells = view.map(|view| CellRange::new(solver, view));
+        let visible = |p: &Particle| cells.map_or(true, |cells| cells.contains(solver.get_cell(p.pos)));
+        let tick_pos = |i: usize| {
+            let pos = solver.particles[i].pos;
+            tick_positions.map_or(pos, |positions| positions.get(i, pos))
+        };
+
+        let particles = solver
+            .particles
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| visible(p))
+            .map(|(i, p)| particle::Raw::from_particle(p).moved_from(tick_pos(i)))
+            .collect();
+        self.particles.write(render_device, render_queue, particles);
+
+        let links = solver
+            .connections
+            .iter()
+            .filter(|(i, j, _)| visible(&solver.particles[*i]) || visible(&solver.particles[*j]))
+            .filter_map(|(i, j, link)| {
+                let raw = link::Raw::from_link(&solver.particles[*i], &solver.particles[*j], link)?;
+                Some(raw.moved_from(tick_pos(*i), tick_pos(*j)))
+            })
+            .collect();
+        self.links.write(render_device, render_queue, links);
+
+        let trails = trails.map_or(vec![], |trails| {
+            trails.segments(&solver.particles, |pos| cells.map_or(true, |cells| cells.contains(solver.get_cell(pos))))
+        });
+        self.trails.write(render_device, render_queue, trails);
+    }
+}
+
+/// Grid cells of the solver covered by a view, a coarse test that keeps the cells on its edges.
+#[derive(Clone, Copy)]
+struct CellRange {
+    min: (usize, usize),
+    max: (usize, usize),
+}
+
+impl CellRange {
+    const MARGIN: f32 = 2.; // world units around the view, particles just outside still show their edge
+
+    fn new(solver: &Solver, view: Rect) -> Self {
+        let view = view.inflate(Self::MARGIN);
+        Self {
+            min: solver.get_cell(view.min),
+            max: solver.get_cell(view.max),
+        }
+    }
+
+    fn contains(&self, (col, row): (usize, usize)) -> bool {
+        (self.min.0..=self.max.0).contains(&col) && (self.min.1..=self.max.1).contains(&row)
+    }
+}
+
+/// Clip space of the view as seen from the world.
+fn clip_from_world(view: &ExtractedView) -> Mat4 {
+    view.clip_from_view * view.world_from_view.compute_matrix().inverse()
+}
+
+/// Part of the world seen through the view.
+fn view_rect(view: &ExtractedView) -> Rect {
+    transform_rect(Rect::new(-1., -1., 1., 1.), &clip_from_world(view).inverse())
+}
+
+/// Bounds of the rect moved by the transform, turned rects grow to hold their corners.
+fn transform_rect(rect: Rect, transform: &Mat4) -> Rect {
+    let corner = |x: f32, y: f32| transform.project_point3(vec2(x, y).extend(0.)).truncate();
+    Rect::from_corners(corner(rect.min.x, rect.min.y), corner(rect.max.x, rect.max.y))
+        .union_point(corner(rect.min.x, rect.max.y))
+        .union_point(corner(rect.max.x, rect.min.y))
+}
+
+/// Unchanged instances between two changed ranges that are still written with them, one write costs more.
+const MERGE_GAP: usize = 64;
+
+/// Ranges of the instances that differ from the uploaded ones, everything past the old ones counts as changed.
+fn changed_ranges<T: Instance>(old: &[T], new: &[T]) -> Vec<Range<usize>> {
+    let common = old.len().min(new.len());
+    let mut ranges: Vec<Range<usize>> = vec![];
+    for i in (0..common).filter(|&i| !new[i].same(&old[i])) {
+        match ranges.last_mut() {
+            Some(last) if i - last.end <= MERGE_GAP => last.end = i + 1,
+            _ => ranges.push(i..i + 1),
+        }
+    }
+    if new.len() > common {
+        match ranges.last_mut() {
+            Some(last) if common - last.end <= MERGE_GAP => last.end = new.len(),
+            _ => ranges.push(common..new.len()),
+        }
+    }
+    ranges
+}
+
 #[derive(Component)]
 struct SimulationBackground;
 
+/// Sprite of a parallax layer behind the background, `origin` is where it sits when the camera is on it.
+#[derive(Component)]
+struct ParallaxBackground {
+    factor: f32,
+    origin: Vec3,
+}
+
 /// The custom draw commands that Bevy executes for each entity we enqueue into
 /// the render phase.
 type DrawSimulationCommands = (SetItemPipeline, DrawSimulation);
+type DrawLinksCommands = (SetItemPipeline, DrawLinks);
+type DrawTrailsCommands = (SetItemPipeline, DrawTrails);
+
+/// Where the simulation's entity places it in the world, so several of them can share a view.
+#[derive(Component, Clone, Copy)]
+struct SimulationTransform(Mat4);
 
 impl ExtractComponent for RenderedSimulation {
-    type QueryData = &'static RenderedSimulation;
+    type QueryData = (&'static RenderedSimulation, Option<&'static GlobalTransform>);
     type QueryFilter = ();
-    type Out = Self;
+    type Out = (Self, SimulationTransform);
 
-    fn extract_component(item: QueryItem<'_, Self::QueryData>) -> Option<Self> {
-        Some(RenderedSimulation(item.0.clone()))
+    fn extract_component((simulation, transform): QueryItem<'_, Self::QueryData>) -> Option<Self::Out> {
+        let transform = transform.map_or(Mat4::IDENTITY, GlobalTransform::compute_matrix);
+        Some((RenderedSimulation(simulation.0.clone()), SimulationTransform(transform)))
     }
 }
 
@@ -160,29 +606,72 @@ fn update_simulation_background(
             .insert(SimulationBackground);
     }
 }
+
+/// Shifts the parallax layers along with the camera, the further a layer is the more it follows the camera.
+fn scroll_parallax(
+    camera: Query<&Transform, (With<SimulationCamera>, Without<ParallaxBackground>)>,
+    mut layers: Query<(&ParallaxBackground, &mut Transform)>,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+    for (layer, mut transform) in &mut layers {
+        let offset = (camera.translation - layer.origin).truncate() * layer.factor;
+        transform.translation = layer.origin + offset.extend(0.);
+    }
+}
+
+/// The background and the parallax layers are sprites, they're tinted with the ambient of the simulation here.
+fn light_backgrounds(
+    simulation: Query<&Ambient, With<RenderedSimulation>>,
+    mut sprites: Query<&mut Sprite, Or<(With<SimulationBackground>, With<ParallaxBackground>)>>,
+) {
+    let tint = simulation.get_single().copied().unwrap_or_default().tint();
+    let color = Color::linear_rgba(tint.x, tint.y, tint.z, tint.w);
+    for mut sprite in &mut sprites {
+        if sprite.color != color {
+            sprite.color = color;
+        }
+    }
+}
+
 pub struct RenderSimulationPlugin;
 
 impl Plugin for RenderSimulationPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(GpuFeatureSupportChecker)
+            .add_plugins(camera::ObserverCameraPlugin)
+            .init_resource::<palette::TeamColors>()
+            .init_resource::<settings::Settings>()
             .add_plugins(ExtractComponentPlugin::<RenderedSimulation>::default())
             .add_plugins(ExtractComponentPlugin::<SimulationCamera>::default())
-            .add_systems(Update, update_simulation_background);
+            .add_plugins(ExtractComponentPlugin::<Trails>::default())
+            .add_plugins(ExtractComponentPlugin::<TickPositions>::default())
+            .add_plugins(ExtractComponentPlugin::<Ambient>::default())
+            .add_systems(FixedFirst, interpolation::snapshot_system)
+            .add_systems(Update, (update_simulation_background, scroll_parallax, light_backgrounds, trail::update_trails));
     }
 
     fn finish(&self, app: &mut App) {
         app.sub_app_mut(RenderApp)
             .init_resource::<SimulationTextures>()
             .init_resource::<SimulationPipeline>()
+            .init_resource::<PreparedSimulations>()
+            .init_resource::<TickBlend>()
             .init_resource::<SpecializedRenderPipelines<SimulationPipeline>>()
             .add_render_command::<Transparent2d, DrawSimulationCommands>()
+            .add_render_command::<Transparent2d, DrawLinksCommands>()
+            .add_render_command::<Transparent2d, DrawTrailsCommands>()
             .add_systems(
                 Render,
                 (prepare_simulation_buffers.run_if(textures_prepared))
                     .in_set(RenderSet::PrepareResources),
             )
             .add_systems(Render, queue_simulation.in_set(RenderSet::Queue))
-            .add_systems(ExtractSchedule, update_simulation_textures);
+            .add_systems(
+                ExtractSchedule,
+                (update_simulation_textures, atlas::extract_packed_textures, interpolation::extract_tick_blend),
+            );
     }
 }
 
@@ -202,12 +691,11 @@ impl Plugin for GpuFeatureSupportChecker {
             .features()
             .contains(wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING)
         {
-            error!(
+            warn!(
                 "Render device doesn't support feature \
                 SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING, \
-                which is required for texture binding arrays"
+                the particle textures are packed into an atlas instead"
             );
-            std::process::exit(1);
         }
     }
 }
@@ -221,39 +709,71 @@ fn queue_simulation(
     mut transparent_render_phases: ResMut<ViewSortedRenderPhases<Transparent2d>>,
     transparent_draw_function: Res<DrawFunctions<Transparent2d>>,
     mut specialized_render_pipelines: ResMut<SpecializedRenderPipelines<SimulationPipeline>>,
-    views: Query<Entity, (With<ExtractedView> /*With<SimulationCamera>*/,)>,
-    simulations: Query<Entity, With<RenderedSimulation>>,
+    views: Query<(Entity, &ExtractedView, &ViewTarget), With<SimulationCamera>>,
+    simulations: Query<(Entity, &SimulationTransform), With<RenderedSimulation>>,
 ) {
     let draw_simulation = transparent_draw_function
         .read()
         .id::<DrawSimulationCommands>();
+    let draw_links = transparent_draw_function.read().id::<DrawLinksCommands>();
+    let draw_trails = transparent_draw_function.read().id::<DrawTrailsCommands>();
 
     // Render phases are per-view, so we need to iterate over all views so that
     // the entity appears in them. (In this example, we have only one view, but
     // it's good practice to loop over all views anyway.)
-    for view_entity in views.iter() {
+    for (view_entity, view, target) in views.iter() {
         let Some(transparent_phase) = transparent_render_phases.get_mut(&view_entity) else {
             continue;
         };
 
         // Find all the custom rendered entities that are visible from this
         // view.
-        for entity in simulations.iter() {
-            // Ordinarily, the [`SpecializedRenderPipeline::Key`] would contain
-            // some per-view settings, such as whether the view is HDR, but for
-            // simplicity's sake we simply hard-code the view's characteristics,
-            // with the exception of number of MSAA samples.
+        for (entity, transform) in simulations.iter() {
+            // simulations further back are drawn first, each one with its links under its particles
+            let depth = transform.0.w_axis.z;
+            let key = |primitive| SimulationPipelineKey {
+                primitive,
+                msaa: *msaa,
+                hdr: view.hdr,
+                format: target.main_texture_format(),
+            };
             let pipeline_id = specialized_render_pipelines.specialize(
                 &pipeline_cache,
                 &simulation_pipeline,
-                *msaa,
+                key(Primitive::Particles),
             );
 
             transparent_phase.add(Transparent2d {
                 entity,
                 pipeline: pipeline_id,
                 draw_function: draw_simulation,
-                sort_key: FloatOrd(-1.),
+                sort_key: FloatOrd(depth - 1.),
+                batch_range: 0..1,
+                extra_index: PhaseItemExtraIndex::NONE,
+            });
+
+            // links go under the particles, so only the stretched ones show
+            let pipeline_id = specialized_render_pipelines.specialize(
+                &pipeline_cache,
+                &simulation_pipeline,
+                key(Primitive::Links),
+            );
+
+            transparent_phase.add(Transparent2d {
+                entity,
+                pipeline: pipeline_id,
+                draw_function: draw_links,
+                sort_key: FloatOrd(depth - 1.5),
+                batch_range: 0..1,
+                extra_index: PhaseItemExtraIndex::NONE,
+            });
+
+            // trails are drawn with the links pipeline, behind the particles that leave them
+            transparent_phase.add(Transparent2d {
+                entity,
+                pipeline: pipeline_id,
+                draw_function: draw_trails,
+                sort_key: FloatOrd(depth - 1.25),
                 batch_range: 0..1,
                 extra_index: PhaseItemExtraIndex::NONE,
             });
@@ -262,31 +782,52 @@ fn queue_simulation(
 }
 
 impl SpecializedRenderPipeline for SimulationPipeline {
-    type Key = Msaa;
+    type Key = SimulationPipelineKey;
 
-    fn specialize(&self, msaa: Self::Key) -> RenderPipelineDescriptor {
-        RenderPipelineDescriptor {
-            label: Some("simulation render pipeline".into()),
-            layout: vec![
-                self.uniforms_bind_group_layout.clone(),
-                self.textures_bind_group_layout.clone(),
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let shader_defs = match self.atlas {
+            true => vec![
+                "TEXTURE_ATLAS".into(),
+                ShaderDefVal::UInt("MAX_ATLAS_RECTS".into(), atlas::MAX_ATLAS_RECTS as u32),
             ],
+            false => vec![],
+        };
+        let (label, shader, layout, instances) = match key.primitive {
+            Primitive::Particles => (
+                "simulation render pipeline",
+                &self.shader,
+                vec![
+                    self.uniforms_bind_group_layout.clone(),
+                    self.textures_bind_group_layout.clone(),
+                ],
+                particle::Raw::desc(),
+            ),
+            Primitive::Links => (
+                "links render pipeline",
+                &self.links_shader,
+                vec![self.uniforms_bind_group_layout.clone()],
+                link::Raw::desc(),
+            ),
+        };
+        RenderPipelineDescriptor {
+            label: Some(match key.hdr {
+                true => format!("{label} (hdr)").into(),
+                false => label.into(),
+            }),
+            layout,
             push_constant_ranges: vec![],
             vertex: VertexState {
-                shader: self.shader.clone(),
-                shader_defs: vec![],
+                shader: shader.clone(),
+                shader_defs: shader_defs.clone(),
                 entry_point: "vs_main".into(),
-                buffers: vec![Vertex::desc(), particle::Raw::desc()],
+                buffers: vec![Vertex::desc(), instances],
             },
             fragment: Some(FragmentState {
-                shader: self.shader.clone(),
-                shader_defs: vec![],
+                shader: shader.clone(),
+                shader_defs,
                 entry_point: "fs_main".into(),
                 targets: vec![Some(ColorTargetState {
-                    // Ordinarily, you'd want to check whether the view has the
-                    // HDR format and substitute the appropriate texture format
-                    // here, but we omit that for simplicity.
-                    format: TextureFormat::bevy_default(),
+                    format: key.format,
                     blend: Some(wgpu::BlendState {
                         color: wgpu::BlendComponent {
                             src_factor: wgpu::BlendFactor::SrcAlpha,
@@ -307,7 +848,7 @@ impl SpecializedRenderPipeline for SimulationPipeline {
             // changed.
             depth_stencil: None,
             multisample: MultisampleState {
-                count: msaa.samples(),
+                count: key.msaa.samples(),
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
@@ -319,98 +860,74 @@ fn textures_prepared(
     simulation_textures: Res<SimulationTextures>,
     image_assets: Res<RenderAssets<GpuImage>>,
 ) -> bool {
-    simulation_textures.textures.iter().all(|handle| {
+    let atlas = simulation_textures.atlas.as_ref().map(|atlas| &atlas.image);
+    simulation_textures.textures.iter().chain(atlas).all(|handle| {
         //println!("{:?}", handle.path());
         image_assets.get(handle).is_some()
     })
 }
 
 fn prepare_simulation_buffers(
-    mut commands: Commands,
     views: Query<(Entity, &ExtractedView), With<SimulationCamera>>,
-    //view_uniforms: Res<ViewUniforms>,
-    simulations: Query<(Entity, &RenderedSimulation)>,
+    simulations: Query<(
+        Entity,
+        &RenderedSimulation,
+        &SimulationTransform,
+        Option<&TickPositions>,
+        Option<&Trails>,
+        Option<&Ambient>,
+    )>,
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
     image_assets: Res<RenderAssets<GpuImage>>,
     simulation_textures: Res<SimulationTextures>,
+    packed: Option<Res<PackedTextures>>,
     pipeline: Res<SimulationPipeline>,
+    blend: Res<TickBlend>,
+    mut prepared: ResMut<PreparedSimulations>,
 ) {
-    for (_, extracted_view) in views.iter() {
-        let world_from_view = extracted_view.world_from_view.compute_matrix(); // TODO: replace with Res<ViewUniforms>
-        let view_from_world = world_from_view.inverse();
-        let clip_from_world = extracted_view.clip_from_view * view_from_world;
-
-        for (entity, simulation) in &simulations {
-            // handling particles
-            let vertices =
-                render_device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
-                    label: Some("simulation vertex buffer"),
-                    contents: bytemuck::cast_slice(&particle::Raw::vertices()),
-                    usage: BufferUsages::VERTEX,
-                });
-
-            let mut particles = RawBufferVec::new(BufferUsages::VERTEX);
-            for p in simulation.0.particles.iter() {
-                particles.push(particle::Raw::from_particle(p));
-            }
-
-            particles.write_buffer(&render_device, &render_queue);
-
-            let indices =
-                render_device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
-                    label: Some("simulation index buffer"),
-                    contents: bytemuck::cast_slice(&particle::Raw::indices()),
-                    usage: BufferUsages::INDEX,
-                });
-
-            // handling uniforms
-            let uniforms =
-                render_device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
-                    label: Some("simulation uniform buffer"),
-                    contents: bytemuck::bytes_of(&clip_from_world),
-                    usage: wgpu::BufferUsages::UNIFORM,
-                });
-
-            let uniforms_bind_group = render_device.create_bind_group(
-                Some("simulation uniform bind group"),
-                &pipeline.uniforms_bind_group_layout,
-                &[wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: uniforms.as_entire_binding(),
-                }],
-            );
+    // the bind group layouts are recreated together with the textures
+    let repacked = packed.as_ref().is_some_and(|packed| packed.is_changed());
+    if pipeline.is_changed() || simulation_textures.is_changed() || repacked {
+        prepared.0.clear();
+    }
+    prepared.0.retain(|entity, _| simulations.contains(*entity));
 
-            // TODO: binding textures every frame is not optimal, need to move this code into another function
-            // handling textures
-            let mut images = vec![];
-            for handle in simulation_textures.textures.iter() {
-                match image_assets.get(handle) {
-                    Some(image) => images.push(image),
-                    None => panic!("No image {handle:?} found in assets folder!"),
-                }
-            }
+    // every camera sees its part of the simulation, one instance buffer serves them all
+    let view = views.iter().map(|(_, view)| view_rect(view)).reduce(|a, b| a.union(b));
 
-            let sampler = &images[0].sampler;
-            let textures: Vec<&wgpu::TextureView> = images
-                .into_iter()
-                .map(|image| &*image.texture_view)
-                .collect();
+    for (entity, simulation, transform, tick_positions, trails, ambient) in &simulations {
+        if !prepared.0.contains_key(&entity) {
+            // textures that are still loading or failed to load leave the simulation undrawn
+            let Some(buffers) = SimulationBuffers::new(
+                &render_device,
+                &image_assets,
+                &simulation_textures,
+                packed.as_deref(),
+                &pipeline,
+            ) else {
+                continue;
+            };
+            prepared.0.insert(entity, buffers);
+        }
+        let buffers = prepared.0.get_mut(&entity).unwrap();
+        // the views are culled in the solver's coordinates
+        let local_view = view.map(|view| transform_rect(view, &transform.0.inverse()));
+        buffers.write_simulation(&render_device, &render_queue, &simulation.0, tick_positions, trails, local_view);
 
-            let textures_bind_group = render_device.create_bind_group(
-                "simulation textures bind group",
-                &pipeline.textures_bind_group_layout,
-                &BindGroupEntries::sequential((&textures[..], sampler)),
+        buffers.uniforms.retain(|view, _| views.contains(*view));
+        for (view_entity, extracted_view) in views.iter() {
+            let clip_from_simulation = clip_from_world(extracted_view) * transform.0; // TODO: replace with Res<ViewUniforms>
+            let ambient = ambient.copied().unwrap_or_default().tint();
+            buffers.write_uniforms(
+                &render_device,
+                &render_queue,
+                &pipeline,
+                view_entity,
+                &clip_from_simulation,
+                ambient,
+                blend.0,
             );
-
-            commands.entity(entity).insert(SimulationBuffers {
-                vertices,
-                particles,
-                indices,
-                _uniforms: uniforms,
-                uniforms_bind_group,
-                textures_bind_group,
-            });
         }
     }
 }
@@ -419,6 +936,16 @@ fn prepare_simulation_buffers(
 pub struct SimulationTextures {
     pub textures: Vec<Handle<Image>>,
     pub background: Option<Handle<Image>>,
+    pub parallax: Vec<ParallaxLayer>, // behind the background, from the furthest one
+    pub atlas: Option<AtlasTextures>, // takes the place of the textures
+}
+
+/// Background image scrolling slower than the map. A `factor` of 0 keeps it in place like the background,
+/// 1 moves it along with the camera as if it were infinitely far.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParallaxLayer {
+    pub image: Handle<Image>,
+    pub factor: f32,
 }
 
 impl SimulationTextures {
@@ -432,14 +959,40 @@ impl SimulationTextures {
 }
 
 fn update_simulation_textures(mut commands: Commands, mut main_world: ResMut<MainWorld>) {
-    let mut simulations = main_world.query::<(&mut Handle<Image>, &mut Visibility, &SimulationBackground)>();
+    let mut simulations =
+        main_world.query::<(&mut Handle<Image>, &mut Visibility, &Sprite, &Transform, &SimulationBackground)>();
     let Some(textures) = main_world.remove_resource::<SimulationTextures>() else {
         return;
     };    
 
-    for (mut handle, mut visibility, _) in simulations.iter_mut(&mut main_world) {
+    let mut backgrounds = vec![];
+    for (mut handle, mut visibility, sprite, transform, _) in simulations.iter_mut(&mut main_world) {
         *handle = textures.background.as_ref().map_or(default(), |handle| handle.clone());
         *visibility = textures.background.as_ref().map_or(Visibility::Hidden, |_| Visibility::Visible);
+        backgrounds.push((sprite.custom_size, transform.translation));
+    }
+
+    // the parallax layers cover the map like the background, each one a bit further back
+    let mut old_layers = main_world.query_filtered::<Entity, With<ParallaxBackground>>();
+    for entity in old_layers.iter(&main_world).collect::<Vec<_>>() {
+        main_world.despawn(entity);
+    }
+    for (size, translation) in backgrounds {
+        for (i, layer) in textures.parallax.iter().enumerate() {
+            let origin = translation - Vec3::Z * (textures.parallax.len() - i) as f32 * 0.1;
+            main_world.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        custom_size: size,
+                        ..default()
+                    },
+                    texture: layer.image.clone(),
+                    transform: Transform::from_translation(origin),
+                    ..default()
+                },
+                ParallaxBackground { factor: layer.factor, origin },
+            ));
+        }
     }
 
     commands.remove_resource::<SimulationPipeline>();
@@ -461,6 +1014,8 @@ impl FromWorld for SimulationTextures {
         Self {
             textures,
             background: None,
+            parallax: vec![],
+            atlas: None,
         }
     }
 }
@@ -485,29 +1040,94 @@ impl FromWorld for SimulationPipeline {
             }],
         );
 
-        let textures = &world.resource::<SimulationTextures>().textures;
+        let simulation_textures = world.resource::<SimulationTextures>();
+        let atlas = simulation_textures.atlas.is_some()
+            || !render_device
+                .features()
+                .contains(wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING);
 
+        // particle textures, an atlas holds them all in one texture with the rect of each
+        let texture = texture_2d(TextureSampleType::Float { filterable: true });
         let textures_bind_group_layout = render_device.create_bind_group_layout(
             Some("particles textures bind group layout"),
-            // particle textures
-            &BindGroupLayoutEntries::with_indices(
-                ShaderStages::FRAGMENT,
-                (
+            &match atlas {
+                true => BindGroupLayoutEntries::with_indices(
+                    ShaderStages::FRAGMENT,
                     (
-                        0,
-                        texture_2d(TextureSampleType::Float { filterable: true })
-                            .count(NonZeroU32::new(textures.len() as u32).unwrap()),
+                        (0, texture),
+                        (1, sampler(SamplerBindingType::Filtering)),
+                        (2, uniform_buffer_sized(false, None)),
                     ),
-                    (1, sampler(SamplerBindingType::Filtering)),
-                ),
-            )
-            .to_vec(),
+                )
+                .to_vec(),
+                false => BindGroupLayoutEntries::with_indices(
+                    ShaderStages::FRAGMENT,
+                    (
+                        (0, texture.count(NonZeroU32::new(simulation_textures.textures.len() as u32).unwrap())),
+                        (1, sampler(SamplerBindingType::Filtering)),
+                    ),
+                )
+                .to_vec(),
+            },
         );
 
         SimulationPipeline {
             shader: asset_server.load("shaders/simulation.wgsl"),
+            links_shader: asset_server.load("shaders/links.wgsl"),
             uniforms_bind_group_layout,
             textures_bind_group_layout,
+            atlas,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::vec2;
+    use solver::{particle::METAL, Constraint};
+
+    use super::*;
+
+    #[test]
+    fn changed_ranges_test() {
+        let raw = |x: f32| particle::Raw::from_particle(&METAL.with_position(vec2(x, 0.)));
+        let old: Vec<_> = (0..200).map(|i| raw(i as f32)).collect();
+        let mut new = old.clone();
+        assert!(changed_ranges(&old, &new).is_empty());
+
+        // moves below the epsilon aren't uploaded
+        new[0] = raw(0.001);
+        assert!(changed_ranges(&old, &new).is_empty());
+
+        // close changes are written together, far ones apart
+        new[1] = raw(10.);
+        new[3] = raw(10.);
+        new[150] = raw(10.);
+        assert_eq!(changed_ranges(&old, &new), vec![1..4, 150..151]);
+
+        new.push(raw(200.));
+        assert_eq!(changed_ranges(&old, &new), vec![1..4, 150..201]);
+        assert!(changed_ranges(&old, &old[..4]).is_empty());
+    }
+
+    #[test]
+    fn cell_range_test() {
+        let solver = Solver::new(Constraint::Box(vec2(-50., -50.), vec2(50., 50.)), &[], &[]);
+        let cells = CellRange::new(&solver, Rect::new(0., 0., 10., 10.));
+        assert!(cells.contains(solver.get_cell(vec2(5., 5.))));
+        assert!(cells.contains(solver.get_cell(vec2(-1., 11.)))); // within the margin
+        assert!(!cells.contains(solver.get_cell(vec2(20., 5.))));
+        assert!(!cells.contains(solver.get_cell(vec2(5., -40.))));
+    }
+
+    #[test]
+    fn transform_rect_test() {
+        let transform = Mat4::from_scale_rotation_translation(Vec3::splat(2.), Quat::IDENTITY, Vec3::new(10., 0., 1.));
+        let rect = Rect::new(0., 0., 1., 2.);
+        assert_eq!(transform_rect(rect, &transform), Rect::new(10., 0., 12., 4.));
+        // back to the simulation's coordinates
+        assert!(transform_rect(transform_rect(rect, &transform), &transform.inverse())
+            .min
+            .abs_diff_eq(rect.min, 1e-5));
+    }
+}
@@ -0,0 +1,96 @@
+use bevy::{math::{vec4, Vec2, Vec4}, render::render_resource::{BufferAddress, VertexAttribute, VertexBufferLayout, VertexStepMode}};
+use wgpu::vertex_attr_array;
+
+use solver::{particle::Particle, Link};
+
+const WIDTH: f32 = 0.15;
+const WEAK_COLOR: Vec4 = vec4(1., 0.15, 0.1, 0.8);
+const STRONG_COLOR: Vec4 = vec4(0.6, 0.6, 0.6, 0.6);
+const FORCE_COLOR: Vec4 = vec4(0.3, 0.5, 1., 0.4);
+const POSITION_EPSILON: f32 = 0.01; // see `particle::Raw::same`
+
+#[derive(Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable, Debug)]
+#[repr(C)]
+pub struct Raw {
+    color: Vec4,
+    start: Vec2,
+    end: Vec2,
+    tick_start: Vec2, // ends before the last tick, the shader blends them with `start` and `end`
+    tick_end: Vec2,
+    width: f32,
+    _padding: [f32; 3], // keeps the struct free of implicit padding
+}
+
+impl Raw {
+    const ATTRIBS: [VertexAttribute; 6] = vertex_attr_array![
+        // color
+        2 => Float32x4,
+        // start
+        3 => Float32x2,
+        // end
+        4 => Float32x2,
+        // ends before the last tick
+        6 => Float32x2,
+        7 => Float32x2,
+        // width
+        5 => Float32,
+    ];
+
+    pub fn desc() -> VertexBufferLayout {
+        VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as BufferAddress,
+            step_mode: VertexStepMode::Instance,
+            attributes: Self::ATTRIBS.into(),
+        }
+    }
+}
+
+impl Raw {
+    /// A plain segment, e.g. a piece of a particle's trail.
+    pub fn segment(start: Vec2, end: Vec2, width: f32, color: Vec4) -> Raw {
+        Raw {
+            color,
+            start,
+            end,
+            tick_start: start,
+            tick_end: end,
+            width,
+            _padding: [0.; 3],
+        }
+    }
+
+    /// Whether the link is drawn the same as the uploaded one, its ends may differ by less than the epsilon.
+    pub fn same(&self, uploaded: &Raw) -> bool {
+        self.color == uploaded.color
+            && self.width == uploaded.width
+            && [self.start, self.end, self.tick_start, self.tick_end]
+                .iter()
+                .zip([uploaded.start, uploaded.end, uploaded.tick_start, uploaded.tick_end])
+                .all(|(a, b)| a.abs_diff_eq(b, POSITION_EPSILON))
+    }
+
+    /// Draws the link on its way from `start` and `end`, where its ends were before the last tick.
+    pub fn moved_from(self, start: Vec2, end: Vec2) -> Raw {
+        Raw {
+            tick_start: start,
+            tick_end: end,
+            ..self
+        }
+    }
+
+    /// Broken links, angle links and links to hidden particles are not drawn.
+    pub fn from_link(p1: &Particle, p2: &Particle, link: &Link) -> Option<Raw> {
+        if link.durability() < 0. || link.pivot().is_some() || p1.kind.hidden() || p2.kind.hidden() {
+            return None;
+        }
+        let color = match link {
+            Link::Force(_) => FORCE_COLOR,
+            // fades to red as the link wears out, without depending on the durability scale
+            _ => {
+                let durability = link.durability();
+                WEAK_COLOR.lerp(STRONG_COLOR, durability / (durability + 1.))
+            }
+        };
+        Some(Raw::segment(p1.pos, p2.pos, WIDTH, color))
+    }
+}
@@ -0,0 +1,88 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Set of colors used to tell the teams apart.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum Palette {
+    /// Hues spread evenly around the color wheel.
+    #[default]
+    Rainbow,
+    /// Okabe-Ito colors, distinguishable with the common color vision deficiencies.
+    Colorblind,
+    /// Few saturated colors far apart in brightness.
+    HighContrast,
+}
+
+impl Palette {
+    pub const ALL: [Palette; 3] = [Palette::Rainbow, Palette::Colorblind, Palette::HighContrast];
+    const TEAMS: usize = 8;
+
+    const COLORBLIND: [Color; Self::TEAMS] = [
+        Color::srgb(0.9, 0.62, 0.),
+        Color::srgb(0.34, 0.71, 0.91),
+        Color::srgb(0., 0.62, 0.45),
+        Color::srgb(0.94, 0.89, 0.26),
+        Color::srgb(0., 0.45, 0.7),
+        Color::srgb(0.84, 0.37, 0.),
+        Color::srgb(0.8, 0.47, 0.65),
+        Color::srgb(0.73, 0.73, 0.73),
+    ];
+
+    const HIGH_CONTRAST: [Color; Self::TEAMS] = [
+        Color::srgb(1., 0.2, 0.2),
+        Color::srgb(0.2, 0.4, 1.),
+        Color::srgb(1., 1., 0.2),
+        Color::srgb(1., 1., 1.),
+        Color::srgb(1., 0.2, 1.),
+        Color::srgb(0.2, 1., 1.),
+        Color::srgb(0.2, 1., 0.2),
+        Color::srgb(1., 0.6, 0.),
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Palette::Rainbow => "rainbow",
+            Palette::Colorblind => "colorblind",
+            Palette::HighContrast => "high contrast",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            Palette::Rainbow => Palette::Colorblind,
+            Palette::Colorblind => Palette::HighContrast,
+            Palette::HighContrast => Palette::Rainbow,
+        }
+    }
+
+    pub fn color(&self, team: usize) -> Color {
+        let team = team % Self::TEAMS;
+        match self {
+            Palette::Rainbow => Color::hsl(360. * team as f32 / Self::TEAMS as f32, 1., 0.5),
+            Palette::Colorblind => Self::COLORBLIND[team],
+            Palette::HighContrast => Self::HIGH_CONTRAST[team],
+        }
+    }
+}
+
+/// Team colors chosen by the user, everything that paints a team should ask this resource.
+#[derive(Resource, Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TeamColors {
+    pub palette: Palette,
+    pub overrides: Vec<(usize, [f32; 3])>, // team and its sRGB color
+}
+
+impl TeamColors {
+    pub fn color(&self, team: usize) -> Color {
+        match self.overrides.iter().find(|(t, _)| *t == team) {
+            Some((_, [r, g, b])) => Color::srgb(*r, *g, *b),
+            None => self.palette.color(team),
+        }
+    }
+
+    /// Color in the format used by the solver particles.
+    pub fn particle_color(&self, team: usize) -> Vec4 {
+        self.color(team).to_srgba().to_vec4()
+    }
+}
@@ -1,22 +1,27 @@
 
 
-use bevy::{math::{vec2, Vec2, Vec4}, render::render_resource::{BufferAddress, VertexAttribute, VertexBufferLayout, VertexStepMode}};
+use bevy::{math::{vec2, vec4, Vec2, Vec4}, render::render_resource::{BufferAddress, VertexAttribute, VertexBufferLayout, VertexStepMode}};
 use wgpu::vertex_attr_array;
 
 use super::vertex::Vertex;
-use solver::particle::Particle;
+use solver::particle::{Particle, BURN_TEMPERATURE};
 
-#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, Debug)]
+const GLOW_COLOR: Vec4 = vec4(1., 0.45, 0.05, 1.);
+const POSITION_EPSILON: f32 = 0.01; // world units a particle moves before it's uploaded again
+
+#[derive(Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable, Debug)]
 #[repr(C)]
 pub struct Raw {
     size: f32,
     pos: Vec2,
     texture: u32, 
     color: Vec4,
+    tick_pos: Vec2, // before the last tick, the shader blends it with `pos`
+    _padding: [f32; 2], // keeps the struct free of implicit padding
 }
 
 impl Raw {
-    const ATTRIBS: [VertexAttribute; 4] = vertex_attr_array![
+    const ATTRIBS: [VertexAttribute; 5] = vertex_attr_array![
         // size
         2 => Float32,
         // position
@@ -25,6 +30,8 @@ impl Raw {
         4 => Uint32,
         // color
         5 => Float32x4,
+        // position before the last tick
+        6 => Float32x2,
     ];
 
     pub fn desc() -> VertexBufferLayout {
@@ -38,14 +45,33 @@ impl Raw {
 
 impl Raw {
     pub fn from_particle(particle: &Particle) -> Raw {
+        // hot particles glow towards orange
+        let glow = (particle.temperature / BURN_TEMPERATURE).clamp(0., 1.);
         Raw {
-            size: particle.radius,
+            // the shader draws a negative size as a soft fluid blob
+            size: if particle.is_fluid() { -particle.radius } else { particle.radius },
             pos: particle.pos,
             texture: particle.texture,
-            color: particle.color,
+            color: particle.color.lerp(GLOW_COLOR, glow * 0.8),
+            tick_pos: particle.pos,
+            _padding: [0.; 2],
         }
     }
 
+    /// Whether the particle is drawn the same as the uploaded one, its positions may differ by less than the epsilon.
+    pub fn same(&self, uploaded: &Raw) -> bool {
+        self.size == uploaded.size
+            && self.texture == uploaded.texture
+            && self.color == uploaded.color
+            && self.pos.abs_diff_eq(uploaded.pos, POSITION_EPSILON)
+            && self.tick_pos.abs_diff_eq(uploaded.tick_pos, POSITION_EPSILON)
+    }
+
+    /// Draws the particle on its way from `pos`, where it was before the last tick.
+    pub fn moved_from(self, pos: Vec2) -> Raw {
+        Raw { tick_pos: pos, ..self }
+    }
+
     pub const fn vertices() -> [Vertex; 4] {
         [
             Vertex {
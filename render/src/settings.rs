@@ -0,0 +1,367 @@
+use bevy::{
+    input::gamepad::{GamepadAxisType, GamepadButtonType},
+    prelude::*,
+};
+use common::config;
+use serde::{Deserialize, Serialize};
+
+use crate::trail::TrailSettings;
+
+const SETTINGS_FILE: &str = "settings.ron";
+pub const VOLUME_PRESETS: [f32; 5] = [1., 0.75, 0.5, 0.25, 0.];
+pub const SENSITIVITY_PRESETS: [f32; 4] = [1., 1.5, 2., 0.5];
+
+/// Keys of the tank and of the camera following it. Holding the aim key speeds the camera up.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameKeys {
+    pub drive_left: KeyCode,
+    pub drive_right: KeyCode,
+    pub tilt_left: KeyCode,
+    pub tilt_right: KeyCode,
+    pub gear_up: KeyCode,
+    pub gear_down: KeyCode,
+    pub dash: KeyCode,
+    pub shield: KeyCode,
+    pub aim: KeyCode, // held to point the muzzle at the cursor
+    pub camera_left: KeyCode,
+    pub camera_right: KeyCode,
+    pub camera_up: KeyCode,
+    pub camera_down: KeyCode,
+    pub follow: KeyCode, // toggles the camera chasing the tank
+    pub projectiles: Vec<KeyCode>, // in the order of the projectiles
+}
+
+impl Default for GameKeys {
+    fn default() -> Self {
+        Self {
+            drive_left: KeyCode::KeyA,
+            drive_right: KeyCode::KeyD,
+            tilt_left: KeyCode::KeyQ,
+            tilt_right: KeyCode::KeyE,
+            gear_up: KeyCode::KeyW,
+            gear_down: KeyCode::KeyS,
+            dash: KeyCode::Space,
+            shield: KeyCode::KeyR,
+            aim: KeyCode::ShiftLeft,
+            camera_left: KeyCode::ArrowLeft,
+            camera_right: KeyCode::ArrowRight,
+            camera_up: KeyCode::ArrowUp,
+            camera_down: KeyCode::ArrowDown,
+            follow: KeyCode::KeyF,
+            projectiles: vec![
+                KeyCode::Digit1,
+                KeyCode::Digit2,
+                KeyCode::Digit3,
+                KeyCode::Digit4,
+                KeyCode::Digit5,
+                KeyCode::Digit6,
+            ],
+        }
+    }
+}
+
+impl GameKeys {
+    /// Every single key binding with its name, the projectiles are left out.
+    pub fn actions(&self) -> [(&'static str, KeyCode); 14] {
+        [
+            ("drive_left", self.drive_left),
+            ("drive_right", self.drive_right),
+            ("tilt_left", self.tilt_left),
+            ("tilt_right", self.tilt_right),
+            ("gear_up", self.gear_up),
+            ("gear_down", self.gear_down),
+            ("dash", self.dash),
+            ("shield", self.shield),
+            ("aim", self.aim),
+            ("camera_left", self.camera_left),
+            ("camera_right", self.camera_right),
+            ("camera_up", self.camera_up),
+            ("camera_down", self.camera_down),
+            ("follow", self.follow),
+        ]
+    }
+
+    /// Binds the key to the action named as in [`GameKeys::actions`], false for an unknown action.
+    pub fn bind(&mut self, action: &str, key: KeyCode) -> bool {
+        let binding = match action {
+            "drive_left" => &mut self.drive_left,
+            "drive_right" => &mut self.drive_right,
+            "tilt_left" => &mut self.tilt_left,
+            "tilt_right" => &mut self.tilt_right,
+            "gear_up" => &mut self.gear_up,
+            "gear_down" => &mut self.gear_down,
+            "dash" => &mut self.dash,
+            "shield" => &mut self.shield,
+            "aim" => &mut self.aim,
+            "camera_left" => &mut self.camera_left,
+            "camera_right" => &mut self.camera_right,
+            "camera_up" => &mut self.camera_up,
+            "camera_down" => &mut self.camera_down,
+            "follow" => &mut self.follow,
+            _ => return false,
+        };
+        *binding = key;
+        true
+    }
+}
+
+/// Keys panning the free camera of spectators and of the editor. Holding `fast` speeds it up.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraKeys {
+    pub left: KeyCode,
+    pub right: KeyCode,
+    pub up: KeyCode,
+    pub down: KeyCode,
+    pub fast: KeyCode,
+}
+
+impl Default for CameraKeys {
+    fn default() -> Self {
+        Self {
+            left: KeyCode::KeyA,
+            right: KeyCode::KeyD,
+            up: KeyCode::KeyW,
+            down: KeyCode::KeyS,
+            fast: KeyCode::ShiftLeft,
+        }
+    }
+}
+
+/// Keys of the map editor. The modifiers stay the same: Alt edits the layer, Ctrl the boundary and the pickups,
+/// Shift the flags and the selection.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EditorKeys {
+    pub previous_layer: KeyCode,
+    pub next_layer: KeyCode,
+    pub show_layer: KeyCode,
+    pub remove_layer: KeyCode,
+    pub fixed: KeyCode,       // with Alt
+    pub water: KeyCode,       // with Alt
+    pub wood: KeyCode,        // with Alt
+    pub triangulate: KeyCode, // with Alt
+    pub unlink: KeyCode,      // with Alt
+    pub bake: KeyCode,
+    pub preview: KeyCode,
+    pub simulate: KeyCode,
+    pub boundary: KeyCode, // with Ctrl
+    pub save: KeyCode,     // with Ctrl
+    pub export: KeyCode,   // with Ctrl, the layout as a vehicle
+    pub hill: KeyCode,
+    pub field: KeyCode,  // force fields, pressed at both corners
+    pub hazard: KeyCode, // hazards, the same way
+    pub teams: Vec<KeyCode>, // spawns, flags and pickups of the teams in order
+    pub rotate: KeyCode,
+    pub mirror: KeyCode,
+    pub move_selection: KeyCode,
+    pub duplicate: KeyCode,
+    pub delete: KeyCode,
+}
+
+impl Default for EditorKeys {
+    fn default() -> Self {
+        Self {
+            previous_layer: KeyCode::ArrowLeft,
+            next_layer: KeyCode::ArrowRight,
+            show_layer: KeyCode::ArrowDown,
+            remove_layer: KeyCode::Delete,
+            fixed: KeyCode::KeyF,
+            water: KeyCode::KeyW,
+            wood: KeyCode::KeyO,
+            triangulate: KeyCode::KeyT,
+            unlink: KeyCode::Backspace,
+            bake: KeyCode::Enter,
+            preview: KeyCode::Tab,
+            simulate: KeyCode::Space,
+            boundary: KeyCode::KeyB,
+            save: KeyCode::KeyS,
+            export: KeyCode::KeyE,
+            hill: KeyCode::KeyH,
+            field: KeyCode::KeyG,
+            hazard: KeyCode::KeyJ,
+            teams: vec![
+                KeyCode::Digit1,
+                KeyCode::Digit2,
+                KeyCode::Digit3,
+                KeyCode::Digit4,
+                KeyCode::Digit5,
+                KeyCode::Digit6,
+                KeyCode::Digit7,
+                KeyCode::Digit8,
+            ],
+            rotate: KeyCode::KeyR,
+            mirror: KeyCode::KeyF,
+            move_selection: KeyCode::KeyM,
+            duplicate: KeyCode::KeyC,
+            delete: KeyCode::KeyX,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Stick {
+    Left,
+    Right,
+}
+
+impl Stick {
+    pub fn axes(&self) -> (GamepadAxisType, GamepadAxisType) {
+        match self {
+            Self::Left => (GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY),
+            Self::Right => (GamepadAxisType::RightStickX, GamepadAxisType::RightStickY),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PadButton {
+    LeftTrigger,
+    RightTrigger,
+    LeftBumper,
+    RightBumper,
+    South,
+    East,
+    North,
+    West,
+}
+
+impl PadButton {
+    pub fn button_type(&self) -> GamepadButtonType {
+        match self {
+            Self::LeftTrigger => GamepadButtonType::LeftTrigger2,
+            Self::RightTrigger => GamepadButtonType::RightTrigger2,
+            Self::LeftBumper => GamepadButtonType::LeftTrigger,
+            Self::RightBumper => GamepadButtonType::RightTrigger,
+            Self::South => GamepadButtonType::South,
+            Self::East => GamepadButtonType::East,
+            Self::North => GamepadButtonType::North,
+            Self::West => GamepadButtonType::West,
+        }
+    }
+}
+
+/// Gamepad controls of the tank, the x axis of the `drive` stick moves it and the `aim` stick points the muzzle.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GamepadBindings {
+    pub drive: Stick,
+    pub aim: Stick,
+    pub fire: PadButton,
+    pub dash: PadButton,
+    pub shield: PadButton,
+    pub gear_up: PadButton,
+    pub gear_down: PadButton,
+    pub next_projectile: PadButton,
+    pub dead_zone: f32, // stick deflection that's ignored
+    pub aim_distance: f32, // from the tank to the point the full deflection aims at
+}
+
+impl Default for GamepadBindings {
+    fn default() -> Self {
+        Self {
+            drive: Stick::Left,
+            aim: Stick::Right,
+            fire: PadButton::RightTrigger,
+            dash: PadButton::LeftTrigger,
+            shield: PadButton::West,
+            gear_up: PadButton::RightBumper,
+            gear_down: PadButton::LeftBumper,
+            next_projectile: PadButton::North,
+            dead_zone: 0.2,
+            aim_distance: 50.,
+        }
+    }
+}
+
+/// Controls and audio shared by the game and the editor, kept in the platform config directory.
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub game: GameKeys,
+    pub camera: CameraKeys,
+    pub editor: EditorKeys,
+    pub gamepad: GamepadBindings,
+    pub volume: f32, // of every sound, from 0 to 1
+    pub camera_sensitivity: f32, // multiplies the panning and the zoom of every camera
+    pub trails: TrailSettings,
+    pub debug_overlay: KeyCode, // toggles the solver overlay, in the game and in the editor
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            game: GameKeys::default(),
+            camera: CameraKeys::default(),
+            editor: EditorKeys::default(),
+            gamepad: GamepadBindings::default(),
+            volume: 1.,
+            camera_sensitivity: 1.,
+            trails: TrailSettings::default(),
+            debug_overlay: KeyCode::F3,
+        }
+    }
+}
+
+impl Settings {
+    /// Falls back to the defaults if the file is missing or broken.
+    pub fn load() -> Self {
+        config::load(SETTINGS_FILE)
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        config::save(SETTINGS_FILE, self)
+    }
+
+    /// Volume preset after the current one, a volume edited by hand goes back to the first preset.
+    pub fn next_volume(&self) -> f32 {
+        let current = VOLUME_PRESETS.iter().position(|volume| *volume == self.volume);
+        VOLUME_PRESETS[current.map_or(0, |i| (i + 1) % VOLUME_PRESETS.len())]
+    }
+
+    /// Sensitivity preset after the current one, a sensitivity edited by hand goes back to the first preset.
+    pub fn next_sensitivity(&self) -> f32 {
+        let current = SENSITIVITY_PRESETS.iter().position(|s| *s == self.camera_sensitivity);
+        SENSITIVITY_PRESETS[current.map_or(0, |i| (i + 1) % SENSITIVITY_PRESETS.len())]
+    }
+
+    /// Scale of the camera after the wheel turned by `scroll`.
+    pub fn zoom(&self, scale: f32, scroll: f32) -> f32 {
+        scale * f32::powf(1.25, scroll * self.camera_sensitivity)
+    }
+}
+
+/// Short name of the key for the settings screen, e.g. `A` for `KeyA` and `1` for `Digit1`.
+pub fn key_name(key: KeyCode) -> String {
+    let name = format!("{key:?}");
+    match name.strip_prefix("Key").or_else(|| name.strip_prefix("Digit")) {
+        Some(short) if !short.is_empty() => short.to_string(),
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_test() {
+        let mut settings = Settings::default();
+        assert!(settings.game.bind("dash", KeyCode::KeyF));
+        assert!(!settings.game.bind("fly", KeyCode::KeyF));
+        assert_eq!(settings.game.dash, KeyCode::KeyF);
+
+        // missing fields keep their defaults
+        let parsed: Settings = ron::from_str("(volume: 0.5, game: (dash: KeyF))").unwrap();
+        assert_eq!(parsed.volume, 0.5);
+        assert_eq!(parsed.game, settings.game);
+        assert_eq!(parsed.editor, EditorKeys::default());
+        let saved = ron::ser::to_string(&settings).unwrap();
+        assert_eq!(ron::from_str::<Settings>(&saved).unwrap(), settings);
+
+        assert_eq!(key_name(KeyCode::KeyA), "A");
+        assert_eq!(key_name(KeyCode::Digit1), "1");
+        assert_eq!(key_name(KeyCode::ShiftLeft), "ShiftLeft");
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy::{ecs::query::QueryItem, prelude::*, render::extract_component::ExtractComponent};
+use serde::{Deserialize, Serialize};
+use solver::particle::{Kind, Particle};
+
+use crate::{link, settings::Settings, RenderedSimulation};
+
+const TELEPORT_DISTANCE: f32 = 20.; // a particle that jumps farther took the index of a removed one
+const HEAD_ALPHA: f32 = 0.6; // of the segment behind the particle, the last one fades out
+
+/// Streak left behind a fast particle.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TrailStyle {
+    pub length: usize,  // positions kept, one per tick the particle is seen at a new one
+    pub min_speed: f32, // world units per tick, slower particles let their trail shrink away
+    pub width: f32,     // part of the particle's diameter
+}
+
+/// Trails of the particles by kind, `None` leaves a kind without one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrailSettings {
+    pub enabled: bool,
+    pub incendiary: Option<TrailStyle>,
+    pub explosive: Option<TrailStyle>,
+    pub projectile: Option<TrailStyle>, // anything fired by a tank that has no style of its own kind
+    pub other: Option<TrailStyle>,      // debris and whatever else flies fast enough
+}
+
+impl Default for TrailSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            incendiary: Some(TrailStyle { length: 10, min_speed: 0.3, width: 0.8 }),
+            explosive: Some(TrailStyle { length: 8, min_speed: 0.5, width: 0.6 }),
+            projectile: Some(TrailStyle { length: 6, min_speed: 0.8, width: 0.5 }),
+            other: None,
+        }
+    }
+}
+
+impl TrailSettings {
+    pub fn style(&self, particle: &Particle) -> Option<TrailStyle> {
+        if particle.kind.hidden() {
+            return None;
+        }
+        match particle.kind {
+            Kind::Incendiary(_) => self.incendiary,
+            Kind::Explosive(_) => self.explosive,
+            _ if particle.projectile => self.projectile,
+            _ => self.other,
+        }
+    }
+}
+
+/// Last positions of a particle, the newest first.
+#[derive(Debug, Clone, Default)]
+pub struct Trail {
+    pub points: VecDeque<Vec2>,
+    pub width: f32,
+}
+
+/// Trails of a rendered simulation by particle index, kept by the main world between frames.
+#[derive(Component, Debug, Clone, Default)]
+pub struct Trails(pub HashMap<usize, Trail>);
+
+impl Trails {
+    /// Records the particles seen at a new position, trails of the ones that slowed down lose their oldest point.
+    pub fn update(&mut self, particles: &[Particle], settings: &TrailSettings) {
+        self.0.retain(|i, _| *i < particles.len());
+        for (i, particle) in particles.iter().enumerate() {
+            let style = settings.style(particle);
+            let fast = style.filter(|style| particle.velocity().length() >= style.min_speed);
+            match fast {
+                Some(style) => {
+                    let trail = self.0.entry(i).or_default();
+                    if trail.points.front().is_some_and(|front| front.distance(particle.pos) > TELEPORT_DISTANCE) {
+                        trail.points.clear();
+                    }
+                    if trail.points.front() != Some(&particle.pos) {
+                        trail.points.push_front(particle.pos);
+                    }
+                    trail.points.truncate(style.length);
+                    trail.width = style.width * particle.radius * 2.;
+                }
+                None => {
+                    let Some(trail) = self.0.get_mut(&i) else {
+                        continue;
+                    };
+                    trail.points.pop_back();
+                    if style.is_none() || trail.points.len() < 2 {
+                        self.0.remove(&i);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Segments between the points of every trail, fading out toward its end.
+    pub fn segments(&self, particles: &[Particle], visible: impl Fn(Vec2) -> bool) -> Vec<link::Raw> {
+        let mut segments = vec![];
+        for (i, trail) in &self.0 {
+            let Some(particle) = particles.get(*i) else {
+                continue;
+            };
+            if !trail.points.front().is_some_and(|head| visible(*head)) {
+                continue;
+            }
+            let count = trail.points.len().saturating_sub(1);
+            for (j, (start, end)) in trail.points.iter().zip(trail.points.iter().skip(1)).enumerate() {
+                let fade = 1. - j as f32 / count as f32;
+                let color = particle.color.truncate().extend(particle.color.w * HEAD_ALPHA * fade);
+                segments.push(link::Raw::segment(*start, *end, trail.width * fade, color));
+            }
+        }
+        segments
+    }
+}
+
+impl ExtractComponent for Trails {
+    type QueryData = &'static Trails;
+    type QueryFilter = ();
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::QueryData>) -> Option<Self> {
+        Some(item.clone())
+    }
+}
+
+/// Follows the fast particles of every rendered simulation, clearing the trails once they're turned off.
+pub(crate) fn update_trails(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mut simulations: Query<(Entity, &RenderedSimulation, Option<&mut Trails>)>,
+) {
+    for (entity, simulation, trails) in &mut simulations {
+        match (settings.trails.enabled, trails) {
+            (true, Some(mut trails)) => trails.update(&simulation.0.particles, &settings.trails),
+            (true, None) => {
+                let mut trails = Trails::default();
+                trails.update(&simulation.0.particles, &settings.trails);
+                commands.entity(entity).insert(trails);
+            }
+            (false, Some(_)) => {
+                commands.entity(entity).remove::<Trails>();
+            }
+            (false, None) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trails_test() {
+        let settings = TrailSettings::default();
+        let mut particle = Particle::default();
+        particle.projectile = true;
+        let mut particles = vec![particle];
+        let mut trails = Trails::default();
+
+        // a particle at rest leaves no trail
+        trails.update(&particles, &settings);
+        assert!(trails.0.is_empty());
+
+        let length = settings.projectile.unwrap().length;
+        for step in 1..=length + 2 {
+            particles[0].pos_old = particles[0].pos;
+            particles[0].pos = Vec2::new(step as f32, 0.);
+            trails.update(&particles, &settings);
+        }
+        // only the last positions are kept, the newest first
+        let points = &trails.0[&0].points;
+        assert_eq!(points.len(), length);
+        assert_eq!(points.front(), Some(&Vec2::new((length + 2) as f32, 0.)));
+        assert_eq!(trails.segments(&particles, |_| true).len(), length - 1);
+        assert!(trails.segments(&particles, |_| false).is_empty());
+
+        // a stopped particle's trail shrinks, then goes away
+        particles[0].pos_old = particles[0].pos;
+        trails.update(&particles, &settings);
+        assert_eq!(trails.0[&0].points.len(), length - 1);
+        for _ in 0..length {
+            trails.update(&particles, &settings);
+        }
+        assert!(trails.0.is_empty());
+    }
+}
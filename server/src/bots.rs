@@ -0,0 +1,157 @@
+use common::{
+    chassis::Garage,
+    mode::{GameMode, MatchSettings},
+};
+use map_editor::map::{Map as GameMap, Spawn};
+use packet_tools::game_packets::{GamePacket, IndexedGamePacket};
+use render::palette::TeamColors;
+use session::{
+    controller::{Controller, Player},
+    GameSession,
+};
+
+const ENGAGE_DISTANCE: f32 = 80.; // the bot stops driving once the target is this close
+const FIRE_DISTANCE: f32 = 200.; // and starts shooting at this distance
+const AIM_LIFT: f32 = 0.15; // aims above the target by this much of the distance, the shells drop on the way
+
+/// Computer controlled player, it takes a spawn like the others but has no connection.
+#[derive(Debug, Clone)]
+pub struct Bot {
+    pub id: u8,
+    pub name: String,
+}
+
+impl Bot {
+    pub fn new(id: u8, name: String) -> Self {
+        Self { id, name }
+    }
+}
+
+/// Free spawn for a bot, on the given team or on the one with the fewest players, `None` if every spawn is taken.
+pub fn pick_slot(spawns: &[Spawn], taken: &[u8], team: Option<usize>) -> Option<u8> {
+    let players_in = |team: usize| {
+        taken
+            .iter()
+            .filter(|id| spawns.get(**id as usize).is_some_and(|spawn| spawn.team == team))
+            .count()
+    };
+    (0..spawns.len().min(u8::MAX as usize))
+        .filter(|id| !taken.contains(&(*id as u8)))
+        .filter(|id| team.map_or(true, |team| spawns[*id].team == team))
+        .min_by_key(|id| players_in(spawns[*id].team))
+        .map(|id| id as u8)
+}
+
+/// What a bot keeps between its decisions.
+struct Brain {
+    id: u8,
+    driving: f32,
+    reloaded_at: u128, // tick the bot may fire again
+}
+
+/// The bots of a match. They follow the same ticks as the clients and answer them with their inputs.
+pub struct BotPlayers {
+    session: GameSession,
+    brains: Vec<Brain>,
+}
+
+impl BotPlayers {
+    /// `players` are everyone with a tank, the bots among them. The bots drive the tank, whatever the others chose.
+    pub fn new(
+        bots: &[Bot],
+        map: &GameMap,
+        players: &[(u8, String)],
+        garage: &Garage,
+        settings: &MatchSettings,
+    ) -> Self {
+        // the bots share a session, so it follows the match as a spectator
+        let session =
+            GameSession::new(u8::MAX, "bots".to_string(), map, players, garage, settings, &TeamColors::default());
+        let brains = bots
+            .iter()
+            .map(|bot| Brain { id: bot.id, driving: 0., reloaded_at: 0 })
+            .collect();
+        Self { session, brains }
+    }
+
+    /// Simulates the ticks and returns the inputs of the bots for the next ones.
+    pub fn play(&mut self, ticks: &[Vec<IndexedGamePacket>]) -> Vec<IndexedGamePacket> {
+        for packets in ticks {
+            self.session.tick(packets);
+        }
+        let mut inputs = vec![];
+        for brain in self.brains.iter_mut() {
+            let packets = think(brain, &self.session);
+            inputs.extend(packets.into_iter().map(|packet| IndexedGamePacket::new(brain.id, packet)));
+        }
+        inputs
+    }
+}
+
+/// Drives toward the nearest enemy, aims at it and fires whenever the gun is loaded and the enemy is in range.
+fn think(brain: &mut Brain, session: &GameSession) -> Vec<GamePacket> {
+    let GameSession { controller, solver } = session;
+    let Some(bot) = controller.get_player(brain.id) else {
+        return vec![];
+    };
+    // the next tank starts with its motors off
+    if !Controller::player_alive(bot, solver) {
+        brain.driving = 0.;
+        return vec![];
+    }
+
+    let pos = Controller::get_player_pos(bot, solver);
+    let everyone_is_enemy = controller.mode.mode == GameMode::Deathmatch;
+    let target = controller
+        .players
+        .iter()
+        .filter(|p| p.id != bot.id && (everyone_is_enemy || p.team != bot.team))
+        .filter(|p| Controller::player_alive(p, solver))
+        .map(|p| Controller::get_player_pos(p, solver))
+        .min_by(|a, b| a.distance(pos).total_cmp(&b.distance(pos)));
+
+    let mut packets = vec![];
+    let driving = match target {
+        Some(target) if target.distance(pos) > ENGAGE_DISTANCE => match target.x < pos.x {
+            true => 1.,
+            false => -1.,
+        },
+        _ => 0.,
+    };
+    if driving != brain.driving {
+        packets.extend(bot.drive(driving));
+        brain.driving = driving;
+    }
+
+    let Some(target) = target else {
+        return packets;
+    };
+    let distance = target.distance(pos);
+    let mut aim = target;
+    aim.y += distance * AIM_LIFT;
+    packets.push(GamePacket::Muzzle(aim));
+    if distance < FIRE_DISTANCE && controller.tick >= brain.reloaded_at {
+        // the tank starts with the first projectile the rules allow
+        packets.push(GamePacket::Fire(bot.projectile));
+        brain.reloaded_at = controller.tick + Player::reload_ticks(bot.projectile) as u128;
+    }
+    packets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pick_slot_test() {
+        let spawn = |team| Spawn { pos: Default::default(), team };
+        let spawns = [spawn(0), spawn(1), spawn(0), spawn(1)];
+
+        // the team with fewer players gets the bot
+        assert_eq!(pick_slot(&spawns, &[0], None), Some(1));
+        assert_eq!(pick_slot(&spawns, &[0, 1], None), Some(2));
+        assert_eq!(pick_slot(&spawns, &[0, 1], Some(1)), Some(3));
+        assert_eq!(pick_slot(&spawns, &[0, 2], Some(0)), None);
+        assert_eq!(pick_slot(&spawns, &[0, 1, 2, 3], None), None);
+    }
+}
@@ -1,12 +1,16 @@
 #[derive(Debug)]
 pub enum ServerError {
     AuthenticationError,
+    Banned(std::net::IpAddr),
+    WrongPassword(std::net::IpAddr),
 }
 
 impl std::fmt::Display for ServerError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::AuthenticationError => write!(f, "Client-side authentication error"),
+            Self::Banned(ip) => write!(f, "{ip} is banned"),
+            Self::WrongPassword(ip) => write!(f, "{ip} gave a wrong password"),
         }
     }
 }
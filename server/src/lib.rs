@@ -1,115 +1,462 @@
+pub mod bots;
 pub mod error;
 
 pub mod lobby {
-    use tokio::net::TcpStream;
+    use std::{collections::HashMap, net::IpAddr, path::PathBuf, time::Duration};
+
+    use common::{chassis::Chassis, config, mode::MatchSettings, ATLAS_FILE, BACKGROUND_FILE, MAP_FILE, THUMBNAIL_FILE};
+    use map_editor::map::Map as GameMap;
+    use session::controller::model::RawPlayerModel;
+    use packet_tools::{
+        client_packets::ClientPacket, compression, files, game_packets::PACKET_SIZE, server_packets::ServerPacket,
+        UnsizedPacketRead, UnsizedPacketWrite, INPUTS_END_MARKER,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        time::{timeout_at, Instant},
+    };
+    use tracing::{info, warn};
 
     pub struct Player {
         pub id: u8,
         pub name: String,
+        pub session: u64, // token the player reconnects with
+        pub spectator: bool, // gets the ticks but has no tank
+        pub compressed: bool, // the map files and the game stream are LZ4 compressed for the player
+        pub chassis: Chassis, // picked in the lobby, read with `read_chassis` when the game starts
+        pub vehicle: Option<Vec<u8>>, // uploaded for `Chassis::Custom`, kept only if it loads
         pub stream: TcpStream,
     }
 
     impl Player {
-        pub fn new(id: u8, name: String, stream: TcpStream) -> Self {
-            Self { id, name, stream }
+        pub fn new(id: u8, name: String, session: u64, spectator: bool, compressed: bool, stream: TcpStream) -> Self {
+            Self { id, name, session, spectator, compressed, chassis: Chassis::default(), vehicle: None, stream }
+        }
+
+        /// Chassis the player drives, a custom one without a vehicle that loads falls back to the tank.
+        pub fn driven(&self) -> Chassis {
+            match (self.chassis, &self.vehicle) {
+                (Chassis::Custom, None) => Chassis::default(),
+                (chassis, _) => chassis,
+            }
+        }
+
+        pub fn ip(&self) -> Option<IpAddr> {
+            self.stream.peer_addr().ok().map(|addr| addr.ip())
         }
     }
 
     pub type Lobby = Vec<Player>;
+
+    /// Removes the player from the lobby, the reason is shown on its error screen.
+    pub async fn kick(lobby: &mut Lobby, id: u8, reason: &str) -> Option<Player> {
+        let i = lobby.iter().position(|p| p.id == id)?;
+        let mut player = lobby.remove(i);
+        let _ = player.stream.write_packet(&ServerPacket::Kicked(reason.to_string())).await;
+        Some(player)
+    }
+
+    /// Kicks every player connected from the address.
+    pub async fn kick_ip(lobby: &mut Lobby, ip: IpAddr, reason: &str) -> Vec<Player> {
+        let ids: Vec<u8> = lobby.iter().filter(|p| p.ip() == Some(ip)).map(|p| p.id).collect();
+        let mut kicked = vec![];
+        for id in ids {
+            kicked.extend(kick(lobby, id, reason).await);
+        }
+        kicked
+    }
+
+    /// The name is sent to everyone with the other players once the game starts.
+    pub fn rename(lobby: &mut Lobby, id: u8, name: String) -> bool {
+        match lobby.iter_mut().find(|p| p.id == id) {
+            Some(player) => {
+                player.name = name;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Size of the pieces map files are sent in, so a big texture neither stalls the lobby nor needs one huge read.
+    pub const FILE_CHUNK: usize = 64 * 1024;
+
+    /// Tells the player the map with the checksums of its files and sends the files the player lacks or has in
+    /// another version, returns whether any were sent. The files are compressed for a player that accepted compression.
+    /// The map file goes last, so a download that broke off leaves no map that looks complete, and resumes where it stopped.
+    pub async fn send_map(socket: &mut TcpStream, map: &GameMap, compressed: bool) -> anyhow::Result<bool> {
+        let maps_dir = config::maps_dir();
+        let mut paths: Vec<(String, PathBuf)> = map
+            .texture_paths(&maps_dir)
+            .into_iter()
+            .map(|path| (path.file_name().unwrap().to_owned().into_string().unwrap(), path))
+            .collect();
+        if let Some(background_path) = map.background_path(&maps_dir) {
+            paths.push((BACKGROUND_FILE.to_string(), background_path));
+        }
+        paths.extend(
+            map.parallax_paths(&maps_dir)
+                .into_iter()
+                .map(|path| (path.file_name().unwrap().to_owned().into_string().unwrap(), path)),
+        );
+        if let Some(atlas_path) = map.atlas_path(&maps_dir) {
+            paths.push((ATLAS_FILE.to_string(), atlas_path));
+        }
+        // maps saved before thumbnails existed have none
+        let thumbnail_path = GameMap::get_thumbnail_path(&map.name, &maps_dir);
+        if thumbnail_path.is_file() {
+            paths.push((THUMBNAIL_FILE.to_string(), thumbnail_path));
+        }
+        paths.push((MAP_FILE.to_string(), maps_dir.join(&map.name).join(MAP_FILE)));
+        let mut map_files = vec![];
+        for (name, path) in paths {
+            map_files.push((name, tokio::fs::read(path).await?));
+        }
+
+        let checksums = map_files.iter().map(|(name, contents)| (name.clone(), files::checksum(contents))).collect();
+        socket.write_packet(&ServerPacket::SetMap { name: map.name.clone(), files: checksums }).await?;
+        // a vote that came too late is still on the way
+        let map_packet = loop {
+            match socket.read_packet().await? {
+                ClientPacket::VoteMap(_) => continue,
+                packet => break packet,
+            }
+        };
+        let ClientPacket::RequestFiles(requested) = map_packet else {
+            return Ok(false);
+        };
+        let requested: HashMap<String, u64> = requested.into_iter().collect();
+
+        for (name, contents) in map_files {
+            let Some(&from) = requested.get(&name) else {
+                continue;
+            };
+            let contents = if compressed { compression::compress(&contents) } else { contents };
+            send_file(socket, name, &contents, from).await?;
+        }
+        Ok(true)
+    }
+
+    /// Sends the file from the offset on, a part longer than the file is sent again whole.
+    async fn send_file(socket: &mut TcpStream, name: String, contents: &[u8], from: u64) -> std::io::Result<()> {
+        let total = contents.len() as u64;
+        let mut offset = if from > total { 0 } else { from as usize };
+        loop {
+            let end = (offset + FILE_CHUNK).min(contents.len());
+            let chunk = ServerPacket::FileChunk {
+                name: name.clone(),
+                offset: offset as u64,
+                total,
+                data: contents[offset..end].to_vec(),
+            };
+            socket.write_packet(&chunk).await?;
+            // an empty file still takes a chunk to be created
+            if end == contents.len() {
+                return Ok(());
+            }
+            offset = end;
+        }
+    }
+
+    /// Ends the game stream with the maps to vote for and announces the most voted one, ties go to the earlier map.
+    /// Without any votes the `fallback` map is played. Players whose stream closed leave the lobby.
+    pub async fn vote_map(lobby: &mut Lobby, maps: &[String], fallback: usize, time: Duration) -> usize {
+        let offer = packet_tools::serialize_server_packet(&ServerPacket::MapVote(maps.to_vec()));
+        let mut left = vec![];
+        for player in lobby.iter_mut() {
+            if player.stream.write_all(&offer).await.is_err() {
+                left.push(player.id);
+            }
+        }
+
+        // the players answer at once, so their votes are read one after another until the time is up
+        let deadline = Instant::now() + time;
+        for player in lobby.iter_mut().filter(|p| !left.contains(&p.id)) {
+            if !matches!(timeout_at(deadline, skip_inputs(&mut player.stream)).await, Ok(Ok(()))) {
+                left.push(player.id);
+            }
+        }
+        let mut votes = vec![0; maps.len()];
+        for player in lobby.iter_mut().filter(|p| !left.contains(&p.id)) {
+            // only waiting may time out, a packet that started to arrive is read whole
+            let Ok(readable) = timeout_at(deadline, player.stream.readable()).await else {
+                continue;
+            };
+            let vote = match readable {
+                Ok(()) => player.stream.read_packet::<ClientPacket>().await,
+                Err(e) => Err(e),
+            };
+            match vote {
+                Ok(ClientPacket::VoteMap(map)) => match maps.iter().position(|m| *m == map) {
+                    Some(i) => votes[i] += 1,
+                    None => warn!("{} voted for {map}, which isn't offered", player.name),
+                },
+                Ok(_) => (),
+                Err(_) => left.push(player.id),
+            }
+        }
+        for player in lobby.iter().filter(|p| left.contains(&p.id)) {
+            info!("{} left after the match", player.name);
+        }
+        lobby.retain(|p| !left.contains(&p.id));
+
+        let best = votes.iter().copied().max().unwrap_or(0);
+        let chosen = match best {
+            0 => fallback.min(maps.len().saturating_sub(1)),
+            _ => votes.iter().position(|v| *v == best).unwrap(),
+        };
+        for player in lobby.iter_mut() {
+            let _ = player.stream.write_packet(&ServerPacket::MapVoteResult(maps[chosen].clone())).await;
+        }
+        chosen
+    }
+
+    /// Reads the chassis the players picked in the lobby and their custom vehicles, the ones that sent nothing
+    /// keep the last ones. Only the packets that already came are read, nobody is waited for.
+    pub async fn read_chassis(lobby: &mut Lobby) {
+        for player in lobby.iter_mut() {
+            let mut byte = [0; 1];
+            // only peeking may time out, a packet that started to arrive is read whole
+            while let Ok(Ok(peeked)) = timeout_at(Instant::now(), player.stream.peek(&mut byte)).await {
+                // a closed stream is found out once the game starts
+                if peeked == 0 {
+                    break;
+                }
+                match player.stream.read_packet::<ClientPacket>().await {
+                    Ok(ClientPacket::SelectChassis(chassis)) => player.chassis = chassis,
+                    Ok(ClientPacket::UploadVehicle(vehicle)) => match RawPlayerModel::from_bytes(&vehicle) {
+                        Ok(_) => player.vehicle = Some(vehicle),
+                        Err(e) => {
+                            warn!("Vehicle of {} is rejected: {e}", player.name);
+                            player.vehicle = None;
+                        }
+                    },
+                    Ok(_) => (),
+                    Err(e) => {
+                        warn!("Can't read the chassis of {}: {e}", player.name);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads the inputs the player sent before it saw the game end, up to the packet that ends them.
+    async fn skip_inputs(stream: &mut TcpStream) -> std::io::Result<()> {
+        let mut packet = [0; PACKET_SIZE];
+        loop {
+            stream.read_exact(&mut packet).await?;
+            if packet[0] == INPUTS_END_MARKER {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Sets up the next round in the lobby that stayed after a match, players who fail to get the map are dropped.
+    pub async fn next_round(lobby: &mut Lobby, map: &GameMap, settings: MatchSettings) {
+        let mut left = vec![];
+        for player in lobby.iter_mut() {
+            let sent = async {
+                player.stream.write_packet(&ServerPacket::MatchSettings(settings)).await?;
+                // the client's lobby starts over without compression
+                if player.compressed {
+                    player.stream.write_packet(&ServerPacket::EnableCompression).await?;
+                }
+                send_map(&mut player.stream, map, player.compressed).await
+            };
+            if let Err(e) = sent.await {
+                warn!("{} failed to get the map {}: {e}", player.name, map.name);
+                left.push(player.id);
+            }
+        }
+        lobby.retain(|p| !left.contains(&p.id));
+    }
 }
 
 pub mod server {
     use anyhow::Result;
-    use common::{BACKGROUND_FILE, MAP_FILE, RELATIVE_MAPS_PATH};
+    use common::{mode::MatchSettings, DISCOVERY_PORT};
     use crossbeam_channel::unbounded;
-    use log::{info, trace, warn};
+    use tracing::{info, info_span, trace, trace_span, warn, Instrument};
     use map_editor::map::Map as GameMap;
     use packet_tools::{
-        client_packets::ClientPacket, server_packets::ServerPacket, IndexedPacket, TimedQueue,
-        UnsizedPacketRead, UnsizedPacketWrite,
+        client_packets::ClientPacket, game_packets::{GamePacket, IndexedGamePacket}, query_packets::{QueryPacket, ServerInfo},
+        server_packets::ServerPacket, udp::{Datagram, ReliableSender, Transport, MAX_DATAGRAM},
+        IndexedPacket, Packet, TimedQueue, UnsizedPacket, UnsizedPacketRead, UnsizedPacketWrite,
     };
+    use render::palette::TeamColors;
+    use session::GameSession;
+    use common::chassis::{Chassis, Garage};
     use std::{
-        path::PathBuf,
-        sync::{atomic::AtomicBool, Arc},
-        time::Duration,
+        collections::{hash_map::RandomState, HashMap, HashSet},
+        hash::BuildHasher,
+        net::{IpAddr, SocketAddr},
+        sync::{atomic::{AtomicBool, AtomicU8}, Arc, Mutex},
+        time::{Duration, Instant},
     };
     use tokio::{
         self,
-        net::{TcpListener, ToSocketAddrs},
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream, ToSocketAddrs, UdpSocket},
+        sync::{mpsc, watch},
         task::JoinHandle,
-        time::sleep,
+        time::{sleep, timeout},
     };
 
     use crate::{
+        bots::{Bot, BotPlayers},
         error::ServerError,
-        lobby::{Lobby, Player},
+        lobby::{self, Lobby, Player},
     };
 
+    /// Where new connections come from: a listener of its own or a hub that routes them by lobby code.
+    pub enum Incoming {
+        Listener(TcpListener),
+        Routed(mpsc::UnboundedReceiver<(TcpStream, SocketAddr)>),
+    }
+
+    impl Incoming {
+        /// Next connection, `None` once the hub dropped the lobby.
+        pub async fn accept(&mut self) -> Option<(TcpStream, SocketAddr)> {
+            match self {
+                Self::Listener(listener) => loop {
+                    if let Ok(connection) = listener.accept().await {
+                        return Some(connection);
+                    }
+                },
+                Self::Routed(receiver) => receiver.recv().await,
+            }
+        }
+    }
+
     pub struct LobbyServer {
-        lobby_task: JoinHandle<Lobby>,
+        lobby_task: JoinHandle<(Lobby, Incoming)>,
         accept_players: Arc<AtomicBool>,
+        banned: Arc<Mutex<HashSet<IpAddr>>>, // refused while the lobby is open
+        compression: Arc<AtomicBool>, // offered to the clients that accept it
+        addr: SocketAddr,
     }
 
     impl LobbyServer {
-        pub async fn new<A: ToSocketAddrs>(addr: A, map: GameMap) -> Result<Self> {
+        /// Players have to give the `password` to join, if there is one.
+        pub async fn new<A: ToSocketAddrs>(
+            addr: A,
+            name: String,
+            map: GameMap,
+            settings: MatchSettings,
+            password: Option<String>,
+        ) -> Result<Self> {
             let listener = TcpListener::bind(addr).await?;
+            let addr = listener.local_addr()?;
             let accept_players = Arc::new(AtomicBool::new(true));
 
+            // answer server browser queries while the lobby is open
+            let player_count = Arc::new(AtomicU8::new(0));
+            let info = ServerInfo {
+                name,
+                port: listener.local_addr()?.port(),
+                map: map.name.clone(),
+                mode: settings.mode,
+                players: 0,
+                max_players: map.spawns.len() as u8,
+            };
+            let query_socket = UdpSocket::bind(listener.local_addr()?).await?;
+            tokio::spawn(answer_queries(query_socket, info.clone(), player_count.clone(), accept_players.clone()));
+            match UdpSocket::bind(("0.0.0.0", DISCOVERY_PORT)).await {
+                Ok(discovery_socket) => {
+                    tokio::spawn(answer_queries(discovery_socket, info, player_count.clone(), accept_players.clone()));
+                }
+                Err(e) => warn!("LAN discovery is unavailable: {e}"),
+            }
+
+            Ok(Self::open(Incoming::Listener(listener), addr, map, settings, password, accept_players, player_count))
+        }
+
+        /// Lobby fed by a hub, it doesn't answer server browser queries since the hub's port is shared.
+        pub fn hosted(incoming: Incoming, addr: SocketAddr, map: GameMap, settings: MatchSettings) -> Self {
+            Self::open(incoming, addr, map, settings, None, Arc::new(AtomicBool::new(true)), Arc::new(AtomicU8::new(0)))
+        }
+
+        fn open(
+            mut incoming: Incoming,
+            addr: SocketAddr,
+            map: GameMap,
+            settings: MatchSettings,
+            password: Option<String>,
+            accept_players: Arc<AtomicBool>,
+            player_count: Arc<AtomicU8>,
+        ) -> Self {
+            let spectator_count = Arc::new(AtomicU8::new(0));
             let map = Arc::new(map);
+            let password = Arc::new(password);
+            let banned = Arc::new(Mutex::new(HashSet::new()));
+            let banned_ips = banned.clone();
+            let compression = Arc::new(AtomicBool::new(true));
+            let compression_offered = compression.clone();
             let running = accept_players.clone();
-            let lobby_task: JoinHandle<Lobby> = tokio::spawn(async move {
-                info!(
-                    "Listening for new connections on {:?}",
-                    listener.local_addr().unwrap()
-                );
+            let lobby_task: JoinHandle<(Lobby, Incoming)> = tokio::spawn(async move {
+                info!("Listening for new connections on {addr:?}");
                 let mut connections = vec![];
                 while running.load(std::sync::atomic::Ordering::Relaxed) {
                     tokio::select! {
-                        socket = listener.accept() => {
-                            let Ok((mut socket, _)) = socket else { continue; };
+                        socket = incoming.accept() => {
+                            let Some((mut socket, addr)) = socket else { break; };
 
-                            let id = connections.len() as u8;
+                            let connection = connections.len();
                             let map = map.clone();
+                            let player_count = player_count.clone();
+                            let spectator_count = spectator_count.clone();
+                            let password = password.clone();
+                            let offered = compression_offered.load(std::sync::atomic::Ordering::Relaxed);
+                            let banned = banned_ips.lock().unwrap().contains(&addr.ip());
                             let connection_task = tokio::spawn(async move {
-                                let name_packet: ClientPacket =
-                                    socket.read_packet().await?;
-                                let ClientPacket::SetName(name) = name_packet else {
-                                    return Err(ServerError::AuthenticationError)?;
+                                if banned {
+                                    socket.write_packet(&ServerPacket::Kicked("Banned from the server".to_string())).await?;
+                                    return Err(ServerError::Banned(addr.ip()))?;
+                                }
+                                // the optional packets come before the one that joins
+                                let mut given = None;
+                                let mut accepts_compression = false;
+                                let name_packet = loop {
+                                    match socket.read_packet().await? {
+                                        ClientPacket::Password(password) => given = Some(password),
+                                        ClientPacket::AcceptCompression => accepts_compression = true,
+                                        packet => break packet,
+                                    }
                                 };
-                                socket.write_packet(&ServerPacket::SetId(id)).await?;
-                                socket.write_packet(&ServerPacket::SetMap(map.name.clone())).await?;
-                                let map_packet: ClientPacket = socket.read_packet().await?;
-                                match map_packet {
-                                    ClientPacket::RequestMap => {
-                                        let mut map_path = PathBuf::from(RELATIVE_MAPS_PATH);
-                                        map_path.push(&map.name);
-                                        map_path.push(MAP_FILE);
-                                        let map_contents = tokio::fs::read(&map_path).await?;
-                                        socket.write_packet(&ServerPacket::CreateFile {name: MAP_FILE.to_string(), contents: map_contents}).await?;
-
-                                        let texture_paths = map.texture_paths(RELATIVE_MAPS_PATH);
-                                        for texture_path in texture_paths.into_iter() {
-                                            let texture_contents = tokio::fs::read(&texture_path).await?;
-                                            let texture_name = texture_path.file_name().unwrap().to_owned().into_string().unwrap();
-                                            socket.write_packet(&ServerPacket::CreateFile {
-                                                name: texture_name,
-                                                contents: texture_contents}).await?;
-                                        }
-                                        if let Some(background_path) = map.background_path(RELATIVE_MAPS_PATH) {
-                                            let background_contents = tokio::fs::read(&background_path).await?;
-                                            socket.write_packet(&ServerPacket::CreateFile {
-                                                name: BACKGROUND_FILE.to_string(),
-                                                contents: background_contents}).await?;
-                                        }
-
-                                        info!("Map successfully sent to {name} ({})", socket.peer_addr().unwrap())
+                                if password.is_some() && *password != given {
+                                    socket.write_packet(&ServerPacket::Rejected("Wrong password".to_string())).await?;
+                                    return Err(ServerError::WrongPassword(addr.ip()))?;
+                                }
+                                // spectators count down from the last id, so they never take a spawn
+                                let (id, name, spectator) = match name_packet {
+                                    ClientPacket::SetName(name) => {
+                                        (player_count.fetch_add(1, std::sync::atomic::Ordering::Relaxed), name, false)
+                                    }
+                                    ClientPacket::JoinAsSpectator(name) => {
+                                        (u8::MAX - spectator_count.fetch_add(1, std::sync::atomic::Ordering::Relaxed), name, true)
                                     }
-                                    _ => (),
+                                    _ => return Err(ServerError::AuthenticationError)?,
+                                };
+                                // random, so other players can't take over the connection
+                                let session = RandomState::new().hash_one(id);
+                                socket.write_packet(&ServerPacket::SetId(id)).await?;
+                                socket.write_packet(&ServerPacket::SetSession(session)).await?;
+                                socket.write_packet(&ServerPacket::MatchSettings(settings)).await?;
+                                let compressed = offered && accepts_compression;
+                                if compressed {
+                                    socket.write_packet(&ServerPacket::EnableCompression).await?;
+                                }
+                                if lobby::send_map(&mut socket, &map, compressed).await? {
+                                    info!("Map successfully sent to {name} ({})", socket.peer_addr().unwrap())
                                 }
 
-                                info!("{name} joined the game from: {}", socket.peer_addr().unwrap());
-                                anyhow::Ok(Player::new(id, name, socket))
-                            });
+                                match spectator {
+                                    true => info!("{name} spectates the game from: {}", socket.peer_addr().unwrap()),
+                                    false => info!("{name} joined the game from: {}", socket.peer_addr().unwrap()),
+                                }
+                                anyhow::Ok(Player::new(id, name, session, spectator, compressed, socket))
+                            }.instrument(info_span!("handshake", connection)));
 
                             connections.push(connection_task);
                         },
@@ -123,112 +470,404 @@ pub mod server {
 
                 let mut players = vec![];
                 for task in connections.into_iter() {
-                    if let Ok(player) = task.await.unwrap() {
-                        players.push(player);
+                    match task.await.unwrap() {
+                        Ok(player) => players.push(player),
+                        Err(e) => warn!("A player failed to join: {e}"),
                     }
                 }
-                players
+                (players, incoming)
             });
 
-            Ok(Self {
+            Self {
                 lobby_task,
                 accept_players,
-            })
+                banned,
+                compression,
+                addr,
+            }
+        }
+
+        /// New connections from the address are refused, the players already in are kicked with `lobby::kick_ip`.
+        pub fn ban(&self, ip: IpAddr) {
+            self.banned.lock().unwrap().insert(ip);
+        }
+
+        /// Whether the players joining from now on get the map files and the game stream compressed, on by default.
+        pub fn set_compression(&self, on: bool) {
+            self.compression.store(on, std::sync::atomic::Ordering::Relaxed);
+        }
+
+        /// Address the lobby listens on, useful when it was bound to port 0.
+        pub fn local_addr(&self) -> SocketAddr {
+            self.addr
         }
 
         pub async fn get_lobby(self) -> Lobby {
+            self.close().await.0
+        }
+
+        /// Stops accepting new players, the connections keep coming for the players reconnecting mid-game.
+        pub async fn close(self) -> (Lobby, Incoming) {
             self.accept_players
                 .store(false, std::sync::atomic::Ordering::Relaxed);
             self.lobby_task.await.unwrap()
         }
     }
 
+    async fn answer_queries(socket: UdpSocket, info: ServerInfo, player_count: Arc<AtomicU8>, running: Arc<AtomicBool>) {
+        let mut buf = [0; 64];
+        while running.load(std::sync::atomic::Ordering::Relaxed) {
+            tokio::select! {
+                received = socket.recv_from(&mut buf) => {
+                    let Ok((len, peer)) = received else { continue; };
+                    let Some(QueryPacket::Request) = QueryPacket::parse(&buf[..len]) else { continue; };
+                    let info = ServerInfo {
+                        players: player_count.load(std::sync::atomic::Ordering::Relaxed),
+                        ..info.clone()
+                    };
+                    trace!("Answering query from {peer}");
+                    let _ = socket.send_to(&QueryPacket::Info(info).to_bytes(), peer).await;
+                },
+                _ = sleep(Duration::from_millis(100)) => {
+                    continue
+                }
+            }
+        }
+    }
+
+    /// UDP address of a player and the packets it hasn't acknowledged yet.
+    struct UdpPeer {
+        addr: Option<SocketAddr>, // unknown until the player says hello
+        sender: ReliableSender,
+        spectator: bool,
+        compressed: bool, // the player accepts compressed runs of the game stream
+        lost: bool,       // stopped acknowledging, nothing goes to it over UDP any more
+        tcp: bool,        // reconnected, the rest of the game stream goes over its new TCP stream
+    }
+
+    type UdpPeers = Arc<Mutex<HashMap<u8, UdpPeer>>>;
+
+    /// Every tick broadcast, replayed to the players that reconnect.
+    type SentTicks<const PACKET_SIZE: usize> =
+        Arc<tokio::sync::Mutex<Vec<Vec<IndexedPacket<[u8; PACKET_SIZE], PACKET_SIZE>>>>>;
+
+    const RECONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+    pub const SYNC_INTERVAL: u128 = 512; // ticks between the state syncs of a simulating server (~1 second)
+
+    /// The match simulated by the server, its state overrides the one of the clients.
+    struct Simulation {
+        session: GameSession,
+        sync_interval: Option<u128>, // only watching for the end of the match without it
+        over: bool,
+    }
+
+    impl Simulation {
+        /// Simulates the ticks and returns them serialized for the game stream, with a state sync every `sync_interval` ticks.
+        fn stream<const PACKET_SIZE: usize>(&mut self, data: &[Vec<IndexedPacket<[u8; PACKET_SIZE], PACKET_SIZE>>]) -> Vec<u8> {
+            let mut bytes = vec![];
+            for packets in data.iter() {
+                bytes.extend(packet_tools::serialize_queue(&vec![packets.clone()]));
+
+                if self.session.tick(&game_packets(packets)).is_some() {
+                    self.over = true;
+                }
+                self.log_kills();
+                if self.sync_interval.is_some_and(|interval| self.session.controller.tick % interval == 0) {
+                    let _span = trace_span!("state_sync", tick = self.session.controller.tick as u64).entered();
+                    let sync = ServerPacket::StateSync(self.session.state_sync());
+                    bytes.extend(packet_tools::serialize_server_packet(&sync));
+                }
+            }
+            bytes
+        }
+
+        /// Writes the tanks destroyed on the last tick to the log.
+        fn log_kills(&self) {
+            let controller = &self.session.controller;
+            let name = |id: u8| controller.get_player(id).map_or("unknown", |player| player.name.as_str());
+            for kill in controller.kills.iter().filter(|kill| kill.tick == controller.tick) {
+                match kill.killer {
+                    Some(killer) => info!("{} destroyed {}", name(killer), name(kill.victim)),
+                    None => info!("{} was destroyed", name(kill.victim)),
+                }
+            }
+        }
+    }
+
+    /// Packets of another size can't be game packets, the tick goes without them.
+    fn game_packets<const PACKET_SIZE: usize>(packets: &[IndexedPacket<[u8; PACKET_SIZE], PACKET_SIZE>]) -> Vec<IndexedGamePacket> {
+        packets
+            .iter()
+            .filter_map(|p| Some(IndexedPacket::new(p.id, GamePacket::from_bytes(p.contents.as_slice().try_into().ok()?).ok()?)))
+            .collect()
+    }
+
+    /// A player in the game, its stream is replaced when the player reconnects.
+    struct Connection {
+        id: u8,
+        name: String,
+        session: u64,
+        spectator: bool,
+        compressed: bool,
+        chassis: Chassis, // kept for the next round
+        vehicle: Option<Vec<u8>>,
+        stream: watch::Sender<Arc<TcpStream>>,
+    }
+
+    impl Connection {
+        fn new(player: Player) -> Self {
+            Self {
+                id: player.id,
+                name: player.name,
+                session: player.session,
+                spectator: player.spectator,
+                compressed: player.compressed,
+                chassis: player.chassis,
+                vehicle: player.vehicle,
+                stream: watch::Sender::new(Arc::new(player.stream)),
+            }
+        }
+
+        fn stream(&self) -> Arc<TcpStream> {
+            self.stream.borrow().clone()
+        }
+    }
+
     pub struct GameServer {
-        players: Vec<Arc<Player>>,
+        players: Vec<Player>, // become connections once the game starts
+        connections: Vec<Arc<Connection>>,
+        incoming: Option<Incoming>, // reconnecting players
         slot_duration: Duration,
         slots_stored: usize,
+        transport: Transport,
+        simulation: Option<(GameMap, MatchSettings, Option<u128>)>, // the match is simulated once the players are known
+        bots: Option<(GameMap, MatchSettings, Vec<Bot>)>,
         listen_tasks: Vec<Option<JoinHandle<()>>>,
+        reconnect_task: Option<JoinHandle<Incoming>>,
         send_task: Option<JoinHandle<()>>,
         running: Arc<AtomicBool>,
+        over: Arc<AtomicBool>, // set by a simulating server once the match is won
     }
 
     impl GameServer {
         pub async fn new(lobby: Lobby, slot_duration: Duration, slots_stored: usize) -> Self {
-            let players: Vec<_> = lobby.into_iter().map(|player| Arc::new(player)).collect();
             Self {
-                players,
+                players: lobby,
+                connections: vec![],
+                incoming: None,
                 slot_duration,
                 slots_stored,
+                transport: Transport::default(),
+                simulation: None,
+                bots: None,
                 listen_tasks: vec![],
+                reconnect_task: None,
                 send_task: None,
                 running: Arc::new(AtomicBool::new(false)),
+                over: Arc::new(AtomicBool::new(false)),
             }
         }
 
-        pub async fn run<const PACKET_SIZE: usize>(&mut self) {
+        /// Inputs and ticks go over the transport, the lobby is always on TCP.
+        pub fn with_transport(mut self, transport: Transport) -> Self {
+            self.transport = transport;
+            self
+        }
+
+        /// Players whose stream drops may connect again and resume the game.
+        pub fn with_reconnects(mut self, incoming: Incoming) -> Self {
+            self.incoming = Some(incoming);
+            self
+        }
+
+        /// Simulates the match alongside the clients and sends them its state every `sync_interval` ticks.
+        /// A client that was tampered with or desynced is brought back to the server's state.
+        /// Over UDP the state is split into datagrams with the rest of the stream.
+        pub fn with_simulation(mut self, map: GameMap, settings: MatchSettings, sync_interval: u128) -> Self {
+            self.simulation = Some((map, settings, Some(sync_interval.max(1))));
+            self
+        }
+
+        /// Simulates the match only to know when it's over, the clients get no state.
+        /// Broadcasting stops with the last tick, see [`GameServer::into_lobby`].
+        pub fn with_referee(mut self, map: GameMap, settings: MatchSettings) -> Self {
+            if self.simulation.is_none() {
+                self.simulation = Some((map, settings, None));
+            }
+            self
+        }
+
+        /// Bots take their spawns next to the players, their inputs join the next ticks like the ones that came
+        /// over the network. Each bot plays on what it saw up to the last ticks sent.
+        pub fn with_bots(mut self, map: GameMap, settings: MatchSettings, bots: Vec<Bot>) -> Self {
+            if !bots.is_empty() {
+                self.bots = Some((map, settings, bots));
+            }
+            self
+        }
+
+        /// Starts the game, inputs that don't parse as `P` are dropped instead of being sent to every client.
+        pub async fn run<P: Packet<PACKET_SIZE>, const PACKET_SIZE: usize>(&mut self) {
             self.running
                 .store(true, std::sync::atomic::Ordering::Relaxed);
 
-            // send lobby info to players
-            let player_info: Vec<_> = self
+            // send lobby info to players, spectators don't get a tank
+            lobby::read_chassis(&mut self.players).await;
+            let drivers = || self.players.iter().filter(|p| !p.spectator);
+            let garage = Garage {
+                chassis: drivers().map(|p| (p.id, p.driven())).collect(),
+                vehicles: drivers()
+                    .filter(|p| p.driven() == Chassis::Custom)
+                    .filter_map(|p| p.vehicle.clone().map(|vehicle| (p.id, vehicle)))
+                    .collect(),
+            };
+            let mut player_info: Vec<_> = self
                 .players
                 .iter()
+                .filter(|p| !p.spectator)
                 .map(|p| (p.id, p.name.clone()))
                 .collect();
+            let mut bots = self.bots.take().map(|(map, settings, bots)| {
+                info!("{} bots join the match", bots.len());
+                player_info.extend(bots.iter().map(|bot| (bot.id, bot.name.clone())));
+                BotPlayers::new(&bots, &map, &player_info, &garage, &settings)
+            });
+            let mut simulation = self.simulation.take().map(|(map, settings, sync_interval)| {
+                match sync_interval {
+                    Some(interval) => info!("Simulating the match, syncing the state every {interval} ticks"),
+                    None => info!("Simulating the match to see when it's over"),
+                }
+                // the server has no tank, so it plays as a spectator
+                let session = GameSession::new(u8::MAX, "server".to_string(), &map, &player_info, &garage, &settings, &TeamColors::default());
+                Simulation { session, sync_interval, over: false }
+            });
             let player_info = ServerPacket::SetPlayers(player_info);
+            let vehicles = ServerPacket::SetVehicles(garage.vehicles);
+            let chassis = ServerPacket::SetChassis(garage.chassis);
+
+            // the game socket listens on the address the players connected to
+            let udp = match self.transport {
+                Transport::Tcp => None,
+                Transport::Udp => {
+                    let ip = self.players.first().and_then(|p| p.stream.local_addr().ok()).map(|addr| addr.ip());
+                    match UdpSocket::bind((ip.unwrap_or([0, 0, 0, 0].into()), 0)).await {
+                        Ok(socket) => Some(Arc::new(socket)),
+                        Err(e) => {
+                            warn!("Can't bind the UDP socket, falling back to TCP: {e}");
+                            None
+                        }
+                    }
+                }
+            };
+            let udp_port = udp.as_ref().and_then(|socket| socket.local_addr().ok()).map(|addr| addr.port());
+
             for player in self.players.iter_mut() {
-                // player is borrowed only once therefore this line won't panic
-                let player = Arc::get_mut(player).unwrap();
+                let _ = player.stream.write_packet(&vehicles).await;
+                let _ = player.stream.write_packet(&chassis).await;
                 let _ = player.stream.write_packet(&player_info).await;
+                if let Some(port) = udp_port {
+                    let _ = player.stream.write_packet(&ServerPacket::UdpPort(port)).await;
+                }
                 let _ = player.stream.write_packet(&ServerPacket::StartGame).await;
             }
+            self.connections = self.players.drain(..).map(|player| Arc::new(Connection::new(player))).collect();
 
             let (packet_write, packet_read) = unbounded();
+            let peers: UdpPeers = Arc::new(Mutex::new(
+                self.connections
+                    .iter()
+                    .map(|p| {
+                        let sender = ReliableSender::default();
+                        let peer = UdpPeer { addr: None, sender, spectator: p.spectator, compressed: p.compressed, lost: false, tcp: false };
+                        (p.id, peer)
+                    })
+                    .collect(),
+            ));
+            let sent_ticks: SentTicks<PACKET_SIZE> = Default::default();
 
             {
                 let mut listen_tasks = Vec::new();
                 info!("Start listening to incoming packets");
                 // listening tasks
-                for player in self.players.iter() {
+                for connection in self.connections.iter() {
                     let running = self.running.clone();
-                    let player = player.clone();
+                    let connection = connection.clone();
                     let packet_write = packet_write.clone();
                     let listen_task = tokio::spawn(async move {
+                        let mut streams = connection.stream.subscribe();
                         while running.load(std::sync::atomic::Ordering::Relaxed) {
-                            let _ = player.stream.readable().await;
+                            let stream = streams.borrow_and_update().clone();
+                            tokio::select! {
+                                _ = stream.readable() => (),
+                                // the player reconnected before the old stream was closed
+                                _ = streams.changed() => continue,
+                            }
                             let mut packet = [0; PACKET_SIZE];
-                            match player.stream.try_read(&mut packet) {
+                            let disconnected = match stream.try_read(&mut packet) {
                                 Ok(0) => {
                                     warn!(
-                                        "Player {} ({}) seems to have disconnected. Closing connection",
-                                        player.name,
-                                        player.stream.peer_addr().unwrap()
+                                        "Player {} seems to have disconnected. Waiting for them to reconnect",
+                                        connection.name,
                                     );
-                                    break;
+                                    true
                                 }
+                                Ok(_) if connection.spectator => false,
                                 Ok(n) => {
                                     trace!(
                                         "Received {n} bytes from {:?}",
-                                        player.stream.peer_addr().unwrap()
+                                        stream.peer_addr().unwrap()
                                     );
-                                    let packet = IndexedPacket::new(player.id as u8, packet);
-                                    packet_write.send(packet).unwrap();
+                                    match P::from_bytes(&packet) {
+                                        Ok(_) => {
+                                            let packet = IndexedPacket::new(connection.id, packet);
+                                            packet_write.send(packet).unwrap();
+                                        }
+                                        Err(e) => warn!("Dropping a packet from {}: {e}", connection.name),
+                                    }
+                                    false
                                 }
                                 Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
                                     continue
                                 }
                                 Err(e) => {
                                     warn!(
-                                        "{e} occured with {}. Closing connection",
-                                        player.stream.peer_addr().unwrap()
+                                        "{e} occured with {}. Waiting for them to reconnect",
+                                        connection.name,
                                     );
-                                    break;
+                                    true
                                 }
+                            };
+                            if disconnected && streams.changed().await.is_err() {
+                                break;
                             }
                         }
                     });
                     listen_tasks.push(Some(listen_task));
                 }
+
+                if let Some(incoming) = self.incoming.take() {
+                    info!("Accepting reconnecting players");
+                    self.reconnect_task = Some(tokio::spawn(accept_reconnects(
+                        incoming,
+                        self.connections.clone(),
+                        sent_ticks.clone(),
+                        peers.clone(),
+                        self.running.clone(),
+                    )));
+                }
+
+                if let Some(socket) = udp.clone() {
+                    info!("Start listening to UDP packets on port {}", udp_port.unwrap_or_default());
+                    let connections = self.connections.clone();
+                    let receive_task = tokio::spawn(receive_datagrams::<P, PACKET_SIZE>(
+                        socket.clone(),
+                        connections,
+                        peers.clone(),
+                        packet_write.clone(),
+                    ));
+                    listen_tasks.push(Some(receive_task));
+                    listen_tasks.push(Some(tokio::spawn(resend_datagrams(socket, peers.clone()))));
+                }
                 self.listen_tasks = listen_tasks;
             }
 
@@ -236,7 +875,8 @@ pub mod server {
                 info!("Start broadcasting");
                 // broadcasting task
                 let running = self.running.clone();
-                let players = self.players.clone();
+                let over = self.over.clone();
+                let connections = self.connections.clone();
                 let slots_stored = self.slots_stored;
                 let slot_duration = self.slot_duration;
                 let broadcast_task = tokio::spawn(async move {
@@ -244,57 +884,407 @@ pub mod server {
                         IndexedPacket<[u8; PACKET_SIZE], PACKET_SIZE>,
                     >::new(slot_duration);
 
-                    while running.load(std::sync::atomic::Ordering::Relaxed) {
+                    while running.load(std::sync::atomic::Ordering::Relaxed) && !over.load(std::sync::atomic::Ordering::Relaxed) {
                         while let Ok(packet) = packet_read.try_recv() {
                             trace!("received: {packet:?}");
                             packet_queue.push(packet);
                             if packet_queue.time_since_take() > slot_duration * slots_stored as u32 { break; }
                         }
 
-                        if packet_queue.time_since_take() < slot_duration * slots_stored as u32 { continue; }
+                        if packet_queue.time_since_take() < slot_duration * slots_stored as u32 {
+                            // let the listening tasks run, they may share the thread with this one
+                            tokio::task::yield_now().await;
+                            continue;
+                        }
 
                         let data = packet_queue.take(slots_stored);
-                        let bytes = packet_tools::serialize_queue(&data);
-
-                        for player in players.iter() {
-                            'try_send: loop {
-                                let _ = player.stream.writable().await;
-                                match player.stream.try_write(&bytes) {
-                                    Ok(_) => {
-                                        trace!(
-                                            "Sending: {data:?} to {:?}",
-                                            player.stream.peer_addr()
-                                        );
-                                        break 'try_send;
+                        if let Some(bots) = bots.as_mut() {
+                            let ticks: Vec<_> = data.iter().map(|packets| game_packets(packets)).collect();
+                            let inputs = trace_span!("bots").in_scope(|| bots.play(&ticks));
+                            for input in inputs {
+                                if let Ok(packet) = input.contents.to_bytes().as_slice().try_into() {
+                                    packet_queue.push(IndexedPacket::new(input.id, packet));
+                                }
+                            }
+                        }
+                        let stream_bytes = match simulation.as_mut() {
+                            Some(simulation) => trace_span!("simulate").in_scope(|| simulation.stream(&data)),
+                            None => packet_tools::serialize_queue(&data),
+                        };
+                        // these are the last ticks, the clients see the match end in them too
+                        if simulation.as_ref().is_some_and(|simulation| simulation.over) {
+                            info!("The match is over");
+                            over.store(true, std::sync::atomic::Ordering::Relaxed);
+                        }
+
+                        let broadcast = trace_span!("broadcast", bytes = stream_bytes.len());
+                        // compressed once for all the players that accept it
+                        let compressed_bytes = match connections.iter().any(|c| c.compressed) {
+                            true => trace_span!("compress").in_scope(|| packet_tools::compress_stream(&stream_bytes)),
+                            false => vec![],
+                        };
+                        // held while sending, so a reconnecting player can't miss or repeat these ticks
+                        let mut sent = sent_ticks.lock().await;
+                        sent.extend(data.iter().cloned());
+                        let mut on_tcp = vec![]; // players of a UDP game that reconnected
+                        if let Some(socket) = udp.as_ref() {
+                            async {
+                                // peers are locked only to build the datagrams, they are sent afterwards
+                                let mut datagrams = vec![];
+                                for (id, peer) in peers.lock().unwrap().iter_mut() {
+                                    if peer.tcp {
+                                        on_tcp.push(*id);
+                                    }
+                                    if peer.lost || peer.tcp {
+                                        continue;
+                                    }
+                                    // buffered until the player says hello
+                                    let stream_bytes = if peer.compressed { &compressed_bytes } else { &stream_bytes };
+                                    match peer.sender.send_stream(stream_bytes) {
+                                        Ok(run) => datagrams.push((peer.addr, run)),
+                                        Err(e) => {
+                                            warn!("{e}, player {id} has to reconnect");
+                                            peer.lost = true;
+                                            peer.addr = None;
+                                            peer.sender = ReliableSender::default();
+                                        }
                                     }
-                                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                                }
+                                for (addr, datagrams) in datagrams {
+                                    let Some(addr) = addr else {
                                         continue;
+                                    };
+                                    for datagram in datagrams {
+                                        let _ = socket.send_to(&datagram.to_bytes(), addr).await;
                                     }
-                                    _ => break 'try_send,
                                 }
-                            }
+                            }.instrument(broadcast.clone()).await;
                         }
+                        async {
+                            let connections = connections.iter().filter(|c| udp.is_none() || on_tcp.contains(&c.id));
+                            for connection in connections {
+                                let stream_bytes = if connection.compressed { &compressed_bytes } else { &stream_bytes };
+                                let stream = connection.stream();
+                                let mut written = 0; // state syncs may not fit the socket buffer at once
+                                'try_send: loop {
+                                    let _ = stream.writable().await;
+                                    match stream.try_write(&stream_bytes[written..]) {
+                                        Ok(n) if n > 0 && written + n < stream_bytes.len() => written += n,
+                                        Ok(_) => {
+                                            trace!(
+                                                "Sending: {data:?} to {:?}",
+                                                stream.peer_addr()
+                                            );
+                                            break 'try_send;
+                                        }
+                                        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                                            continue;
+                                        }
+                                        _ => break 'try_send,
+                                    }
+                                }
+                            }
+                        }.instrument(broadcast).await;
+                        drop(sent);
                     }
                 });
                 self.send_task = Some(broadcast_task);
             }
         }
 
+        /// Waits until a simulating server sees the match won, forever without a simulation.
+        pub async fn wait_over(&self) {
+            while !self.over.load(std::sync::atomic::Ordering::Relaxed) {
+                sleep(Duration::from_millis(100)).await;
+            }
+        }
+
+        /// Stops the game and gives its players back as a lobby for the next round, along with the connections
+        /// for reconnects. The game streams carry nothing after the last tick, so the lobby can go on over them.
+        pub async fn into_lobby(mut self) -> (Lobby, Option<Incoming>) {
+            self.running
+                .store(false, std::sync::atomic::Ordering::Relaxed);
+            let incoming = match self.reconnect_task.take() {
+                Some(task) => task.await.ok(),
+                None => None,
+            };
+            // the tasks hold the streams until they are gone, the last ticks are sent whole
+            for task in self.listen_tasks.iter_mut().filter_map(Option::take) {
+                task.abort();
+                let _ = task.await;
+            }
+            if let Some(task) = self.send_task.take() {
+                let _ = task.await;
+            }
+
+            let mut lobby = vec![];
+            for connection in self.connections.drain(..) {
+                let Ok(Connection { id, name, session, spectator, compressed, chassis, vehicle, stream }) = Arc::try_unwrap(connection) else {
+                    continue;
+                };
+                let current = Arc::clone(&stream.borrow());
+                drop(stream);
+                match Arc::try_unwrap(current) {
+                    Ok(stream) => lobby.push(Player { chassis, vehicle, ..Player::new(id, name, session, spectator, compressed, stream) }),
+                    Err(_) => warn!("{name} is still reconnecting and leaves the lobby"),
+                }
+            }
+            info!("{} players stay for the next round", lobby.len());
+            (lobby, incoming)
+        }
+
         pub fn stop(&mut self) {
             self.running
                 .store(false, std::sync::atomic::Ordering::Relaxed);
             self.listen_tasks.iter_mut().for_each(|task| {
                 task.take().map(|c| c.abort());
             });
+            self.reconnect_task.take().map(|c| c.abort());
 
             self.send_task.take().map(|c| c.abort());
             info!("Server stopped")
         }
     }
 
+    /// Gives a player that presents its session a new stream, starting with the ticks it missed.
+    /// Hands the incoming connections back once the server stops.
+    async fn accept_reconnects<const PACKET_SIZE: usize>(
+        mut incoming: Incoming,
+        connections: Vec<Arc<Connection>>,
+        sent_ticks: SentTicks<PACKET_SIZE>,
+        peers: UdpPeers,
+        running: Arc<AtomicBool>,
+    ) -> Incoming {
+        while running.load(std::sync::atomic::Ordering::Relaxed) {
+            let (mut socket, addr) = tokio::select! {
+                connection = incoming.accept() => match connection {
+                    Some(connection) => connection,
+                    None => break,
+                },
+                _ = sleep(Duration::from_millis(100)) => continue,
+            };
+            // a silent client must not keep the others from reconnecting
+            let Ok(Ok(ClientPacket::Reconnect { session, received })) =
+                timeout(RECONNECT_TIMEOUT, socket.read_packet()).await
+            else {
+                warn!("{addr} connected mid-game without reconnecting");
+                continue;
+            };
+            let Some(connection) = connections.iter().find(|c| c.session == session) else {
+                warn!("{addr} tried to reconnect with an unknown session");
+                continue;
+            };
+
+            // the broadcast needs the ticks too, they're copied out and written without holding them
+            let mut resent = received as usize;
+            let resumed = timeout(RECONNECT_TIMEOUT, async {
+                socket.write_packet(&ServerPacket::SetSession(session)).await?;
+                loop {
+                    let sent = sent_ticks.lock().await;
+                    let missed = sent[resent.min(sent.len())..].to_vec();
+                    // caught up with the ticks broadcast meanwhile, the next ones go to the new stream
+                    if missed.is_empty() {
+                        return std::io::Result::Ok(sent);
+                    }
+                    drop(sent);
+                    let missed_bytes = packet_tools::serialize_queue(&missed);
+                    match connection.compressed {
+                        true => socket.write_all(&packet_tools::compress_stream(&missed_bytes)).await?,
+                        false => socket.write_all(&missed_bytes).await?,
+                    }
+                    resent += missed.len();
+                }
+            });
+            match resumed.await {
+                Ok(Ok(sent)) => {
+                    let missed = resent.saturating_sub(received as usize);
+                    info!("{} reconnected from {addr}, resent {missed} ticks", connection.name);
+                    connection.stream.send_replace(Arc::new(socket));
+                    // a player of a UDP game plays the rest of it over TCP
+                    if let Some(peer) = peers.lock().unwrap().get_mut(&connection.id) {
+                        peer.tcp = true;
+                        peer.addr = None;
+                    }
+                    drop(sent);
+                }
+                Ok(Err(e)) => warn!("{} failed to reconnect: {e}", connection.name),
+                Err(_) => warn!("{} didn't take the missed ticks in time", connection.name),
+            }
+        }
+        incoming
+    }
+
+    async fn receive_datagrams<P: Packet<PACKET_SIZE>, const PACKET_SIZE: usize>(
+        socket: Arc<UdpSocket>,
+        connections: Vec<Arc<Connection>>,
+        peers: UdpPeers,
+        packet_write: crossbeam_channel::Sender<IndexedPacket<[u8; PACKET_SIZE], PACKET_SIZE>>,
+    ) {
+        let mut buf = vec![0; MAX_DATAGRAM];
+        loop {
+            let Ok((len, addr)) = socket.recv_from(&mut buf).await else {
+                continue;
+            };
+            let Some(datagram) = Datagram::parse(&buf[..len]) else {
+                continue;
+            };
+            let mut peers = peers.lock().unwrap();
+            if let Datagram::Hello(id) = datagram {
+                // only the address the player joined the lobby from may speak for it
+                let joined_from = connections.iter().find(|c| c.id == id).and_then(|c| c.stream().peer_addr().ok());
+                if let (Some(peer), Some(joined_from)) = (peers.get_mut(&id), joined_from) {
+                    if joined_from.ip() == addr.ip() && peer.addr != Some(addr) && !peer.lost && !peer.tcp {
+                        info!("Player {id} sends UDP packets from {addr}");
+                        peer.addr = Some(addr);
+                    }
+                }
+                continue;
+            }
+            let Some((&id, peer)) = peers.iter_mut().find(|(_, peer)| peer.addr == Some(addr)) else {
+                continue;
+            };
+            match datagram {
+                Datagram::Unreliable(bytes) if !peer.spectator => {
+                    let Ok(packet) = <[u8; PACKET_SIZE]>::try_from(bytes) else {
+                        continue;
+                    };
+                    if let Err(e) = P::from_bytes(&packet) {
+                        warn!("Dropping a packet from {addr}: {e}");
+                        continue;
+                    }
+                    trace!("Received {PACKET_SIZE} bytes from {addr} over UDP");
+                    let _ = packet_write.send(IndexedPacket::new(id, packet));
+                }
+                Datagram::Ack(seq) => peer.sender.ack(seq),
+                _ => (),
+            }
+        }
+    }
+
+    async fn resend_datagrams(socket: Arc<UdpSocket>, peers: UdpPeers) {
+        let mut interval = tokio::time::interval(Duration::from_millis(10));
+        loop {
+            interval.tick().await;
+            let now = Instant::now();
+            let datagrams: Vec<_> = peers
+                .lock()
+                .unwrap()
+                .values_mut()
+                .filter_map(|peer| Some((peer.addr?, peer.sender.resend(now))))
+                .collect();
+            for (addr, datagrams) in datagrams {
+                for datagram in datagrams {
+                    let _ = socket.send_to(&datagram.to_bytes(), addr).await;
+                }
+            }
+        }
+    }
+
     impl Drop for GameServer {
         fn drop(&mut self) {
             self.stop();
         }
     }
 }
+
+pub mod hub {
+    use anyhow::Result;
+    use common::mode::MatchSettings;
+    use map_editor::map::Map as GameMap;
+    use packet_tools::{client_packets::ClientPacket, server_packets::ServerPacket, UnsizedPacketRead, UnsizedPacketWrite};
+    use std::{
+        collections::HashMap,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use tokio::{
+        net::{TcpListener, TcpStream, ToSocketAddrs},
+        sync::mpsc,
+        task::JoinHandle,
+        time::timeout,
+    };
+    use tracing::{info, warn};
+
+    use crate::server::{Incoming, LobbyServer};
+
+    const SELECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    type Routes = Arc<Mutex<HashMap<String, mpsc::UnboundedSender<(TcpStream, SocketAddr)>>>>;
+
+    /// Hosts several lobbies on one address, a client picks one by its code before joining or reconnecting.
+    /// Each lobby becomes a game server of its own once it's closed.
+    pub struct Hub {
+        routes: Routes,
+        accept_task: JoinHandle<()>,
+        addr: SocketAddr,
+    }
+
+    impl Hub {
+        pub async fn new<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+            let listener = TcpListener::bind(addr).await?;
+            let addr = listener.local_addr()?;
+            let routes = Routes::default();
+            info!("Hosting lobbies on {addr:?}");
+            let accept_task = tokio::spawn(route_connections(listener, routes.clone()));
+            Ok(Self { routes, accept_task, addr })
+        }
+
+        /// Opens a lobby for the clients that select `code`, `None` if the code is taken.
+        pub fn open(&self, code: String, map: GameMap, settings: MatchSettings) -> Option<LobbyServer> {
+            let mut routes = self.routes.lock().unwrap();
+            if routes.contains_key(&code) {
+                return None;
+            }
+            let (sender, receiver) = mpsc::unbounded_channel();
+            routes.insert(code, sender);
+            Some(LobbyServer::hosted(Incoming::Routed(receiver), self.addr, map, settings))
+        }
+
+        /// Forgets the lobby, its players can't reconnect anymore.
+        pub fn remove(&self, code: &str) -> bool {
+            self.routes.lock().unwrap().remove(code).is_some()
+        }
+
+        pub fn codes(&self) -> Vec<String> {
+            let mut codes: Vec<_> = self.routes.lock().unwrap().keys().cloned().collect();
+            codes.sort();
+            codes
+        }
+
+        pub fn local_addr(&self) -> SocketAddr {
+            self.addr
+        }
+    }
+
+    impl Drop for Hub {
+        fn drop(&mut self) {
+            self.accept_task.abort();
+        }
+    }
+
+    async fn route_connections(listener: TcpListener, routes: Routes) {
+        loop {
+            let Ok((socket, addr)) = listener.accept().await else {
+                continue;
+            };
+            // a silent client must not keep the others waiting
+            tokio::spawn(select_lobby(socket, addr, routes.clone()));
+        }
+    }
+
+    async fn select_lobby(mut socket: TcpStream, addr: SocketAddr, routes: Routes) {
+        let Ok(Ok(ClientPacket::SelectLobby(code))) = timeout(SELECT_TIMEOUT, socket.read_packet()).await else {
+            warn!("{addr} connected without selecting a lobby");
+            return;
+        };
+        let route = routes.lock().unwrap().get(&code).cloned();
+        match route {
+            Some(route) if route.send((socket, addr)).is_ok() => (),
+            _ => {
+                warn!("{addr} selected an unknown lobby {code}");
+                let _ = socket.write_packet(&ServerPacket::Kicked(format!("No lobby {code} on the server"))).await;
+            }
+        }
+    }
+}
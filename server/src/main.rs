@@ -1,19 +1,17 @@
-use common::RELATIVE_MAPS_PATH;
+use common::{mode::{GameMode, MatchSettings}, config, DEFAULT_PROJECTILES, DEFAULT_ROUND_TIME, PHYSICS_TICK};
 use itertools::Itertools;
-use log::{error, info};
+use tracing::{error, info, warn};
 use map_editor::map::{Map as GameMap, Spawn};
-use packet_tools::{game_packets::PACKET_SIZE, server_packets::ServerPacket, UnsizedPacketWrite};
-use server::{lobby::Player, server::{GameServer, LobbyServer}};
+use packet_tools::{game_packets::{GamePacket, PACKET_SIZE}, server_packets::ServerPacket, udp::Transport, UnsizedPacketWrite};
+use server::{bots::{self, Bot}, hub::Hub, lobby::{self, Lobby, Player}, server::{GameServer, Incoming, LobbyServer, SYNC_INTERVAL}};
 use text_io::try_scan;
-use std::{collections::HashMap, io::{stdout, Write}, time::Duration};
+use std::{collections::HashMap, io::{stdout, Write}, net::IpAddr, str::FromStr, time::Duration};
+
+const VOTE_TIME: Duration = Duration::from_secs(20);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let env = env_logger::Env::default()
-        .filter_or("MY_LOG_LEVEL", "info")
-        .write_style_or("MY_LOG_STYLE", "always");
-
-    env_logger::init_from_env(env);
+    common::trace::init("info");
 
     let args: Vec<_> = std::env::args().collect();
     if args.len() < 1 {
@@ -22,17 +20,55 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     let addr = &args[1];
+    if let Ok("on") = std::env::var("SMOG_HUB").as_deref() {
+        return run_hub(addr).await;
+    }
     let map = "default".to_string();
     let map = args.get(2).unwrap_or(&map);
 
-    let map = GameMap::init_from_file(&map, RELATIVE_MAPS_PATH).unwrap();
+    let mode = match args.get(3) {
+        Some(mode) => mode.parse::<GameMode>()?,
+        None => GameMode::default(),
+    };
+
+    let round_time = match args.get(4) {
+        Some(round_time) => round_time.parse::<u32>()?,
+        None => DEFAULT_ROUND_TIME,
+    };
+
+    let Some(map) = load_map(map, mode) else {
+        return Ok(());
+    };
+    info!("Hosting \"{}\" in the {} mode, sudden death after {round_time}s", map.name, mode.name());
+    let mut settings = MatchSettings {
+        mode,
+        round_time,
+        ..Default::default()
+    };
     let spawns = map.spawns.clone();
-    let lobby_server = LobbyServer::new(addr, map).await?;
-    info!("Press enter to adjust the lobby");
-    let mut input = String::new();
-    let _ = std::io::stdin().read_line(&mut input);
+    let rotation = rotation(&map, mode);
+    let name = std::env::var("SMOG_SERVER_NAME").unwrap_or("SMOG server".to_string());
+    let password = std::env::var("SMOG_PASSWORD").ok().filter(|password| !password.is_empty());
+    if password.is_some() {
+        info!("The lobby is protected with a password");
+    }
+    let lobby_server = LobbyServer::new(addr, name, map.clone(), settings, password).await?;
+    lobby_server.set_compression(compression());
+    info!("Press enter to adjust the lobby, ban <ip> refuses the address meanwhile");
+    loop {
+        let mut input = String::new();
+        let _ = std::io::stdin().read_line(&mut input);
+        match parse_setting::<IpAddr>(&input, "ban") {
+            Ok(ip) => {
+                lobby_server.ban(ip);
+                info!("Connections from {ip} are refused");
+            }
+            Err(_) => break,
+        }
+    }
 
-    let mut lobby  = lobby_server.get_lobby().await;
+    let (mut lobby, incoming) = lobby_server.close().await;
+    let mut bots: Vec<Bot> = vec![];
     loop {
         print!(">>> ");
         stdout().flush().unwrap();
@@ -40,12 +76,83 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let _ = std::io::stdin().read_line(&mut input);
 
         if let Ok((i, j)) = parse_swap(&input) {
-            swap_ids(&mut lobby, i, j).await;
-            display_players(&lobby, &spawns);
+            swap_ids(&mut lobby, &mut bots, i, j).await;
+            display_players(&lobby, &bots, &spawns);
+        }
+
+        if let Ok(id) = parse_setting::<u8>(&input, "kick") {
+            if let Some(i) = bots.iter().position(|bot| bot.id == id) {
+                info!("Removed {}", bots.remove(i).name);
+            } else {
+                match lobby::kick(&mut lobby, id, "Kicked by the host").await {
+                    Some(player) => info!("Kicked {}", player.name),
+                    None => error!("No player with id {id}"),
+                }
+            }
+        }
+
+        if let Ok(team) = parse_addbot(&input) {
+            let taken: Vec<u8> = lobby.iter().filter(|p| !p.spectator).map(|p| p.id).chain(bots.iter().map(|bot| bot.id)).collect();
+            match bots::pick_slot(&spawns, &taken, team) {
+                Some(id) => {
+                    let bot = Bot::new(id, format!("Bot {id}"));
+                    info!("{} takes spawn {id} on team #{}", bot.name, spawns[id as usize].team);
+                    bots.push(bot);
+                }
+                None => error!("No free spawn for a bot"),
+            }
+        }
+
+        if let Ok(ip) = parse_setting::<IpAddr>(&input, "ban") {
+            for player in lobby::kick_ip(&mut lobby, ip, "Banned from the server").await {
+                info!("Kicked {}", player.name);
+            }
+        }
+
+        if let Ok((id, name)) = parse_rename(&input) {
+            match lobby::rename(&mut lobby, id, name.clone()) {
+                true => info!("Player {id} is now {name}"),
+                false => error!("No player with id {id}"),
+            }
+        }
+
+        if let Ok(friendly_fire) = parse_friendly_fire(&input) {
+            settings.friendly_fire = friendly_fire;
+            send_settings(&mut lobby, settings).await;
+            info!("Friendly fire is {}", if friendly_fire { "on" } else { "off" });
+        }
+
+        if let Ok(respawns) = parse_setting::<u32>(&input, "respawns") {
+            settings.respawns = respawns;
+            send_settings(&mut lobby, settings).await;
+            info!("Every player gets {respawns} respawns");
+        }
+
+        if let Ok(projectiles) = parse_projectiles(&input) {
+            settings.projectiles = projectiles;
+            send_settings(&mut lobby, settings).await;
+            let allowed = (0..u8::BITS).filter(|p| projectiles & 1 << p != 0).join(", ");
+            info!("Tanks fire projectiles {allowed}");
+        }
+
+        if let Ok(round_time) = parse_setting::<u32>(&input, "round_time") {
+            settings.round_time = round_time;
+            send_settings(&mut lobby, settings).await;
+            info!("Sudden death after {round_time}s");
+        }
+
+        if let Ok(mode) = parse_setting::<GameMode>(&input, "mode") {
+            if mode_supported(&map, mode) {
+                settings.mode = mode;
+                send_settings(&mut lobby, settings).await;
+                info!("Playing in the {} mode", mode.name());
+            } else {
+                error!("Map \"{}\" can't be played in the {} mode", map.name, mode.name());
+            }
         }
 
         if input.starts_with("teams") {
-            display_players(&lobby, &spawns);
+            display_players(&lobby, &bots, &spawns);
         }
         if input.starts_with("start") {
             break;
@@ -55,14 +162,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    let mut server = GameServer::new(
-        lobby,
-        Duration::from_nanos(2300000), // 2.3ms per PHYSICS TICK ~ 55 fps client
-        16,
-    )
-    .await;
-
-    server.run::<PACKET_SIZE>().await;
+    let server = start_game(lobby, bots.clone(), Some(incoming), map, settings, rotation.len() > 1).await;
+    let _server = match rotation.len() > 1 {
+        true => {
+            tokio::spawn(rotate(server, bots, rotation, settings));
+            None
+        }
+        false => Some(server),
+    };
 
     loop {
         print!(">>> ");
@@ -78,6 +185,163 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// Hosts lobbies created from the console on one address, the clients join them by their codes.
+async fn run_hub(addr: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let hub = Hub::new(addr).await?;
+    let mut lobbies = HashMap::<String, (LobbyServer, GameMap, MatchSettings)>::new();
+    let mut games = vec![];
+    loop {
+        print!(">>> ");
+        stdout().flush().unwrap();
+        let mut input = String::new();
+        let _ = std::io::stdin().read_line(&mut input);
+        let args: Vec<&str> = input.split_whitespace().collect();
+
+        match args.as_slice() {
+            ["create", code, map, rest @ ..] => {
+                let mode = match rest.first().map(|mode| mode.parse::<GameMode>()) {
+                    Some(Ok(mode)) => mode,
+                    Some(Err(_)) => {
+                        error!("Unknown mode {}", rest[0]);
+                        continue;
+                    }
+                    None => GameMode::default(),
+                };
+                let Some(map) = load_map(map, mode) else {
+                    continue;
+                };
+                let settings = MatchSettings {
+                    mode,
+                    round_time: DEFAULT_ROUND_TIME,
+                    ..Default::default()
+                };
+                match hub.open(code.to_string(), map.clone(), settings) {
+                    Some(lobby) => {
+                        lobby.set_compression(compression());
+                        info!("Lobby {code} hosts \"{}\" in the {} mode, join it at {}/{code}", map.name, mode.name(), hub.local_addr());
+                        lobbies.insert(code.to_string(), (lobby, map, settings));
+                    }
+                    None => error!("Lobby {code} already exists"),
+                }
+            }
+            ["start", code] => {
+                let Some((lobby, map, settings)) = lobbies.remove(*code) else {
+                    error!("No open lobby {code}");
+                    continue;
+                };
+                let (lobby, incoming) = lobby.close().await;
+                info!("Lobby {code} starts with {} players", lobby.len());
+                games.push(start_game(lobby, vec![], Some(incoming), map, settings, false).await);
+            }
+            ["lobbies"] => {
+                for code in hub.codes() {
+                    match lobbies.get(&code) {
+                        Some((_, map, settings)) => println!("{code}: \"{}\" in the {} mode, open", map.name, settings.mode.name()),
+                        None => println!("{code}: playing"),
+                    }
+                }
+            }
+            ["stop"] => return Ok(()),
+            _ => error!("Expected create <code> <map> [mode], start <code>, lobbies or stop"),
+        }
+    }
+}
+
+/// Reads the map from the maps directory, `None` if it's missing or can't be played in the mode.
+fn load_map(name: &str, mode: GameMode) -> Option<GameMap> {
+    let maps_dir = config::maps_dir();
+    let map = match GameMap::init_from_file(name, &maps_dir).and_then(|map| map.check_files(&maps_dir).map(|_| map)) {
+        Ok(map) => map,
+        Err(e) => {
+            error!("{e}");
+            return None;
+        }
+    };
+    if !mode_supported(&map, mode) {
+        error!("Map \"{}\" can't be played in the {} mode", map.name, mode.name());
+        return None;
+    }
+    Some(map)
+}
+
+/// Maps of `SMOG_ROTATION` that can be played in the mode, after the hosted one. The next round is only offered over TCP.
+fn rotation(map: &GameMap, mode: GameMode) -> Vec<GameMap> {
+    let Ok(names) = std::env::var("SMOG_ROTATION") else {
+        return vec![];
+    };
+    if transport() == Transport::Udp {
+        warn!("The map rotation needs the TCP transport, the server stops after the match");
+        return vec![];
+    }
+    let mut maps = vec![map.clone()];
+    for name in names.split(',').map(str::trim).filter(|name| !name.is_empty() && *name != map.name) {
+        maps.extend(load_map(name, mode));
+    }
+    info!("Rotating between {}", maps.iter().map(|map| &map.name).join(", "));
+    maps
+}
+
+/// Plays round after round, after each match the players vote for the map of the next one. The bots stay for every round.
+async fn rotate(mut server: GameServer, bots: Vec<Bot>, maps: Vec<GameMap>, settings: MatchSettings) {
+    let names: Vec<String> = maps.iter().map(|map| map.name.clone()).collect();
+    let mut current = 0;
+    loop {
+        server.wait_over().await;
+        let (mut lobby, incoming) = server.into_lobby().await;
+        current = lobby::vote_map(&mut lobby, &names, (current + 1) % maps.len(), VOTE_TIME).await;
+        let map = &maps[current];
+        lobby::next_round(&mut lobby, map, settings).await;
+        if lobby.is_empty() {
+            info!("Everyone left, the rotation stops");
+            return;
+        }
+        info!("The next round is played on \"{}\" by {} players", map.name, lobby.len());
+        server = start_game(lobby, bots.clone(), incoming, map.clone(), settings, true).await;
+    }
+}
+
+fn compression() -> bool {
+    !matches!(std::env::var("SMOG_COMPRESSION").as_deref(), Ok("off"))
+}
+
+fn transport() -> Transport {
+    match std::env::var("SMOG_TRANSPORT").as_deref() {
+        Ok("udp") => Transport::Udp,
+        _ => Transport::Tcp,
+    }
+}
+
+/// Game server for the closed lobby and its bots, configured from the environment. A referee simulates the match to end it.
+async fn start_game(
+    lobby: Lobby,
+    bots: Vec<Bot>,
+    incoming: Option<Incoming>,
+    map: GameMap,
+    settings: MatchSettings,
+    referee: bool,
+) -> GameServer {
+    let mut server = GameServer::new(
+        lobby,
+        PHYSICS_TICK,
+        16,
+    )
+    .await
+    .with_transport(transport())
+    .with_bots(map.clone(), settings, bots);
+    if let Some(incoming) = incoming {
+        server = server.with_reconnects(incoming);
+    }
+    if let Ok("on") = std::env::var("SMOG_SIMULATION").as_deref() {
+        server = server.with_simulation(map.clone(), settings, SYNC_INTERVAL);
+    }
+    if referee {
+        server = server.with_referee(map, settings);
+    }
+
+    server.run::<GamePacket, PACKET_SIZE>().await;
+    server
+}
+
 fn parse_swap(input: &String) -> Result<(u8, u8), Box<dyn std::error::Error>> {
     let i: u8;
     let j: u8;
@@ -85,7 +349,89 @@ fn parse_swap(input: &String) -> Result<(u8, u8), Box<dyn std::error::Error>> {
     Ok((i, j))
 }
 
-async fn swap_ids(players: &mut Vec<Player>, i: u8, j: u8) {
+/// The name is the rest of the line, so it may have spaces.
+fn parse_rename(input: &str) -> Result<(u8, String), Box<dyn std::error::Error>> {
+    let (id, name) = input
+        .trim()
+        .strip_prefix("rename ")
+        .and_then(|args| args.split_once(' '))
+        .ok_or("expected rename <id> <name>")?;
+    let name = name.trim();
+    if name.is_empty() {
+        Err("expected a name")?
+    }
+    Ok((id.parse()?, name.to_string()))
+}
+
+/// `addbot` fills the team with the fewest players, `addbot <team>` the given one.
+fn parse_addbot(input: &str) -> Result<Option<usize>, Box<dyn std::error::Error>> {
+    let mut args = input.split_whitespace();
+    if args.next() != Some("addbot") {
+        Err("expected addbot [team]")?
+    }
+    Ok(args.next().map(|team| team.parse()).transpose()?)
+}
+
+fn parse_friendly_fire(input: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    let state: String;
+    try_scan!(input.bytes() => "friendly_fire {}", state);
+    match state.as_str() {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err("expected on or off")?,
+    }
+}
+
+/// `projectiles all` allows every projectile, `projectiles <id>,<id>,...` only the listed ones.
+fn parse_projectiles(input: &str) -> Result<u8, Box<dyn std::error::Error>> {
+    let list: String;
+    try_scan!(input.bytes() => "projectiles {}", list);
+    if list == "all" {
+        return Ok(DEFAULT_PROJECTILES);
+    }
+    let mut projectiles = 0;
+    for id in list.split(',') {
+        let id: u8 = id.parse()?;
+        if id >= u8::BITS as u8 || DEFAULT_PROJECTILES & 1 << id == 0 {
+            Err(format!("no projectile {id}"))?
+        }
+        projectiles |= 1 << id;
+    }
+    Ok(projectiles)
+}
+
+fn parse_setting<T: FromStr>(input: &str, setting: &str) -> Result<T, Box<dyn std::error::Error>> {
+    let name: String;
+    let value: String;
+    try_scan!(input.bytes() => "{} {}", name, value);
+    if name != setting {
+        Err(format!("expected {setting}"))?
+    }
+    Ok(value.parse::<T>().map_err(|_| format!("invalid value for {setting}"))?)
+}
+
+fn mode_supported(map: &GameMap, mode: GameMode) -> bool {
+    match mode {
+        GameMode::KingOfTheHill { .. } => map.hill.is_some(),
+        GameMode::CaptureTheFlag { .. } => !map.flags.is_empty(),
+        _ => true,
+    }
+}
+
+async fn send_settings(players: &mut Vec<Player>, settings: MatchSettings) {
+    for player in players {
+        let _ = player.stream.write_packet(&ServerPacket::MatchSettings(settings)).await;
+    }
+}
+
+async fn swap_ids(players: &mut Vec<Player>, bots: &mut Vec<Bot>, i: u8, j: u8) {
+    for bot in bots {
+        if bot.id == i {
+            bot.id = j;
+        } else if bot.id == j {
+            bot.id = i;
+        }
+    }
     for player in players {
         if player.id == i {
             player.id = j;
@@ -97,7 +443,7 @@ async fn swap_ids(players: &mut Vec<Player>, i: u8, j: u8) {
     }
 }
 
-fn display_players(players: &Vec<Player>, spawns: &Vec<Spawn>) {
+fn display_players(players: &Vec<Player>, bots: &Vec<Bot>, spawns: &Vec<Spawn>) {
     let mut spawn_ids = HashMap::<usize, Vec<usize>>::new();
     let mut player_ids = HashMap::<usize, String>::new();
 
@@ -112,6 +458,9 @@ fn display_players(players: &Vec<Player>, spawns: &Vec<Spawn>) {
     for player in players {
         player_ids.insert(player.id as usize, player.name.clone());
     }
+    for bot in bots {
+        player_ids.insert(bot.id as usize, format!("{} (bot)", bot.name));
+    }
 
     println!("Displaying teams:\n");
     for (team, ids) in spawn_ids.iter().sorted_by_key(|s| s.0) {
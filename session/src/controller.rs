@@ -0,0 +1,869 @@
+use bevy::{
+    color::Color,
+    math::{vec2, vec4, Vec2, Vec4},
+};
+
+use common::mode::MatchSettings;
+use map_editor::map::Spawn;
+use render::palette::TeamColors;
+use event::{EventTracker, GameEvent, EVENT_TICKS};
+use mode::ModeState;
+use pickup::PickupState;
+use stats::{Kill, PlayerStats};
+use model::{PlayerModel, RawPlayerModel, PISTOL_HP};
+use packet_tools::game_packets::{GamePacket, IndexedGamePacket};
+use serde::{Deserialize, Serialize};
+
+use solver::{
+    particle::{
+        Kind, Particle, GROUND, MINE_ARM_TICKS, PROJECTILE_EXPLOSIVE, PROJECTILE_HEAVY, PROJECTILE_IMPULSE,
+        PROJECTILE_INCENDIARY, PROJECTILE_MINE, PROJECTILE_STICKY,
+    },
+    Solver,
+};
+
+pub mod event;
+pub mod mode;
+pub mod model;
+pub mod pickup;
+pub mod stats;
+
+const SPAWN_PROTECTION: u128 = 1500; // ticks of invulnerability after spawn
+const SHIELD_TICKS: u128 = 1200; // ticks of invulnerability the shield gives
+const SHIELD_COOLDOWN: u128 = 9600; // ticks from raising a shield until the next one can be raised
+const SHIMMER_COLOR: Vec4 = vec4(0.6, 0.9, 1., 1.);
+const RESPAWN_DELAY: u128 = 1300; // ticks between losing a tank and getting a new one (~3 seconds)
+pub const HIT_TICKS: u128 = 400; // ticks a hit stays on the screen (~1 second)
+const HIT_THRESHOLD: f32 = 0.05; // smaller losses come from driving and burning
+const HIT_RADIUS: f32 = 6.; // projectiles this close to the damage are blamed for it
+const HIT_MERGE: f32 = 0.9; // cosine between the directions of hits shown as one
+const PRUNE_TICKS: u128 = 4096; // ticks between removals of the broken links and the expired particles
+const PROJECTILE_LIFETIME: u32 = 14400; // ticks a fired projectile stays in the match (~30 seconds)
+const MINE_LIFETIME: u32 = 57600; // ticks a mine waits for its target (~2 minutes)
+const SPAWN_LIFETIME: u32 = 28800; // ticks the ground spawned by a player stays (~1 minute)
+const HAZARD_TICKS: u128 = 120; // ticks between the damage of the map's hazards (~4 times a second)
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Player {
+    pub id: u8,
+    pub team: usize,
+    #[serde(skip)]
+    pub name: String,
+    pub model: PlayerModel,
+    #[serde(skip)]
+    pub tank: RawPlayerModel, // placed again at every respawn
+    pub gear: usize,
+    pub projectile: u8,
+
+    // timers
+    pub reload_timer: TickTimer,
+    pub dash_timer: TickTimer,
+    pub shield_timer: TickTimer,
+    pub spawn_tick: u128,
+    pub boost_tick: u128, // damage boost lasts until this tick
+    pub speed_tick: u128, // speed boost lasts until this tick
+    pub shield_tick: u128, // shield lasts until this tick
+    pub shield_ready: u128, // tick the next shield can be raised on
+    pub respawns: u32,    // tanks left after the current one
+    pub stats: PlayerStats,
+    respawn_tick: Option<u128>,
+
+    // state of the tank while it's protected after spawn or by the shield
+    spawn_links: Vec<f32>,
+    #[serde(skip)]
+    spawn_colors: Vec<Vec4>,
+
+    // energy spent by the dash, the shield and high gears
+    pub energy: f32,
+    driving: bool,
+
+    // utils
+    pub thrust: (f32, f32),
+    pub aim: Option<Vec2>,
+}
+
+impl Player {
+    const BASE_POWER: f32 = 16.;
+    const GEAR_POWER: f32 = 2.;
+    const MAX_GEAR: usize = 5;
+    const BOOST_TICKS: u128 = 4000;
+    const DAMAGE_BOOST: f32 = 1.5; // projectile mass multiplier
+    const SPEED_BOOST: f32 = 1.5; // motor power multiplier
+    pub const MAX_ENERGY: f32 = 1.;
+    const ENERGY_REGEN: f32 = 0.0002; // per tick
+    const DASH_ENERGY: f32 = 0.5;
+    const SHIELD_ENERGY: f32 = 0.5;
+    const HIGH_GEAR: usize = 3; // first gear that drains energy
+    const GEAR_ENERGY: f32 = 0.0003; // drain per tick for every high gear
+
+    pub fn new(id: u8, team: usize, name: String, model: PlayerModel) -> Self {
+        Self {
+            id,
+            team,
+            name,
+            model,
+            gear: 0,
+            projectile: 0,
+            energy: Self::MAX_ENERGY,
+            ..Default::default()
+        }
+    }
+
+    pub fn protected(&self, tick: u128) -> bool {
+        tick <= self.protection_end()
+    }
+
+    /// Last tick of the protection after spawn or of the shield, whichever ends later.
+    fn protection_end(&self) -> u128 {
+        (self.spawn_tick + SPAWN_PROTECTION).max(self.shield_tick)
+    }
+
+    pub fn get_power(&self) -> f32 {
+        Self::BASE_POWER * f32::powf(Self::GEAR_POWER, self.gear as f32)
+    }
+
+    /// Motor packets driving the tank at its gear's power, a positive `coeff` drives it left.
+    pub fn drive(&self, coeff: f32) -> Vec<GamePacket> {
+        let power = coeff * self.get_power();
+        self.model
+            .left_motors
+            .iter()
+            .map(|ind| GamePacket::Motor(*ind as u32, power))
+            .chain(self.model.right_motors.iter().map(|ind| GamePacket::Motor(*ind as u32, -power)))
+            .collect()
+    }
+
+    /// Ticks until the projectile can be fired again.
+    pub fn reload_ticks(projectile: u8) -> isize {
+        match projectile {
+            0 => 400,
+            1 => 1500,
+            2 => 16,
+            3 => 2400,
+            4 => 800,
+            5 => 1200,
+            _ => 0,
+        }
+    }
+
+    pub fn gear_up(&mut self) {
+        self.gear = usize::min(self.gear + 1, Self::MAX_GEAR);
+    }
+
+    pub fn gear_down(&mut self) {
+        self.gear = usize::max(self.gear, 1) - 1;
+    }
+
+    fn update_energy(&mut self) {
+        let mut drain = 0.;
+        if self.driving && self.gear >= Self::HIGH_GEAR {
+            drain = Self::GEAR_ENERGY * (self.gear - Self::HIGH_GEAR + 1) as f32;
+        }
+        self.energy = (self.energy + Self::ENERGY_REGEN - drain).clamp(0., Self::MAX_ENERGY);
+
+        // out of energy, shift down to the last free gear
+        if self.energy == 0. {
+            self.gear = self.gear.min(Self::HIGH_GEAR - 1);
+        }
+    }
+}
+
+/// Damage taken by the local tank.
+#[derive(Clone, Copy, Debug)]
+pub struct Hit {
+    pub tick: u128,
+    pub direction: Vec2, // from the tank towards the source of the damage
+    pub damage: f32,
+}
+
+/// Part of the controller a simulating server corrects along with the solver. The colors, the hits and the events
+/// stay up to every client.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ControllerState {
+    players: Vec<Player>,
+    mode: ModeState,
+    pickups: Vec<PickupState>,
+    kills: Vec<Kill>,
+}
+
+#[derive(Clone)]
+pub struct Controller {
+    pub tick: u128,
+    pub player: Player,
+    pub players: Vec<Player>,
+    pub mode: ModeState,
+    pub pickups: Vec<PickupState>,
+    pub team_colors: TeamColors, // used to tint respawned tanks
+    pub projectiles: u8,         // allowed by the rules of the match, see `MatchSettings::projectiles`
+    pub hits: Vec<Hit>,
+    pub kills: Vec<Kill>, // recent kills of the match, oldest first
+    pub events: Vec<(u128, GameEvent)>, // tick it happened on and the event
+    events_tracker: EventTracker,
+    spawn_points: Vec<Vec2>,
+    link_durability: Vec<f32>, // durability of the local tank's links on the last tick
+}
+
+impl Controller {
+    pub fn new(
+        id: u8,
+        name: String,
+        players: Vec<(u8, String, RawPlayerModel, PlayerModel)>,
+        spawns: &Vec<Spawn>,
+        mode: ModeState,
+        pickups: Vec<PickupState>,
+        settings: &MatchSettings,
+    ) -> Self {
+        let projectiles = settings.projectiles;
+        // every tank starts with the first projectile it can fire
+        let projectile = (0..u8::BITS as u8).find(|p| allows(projectiles, *p)).unwrap_or(0);
+        let new_player = |id: u8, name: String, tank: RawPlayerModel, model: PlayerModel| Player {
+            tank,
+            respawns: settings.respawns,
+            projectile,
+            ..Player::new(id, spawns[id as usize].team, name, model)
+        };
+        // a spectator has no tank, its player never comes into the game
+        let player = match players.iter().find(|p| p.0 == id) {
+            Some(p) => new_player(id, name, p.2.clone(), p.3.clone()),
+            None => Player { id, name, ..Default::default() },
+        };
+        Self {
+            tick: 0,
+            mode,
+            pickups,
+            player,
+            players: players
+                .into_iter()
+                .map(|p| new_player(p.0, p.1, p.2, p.3))
+                .collect(),
+            team_colors: TeamColors::default(),
+            projectiles,
+            hits: vec![],
+            kills: vec![],
+            events: vec![],
+            events_tracker: EventTracker::default(),
+            spawn_points: spawns.iter().map(|spawn| spawn.pos).collect(),
+            link_durability: vec![],
+        }
+    }
+
+    /// Whether the rules of the match let the tanks fire the projectile.
+    pub fn projectile_allowed(&self, projectile: u8) -> bool {
+        allows(self.projectiles, projectile)
+    }
+
+    /// Whether the local player only watches the match.
+    pub fn spectating(&self) -> bool {
+        self.get_player(self.player.id).is_none()
+    }
+
+    /// State of the players, the mode and the pickups, sent by a simulating server in its state syncs.
+    pub fn snapshot(&self) -> ControllerState {
+        ControllerState {
+            players: self.players.clone(),
+            mode: self.mode.clone(),
+            pickups: self.pickups.clone(),
+            kills: self.kills.clone(),
+        }
+    }
+
+    /// Takes the server's state over, the names, the tanks kept for the respawns and the colors stay local.
+    pub fn restore(&mut self, state: &ControllerState) {
+        for player in self.players.iter_mut() {
+            let Some(synced) = state.players.iter().find(|p| p.id == player.id) else {
+                continue;
+            };
+            *player = Player {
+                name: std::mem::take(&mut player.name),
+                tank: std::mem::take(&mut player.tank),
+                spawn_colors: std::mem::take(&mut player.spawn_colors),
+                ..synced.clone()
+            };
+        }
+        if let Some(player) = self.players.iter().find(|p| p.id == self.player.id) {
+            self.player.model = player.model.clone();
+            self.player.respawns = player.respawns;
+            self.player.spawn_tick = player.spawn_tick;
+            self.player.shield_tick = player.shield_tick;
+            self.player.shield_ready = player.shield_ready;
+        }
+        self.mode = state.mode.clone();
+        self.pickups = state.pickups.clone();
+        self.kills = state.kills.clone();
+    }
+
+    pub fn get_player(&self, id: u8) -> Option<&Player> {
+        self.players.iter().find(|p| p.id == id)
+    }
+
+    pub fn get_player_mut(&mut self, id: u8) -> Option<&mut Player> {
+        self.players.iter_mut().find(|p| p.id == id)
+    }
+
+    pub fn get_player_pos(player: &Player, solver: &Solver) -> Vec2 {
+        solver.particles[player.model.center].pos
+    }
+
+    pub fn get_player_hp(player: &Player, solver: &Solver) -> f32 {
+        let hp = player.model.base_connections
+            .iter()
+            .map(|i| solver.connections[*i].2.durability())
+            .sum::<f32>() / player.model.max_hp;
+        let threshold = 0.7;
+        ((hp - threshold) / (1. - threshold)).max(0.)
+    }
+
+    pub fn player_alive(player: &Player, solver: &Solver) -> bool {
+        Self::get_player_hp(player, solver) > 0.
+    }
+
+    /// Alive or waiting for a new tank.
+    pub fn player_in_game(player: &Player, solver: &Solver) -> bool {
+        player.respawns > 0 || Self::player_alive(player, solver)
+    }
+
+    fn update_timers(&mut self) {
+        self.tick += 1;
+        self.player.reload_timer.update();
+        self.player.dash_timer.update();
+        self.player.shield_timer.update();
+        self.player.update_energy();
+        // the energy every client agrees on, the local one above also drains in high gears
+        for player in self.players.iter_mut() {
+            player.energy = (player.energy + Player::ENERGY_REGEN).min(Player::MAX_ENERGY);
+        }
+    }
+
+    fn update_respawns(&mut self, solver: &mut Solver) {
+        let tick = self.tick;
+        let mut due = vec![];
+        for player in self.players.iter_mut() {
+            if player.respawns == 0 || Self::player_alive(player, solver) {
+                continue;
+            }
+            let respawn_tick = *player.respawn_tick.get_or_insert(tick + RESPAWN_DELAY);
+            if tick >= respawn_tick {
+                due.push(player.id);
+            }
+        }
+
+        for id in due {
+            // the wreck of the last tank makes room for the new one
+            let wreck = self.get_player(id).expect("respawned players exist").model.range.clone();
+            self.remove_particles(solver, |i, _| wreck.contains(&i));
+
+            let player = self.players.iter_mut().find(|p| p.id == id).expect("respawned players exist");
+            let pos = self.spawn_points[player.id as usize];
+            let tank = player
+                .tank
+                .clone()
+                .with_tint(self.team_colors.particle_color(player.team))
+                .with_team(player.team);
+            player.model = tank.place_in_solver(pos, player.id, solver);
+            player.respawns -= 1;
+            player.respawn_tick = None;
+            player.spawn_tick = tick;
+            player.spawn_links.clear();
+            player.spawn_colors.clear();
+            player.thrust = (0., 0.);
+            player.aim = None;
+
+            if player.id == self.player.id {
+                self.player.model = player.model.clone();
+                self.player.respawns = player.respawns;
+                self.player.spawn_tick = tick;
+            }
+        }
+    }
+
+    fn update_protection(&mut self, solver: &mut Solver) {
+        let tick = self.tick;
+        for player in self.players.iter_mut() {
+            if !player.protected(tick) {
+                continue;
+            }
+            if player.spawn_links.is_empty() {
+                player.spawn_links = solver.connections[player.model.links.clone()]
+                    .iter()
+                    .map(|(_, _, link)| link.durability())
+                    .collect();
+                player.spawn_colors = solver.particles[player.model.range.clone()]
+                    .iter()
+                    .map(|p| p.color)
+                    .collect();
+            }
+
+            // links can't lose durability
+            for (i, durability) in player.model.links.clone().zip(player.spawn_links.iter()) {
+                let link = &mut solver.connections[i].2;
+                *link = link.with_durability(*durability);
+            }
+
+            // shimmer until the protection ends
+            let end = tick == player.protection_end();
+            let shimmer = if end { 0. } else { 0.25 + 0.25 * f32::sin(tick as f32 * 0.02) };
+            for (i, color) in player.model.range.clone().zip(player.spawn_colors.iter()) {
+                solver.particles[i].color = color.lerp(SHIMMER_COLOR, shimmer);
+            }
+            // the next shield keeps the durability the tank has by then
+            if end {
+                player.spawn_links.clear();
+                player.spawn_colors.clear();
+            }
+        }
+    }
+
+    /// Records the hits the local tank took since the last tick and where they came from.
+    fn update_hits(&mut self, solver: &Solver) {
+        let tick = self.tick;
+        self.hits.retain(|hit| tick < hit.tick + HIT_TICKS);
+
+        let links = &solver.connections[self.player.model.links.clone()];
+        let durability: Vec<f32> = links.iter().map(|(_, _, link)| link.durability().max(0.)).collect();
+        let last = std::mem::replace(&mut self.link_durability, durability);
+        if last.len() != self.link_durability.len() {
+            return; // new tank
+        }
+
+        let mut damage = 0.;
+        let mut hit_pos = Vec2::ZERO;
+        for ((i, j, _), (old, new)) in links.iter().zip(last.iter().zip(self.link_durability.iter())) {
+            let loss = old - new;
+            if loss > 0. {
+                damage += loss;
+                hit_pos += loss * (solver.particles[*i].pos + solver.particles[*j].pos) / 2.;
+            }
+        }
+        if damage < HIT_THRESHOLD {
+            return;
+        }
+        hit_pos /= damage;
+
+        // a projectile came from where it flies from, otherwise point at the damaged side
+        let id = self.player.id;
+        let projectile = solver
+            .particles
+            .iter()
+            .filter(|p| p.projectile && p.owner != Some(id) && p.pos.distance(hit_pos) < HIT_RADIUS)
+            .min_by(|p1, p2| p1.pos.distance(hit_pos).total_cmp(&p2.pos.distance(hit_pos)));
+        let direction = match projectile {
+            Some(p) if p.velocity() != Vec2::ZERO => -p.velocity(),
+            _ => hit_pos - Self::get_player_pos(&self.player, solver),
+        }
+        .normalize_or_zero();
+
+        match self.hits.iter_mut().find(|hit| hit.direction.dot(direction) > HIT_MERGE) {
+            Some(hit) => {
+                hit.tick = tick;
+                hit.damage += damage;
+            }
+            None => self.hits.push(Hit { tick, direction, damage }),
+        }
+    }
+
+    /// Wears out the tanks in the hazards of the map now and then, before the spawn protection restores them.
+    fn update_hazards(&mut self, solver: &mut Solver) {
+        if self.tick % HAZARD_TICKS == 0 {
+            solver.apply_hazards();
+        }
+    }
+
+    /// Records what happened on the last tick for the client to play sounds.
+    fn update_events(&mut self, solver: &Solver) {
+        let tick = self.tick;
+        self.events.retain(|(t, _)| tick < t + EVENT_TICKS);
+        self.events_tracker.update(solver, |event| self.events.push((tick, event)));
+    }
+
+    /// Drops the broken links from the solver now and then, so long matches don't pile them up.
+    fn prune_links(&mut self, solver: &mut Solver) {
+        if self.tick % PRUNE_TICKS != 0 {
+            return;
+        }
+        let tanks: Vec<_> = self.players.iter().map(|player| player.model.links.clone()).collect();
+        let ropes: Vec<_> = self.mode.ropes().collect();
+        let moved = solver.prune_connections(|k| tanks.iter().any(|links| links.contains(&k)) || ropes.contains(&k));
+
+        for player in self.players.iter_mut() {
+            player.model.move_links(&moved);
+            if player.id == self.player.id {
+                self.player.model = player.model.clone();
+            }
+        }
+        self.mode.move_ropes(&moved);
+    }
+
+    /// Removes the expired projectiles and ground from the solver now and then.
+    /// Only particles with a lifetime expire, so tanks, flags and pickups are never removed.
+    fn remove_expired(&mut self, solver: &mut Solver) {
+        if self.tick % PRUNE_TICKS != 0 {
+            return;
+        }
+        self.remove_particles(solver, |_, p| p.expired());
+    }
+
+    /// Removes the particles from the solver, moving the indexes kept here. A tank is only removed whole,
+    /// its player is left for the caller to give a new one.
+    fn remove_particles(&mut self, solver: &mut Solver, remove: impl Fn(usize, &Particle) -> bool) {
+        let removed = solver.remove_particles(remove);
+        if removed.particles.iter().all(Option::is_some) {
+            return;
+        }
+
+        for player in self.players.iter_mut() {
+            if removed.particles[player.model.center].is_none() {
+                continue;
+            }
+            player.model.move_particles(&removed.particles);
+            player.model.move_links(&removed.links);
+            if player.id == self.player.id {
+                self.player.model = player.model.clone();
+            }
+        }
+        self.mode.move_flags(&removed.particles);
+        self.mode.move_ropes(&removed.links);
+        for pickup in self.pickups.iter_mut() {
+            pickup.particle = removed.particles[pickup.particle].expect("pickups never expire");
+        }
+        self.events_tracker.move_particles(&removed.particles);
+    }
+
+    fn push_event(&mut self, event: GameEvent) {
+        // every motor of a tank sends a packet, one sound is enough
+        if let GameEvent::Motor { player, .. } = event {
+            let tick = self.tick;
+            if self.events.iter().any(|(t, e)| *t == tick && matches!(e, GameEvent::Motor { player: p, .. } if *p == player)) {
+                return;
+            }
+        }
+        self.events.push((self.tick, event));
+    }
+
+    fn update_player_colors(&self, solver: &mut Solver) {
+        for player in self.players.iter() {
+            let hp = Self::get_player_hp(player, solver);
+            let center = &mut solver.particles[player.model.center];
+            center.color = get_color(hp);
+
+            for pistol in &player.model.pistols {
+                let (pistol_base, _, link) = solver.connections[*pistol];
+                let pistol_base = &mut solver.particles[pistol_base];
+                let hp = link.durability() / PISTOL_HP;
+                pistol_base.color = get_color(hp);
+            }
+        }
+    }
+
+    fn update_players(&self, solver: &mut Solver) {
+        for player in &self.players {
+            if !Self::player_alive(player, solver) {
+                continue;
+            }
+
+            if self.tick % 8 == 0 {
+                let left_motor = player.model.right_motors.first().unwrap();
+                let right_motor = player.model.right_motors.last().unwrap();
+
+                let center = solver.particles[player.model.center];
+                let (center_base, _, _) = solver.connections[player.model.center_connection];
+                let center_base = solver.particles[center_base];
+                let direction_up = center.pos - center_base.pos;
+
+                // thrust
+                if player.thrust.0 != 0. || player.thrust.1 != 0. {
+                    solver.particles[*left_motor].set_velocity(player.thrust.0 * direction_up);
+                    solver.particles[*right_motor].set_velocity(player.thrust.1 * direction_up);
+                }
+
+                // aim
+                let Some(desired_pos) = player.aim else {
+                    continue;
+                };
+                let mut desired_pos = (desired_pos - center.pos).normalize() * 6. + center.pos;
+                if (desired_pos - center.pos).dot(direction_up) < -0.1 {
+                    desired_pos = f32::signum(direction_up.perp_dot(desired_pos - center.pos))
+                        * direction_up.perp()
+                        * 6.
+                        + center.pos;
+                }
+
+                let muzzle = solver.particles[player.model.muzzle];
+                let mut angle = (muzzle.pos - center.pos).angle_between(desired_pos - center.pos);
+                angle = angle.min(0.04).max(-0.04);
+                desired_pos = (muzzle.pos - center.pos)
+                    .rotate(Vec2::from_angle(angle))
+                    .normalize()
+                    * 6.
+                    + center.pos;
+
+                player.model.pistols.iter().for_each(|pistol| {
+                    let (i, _, link) = &mut solver.connections[*pistol];
+                    let base = solver.particles[*i];
+                    *link = link.with_length(desired_pos.distance(base.pos));
+                });
+            }
+        }
+    }
+
+    pub fn handle_packets(&mut self, solver: &mut Solver, packets: &Vec<IndexedGamePacket>) {
+        self.update_timers();
+        self.update_respawns(solver);
+        self.update_hazards(solver);
+        self.update_protection(solver);
+        self.update_hits(solver);
+        self.update_events(solver);
+        self.update_stats(solver);
+        self.prune_links(solver);
+        self.remove_expired(solver);
+        self.update_player_colors(solver);
+        self.update_players(solver);
+        self.update_mode(solver);
+        self.update_pickups(solver);
+
+        for packet in packets {
+            self.handle_packet(solver, packet);
+        }
+    }
+
+    pub fn handle_packet(&mut self, solver: &mut Solver, packet: &IndexedGamePacket) {
+        let tick = self.tick;
+        // every client drops the shots the rules forbid, whatever the shooter's client sent
+        if let GamePacket::Fire(projectile) = packet.contents {
+            if !self.projectile_allowed(projectile) {
+                return;
+            }
+        }
+        let Some(player) = self.get_player_mut(packet.id) else {
+            return;
+        };
+        // reported even while the tank is dead
+        if let GamePacket::Ping(ms) = packet.contents {
+            player.stats.ping = ms;
+            return;
+        }
+        // check if player's tank is active
+        if !Self::player_alive(player, solver) {
+            return;
+        }
+
+        let center = solver.particles[player.model.center];
+        let mut event = None;
+        let mut shield = None;
+
+        match packet.contents {
+            GamePacket::Motor(ind, acc) => {
+                let ind = ind as usize;
+                if solver.particles.get(ind).map_or(false, |p| p.is_motor()) {
+                    // applied here rather than by the driver's client, so every client drives the tank the same
+                    let acc = if tick < player.speed_tick { acc * Player::SPEED_BOOST } else { acc };
+                    solver.particles[ind].set_kind(Kind::Motor(acc));
+                    if acc != 0. {
+                        event = Some(GameEvent::Motor { player: player.id, pos: center.pos });
+                    }
+                }
+            }
+            GamePacket::Spawn(pos) => {
+                solver.add_particle(
+                    GROUND.with_position(pos).with_velocity(vec2(0., -0.5)).with_lifetime(SPAWN_LIFETIME),
+                );
+            }
+            GamePacket::Shield => {
+                // every client checks the cooldown and the energy, whatever the sender's client does
+                if !player.protected(tick) && tick >= player.shield_ready && player.energy >= Player::SHIELD_ENERGY {
+                    player.energy -= Player::SHIELD_ENERGY;
+                    player.shield_tick = tick + SHIELD_TICKS;
+                    player.shield_ready = tick + SHIELD_COOLDOWN;
+                    shield = Some((player.shield_tick, player.shield_ready));
+                }
+            }
+            GamePacket::Dash(coeff) => {
+                let vel = (center.velocity() * coeff).clamp_length(0.05, 0.1);
+                for p in &mut solver.particles[player.model.range.clone()] {
+                    p.set_velocity(coeff * vel);
+                }
+            }
+            GamePacket::Thrust(left, right) => {
+                player.thrust = (left, right);
+                if left != 0. || right != 0. {
+                    event = Some(GameEvent::Motor { player: player.id, pos: center.pos });
+                }
+            }
+            GamePacket::Muzzle(desired_pos) => {
+                player.aim = Some(desired_pos);
+            }
+            GamePacket::ResetMuzzle => {
+                player.aim = None;
+            }
+            GamePacket::Fire(bullet) => {
+                let center = &solver.particles[player.model.center];
+                let muzzle_end = &solver.particles[player.model.muzzle];
+                let muzzle_dir = (muzzle_end.pos - center.pos).normalize();
+                let bullet_pos = center.pos + muzzle_dir * 10.;
+
+                let Some((mut projectile, force)) = (match bullet {
+                    0 => Some((PROJECTILE_HEAVY, 0.6)),
+                    1 => Some((PROJECTILE_IMPULSE, 0.25)),
+                    2 => Some((PROJECTILE_STICKY, 0.1)),
+                    3 => Some((
+                        PROJECTILE_MINE.with_kind(Kind::Mine {
+                            owner: player.id,
+                            arm: MINE_ARM_TICKS,
+                            anchor: None,
+                        }),
+                        0.15,
+                    )),
+                    4 => Some((PROJECTILE_INCENDIARY, 0.3)),
+                    5 => Some((PROJECTILE_EXPLOSIVE, 0.4)),
+                    _ => None,
+                }) else {
+                    return;
+                };
+                if tick < player.boost_tick {
+                    projectile.mass *= Player::DAMAGE_BOOST;
+                }
+                let lifetime = match projectile.kind {
+                    Kind::Mine { .. } => MINE_LIFETIME,
+                    _ => PROJECTILE_LIFETIME,
+                };
+
+                solver.add_particle(
+                    projectile
+                        .with_owner(player.id)
+                        .with_position(bullet_pos)
+                        .with_velocity(muzzle_dir * force * player.model.shot_force)
+                        .with_lifetime(lifetime),
+                );
+
+                let imp = force * player.model.shot_force * muzzle_dir.length() * projectile.mass;
+                let muzzle_end = &mut solver.particles[player.model.muzzle];
+                let recoil = imp / muzzle_end.mass / 100.;
+                player.model.for_each(|i| {
+                    solver.particles[i].add_velocity(-recoil * muzzle_dir);
+                });
+                event = Some(GameEvent::Fire { pos: bullet_pos, projectile: bullet });
+            }
+            GamePacket::None | GamePacket::Ping(_) => (),
+        }
+        if let Some((shield_tick, shield_ready)) = shield.filter(|_| packet.id == self.player.id) {
+            self.player.shield_tick = shield_tick;
+            self.player.shield_ready = shield_ready;
+            self.player.energy = (self.player.energy - Player::SHIELD_ENERGY).max(0.);
+        }
+        if let Some(event) = event {
+            self.push_event(event);
+        }
+    }
+
+    pub fn add_particle(&self, pos: Vec2) -> Vec<GamePacket> {
+        vec![GamePacket::Spawn(pos)]
+    }
+
+    pub fn move_tank(&mut self, coeff: f32) -> Vec<GamePacket> {
+        self.player.driving = coeff != 0.;
+        self.player.drive(coeff)
+    }
+
+    pub fn move_muzzle(&self, desired_pos: Vec2) -> Vec<GamePacket> {
+        vec![GamePacket::Muzzle(desired_pos)]
+    }
+
+    pub fn reset_muzzle(&self) -> Vec<GamePacket> {
+        vec![GamePacket::ResetMuzzle]
+    }
+
+    pub fn fire(&mut self) -> Vec<GamePacket> {
+        if self.player.reload_timer.not_ready() || !self.projectile_allowed(self.player.projectile) {
+            return vec![];
+        };
+
+        self.player.reload_timer.set(Player::reload_ticks(self.player.projectile));
+        vec![GamePacket::Fire(self.player.projectile)]
+    }
+
+    pub fn rotate_tank(&self, force: f32) -> Vec<GamePacket> {
+        let (left, right) = (force, -force);
+
+        vec![GamePacket::Thrust(left, right)]
+    }
+
+    pub fn dash(&mut self) -> Vec<GamePacket> {
+        if self.player.energy < Player::DASH_ENERGY {
+            return vec![];
+        }
+        let energy = &mut self.player.energy;
+        self.player.dash_timer.map_or(vec![], 4800, || {
+            *energy -= Player::DASH_ENERGY;
+            vec![GamePacket::Dash(2.)]
+        })
+    }
+
+    /// Shields the tank for a while, nothing is sent while it's still protected, the last shield cools down
+    /// or there isn't enough energy. The timer only keeps a packet on its way from being sent twice,
+    /// the controller has the last word and charges the energy once the shield is up.
+    pub fn shield(&mut self) -> Vec<GamePacket> {
+        if self.player.protected(self.tick)
+            || self.tick < self.player.shield_ready
+            || self.player.energy < Player::SHIELD_ENERGY
+        {
+            return vec![];
+        }
+        self.player.shield_timer.map_or(vec![], SHIELD_COOLDOWN as isize, || vec![GamePacket::Shield])
+    }
+}
+
+/// Whether projectile `p` is in the mask of allowed projectiles.
+fn allows(projectiles: u8, p: u8) -> bool {
+    p < u8::BITS as u8 && projectiles & 1 << p != 0
+}
+
+fn get_color(a: f32) -> Vec4 {
+    let a = a.max(0.);
+    let color = Color::hsl(a * 120., 1., if a == 0. { 0. } else { 0.7 }).to_linear();
+    vec4(color.red, color.green, color.blue, 1.)
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct TickTimer {
+    pub tick: isize,
+    last: isize,
+}
+
+impl TickTimer {
+    pub fn new() -> Self {
+        Self { tick: 0, last: 0 }
+    }
+
+    pub fn set(&mut self, ticks: isize) {
+        self.tick = ticks;
+        self.last = ticks;
+    }
+
+    pub fn update(&mut self) {
+        self.tick -= 1;
+    }
+
+    pub fn ready(&self) -> bool {
+        self.tick <= 0
+    }
+
+    pub fn not_ready(&self) -> bool {
+        self.tick > 0
+    }
+
+    pub fn map_or<U, F: FnOnce() -> U>(&mut self, default: U, ticks: isize, f: F) -> U {
+        if self.ready() {
+            self.set(ticks);
+            f()
+        } else {
+            default
+        }
+    }
+
+    pub fn progress(&self) -> f32 {
+        if self.last <= 0 {
+            return 1.;
+        }
+        let elapsed = self.last - self.tick;
+        (elapsed as f32 / self.last as f32).clamp(0., 1.)
+    }
+}
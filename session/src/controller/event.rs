@@ -0,0 +1,74 @@
+use bevy::math::Vec2;
+use solver::{Solver, SolverEvent};
+
+pub const EVENT_TICKS: u128 = 400; // ticks an event is kept for the client to pick up (~1 second)
+const IMPACT_THRESHOLD: f32 = 0.1; // smaller changes of a projectile's velocity come from gravity and rolling
+
+/// Something audible that happened in the match, picked up by the client to play a sound.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameEvent {
+    Fire { pos: Vec2, projectile: u8 },
+    Impact { pos: Vec2, strength: f32 }, // change of the projectile's velocity
+    Motor { player: u8, pos: Vec2 },
+    LinkBreak { pos: Vec2 },
+    Explosion { pos: Vec2 },
+    Hazard { player: u8, pos: Vec2 }, // the player's tank is wearing out in a hazard of the map
+}
+
+/// Projectiles on the last tick, compared with the current ones to find the events.
+#[derive(Clone, Default)]
+pub struct EventTracker {
+    projectiles: Vec<(usize, Vec2)>, // index and velocity
+}
+
+impl EventTracker {
+    /// Follows the projectiles after the solver removed the expired ones, the removed ones are forgotten.
+    pub fn move_particles(&mut self, moved: &[Option<usize>]) {
+        self.projectiles = self
+            .projectiles
+            .iter()
+            .filter_map(|(i, velocity)| moved[*i].map(|i| (i, *velocity)))
+            .collect();
+    }
+
+    /// Turns the events of the last solve into game events, the impacts come from the velocities of the projectiles.
+    pub fn update(&mut self, solver: &Solver, mut emit: impl FnMut(GameEvent)) {
+        let mut exploded = vec![];
+        for event in solver.events() {
+            match *event {
+                SolverEvent::LinkBreak { pos, .. } => emit(GameEvent::LinkBreak { pos }),
+                SolverEvent::Explosion { index, pos, .. } => {
+                    exploded.push(index);
+                    emit(GameEvent::Explosion { pos });
+                }
+                SolverEvent::Hazard { index, pos } => {
+                    if let Some(player) = solver.particles[index].owner {
+                        emit(GameEvent::Hazard { player, pos });
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let projectiles: Vec<(usize, Vec2)> = solver
+            .particles
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.projectile)
+            .map(|(i, p)| (i, p.velocity()))
+            .collect();
+        for (i, velocity) in &projectiles {
+            let Ok(k) = self.projectiles.binary_search_by_key(i, |(j, _)| *j) else {
+                continue; // fired this tick
+            };
+            if exploded.contains(i) {
+                continue; // the blast is heard instead
+            }
+            let strength = self.projectiles[k].1.distance(*velocity);
+            if strength > IMPACT_THRESHOLD {
+                emit(GameEvent::Impact { pos: solver.particles[*i].pos, strength });
+            }
+        }
+        self.projectiles = projectiles;
+    }
+}
@@ -0,0 +1,304 @@
+use std::time::Duration;
+
+use bevy::{
+    math::Vec2,
+    utils::{HashMap, HashSet},
+};
+use common::{mode::GameMode, PHYSICS_TICK};
+use map_editor::map::{Flag, Hill};
+use render::palette::TeamColors;
+use serde::{Deserialize, Serialize};
+use solver::{particle::FLAG, Solver};
+
+use super::{Controller, Player};
+
+/// Flag placed in the solver for the capture the flag mode.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FlagState {
+    pub team: usize,
+    pub base: Vec2,
+    pub particle: usize,
+    pub carrier: Option<u8>,
+    rope: Option<usize>, // connection between the flag and its carrier
+}
+
+/// Scoring state of the game mode played in the match.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ModeState {
+    pub mode: GameMode,
+    pub hill: Option<Hill>,
+    pub hill_owner: Option<usize>,   // team holding the hill alone
+    pub flags: Vec<FlagState>,
+    pub scores: HashMap<usize, u32>, // team scores
+    pub round_ticks: Option<u128>,   // ticks until sudden death
+    score_timer: u32,
+}
+
+impl ModeState {
+    const SCORE_PERIOD: u32 = 512; // ticks between hill score updates (~1 second)
+    const FLAG_RADIUS: f32 = 5.; // distance to pick up, return or capture a flag
+    const ROPE_LENGTH: f32 = 6.;
+    const ROPE_DURABILITY: f32 = 1.;
+    const SUDDEN_DEATH_DECAY: f32 = 4e-9; // tank durability decay per tick, grows every tick
+
+    /// Places the flags in the solver if the mode needs them.
+    /// `round_time` is given in seconds, 0 disables the sudden death.
+    pub fn new(
+        mode: GameMode,
+        round_time: u32,
+        hill: Option<Hill>,
+        flags: &[Flag],
+        team_colors: &TeamColors,
+        solver: &mut Solver,
+    ) -> Self {
+        let round_ticks = (round_time > 0)
+            .then(|| Duration::from_secs(round_time as u64).as_nanos() / PHYSICS_TICK.as_nanos());
+        let flags = match mode {
+            GameMode::CaptureTheFlag { .. } => flags
+                .iter()
+                .map(|flag| {
+                    let particle = solver.size();
+                    solver.add_particle(
+                        FLAG.with_position(flag.pos)
+                            .with_color(team_colors.particle_color(flag.team)),
+                    );
+                    FlagState {
+                        team: flag.team,
+                        base: flag.pos,
+                        particle,
+                        carrier: None,
+                        rope: None,
+                    }
+                })
+                .collect(),
+            _ => vec![],
+        };
+
+        Self {
+            mode,
+            hill,
+            flags,
+            round_ticks,
+            ..Default::default()
+        }
+    }
+
+    /// Time left until the sudden death.
+    pub fn time_left(&self, tick: u128) -> Option<Duration> {
+        self.round_ticks
+            .map(|round_ticks| PHYSICS_TICK * round_ticks.saturating_sub(tick) as u32)
+    }
+
+    pub fn sudden_death(&self, tick: u128) -> bool {
+        self.round_ticks.is_some_and(|round_ticks| tick > round_ticks)
+    }
+
+    pub fn score(&self, team: usize) -> u32 {
+        self.scores.get(&team).copied().unwrap_or(0)
+    }
+
+    /// Links between the flags and their carriers.
+    pub fn ropes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.flags.iter().filter_map(|flag| flag.rope)
+    }
+
+    /// Follows the ropes after the solver dropped the broken links, see [`Solver::prune_connections`].
+    pub fn move_ropes(&mut self, moved: &[Option<usize>]) {
+        for flag in self.flags.iter_mut() {
+            flag.rope = flag.rope.and_then(|rope| moved[rope]);
+        }
+    }
+
+    /// Follows the flags after the solver removed the expired particles, see [`Solver::remove_particles`].
+    pub fn move_flags(&mut self, moved: &[Option<usize>]) {
+        for flag in self.flags.iter_mut() {
+            flag.particle = moved[flag.particle].expect("flags never expire");
+        }
+    }
+
+    fn flag_at_base(&self, team: usize, solver: &Solver) -> bool {
+        self.flags.iter().any(|flag| {
+            flag.team == team
+                && flag.carrier.is_none()
+                && solver.particles[flag.particle].pos.distance(flag.base) <= Self::FLAG_RADIUS
+        })
+    }
+
+    fn drop_flag(&mut self, f: usize, solver: &mut Solver) {
+        let flag = &mut self.flags[f];
+        if let Some(rope) = flag.rope.take() {
+            let link = &mut solver.connections[rope].2;
+            *link = link.with_durability(-1.);
+        }
+        flag.carrier = None;
+    }
+
+    fn return_flag(&mut self, f: usize, solver: &mut Solver) {
+        let flag = &self.flags[f];
+        let p = &mut solver.particles[flag.particle];
+        *p = p.with_position(flag.base);
+    }
+}
+
+impl Controller {
+    pub(super) fn update_mode(&mut self, solver: &mut Solver) {
+        self.update_sudden_death(solver);
+        match self.mode.mode {
+            GameMode::KingOfTheHill { .. } => self.update_hill(solver),
+            GameMode::CaptureTheFlag { .. } => self.update_flags(solver),
+            _ => (),
+        }
+    }
+
+    fn update_sudden_death(&self, solver: &mut Solver) {
+        let Some(round_ticks) = self.mode.round_ticks else {
+            return;
+        };
+        if self.tick <= round_ticks {
+            return;
+        }
+
+        // tanks fall apart faster and faster so the match always ends
+        let decay = ModeState::SUDDEN_DEATH_DECAY * (self.tick - round_ticks) as f32;
+        for player in self.players.iter() {
+            for i in player.model.base_connections.iter() {
+                solver.damage_link(*i, decay);
+            }
+        }
+    }
+
+    fn update_hill(&mut self, solver: &Solver) {
+        let Some(hill) = &self.mode.hill else {
+            return;
+        };
+
+        let teams: HashSet<usize> = self
+            .players
+            .iter()
+            .filter(|p| Self::player_alive(p, solver))
+            .filter(|p| hill.contains(Self::get_player_pos(p, solver)))
+            .map(|p| p.team)
+            .collect();
+        self.mode.hill_owner = if teams.len() == 1 {
+            teams.into_iter().next()
+        } else {
+            None
+        };
+
+        self.mode.score_timer += 1;
+        if self.mode.score_timer >= ModeState::SCORE_PERIOD {
+            self.mode.score_timer = 0;
+            if let Some(team) = self.mode.hill_owner {
+                *self.mode.scores.entry(team).or_insert(0) += 1;
+            }
+        }
+    }
+
+    fn update_flags(&mut self, solver: &mut Solver) {
+        for f in 0..self.mode.flags.len() {
+            let flag = &self.mode.flags[f];
+            let flag_pos = solver.particles[flag.particle].pos;
+
+            if let Some(id) = flag.carrier {
+                let carrier = self.get_player(id).unwrap();
+                let rope = flag.rope.unwrap();
+                if !Self::player_alive(carrier, solver) || solver.connections[rope].2.durability() < 0. {
+                    self.mode.drop_flag(f, solver);
+                    continue;
+                }
+
+                let team = carrier.team;
+                let home = self.mode.flags.iter().find(|flag| flag.team == team).map(|flag| flag.base);
+                let captured = home.is_some_and(|home| {
+                    Self::get_player_pos(carrier, solver).distance(home) <= ModeState::FLAG_RADIUS
+                        && self.mode.flag_at_base(team, solver)
+                });
+                if captured {
+                    *self.mode.scores.entry(team).or_insert(0) += 1;
+                    self.mode.drop_flag(f, solver);
+                    self.mode.return_flag(f, solver);
+                }
+                continue;
+            }
+
+            let toucher = self.players.iter().find(|p| {
+                Self::player_alive(p, solver)
+                    && Self::get_player_pos(p, solver).distance(flag_pos) <= ModeState::FLAG_RADIUS
+            });
+            match toucher {
+                Some(player) if player.team != flag.team => {
+                    let flag = &mut self.mode.flags[f];
+                    flag.carrier = Some(player.id);
+                    flag.rope = Some(solver.connections.len());
+                    solver.add_rope(
+                        player.model.center,
+                        flag.particle,
+                        ModeState::ROPE_LENGTH,
+                        ModeState::ROPE_DURABILITY,
+                    );
+                }
+                Some(_) if flag_pos.distance(flag.base) > ModeState::FLAG_RADIUS => {
+                    self.mode.return_flag(f, solver);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Flag carried by the player, if any.
+    pub fn carried_flag(&self, id: u8) -> Option<&FlagState> {
+        self.mode.flags.iter().find(|flag| flag.carrier == Some(id))
+    }
+
+    /// Winning team and its players, if the match is over.
+    pub fn get_winners(&self, solver: &Solver) -> Option<(usize, Vec<&Player>)> {
+        match self.mode.mode {
+            GameMode::TeamDeathmatch => self.last_team_standing(solver),
+            GameMode::Deathmatch => self.last_player_standing(solver),
+            GameMode::KingOfTheHill { score_limit } | GameMode::CaptureTheFlag { score_limit } => self
+                .mode
+                .scores
+                .iter()
+                .find(|(_, score)| **score >= score_limit)
+                .map(|(team, _)| {
+                    let players = self.players.iter().filter(|p| p.team == *team).collect();
+                    (*team, players)
+                })
+                .or_else(|| self.last_team_standing(solver)),
+            GameMode::Sandbox => None,
+        }
+    }
+
+    fn last_team_standing(&self, solver: &Solver) -> Option<(usize, Vec<&Player>)> {
+        let mut team_num = HashMap::<usize, Vec<&Player>>::new();
+        for p in self.players.iter() {
+            if Self::player_in_game(p, solver) {
+                let v = team_num.entry(p.team).or_insert(vec![]);
+                v.push(p);
+            }
+        }
+
+        let team = if team_num.keys().len() == 1 {
+            Some(*team_num.keys().next().unwrap())
+        } else {
+            None
+        };
+
+        team.map(|team| {
+            let players = team_num.remove(&team).unwrap();
+            (team, players)
+        })
+    }
+
+    fn last_player_standing(&self, solver: &Solver) -> Option<(usize, Vec<&Player>)> {
+        let alive: Vec<&Player> = self
+            .players
+            .iter()
+            .filter(|p| Self::player_in_game(p, solver))
+            .collect();
+        match alive[..] {
+            [winner] => Some((winner.team, alive)),
+            _ => None,
+        }
+    }
+}
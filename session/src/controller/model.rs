@@ -0,0 +1,474 @@
+use std::{ops::Range, path::Path};
+
+use bevy::math::{vec4, Vec2, Vec4};
+use common::chassis::{Chassis, Garage};
+use map_editor::vehicle::{Vehicle, VehicleError};
+use serde::{Deserialize, Serialize};
+use solver::{
+    chain_model, model,
+    particle::{team_group, Particle, METAL, MOTOR, SPIKE},
+    Connection, Link, Model, Solver,
+};
+
+pub const CENTER_HP: f32 = 1.;
+pub const CENTER_ELASTICITY: f32 = 100.;
+
+pub const MUZZLE_ELASTICITY: f32 = 100.;
+
+pub const TREAD_ELASTICITY: f32 = 30.;
+pub const TREAD_HP: f32 = 3.;
+
+pub const TEAM_TINT: f32 = 0.6; // how much of the team color is mixed into the metal parts
+
+pub const BASE_HP: f32 = 12.;
+pub const BASE_ELASTICITY: f32 = 10.;
+
+pub const PISTOL_HP: f32 = 7.;
+pub const PISTOL_ELASTICITY: f32 = 25.;
+
+pub const ARTILLERY_HP: f32 = 16.; // of the base links, the artillery takes more hits than the tank
+pub const ARTILLERY_SHOT_FORCE: f32 = 1.6; // its long muzzle throws the shells this many times harder
+
+#[derive(Default, Clone)]
+pub struct RawPlayerModel {
+    pub particles: Vec<Particle>,
+    pub connections: Vec<Connection>,
+    pub base_connections: Vec<usize>, // base connections
+    pub left_motors: Vec<usize>,      // controlled motors
+    pub right_motors: Vec<usize>,     // controlled motors
+    pub pistols: Vec<usize>,          // controlled connnections
+    pub center: usize,                // main particle
+    pub muzzle: usize,                // end of the muzzle
+    pub center_connection: usize,     // hp
+    pub shot_force: f32,              // the muzzle fires the projectiles this many times harder
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PlayerModel {
+    pub range: Range<usize>,          // range of the particles in the solver
+    pub links: Range<usize>,          // range of the connections in the solver
+    pub max_hp: f32,                  // max health of the base
+    pub base_connections: Vec<usize>, // base connections
+    pub left_motors: Vec<usize>,      // controlled motors
+    pub right_motors: Vec<usize>,     // controlled motors
+    pub pistols: Vec<usize>,          // controlled connnections
+    pub center: usize,                // main particle
+    pub muzzle: usize,                // end of the muzzle
+    pub center_connection: usize,     // hp
+    pub shot_force: f32,              // the muzzle fires the projectiles this many times harder
+}
+
+impl PlayerModel {
+    /// Follows the links after the solver dropped the broken ones, the links of a tank are always kept.
+    pub fn move_links(&mut self, moved: &[Option<usize>]) {
+        let shift = |i: usize| moved[i].expect("links of a tank are never pruned");
+        let len = self.links.len();
+        let start = shift(self.links.start);
+        self.links = start..start + len;
+        self.base_connections.iter_mut().for_each(|i| *i = shift(*i));
+        self.pistols.iter_mut().for_each(|i| *i = shift(*i));
+        self.center_connection = shift(self.center_connection);
+    }
+
+    /// Follows the particles after the solver removed the expired ones, the particles of a tank never expire.
+    pub fn move_particles(&mut self, moved: &[Option<usize>]) {
+        let shift = |i: usize| moved[i].expect("particles of a tank never expire");
+        let len = self.range.len();
+        let start = shift(self.range.start);
+        self.range = start..start + len;
+        self.left_motors.iter_mut().for_each(|i| *i = shift(*i));
+        self.right_motors.iter_mut().for_each(|i| *i = shift(*i));
+        self.center = shift(self.center);
+        self.muzzle = shift(self.muzzle);
+    }
+
+    pub fn for_each<F: FnMut(usize)>(&self, mut f: F) {
+        for i in self.range.clone() {
+            f(i);
+        }
+    }
+}
+
+#[allow(unused_mut, unused_assignments)]
+impl RawPlayerModel {
+    /// Model of a built-in chassis, a custom vehicle comes from its file and [`RawPlayerModel::picked`] takes it.
+    pub fn generate(chassis: Chassis) -> Self {
+        match chassis {
+            Chassis::Tank | Chassis::Custom => Self::generate_tank(),
+            Chassis::Scout => Self::generate_scout(),
+            Chassis::Artillery => Self::generate_artillery(),
+        }
+    }
+
+    /// Model the player picked, a custom vehicle that doesn't load is replaced by the tank on every client alike.
+    pub fn picked(garage: &Garage, id: u8) -> Self {
+        match garage.chassis(id) {
+            Chassis::Custom => garage
+                .vehicle(id)
+                .and_then(|bytes| Self::from_bytes(bytes).ok())
+                .unwrap_or_else(Self::generate_tank),
+            chassis => Self::generate(chassis),
+        }
+    }
+
+    /// Loads a vehicle exported by the editor, rejecting the ones too big or too heavy to play with.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, VehicleError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VehicleError> {
+        Vehicle::deserialize(bytes).map(Self::from_vehicle)
+    }
+
+    pub fn from_vehicle(vehicle: Vehicle) -> Self {
+        Self {
+            particles: vehicle.particles,
+            connections: vehicle.connections,
+            base_connections: vehicle.base_connections,
+            left_motors: vehicle.left_motors,
+            right_motors: vehicle.right_motors,
+            pistols: vehicle.pistols,
+            center: vehicle.center,
+            muzzle: vehicle.muzzle,
+            center_connection: vehicle.center_connection,
+            shot_force: 1.,
+        }
+    }
+
+    pub fn generate_tank() -> Self {
+        // TODO: make it a constant
+        let link = Link::Rigid {
+            length: 1.,
+            durability: BASE_HP,
+            elasticity: BASE_ELASTICITY,
+        };
+
+        let mut left_base;
+        let mut center_base;
+        let mut right_base;
+
+        let mut main;
+        let mut muzzle_end;
+
+        let mut main_connection = 0;
+        let mut last_base_connection = 0;
+        let (mut pistol1, mut pistol2) = (0, 0);
+
+        let (mut l0, mut l1, mut l2, mut l3, mut l4, mut l5) = (0, 0, 0, 0, 0, 0); // left motors
+        let (mut r0, mut r1, mut r2) = (0, 0, 0); // right motors
+
+        let mut tank = model! {
+            METAL.with_color(vec4(0.5, 0.8, 0., 1.)); link => .hex:false [
+                @left_base = -4,0; -3,-0.5; -3,0.5; -2,0; -1,-0.5; -1,0.5;
+                0,0; @center_base = 0,1;
+                1,-0.5; 1,0.5; 2,0; 3,-0.5;3,0.5; @right_base = 4,0
+                ] + [0=>1,2; 1,2=>3; 3=>4,5; 4,5=>6,7; 6,7=>8,9; 8,9=>10; 10=>11,12; 11,12=>13; 0=>13]
+
+            METAL.with_color(vec4(0.25, 0.4, 0., 1.)); link.with_elasticity(MUZZLE_ELASTICITY) => .hex:false [
+                @main = 0,2; 0,3; 0,4; 0,5; 0,6; 0,7; @muzzle_end = 0,8
+            ] + [0=>1; 1=>2; 2=>3; 3=>4; 4=>5; 5=>6]
+
+            none; link.with_durability(PISTOL_HP).with_elasticity(PISTOL_ELASTICITY) => .hex:false [] + [
+                .global:true left_base, right_base => .global:true main;
+                @pistol1 = .global:true left_base => .global:true muzzle_end;
+                @pistol2 = .global:true right_base => .global:true muzzle_end
+            ]
+
+            none; link.with_durability(CENTER_HP).with_elasticity(CENTER_ELASTICITY) => .hex:false [] + [
+                @main_connection = .global:true center_base => .global:true main
+            ]
+
+            MOTOR.with_color(vec4(0.25, 0.25, 0.25, 1.)); link => .offset:vec2(0.,-3.), .hex:true [
+                @l0 = -7.5,2; @l1 = -5.5,0; @l2 = -2,0; @l3 = 2,0; @l4 = 5.5,0; @l5 = 5.5,2;
+                @r0 = -5.5,2; @r1 = -1,2; @r2 = 3.5,2
+            ] + [
+                0 => 1; 1 => 2; 2 => 3; 3 => 4; 4 => 5; 0 => 5; 1 => 4; 0 => 4;
+                0,1 => 6; 4,5 => 8; 2,3 => 7;
+
+                .global:true left_base => 0,1; .global:true center_base => 2,3; @last_base_connection = .global:true right_base => 4,5
+            ]
+        };
+
+        let tread = chain_model! [
+            METAL; link.with_elasticity(TREAD_ELASTICITY).with_durability(TREAD_HP); 2=>SPIKE;link.with_elasticity(100.) => .start:vec2(-6., -3.-SHIFT_Y.y);
+            r:12, ur:3, ul:1, l:1, dl:2, l:10, ul:2, l:1, dl:1, dr:3
+        ];
+
+        tank = tank + tread;
+
+        Self {
+            particles: tank.particles,
+            connections: tank.connections,
+            base_connections: (0..=last_base_connection).collect(),
+            center: main,
+            muzzle: muzzle_end,
+            center_connection: main_connection,
+            left_motors: vec![l0, l1, l2, l3, l4, l5],
+            right_motors: vec![r0, r1, r2],
+            pistols: vec![pistol1, pistol2],
+            shot_force: 1.,
+        }
+    }
+
+    /// Shorter tank on four wheels with a short muzzle, about two thirds of the particles of the tank.
+    pub fn generate_scout() -> Self {
+        let link = Link::Rigid {
+            length: 1.,
+            durability: BASE_HP,
+            elasticity: BASE_ELASTICITY,
+        };
+
+        let mut left_base;
+        let mut center_base;
+        let mut right_base;
+
+        let mut main;
+        let mut muzzle_end;
+
+        let mut main_connection = 0;
+        let mut last_base_connection = 0;
+        let (mut pistol1, mut pistol2) = (0, 0);
+
+        let (mut l0, mut l1, mut l2, mut l3) = (0, 0, 0, 0); // left motors
+        let (mut r0, mut r1) = (0, 0); // right motors
+
+        let mut scout = model! {
+            METAL.with_color(vec4(0.5, 0.8, 0., 1.)); link => .hex:false [
+                @left_base = -3,0; -2,-0.5; -2,0.5; -1,0;
+                0,0; @center_base = 0,1;
+                1,0; 2,-0.5; 2,0.5; @right_base = 3,0
+                ] + [0=>1,2; 1,2=>3; 3=>4,5; 4,5=>6; 6=>7,8; 7,8=>9; 0=>9]
+
+            METAL.with_color(vec4(0.25, 0.4, 0., 1.)); link.with_elasticity(MUZZLE_ELASTICITY) => .hex:false [
+                @main = 0,2; 0,3; 0,4; 0,5; @muzzle_end = 0,6
+            ] + [0=>1; 1=>2; 2=>3; 3=>4]
+
+            none; link.with_durability(PISTOL_HP).with_elasticity(PISTOL_ELASTICITY) => .hex:false [] + [
+                .global:true left_base, right_base => .global:true main;
+                @pistol1 = .global:true left_base => .global:true muzzle_end;
+                @pistol2 = .global:true right_base => .global:true muzzle_end
+            ]
+
+            none; link.with_durability(CENTER_HP).with_elasticity(CENTER_ELASTICITY) => .hex:false [] + [
+                @main_connection = .global:true center_base => .global:true main
+            ]
+
+            MOTOR.with_color(vec4(0.25, 0.25, 0.25, 1.)); link => .offset:vec2(0.,-3.), .hex:true [
+                @l0 = -6,2; @l1 = -4,0; @l2 = 4,0; @l3 = 4,2;
+                @r0 = -3.5,2; @r1 = 1.5,2
+            ] + [
+                0 => 1; 1 => 2; 2 => 3; 0 => 3; 1 => 3; 0 => 2;
+                0,1 => 4; 2,3 => 5;
+
+                .global:true left_base => 0,1; .global:true center_base => 1,2; @last_base_connection = .global:true right_base => 2,3
+            ]
+        };
+
+        let tread = chain_model! [
+            METAL; link.with_elasticity(TREAD_ELASTICITY).with_durability(TREAD_HP); 2=>SPIKE;link.with_elasticity(100.) => .start:vec2(-4.5, -3.-SHIFT_Y.y);
+            r:9, ur:3, ul:1, l:1, dl:2, l:7, ul:2, l:1, dl:1, dr:3
+        ];
+
+        scout = scout + tread;
+
+        Self {
+            particles: scout.particles,
+            connections: scout.connections,
+            base_connections: (0..=last_base_connection).collect(),
+            center: main,
+            muzzle: muzzle_end,
+            center_connection: main_connection,
+            left_motors: vec![l0, l1, l2, l3],
+            right_motors: vec![r0, r1],
+            pistols: vec![pistol1, pistol2],
+            shot_force: 1.,
+        }
+    }
+
+    /// The tank with a sturdier base and a muzzle twice as long, which fires further.
+    pub fn generate_artillery() -> Self {
+        let link = Link::Rigid {
+            length: 1.,
+            durability: ARTILLERY_HP,
+            elasticity: BASE_ELASTICITY,
+        };
+
+        let mut left_base;
+        let mut center_base;
+        let mut right_base;
+
+        let mut main;
+        let mut muzzle_end;
+
+        let mut main_connection = 0;
+        let mut last_base_connection = 0;
+        let (mut pistol1, mut pistol2) = (0, 0);
+
+        let (mut l0, mut l1, mut l2, mut l3, mut l4, mut l5) = (0, 0, 0, 0, 0, 0); // left motors
+        let (mut r0, mut r1, mut r2) = (0, 0, 0); // right motors
+
+        let mut artillery = model! {
+            METAL.with_color(vec4(0.5, 0.8, 0., 1.)); link => .hex:false [
+                @left_base = -4,0; -3,-0.5; -3,0.5; -2,0; -1,-0.5; -1,0.5;
+                0,0; @center_base = 0,1;
+                1,-0.5; 1,0.5; 2,0; 3,-0.5;3,0.5; @right_base = 4,0
+                ] + [0=>1,2; 1,2=>3; 3=>4,5; 4,5=>6,7; 6,7=>8,9; 8,9=>10; 10=>11,12; 11,12=>13; 0=>13]
+
+            METAL.with_color(vec4(0.25, 0.4, 0., 1.)); link.with_elasticity(MUZZLE_ELASTICITY) => .hex:false [
+                @main = 0,2; 0,3; 0,4; 0,5; 0,6; 0,7; 0,8; 0,9; 0,10; 0,11; 0,12; 0,13; @muzzle_end = 0,14
+            ] + [0=>1; 1=>2; 2=>3; 3=>4; 4=>5; 5=>6; 6=>7; 7=>8; 8=>9; 9=>10; 10=>11; 11=>12]
+
+            none; link.with_durability(PISTOL_HP).with_elasticity(PISTOL_ELASTICITY) => .hex:false [] + [
+                .global:true left_base, right_base => .global:true main;
+                @pistol1 = .global:true left_base => .global:true muzzle_end;
+                @pistol2 = .global:true right_base => .global:true muzzle_end
+            ]
+
+            none; link.with_durability(CENTER_HP).with_elasticity(CENTER_ELASTICITY) => .hex:false [] + [
+                @main_connection = .global:true center_base => .global:true main
+            ]
+
+            MOTOR.with_color(vec4(0.25, 0.25, 0.25, 1.)); link => .offset:vec2(0.,-3.), .hex:true [
+                @l0 = -7.5,2; @l1 = -5.5,0; @l2 = -2,0; @l3 = 2,0; @l4 = 5.5,0; @l5 = 5.5,2;
+                @r0 = -5.5,2; @r1 = -1,2; @r2 = 3.5,2
+            ] + [
+                0 => 1; 1 => 2; 2 => 3; 3 => 4; 4 => 5; 0 => 5; 1 => 4; 0 => 4;
+                0,1 => 6; 4,5 => 8; 2,3 => 7;
+
+                .global:true left_base => 0,1; .global:true center_base => 2,3; @last_base_connection = .global:true right_base => 4,5
+            ]
+        };
+
+        let tread = chain_model! [
+            METAL; link.with_elasticity(TREAD_ELASTICITY).with_durability(TREAD_HP); 2=>SPIKE;link.with_elasticity(100.) => .start:vec2(-6., -3.-SHIFT_Y.y);
+            r:12, ur:3, ul:1, l:1, dl:2, l:10, ul:2, l:1, dl:1, dr:3
+        ];
+
+        artillery = artillery + tread;
+
+        Self {
+            particles: artillery.particles,
+            connections: artillery.connections,
+            base_connections: (0..=last_base_connection).collect(),
+            center: main,
+            muzzle: muzzle_end,
+            center_connection: main_connection,
+            left_motors: vec![l0, l1, l2, l3, l4, l5],
+            right_motors: vec![r0, r1, r2],
+            pistols: vec![pistol1, pistol2],
+            shot_force: ARTILLERY_SHOT_FORCE,
+        }
+    }
+
+    pub fn with_tint(mut self, tint: Vec4) -> Self {
+        for p in self.particles.iter_mut().filter(|p| p.texture == METAL.texture) {
+            p.color = p.color.lerp(tint, TEAM_TINT);
+        }
+        self
+    }
+
+    /// Puts the tank in the collision group of the team, see [`team_group`].
+    pub fn with_team(mut self, team: usize) -> Self {
+        for p in self.particles.iter_mut() {
+            p.collision_group = team_group(team);
+        }
+        self
+    }
+
+    pub fn model(self) -> Model {
+        let center = self.particles[self.center].pos;
+        Model {
+            particles: self.particles,
+            center,
+            connections: self.connections,
+            bodies: vec![],
+        }
+    }
+
+    pub fn place_in_solver(self, pos: Vec2, owner: u8, solver: &mut Solver) -> PlayerModel {
+        let particles = solver.size();
+        let connections = solver.connections.len();
+        let player_model = PlayerModel {
+            range: particles..particles + self.particles.len(),
+            links: connections..connections + self.connections.len(),
+            max_hp: self.base_connections.iter().map(|i| self.connections[*i].2.durability()).sum(),
+            base_connections: self.base_connections.iter().map(|m| *m + connections).collect(),
+            left_motors: self.left_motors.iter().map(|m| *m + particles).collect(),
+            right_motors: self.right_motors.iter().map(|m| *m + particles).collect(),
+            pistols: self.pistols.iter().map(|m| *m + connections).collect(),
+            center: self.center + particles,
+            muzzle: self.muzzle + particles,
+            center_connection: self.center_connection + connections,
+            shot_force: self.shot_force,
+        };
+
+        let mut model = self.model();
+        model.particles.iter_mut().for_each(|p| p.owner = Some(owner));
+        solver.add_model(&model, pos);
+        player_model
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_tank_test() {
+        let tank = RawPlayerModel::generate_tank();
+        println!("{}", tank.particles.len());
+        println!("{}", tank.connections.len());
+        assert_eq!(tank.pistols[0], 29);
+        assert_eq!(tank.center_connection, 31);
+    }
+
+    #[test]
+    fn generate_chassis_test() {
+        let tank = RawPlayerModel::generate(Chassis::Tank);
+        let scout = RawPlayerModel::generate(Chassis::Scout);
+        let artillery = RawPlayerModel::generate(Chassis::Artillery);
+        assert!(scout.particles.len() < tank.particles.len());
+        assert!(artillery.shot_force > tank.shot_force);
+
+        for model in [tank, scout, artillery] {
+            // the muzzle hangs on the center link and is aimed by the pistols
+            assert_eq!(model.connections[model.center_connection].1, model.center);
+            assert!(model.pistols.iter().all(|i| model.connections[*i].1 == model.muzzle));
+        }
+    }
+
+    #[test]
+    fn vehicle_test() {
+        let vehicle = |model: RawPlayerModel| Vehicle {
+            name: "test".to_string(),
+            particles: model.particles,
+            connections: model.connections,
+            base_connections: model.base_connections,
+            left_motors: model.left_motors,
+            right_motors: model.right_motors,
+            pistols: model.pistols,
+            center: model.center,
+            muzzle: model.muzzle,
+            center_connection: model.center_connection,
+        };
+        // the built-in chassis fit in the limits of a custom one
+        for chassis in Chassis::ALL {
+            let model = RawPlayerModel::generate(chassis);
+            let loaded = RawPlayerModel::from_bytes(&vehicle(model.clone()).serialize()).unwrap();
+            assert_eq!(loaded.particles.len(), model.particles.len());
+            assert_eq!(loaded.center_connection, model.center_connection);
+        }
+
+        let mut heavy = vehicle(RawPlayerModel::generate_tank());
+        heavy.particles.iter_mut().for_each(|p| p.mass = 100.);
+        assert!(matches!(RawPlayerModel::from_bytes(&heavy.serialize()), Err(VehicleError::TooHeavy(_))));
+        let mut broken = vehicle(RawPlayerModel::generate_tank());
+        broken.center_connection = broken.connections.len();
+        assert!(matches!(RawPlayerModel::from_bytes(&broken.serialize()), Err(VehicleError::Corrupt(_))));
+
+        // a vehicle that doesn't load drives the tank instead
+        let garage = Garage { chassis: vec![(0, Chassis::Custom)], vehicles: vec![(0, vec![1, 2, 3])] };
+        let tank = RawPlayerModel::generate_tank();
+        assert_eq!(RawPlayerModel::picked(&garage, 0).particles.len(), tank.particles.len());
+    }
+}
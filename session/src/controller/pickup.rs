@@ -0,0 +1,97 @@
+use bevy::math::Vec2;
+use map_editor::map::Pickup;
+use serde::{Deserialize, Serialize};
+use solver::{
+    particle::{Item, Kind, Particle, PICKUP},
+    Solver,
+};
+
+use super::{model::BASE_HP, Controller, Player};
+
+/// Pickup placed in the solver, hidden while it waits to respawn.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PickupState {
+    pub item: Item,
+    pub pos: Vec2,
+    pub particle: usize,
+    respawn_tick: Option<u128>,
+}
+
+impl PickupState {
+    const RESPAWN_TICKS: u128 = 8000;
+    const REPAIR: f32 = 0.5 * BASE_HP; // durability restored to every base link
+
+    pub fn place(pickups: &[Pickup], solver: &mut Solver) -> Vec<Self> {
+        pickups
+            .iter()
+            .map(|pickup| {
+                let particle = solver.size();
+                solver.add_particle(Self::particle(pickup.item, pickup.pos, true));
+                Self {
+                    item: pickup.item,
+                    pos: pickup.pos,
+                    particle,
+                    respawn_tick: None,
+                }
+            })
+            .collect()
+    }
+
+    fn particle(item: Item, pos: Vec2, active: bool) -> Particle {
+        let mut color = item.color();
+        if !active {
+            color.w = 0.;
+        }
+        PICKUP
+            .with_kind(Kind::Pickup { item, active, taker: None })
+            .with_position(pos)
+            .with_color(color)
+    }
+}
+
+impl Controller {
+    pub(super) fn update_pickups(&mut self, solver: &mut Solver) {
+        for i in 0..self.pickups.len() {
+            let pickup = &self.pickups[i];
+            if let Some(respawn_tick) = pickup.respawn_tick {
+                if self.tick >= respawn_tick {
+                    solver.particles[pickup.particle] = PickupState::particle(pickup.item, pickup.pos, true);
+                    self.pickups[i].respawn_tick = None;
+                }
+                continue;
+            }
+
+            let Kind::Pickup { taker: Some(id), .. } = solver.particles[pickup.particle].kind else {
+                continue;
+            };
+            let (item, pos, particle) = (pickup.item, pickup.pos, pickup.particle);
+            solver.particles[particle] = PickupState::particle(item, pos, false);
+            self.pickups[i].respawn_tick = Some(self.tick + PickupState::RESPAWN_TICKS);
+            self.apply_item(id, item, solver);
+        }
+    }
+
+    fn apply_item(&mut self, id: u8, item: Item, solver: &mut Solver) {
+        let tick = self.tick;
+        let Some(player) = self.get_player_mut(id) else {
+            return;
+        };
+        match item {
+            Item::Repair => {
+                for i in player.model.base_connections.iter() {
+                    let link = &mut solver.connections[*i].2;
+                    if link.durability() >= 0. {
+                        *link = link.with_durability((link.durability() + PickupState::REPAIR).min(BASE_HP));
+                    }
+                }
+            }
+            Item::DamageBoost => player.boost_tick = tick + Player::BOOST_TICKS,
+            Item::SpeedBoost => player.speed_tick = tick + Player::BOOST_TICKS,
+            Item::Ammo => {
+                if id == self.player.id {
+                    self.player.reload_timer.set(0);
+                }
+            }
+        }
+    }
+}
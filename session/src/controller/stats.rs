@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+use solver::Solver;
+
+use super::{Controller, Player};
+
+const ATTACK_RADIUS: f32 = 15.; // projectiles this close to a tank that lost hp are blamed for it
+const ATTRIBUTION_TICKS: u128 = 2000; // later losses still go to the last attacker, e.g. burning (~5 seconds)
+pub const KILL_TICKS: u128 = 2400; // ticks a kill stays in the feed (~5 seconds)
+
+/// What a player did in the match, shown on the scoreboard and once it's over. Damage is measured in whole tanks.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlayerStats {
+    pub damage: f32,          // dealt to enemies
+    pub friendly_damage: f32, // dealt to teammates
+    pub kills: u32,
+    pub deaths: u32,
+    pub ping: u16,            // round trip of the player's inputs in milliseconds, reported by its client
+    hp: f32,                      // hp on the last tick
+    alive: bool,                  // whether the tank was alive on the last tick
+    attacker: Option<(u8, u128)>, // last player who hurt this one and the tick of the hit
+}
+
+/// A tank destroyed in the match, `killer` is the last player who hurt it if that was recent enough to be blamed.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Kill {
+    pub tick: u128,
+    pub killer: Option<u8>,
+    pub victim: u8,
+}
+
+impl Controller {
+    /// Credits the hp every tank lost since the last tick to the player whose projectile caused it.
+    pub(super) fn update_stats(&mut self, solver: &Solver) {
+        let tick = self.tick;
+        let mut credits = vec![]; // attacker, team of the victim, damage and whether it was a kill
+        self.kills.retain(|kill| tick < kill.tick + KILL_TICKS);
+        for player in self.players.iter_mut() {
+            let hp = Self::get_player_hp(player, solver);
+            let alive = Self::player_alive(player, solver);
+            let stats = &mut player.stats;
+            let mut attacker = stats
+                .attacker
+                .filter(|(_, hit)| tick < hit + ATTRIBUTION_TICKS)
+                .map(|(id, _)| id);
+
+            let loss = stats.hp - hp; // a new tank brings the hp back up
+            if loss > 0. {
+                if let Some(id) = Self::find_attacker(player, solver) {
+                    attacker = Some(id);
+                    player.stats.attacker = Some((id, tick));
+                }
+                if let Some(id) = attacker {
+                    credits.push((id, player.team, loss, false));
+                }
+            }
+
+            let stats = &mut player.stats;
+            if stats.alive && !alive {
+                stats.deaths += 1;
+                if let Some(id) = attacker {
+                    credits.push((id, player.team, 0., true));
+                }
+                self.kills.push(Kill { tick, killer: attacker, victim: player.id });
+            }
+            stats.hp = hp;
+            stats.alive = alive;
+        }
+
+        for (id, team, damage, kill) in credits {
+            let Some(attacker) = self.players.iter_mut().find(|p| p.id == id) else {
+                continue;
+            };
+            match attacker.team == team {
+                true => attacker.stats.friendly_damage += damage,
+                false => {
+                    attacker.stats.damage += damage;
+                    attacker.stats.kills += kill as u32;
+                }
+            }
+        }
+    }
+
+    /// Owner of the closest projectile fired by someone else.
+    fn find_attacker(player: &Player, solver: &Solver) -> Option<u8> {
+        let pos = Self::get_player_pos(player, solver);
+        solver
+            .particles
+            .iter()
+            .filter(|p| p.projectile && p.owner.is_some_and(|owner| owner != player.id))
+            .map(|p| (p.owner, p.pos.distance(pos)))
+            .filter(|(_, distance)| *distance < ATTACK_RADIUS)
+            .min_by(|(_, d1), (_, d2)| d1.total_cmp(d2))
+            .and_then(|(owner, _)| owner)
+    }
+}
@@ -0,0 +1,410 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use common::{chassis::Garage, mode::MatchSettings};
+use controller::{mode::ModeState, model::RawPlayerModel, pickup::PickupState, Controller, ControllerState};
+use map_editor::map::Map;
+use packet_tools::game_packets::IndexedGamePacket;
+use render::palette::TeamColors;
+use serde::{Deserialize, Serialize};
+use solver::{Solver, SolverState};
+use tracing::trace_span;
+
+pub mod controller;
+pub mod replay;
+
+pub const SUB_TICKS: usize = 8; // ticks simulated per frame of the client
+pub const TICK_DT: f32 = 1. / 60. / SUB_TICKS as f32;
+
+/// State of the solver and the controller after a tick, sent by a server that simulates the match to correct its clients.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StateSync {
+    pub tick: u128,
+    pub solver: SolverState,
+    pub controller: ControllerState,
+}
+
+/// One match: the controller applying the players' packets and the solver simulating the map.
+/// Holds no ECS state, so the client, servers, bots and replays can all run the same ticks.
+#[derive(Clone)]
+pub struct GameSession {
+    pub controller: Controller,
+    pub solver: Solver,
+}
+
+impl GameSession {
+    /// Places every player's tank on its spawn, `id` and `name` belong to the local player.
+    /// An `id` missing from `players` makes the local player a spectator, one missing from `garage` drives the tank.
+    pub fn new(
+        id: u8,
+        name: String,
+        map: &Map,
+        players: &[(u8, String)],
+        garage: &Garage,
+        settings: &MatchSettings,
+        team_colors: &TeamColors,
+    ) -> Self {
+        let mut solver = map.solver();
+        let spawns = &map.spawns;
+        let mut placed = Vec::new();
+        for (id, name) in players.iter() {
+            let team = spawns[*id as usize].team;
+            let tank = RawPlayerModel::picked(garage, *id);
+            let model = RawPlayerModel::place_in_solver(
+                tank.clone().with_tint(team_colors.particle_color(team)).with_team(team),
+                spawns[*id as usize].pos,
+                *id,
+                &mut solver,
+            );
+            placed.push((*id, name.clone(), tank, model));
+        }
+        let pickups = PickupState::place(&map.pickups, &mut solver);
+        let mode = ModeState::new(settings.mode, settings.round_time, map.hill.clone(), &map.flags, team_colors, &mut solver);
+
+        solver.teams = spawns.iter().map(|spawn| spawn.team).collect();
+        solver.friendly_fire = settings.friendly_fire;
+
+        let mut controller = Controller::new(id, name, placed, spawns, mode, pickups, settings);
+        controller.team_colors = team_colors.clone();
+        Self { controller, solver }
+    }
+
+    /// Simulates one tick, returns the winning team once the match is over.
+    pub fn tick(&mut self, packets: &Vec<IndexedGamePacket>) -> Option<usize> {
+        Self::advance(&mut self.controller, &mut self.solver, packets)
+    }
+
+    /// Hash of the state that has to match on every client, differing hashes mean the clients desynced.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.controller.tick.hash(&mut hasher);
+        self.solver.checksum().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Current state of the solver and the controller, encoded for [`ServerPacket::StateSync`](packet_tools::server_packets::ServerPacket::StateSync).
+    pub fn state_sync(&self) -> Vec<u8> {
+        let sync = StateSync {
+            tick: self.controller.tick,
+            solver: self.solver.snapshot(),
+            controller: self.controller.snapshot(),
+        };
+        postcard::to_stdvec(&sync).unwrap()
+    }
+
+    /// Replaces the state of the solver and the controller with the one the server sent, the colors stay local.
+    /// Returns false and leaves both as they are if the state doesn't parse or belongs to another tick.
+    pub fn apply_state_sync(controller: &mut Controller, solver: &mut Solver, bytes: &[u8]) -> bool {
+        let Ok(sync) = postcard::from_bytes::<StateSync>(bytes) else {
+            return false;
+        };
+        if sync.tick != controller.tick {
+            return false;
+        }
+        solver.restore_physics(&sync.solver);
+        controller.restore(&sync.controller);
+        true
+    }
+
+    /// Same as [`GameSession::tick`] for callers that keep the controller and the solver apart.
+    pub fn advance(controller: &mut Controller, solver: &mut Solver, packets: &Vec<IndexedGamePacket>) -> Option<usize> {
+        let _span = trace_span!("tick", tick = controller.tick as u64).entered();
+        controller.handle_packets(solver, packets);
+        solver.solve(TICK_DT);
+        controller.get_winners(solver).map(|(team, _)| team)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::vec2;
+    use common::{chassis::Chassis, mode::GameMode};
+    use controller::{
+        event::{GameEvent, EVENT_TICKS},
+        Player,
+    };
+    use map_editor::{
+        constructor::MapConstructor,
+        map::{Pickup, Spawn},
+    };
+    use packet_tools::game_packets::GamePacket;
+    use render::palette::Palette;
+    use solver::{
+        particle::{Item, Kind, GROUND},
+        Constraint,
+    };
+
+    use super::*;
+
+    /// Ground under the spawns of two teams.
+    fn test_map() -> Map {
+        let mut constructor = MapConstructor::new(
+            "test".to_string(),
+            Constraint::Box(vec2(-60., -20.), vec2(60., 40.)),
+        );
+        constructor.add_layer();
+        let layer = constructor.layers.last_mut().unwrap();
+        layer.base_particle = GROUND;
+        layer.init_from_fn(|pos| (pos.y < -10.).then_some(image::Rgba([255, 255, 255, 255])));
+        constructor.spawns = vec![
+            Spawn { pos: vec2(-30., 0.), team: 0 },
+            Spawn { pos: vec2(30., 0.), team: 1 },
+        ];
+        constructor.map()
+    }
+
+    fn deathmatch() -> MatchSettings {
+        MatchSettings { mode: GameMode::Deathmatch, ..Default::default() }
+    }
+
+    /// Shots of `projectile` heard by the session.
+    fn fired(session: &GameSession, projectile: u8) -> usize {
+        session.controller.events.iter().filter(|(_, e)| matches!(e, GameEvent::Fire { projectile: p, .. } if *p == projectile)).count()
+    }
+
+    #[test]
+    fn idle_session_test() {
+        let map = test_map();
+        let players = [(0, "first".to_string()), (1, "second".to_string())];
+        let mut session = GameSession::new(0, "first".to_string(), &map, &players, &Garage::default(), &deathmatch(), &TeamColors::default());
+        for _ in 0..100 {
+            assert_eq!(session.tick(&vec![]), None);
+        }
+        assert_eq!(session.controller.tick, 100);
+        assert!(!session.controller.spectating());
+    }
+
+    #[test]
+    fn state_hash_test() {
+        let map = test_map();
+        let players = [(0, "first".to_string()), (1, "second".to_string())];
+        let mut session = GameSession::new(0, "first".to_string(), &map, &players, &Garage::default(), &deathmatch(), &TeamColors::default());
+        let mut other = GameSession::new(1, "second".to_string(), &map, &players, &Garage::default(), &deathmatch(), &TeamColors::default());
+        for _ in 0..100 {
+            session.tick(&vec![]);
+            other.tick(&vec![]);
+        }
+
+        // the state of one session brings another one to the same tick back in sync
+        other.solver.particles[0].pos += vec2(1., 0.);
+        assert_ne!(other.state_hash(), session.state_hash());
+        let sync = session.state_sync();
+        assert!(GameSession::apply_state_sync(&mut other.controller, &mut other.solver, &sync));
+        assert_eq!(other.state_hash(), session.state_hash());
+
+        // but not one that has moved on
+        session.tick(&vec![]);
+        assert!(!GameSession::apply_state_sync(&mut session.controller, &mut session.solver, &sync));
+    }
+
+    #[test]
+    fn fire_event_test() {
+        let map = test_map();
+        let players = [(0, "first".to_string()), (1, "second".to_string())];
+        let mut session = GameSession::new(0, "first".to_string(), &map, &players, &Garage::default(), &deathmatch(), &TeamColors::default());
+
+        // a shot is heard once and forgotten after a while
+        session.tick(&vec![IndexedGamePacket::new(0, GamePacket::Fire(0))]);
+        assert_eq!(fired(&session, 0), 1);
+        for _ in 0..EVENT_TICKS {
+            session.tick(&vec![]);
+        }
+        assert_eq!(fired(&session, 0), 0);
+    }
+
+    #[test]
+    fn ping_test() {
+        let map = test_map();
+        let players = [(0, "first".to_string()), (1, "second".to_string())];
+        let mut session = GameSession::new(0, "first".to_string(), &map, &players, &Garage::default(), &deathmatch(), &TeamColors::default());
+
+        // pings end up on the scoreboard of every client
+        session.tick(&vec![IndexedGamePacket::new(1, GamePacket::Ping(85))]);
+        assert_eq!(session.controller.get_player(1).unwrap().stats.ping, 85);
+    }
+
+    #[test]
+    fn spectator_test() {
+        let map = test_map();
+        let players = [(0, "first".to_string()), (1, "second".to_string())];
+        let spectator = GameSession::new(u8::MAX, "third".to_string(), &map, &players, &Garage::default(), &deathmatch(), &TeamColors::default());
+        assert!(spectator.controller.spectating());
+        assert_eq!(spectator.controller.players.len(), 2);
+    }
+
+    #[test]
+    fn allowed_projectiles_test() {
+        let map = test_map();
+        let players = [(0, "first".to_string()), (1, "second".to_string())];
+        let settings = MatchSettings { projectiles: 0b10, ..deathmatch() };
+        let mut session = GameSession::new(0, "first".to_string(), &map, &players, &Garage::default(), &settings, &TeamColors::default());
+
+        // the shots the rules forbid are dropped, the tanks start with an allowed projectile
+        assert_eq!(session.controller.player.projectile, 1);
+        session.tick(&vec![IndexedGamePacket::new(1, GamePacket::Fire(0))]);
+        assert_eq!(fired(&session, 0), 0);
+        session.tick(&vec![IndexedGamePacket::new(1, GamePacket::Fire(1))]);
+        assert_eq!(fired(&session, 1), 1);
+    }
+
+    #[test]
+    fn state_sync_test() {
+        let map = test_map();
+        let settings = MatchSettings { respawns: 2, ..Default::default() };
+        let players = [(0, "first".to_string()), (1, "second".to_string())];
+        let mut server = GameSession::new(u8::MAX, "server".to_string(), &map, &players, &Garage::default(), &settings, &TeamColors::default());
+        let colors = TeamColors { palette: Palette::Colorblind, ..Default::default() };
+        let mut client = GameSession::new(0, "first".to_string(), &map, &players, &Garage::default(), &settings, &colors);
+        for _ in 0..100 {
+            server.tick(&vec![]);
+            client.tick(&vec![]);
+        }
+        let palette = |session: &GameSession| session.solver.particles.iter().map(|p| p.color).collect::<Vec<_>>();
+        let tinted = palette(&client);
+        assert_ne!(tinted, palette(&server));
+
+        // the server corrects the solver and the controller, the client keeps its own palette
+        client.solver.particles[0].pos += vec2(1., 0.);
+        client.controller.get_player_mut(1).unwrap().respawns = 0;
+        assert!(GameSession::apply_state_sync(&mut client.controller, &mut client.solver, &server.state_sync()));
+        assert_eq!(client.state_hash(), server.state_hash());
+        assert_eq!(client.controller.get_player(1).unwrap().respawns, 2);
+        assert_eq!(client.controller.get_player(1).unwrap().name, "second");
+        assert_eq!(palette(&client), tinted);
+    }
+
+    #[test]
+    fn respawn_test() {
+        let map = test_map();
+        let settings = MatchSettings { respawns: 1, ..Default::default() };
+        let players = [(0, "first".to_string()), (1, "second".to_string())];
+        let garage = Garage { chassis: vec![(0, Chassis::Scout)], vehicles: vec![] };
+        let mut session = GameSession::new(0, "first".to_string(), &map, &players, &garage, &settings, &TeamColors::default());
+        let scout = RawPlayerModel::generate_scout().particles.len();
+        assert_eq!(session.controller.player.tank.particles.len(), scout);
+        while session.controller.player.protected(session.controller.tick) {
+            session.tick(&vec![]);
+        }
+        let particles = session.solver.particles.len();
+
+        let base = session.controller.player.model.base_connections.clone();
+        for i in base {
+            let link = &mut session.solver.connections[i].2;
+            *link = link.with_durability(-1.);
+        }
+        session.tick(&vec![]);
+        assert!(!Controller::player_alive(&session.controller.player, &session.solver));
+
+        // the wreck is cleared out for a new scout, which appears on every client on the same tick
+        while session.controller.player.respawns > 0 {
+            assert!(session.controller.tick < 10000);
+            session.tick(&vec![]);
+        }
+        assert!(Controller::player_alive(&session.controller.player, &session.solver));
+        assert_eq!(session.solver.particles.len(), particles);
+    }
+
+    #[test]
+    fn pickup_test() {
+        let mut map = test_map();
+        map.pickups.push(Pickup { pos: vec2(-30., 0.), item: Item::SpeedBoost });
+        let players = [(0, "first".to_string()), (1, "second".to_string())];
+        let mut session = GameSession::new(0, "first".to_string(), &map, &players, &Garage::default(), &MatchSettings::default(), &TeamColors::default());
+        let speed_tick = |session: &GameSession| session.controller.get_player(0).unwrap().speed_tick;
+        while speed_tick(&session) == 0 {
+            assert!(session.controller.tick < 1000);
+            session.tick(&vec![]);
+        }
+        assert!(speed_tick(&session) > session.controller.tick);
+
+        // the boosted motors spin faster on every client, whatever power the packet asked for
+        let motor = session.controller.player.model.left_motors[0];
+        let power = session.controller.player.get_power();
+        session.tick(&vec![IndexedGamePacket::new(0, GamePacket::Motor(motor as u32, power))]);
+        assert!(matches!(session.solver.particles[motor].kind, Kind::Motor(acc) if acc > power));
+    }
+
+    #[test]
+    fn shield_test() {
+        let map = test_map();
+        let players = [(0, "first".to_string()), (1, "second".to_string())];
+        let mut session = GameSession::new(0, "first".to_string(), &map, &players, &Garage::default(), &MatchSettings::default(), &TeamColors::default());
+        assert!(session.controller.shield().is_empty());
+        while session.controller.player.protected(session.controller.tick) {
+            session.tick(&vec![]);
+        }
+
+        let packets = session.controller.shield();
+        assert_eq!(packets, vec![GamePacket::Shield]);
+        assert!(session.controller.shield().is_empty()); // cooling down
+        // a modified client sending it twice gets one shield
+        session.tick(&vec![IndexedGamePacket::new(0, packets[0])]);
+        let shield_tick = session.controller.get_player(0).unwrap().shield_tick;
+        session.tick(&vec![IndexedGamePacket::new(0, GamePacket::Shield)]);
+        assert_eq!(session.controller.get_player(0).unwrap().shield_tick, shield_tick);
+        assert!(session.controller.get_player(0).unwrap().protected(session.controller.tick));
+        assert!(session.controller.player.protected(session.controller.tick));
+
+        // the links of a shielded tank keep their durability
+        session.tick(&vec![]);
+        let base = session.controller.player.model.base_connections.clone();
+        for i in base.iter() {
+            let link = &mut session.solver.connections[*i].2;
+            *link = link.with_durability(-1.);
+        }
+        session.tick(&vec![]);
+        assert!(Controller::player_alive(&session.controller.player, &session.solver));
+
+        // and lose it once the shield is down
+        while session.controller.player.protected(session.controller.tick) {
+            session.tick(&vec![]);
+        }
+        for i in base.iter() {
+            let link = &mut session.solver.connections[*i].2;
+            *link = link.with_durability(-1.);
+        }
+        session.tick(&vec![]);
+        assert!(!Controller::player_alive(&session.controller.player, &session.solver));
+
+        // nor does one sent right after the shield went down
+        let mut session = GameSession::new(0, "first".to_string(), &map, &players, &Garage::default(), &MatchSettings::default(), &TeamColors::default());
+        while session.controller.player.protected(session.controller.tick) {
+            session.tick(&vec![]);
+        }
+        session.tick(&vec![IndexedGamePacket::new(0, GamePacket::Shield)]);
+        while session.controller.player.protected(session.controller.tick) {
+            session.tick(&vec![]);
+        }
+        session.tick(&vec![IndexedGamePacket::new(0, GamePacket::Shield)]);
+        assert!(!session.controller.get_player(0).unwrap().protected(session.controller.tick));
+        assert!(session.controller.shield().is_empty());
+    }
+
+    #[test]
+    fn shield_energy_test() {
+        let map = test_map();
+        let players = [(0, "first".to_string()), (1, "second".to_string())];
+        let mut session = GameSession::new(0, "first".to_string(), &map, &players, &Garage::default(), &MatchSettings::default(), &TeamColors::default());
+        while session.controller.player.protected(session.controller.tick) {
+            session.tick(&vec![]);
+        }
+
+        // raising the shield costs energy
+        let energy = session.controller.get_player(0).unwrap().energy;
+        session.tick(&vec![IndexedGamePacket::new(0, GamePacket::Shield)]);
+        assert!(session.controller.get_player(0).unwrap().protected(session.controller.tick));
+        assert!(session.controller.get_player(0).unwrap().energy < energy);
+        assert!(session.controller.player.energy < Player::MAX_ENERGY);
+
+        // an out of energy player sends nothing, and a modified client's packet is dropped
+        let mut session = GameSession::new(0, "first".to_string(), &map, &players, &Garage::default(), &MatchSettings::default(), &TeamColors::default());
+        while session.controller.player.protected(session.controller.tick) {
+            session.tick(&vec![]);
+        }
+        session.controller.player.energy = 0.;
+        session.controller.get_player_mut(0).unwrap().energy = 0.;
+        assert!(session.controller.shield().is_empty());
+        session.tick(&vec![IndexedGamePacket::new(0, GamePacket::Shield)]);
+        assert!(!session.controller.get_player(0).unwrap().protected(session.controller.tick));
+        assert!(!session.controller.player.protected(session.controller.tick));
+    }
+}
@@ -0,0 +1,97 @@
+use std::path::Path;
+
+use common::{chassis::Garage, mode::MatchSettings};
+use packet_tools::{
+    game_packets::{GamePacket, IndexedGamePacket, PACKET_SIZE},
+    IndexedPacket, Packet,
+};
+use serde::{Deserialize, Serialize};
+
+/// Recorded match: the lobby it was played in and the packets of every tick, enough to simulate it again.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Replay {
+    pub map: String,
+    pub settings: MatchSettings,
+    pub players: Vec<(u8, String)>,
+    pub garage: Garage, // custom vehicles included, the replay plays without their files
+    ticks: Vec<Vec<(u8, [u8; PACKET_SIZE])>>,
+}
+
+impl Replay {
+    pub fn new(map: String, settings: MatchSettings, players: Vec<(u8, String)>, garage: Garage) -> Self {
+        Self {
+            map,
+            settings,
+            players,
+            garage,
+            ticks: vec![],
+        }
+    }
+
+    /// Appends the packets of the next tick.
+    pub fn record(&mut self, packets: &[IndexedGamePacket]) {
+        self.ticks
+            .push(packets.iter().map(|p| (p.id, p.contents.to_bytes())).collect());
+    }
+
+    /// Packets of the tick, `None` once the recording is over.
+    /// Packets that don't parse are left out, the rest of the tick still plays.
+    pub fn tick(&self, tick: u128) -> Option<Vec<IndexedGamePacket>> {
+        let packets = self.ticks.get(tick as usize)?;
+        let packets = packets
+            .iter()
+            .filter_map(|(id, bytes)| Some(IndexedPacket::new(*id, GamePacket::from_bytes(bytes).ok()?)))
+            .collect();
+        Some(packets)
+    }
+
+    /// Number of recorded ticks.
+    pub fn len(&self) -> u128 {
+        self.ticks.len() as u128
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ticks.is_empty()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        if let Some(dir) = path.as_ref().parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, postcard::to_stdvec(self).unwrap())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        postcard::from_bytes(&bytes)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::vec2;
+    use common::REPLAY_EXTENSION;
+
+    use super::*;
+
+    #[test]
+    fn replay_test() {
+        let mut replay = Replay::new("test".to_string(), MatchSettings::default(), vec![(0, "first".to_string())], Garage::default());
+        replay.record(&[IndexedPacket::new(0, GamePacket::Thrust(1., -1.))]);
+        replay.record(&[]);
+        replay.record(&[IndexedPacket::new(0, GamePacket::Muzzle(vec2(3., 4.)))]);
+
+        let path = std::env::temp_dir().join(format!("smog-replay-{}.{REPLAY_EXTENSION}", std::process::id()));
+        replay.save(&path).unwrap();
+        let loaded = Replay::load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(loaded.map, "test");
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded.tick(0).unwrap()[0].contents, GamePacket::Thrust(1., -1.));
+        assert!(loaded.tick(1).unwrap().is_empty());
+        assert_eq!(loaded.tick(2).unwrap()[0].contents, GamePacket::Muzzle(vec2(3., 4.)));
+        assert!(loaded.tick(3).is_none());
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use bevy::{
+    prelude::*, render::view::screenshot::ScreenshotManager, tasks::AsyncComputeTaskPool,
+    window::PrimaryWindow,
+};
+use common::config;
+use directories::UserDirs;
+use crate::preferences::Preferences;
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    imageops::FilterType,
+    Delay, Frame, RgbaImage,
+};
+
+const CLIP_FPS: f32 = 10.;
+const CLIP_SECONDS: f32 = 5.;
+const CLIP_WIDTH: u32 = 480; // frames are downscaled to keep the buffer small
+
+/// Last few seconds of the game, saved as a gif on demand.
+/// Only filled while recording, every frame is a screen readback.
+#[derive(Resource, Default)]
+struct ClipBuffer {
+    frames: Arc<Mutex<VecDeque<RgbaImage>>>,
+    busy: Arc<AtomicBool>, // a frame is still being read back or downscaled
+    recording: bool,
+    timer: f32,
+}
+
+/// Screenshots go to the user's pictures, or to the saves if there are none.
+fn capture_dir() -> Option<PathBuf> {
+    UserDirs::new()
+        .and_then(|dirs| dirs.picture_dir().map(|dir| dir.join("SMOG")))
+        .or_else(|| Some(config::saves_dir().join("captures")))
+}
+
+fn capture_path(name: &str, extension: &str) -> Result<PathBuf> {
+    let dir = capture_dir().ok_or(anyhow::anyhow!("no directory for captures"))?;
+    std::fs::create_dir_all(&dir)?;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    Ok(dir.join(format!("{name}-{timestamp}.{extension}")))
+}
+
+fn save_gif(path: PathBuf, frames: Vec<RgbaImage>) -> Result<()> {
+    let mut encoder = GifEncoder::new(File::create(path)?);
+    encoder.set_repeat(Repeat::Infinite)?;
+    let delay = Delay::from_numer_denom_ms(1000, CLIP_FPS as u32);
+    encoder.encode_frames(
+        frames
+            .into_iter()
+            .map(|frame| Frame::from_parts(frame, 0, 0, delay)),
+    )?;
+    Ok(())
+}
+
+fn screenshot_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    window: Query<Entity, With<PrimaryWindow>>,
+    mut screenshots: ResMut<ScreenshotManager>,
+) {
+    if !keyboard.just_pressed(KeyCode::F12) || keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ControlLeft]) {
+        return;
+    }
+    let Ok(window) = window.get_single() else {
+        return;
+    };
+    let result = capture_path("screenshot", "png")
+        .and_then(|path| Ok(screenshots.save_screenshot_to_disk(window, &path).map(|_| path)?));
+    match result {
+        Ok(path) => info!("Screenshot saved to {path:?}"),
+        Err(e) => warn!("Failed to take a screenshot: {e}"),
+    }
+}
+
+fn arm_system(keyboard: Res<ButtonInput<KeyCode>>, mut clip: ResMut<ClipBuffer>) {
+    if !keyboard.just_pressed(KeyCode::F12) || !keyboard.pressed(KeyCode::ControlLeft) {
+        return;
+    }
+    clip.recording = !clip.recording;
+    if clip.recording {
+        info!("Clip recording started");
+    } else {
+        clip.frames.lock().unwrap().clear();
+        info!("Clip recording stopped");
+    }
+}
+
+fn clip_system(keyboard: Res<ButtonInput<KeyCode>>, clip: Res<ClipBuffer>) {
+    if !keyboard.just_pressed(KeyCode::F12) || !keyboard.pressed(KeyCode::ShiftLeft) {
+        return;
+    }
+    let frames: Vec<_> = clip.frames.lock().unwrap().iter().cloned().collect();
+    if frames.is_empty() {
+        if !clip.recording {
+            info!("No clip to save, LEFT CTRL + F12 starts recording");
+        }
+        return;
+    }
+    // encoding takes a while, don't freeze the game
+    std::thread::spawn(move || {
+        let result = capture_path("clip", "gif").and_then(|path| {
+            save_gif(path.clone(), frames)?;
+            Ok(path)
+        });
+        match result {
+            Ok(path) => info!("Clip saved to {path:?}"),
+            Err(e) => warn!("Failed to save the clip: {e}"),
+        }
+    });
+}
+
+fn record_system(
+    time: Res<Time>,
+    mut clip: ResMut<ClipBuffer>,
+    window: Query<Entity, With<PrimaryWindow>>,
+    mut screenshots: ResMut<ScreenshotManager>,
+) {
+    if !clip.recording {
+        return;
+    }
+    clip.timer += time.delta_seconds();
+    if clip.timer < 1. / CLIP_FPS {
+        return;
+    }
+    clip.timer = 0.;
+    let Ok(window) = window.get_single() else {
+        return;
+    };
+
+    // a slow machine skips frames instead of queueing readbacks
+    if clip.busy.swap(true, Ordering::AcquireRel) {
+        return;
+    }
+
+    let frames = clip.frames.clone();
+    let busy = clip.busy.clone();
+    let requested = screenshots.take_screenshot(window, move |image| {
+        // the callback runs in the render world, the downscale happens on the compute pool
+        AsyncComputeTaskPool::get()
+            .spawn(async move {
+                if let Ok(image) = image.try_into_dynamic() {
+                    let height = image.height() * CLIP_WIDTH / image.width().max(1);
+                    let frame = image
+                        .resize_exact(CLIP_WIDTH, height, FilterType::Triangle)
+                        .to_rgba8();
+
+                    let mut frames = frames.lock().unwrap();
+                    frames.push_back(frame);
+                    while frames.len() > (CLIP_FPS * CLIP_SECONDS) as usize {
+                        frames.pop_front();
+                    }
+                }
+                busy.store(false, Ordering::Release);
+            })
+            .detach();
+    });
+    // fails if a screenshot was already requested this frame, the clip just skips it
+    if requested.is_err() {
+        clip.busy.store(false, Ordering::Release);
+    }
+}
+
+fn init_recording(preferences: Res<Preferences>, mut clip: ResMut<ClipBuffer>) {
+    clip.recording = preferences.record_clips;
+}
+
+/// F12 takes a screenshot, LEFT SHIFT + F12 saves the last few seconds as a gif.
+/// The clip is only recorded after LEFT CTRL + F12 starts it, or from the start with `record_clips` in the preferences.
+pub struct CapturePlugin;
+
+impl Plugin for CapturePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ClipBuffer>()
+            .add_systems(Startup, init_recording)
+            .add_systems(
+                Update,
+                (screenshot_system, arm_system, clip_system, record_system).chain(),
+            );
+    }
+}
@@ -0,0 +1,159 @@
+use bevy::prelude::*;
+use common::{
+    chassis::Garage,
+    config,
+    mode::{GameMode, MatchSettings},
+};
+use map_editor::map::Map;
+use packet_tools::game_packets::GamePacket;
+
+use crate::{
+    display_error,
+    network::{
+        client::{GameClient, LobbyInfo},
+        error::ClientError,
+    },
+    preferences::Preferences,
+    ui::{main_menu::connect, replay::start_replay},
+    Client, GameState, PACKET_SIZE,
+};
+
+pub const USAGE: &str = "Usage: smog [--connect <addr>] [--name <nickname>] [--password <password>] [--fullscreen] [--map <map>] [--replay <file>]
+    --connect <addr>      join the server right away
+    --name <nickname>     play under this nickname
+    --password <password> join a private lobby
+    --fullscreen          start in fullscreen
+    --map <map>           play the map from assets/maps offline
+    --replay <file>       watch a recorded match";
+
+/// Command line options for scripted launches, e.g. testers and tournament setups.
+#[derive(Resource, Debug, Clone, Default, PartialEq)]
+pub struct LaunchArgs {
+    pub connect: Option<String>,
+    pub name: Option<String>,
+    pub password: Option<String>,
+    pub fullscreen: bool,
+    pub map: Option<String>,
+    pub replay: Option<String>,
+}
+
+impl LaunchArgs {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut launch = Self::default();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{arg} expects a value"));
+            match arg.as_str() {
+                "--connect" => launch.connect = Some(value()?),
+                "--name" => launch.name = Some(value()?),
+                "--password" => launch.password = Some(value()?),
+                "--map" => launch.map = Some(value()?),
+                "--replay" => launch.replay = Some(value()?),
+                "--fullscreen" => launch.fullscreen = true,
+                _ => return Err(format!("Unknown option {arg}")),
+            }
+        }
+        let targets = [&launch.connect, &launch.map, &launch.replay];
+        if targets.iter().filter(|target| target.is_some()).count() > 1 {
+            return Err("--connect, --map and --replay can't be used together".to_string());
+        }
+        Ok(launch)
+    }
+
+    /// Whether the game should skip the menu and go straight into a match.
+    pub fn has_target(&self) -> bool {
+        self.connect.is_some() || self.map.is_some() || self.replay.is_some()
+    }
+}
+
+/// Plays the map without a server. A dummy tank on the second spawn keeps the match going,
+/// in a sandbox the player is alone and the match only ends on ESC.
+pub fn start_offline(
+    commands: &mut Commands,
+    next_state: &mut ResMut<NextState<GameState>>,
+    name: String,
+    garage: Garage,
+    map: String,
+    sandbox: bool,
+) {
+    let spawns = match Map::init_from_file(&map, config::maps_dir()) {
+        Ok(loaded) => loaded.spawns.len(),
+        Err(e) => return display_error(commands, next_state, ClientError::from_map_error(map, &e)),
+    };
+    let mut players = vec![(0, name.clone())];
+    if spawns > 1 && !sandbox {
+        players.push((1, "Dummy".to_string()));
+    }
+    let lobby = LobbyInfo {
+        id: 0,
+        map,
+        settings: MatchSettings {
+            mode: if sandbox { GameMode::Sandbox } else { GameMode::Deathmatch },
+            round_time: 0,
+            ..default()
+        },
+        players,
+        garage,
+        udp_port: None,
+        session: None,
+    };
+    match GameClient::<GamePacket, PACKET_SIZE>::offline(name, lobby) {
+        Ok(client) => {
+            commands.insert_resource(Client(client));
+            next_state.set(GameState::InGame);
+        }
+        Err(e) => display_error(commands, next_state, e),
+    }
+}
+
+/// Runs once, the options are taken so that returning to the menu doesn't launch again.
+fn launch_system(
+    mut commands: Commands,
+    mut launch: ResMut<LaunchArgs>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut preferences: ResMut<Preferences>,
+) {
+    if !launch.has_target() {
+        return;
+    }
+    let name = preferences.nickname.clone();
+    if let Some(addr) = launch.connect.take() {
+        connect(&mut commands, &mut next_state, &mut preferences, addr, name, false);
+    } else if let Some(map) = launch.map.take() {
+        start_offline(&mut commands, &mut next_state, name, preferences.garage(0), map, false);
+    } else if let Some(replay) = launch.replay.take() {
+        start_replay(&mut commands, &mut next_state, replay);
+    }
+}
+
+pub struct LaunchPlugin(pub LaunchArgs);
+
+impl Plugin for LaunchPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.0.clone())
+            .add_systems(Update, launch_system.run_if(in_state(GameState::Menu)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<LaunchArgs, String> {
+        LaunchArgs::parse(args.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn parse_test() {
+        let launch = parse("--connect 127.0.0.1:8080 --name Bob --password hunter2 --fullscreen").unwrap();
+        assert_eq!(launch.connect.as_deref(), Some("127.0.0.1:8080"));
+        assert_eq!(launch.name.as_deref(), Some("Bob"));
+        assert_eq!(launch.password.as_deref(), Some("hunter2"));
+        assert!(launch.fullscreen && launch.map.is_none());
+
+        assert!(parse("--name").is_err());
+        assert!(parse("--windowed").is_err());
+        assert!(parse("--connect 127.0.0.1:8080 --map default").is_err());
+        assert_eq!(parse("--replay match.smogr").unwrap().replay.as_deref(), Some("match.smogr"));
+        assert!(parse("--map default --replay match.smogr").is_err());
+    }
+}
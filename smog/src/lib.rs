@@ -0,0 +1,2 @@
+// networking doesn't need a window, the integration tests connect clients through it
+pub mod network;
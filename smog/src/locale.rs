@@ -0,0 +1,61 @@
+use bevy::prelude::*;
+use common::locale::Locale;
+
+use crate::preferences::Preferences;
+
+/// Translated UI strings of the chosen language together with a font that can draw them.
+#[derive(Resource)]
+pub struct Strings {
+    pub locale: Locale,
+    pub font: Handle<Font>,
+}
+
+impl Strings {
+    pub fn load(language: &str, asset_server: &AssetServer) -> Self {
+        let locale = Locale::load(language);
+        // bevy's default font only has latin glyphs
+        let font = locale
+            .font()
+            .map(|font| asset_server.load(font.to_string()))
+            .unwrap_or_default();
+        Self { locale, font }
+    }
+
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        self.locale.get(key)
+    }
+
+    pub fn format(&self, key: &str, args: &[(&str, &str)]) -> String {
+        self.locale.format(key, args)
+    }
+
+    pub fn text_style(&self, font_size: f32, color: Color) -> TextStyle {
+        TextStyle {
+            font: self.font.clone(),
+            font_size,
+            color,
+        }
+    }
+}
+
+impl FromWorld for Strings {
+    fn from_world(world: &mut World) -> Self {
+        let language = world.resource::<Preferences>().language.clone();
+        Self::load(&language, world.resource::<AssetServer>())
+    }
+}
+
+fn sync_language(preferences: Res<Preferences>, mut strings: ResMut<Strings>, asset_server: Res<AssetServer>) {
+    if preferences.is_changed() && strings.locale.language != preferences.language {
+        *strings = Strings::load(&preferences.language, &asset_server);
+    }
+}
+
+pub struct LocalePlugin;
+
+impl Plugin for LocalePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Strings>()
+            .add_systems(PreUpdate, sync_language);
+    }
+}
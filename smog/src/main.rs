@@ -1,22 +1,30 @@
 #![windows_subsystem = "windows"]
 
-use bevy::{prelude::*, winit::WinitWindows};
+use bevy::{log::{BoxedLayer, LogPlugin}, prelude::*, window::WindowMode, winit::WinitWindows};
+use common::{config, trace::ChromeLayer};
 
 mod ui;
-use network::client::GameClient;
+use network::{client::GameClient, error::ClientError};
+use capture::CapturePlugin;
+use launch::{LaunchArgs, LaunchPlugin, USAGE};
+use locale::LocalePlugin;
+use preferences::{Preferences, PreferencesPlugin};
 use packet_tools::game_packets::{GamePacket, PACKET_SIZE};
-use render::{RenderSimulationPlugin, SimulationCamera};
-use ui::{game::GamePlugin, lobby::LobbyPlugin, main_menu::MainMenuPlugin, over::WinScreenPlugin};
+use render::{debug::SolverDebugPlugin, RenderSimulationPlugin, SimulationCamera};
+use ui::{error::ErrorScreenPlugin, game::GamePlugin, lobby::LobbyPlugin, main_menu::MainMenuPlugin, maps::MapSelectPlugin, over::WinScreenPlugin, replay::ReplayPlugin, settings::SettingsPlugin, tutorial::TutorialPlugin};
 use winit::window::Icon;
 
-mod network;
-mod controller;
+use smog::network;
+mod preferences;
+mod locale;
+mod launch;
+mod capture;
 
 #[derive(Resource)]
 struct Client(GameClient<GamePacket, PACKET_SIZE>);
 
 #[derive(Resource)]
-struct GameError(String);
+struct GameError(ClientError);
 
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States)]
 enum GameState {
@@ -25,6 +33,9 @@ enum GameState {
     InLobby,
     InGame,
     EndGame,
+    Replay,
+    Maps,
+    Settings,
     Error,
 }
 
@@ -35,8 +46,10 @@ fn setup(mut commands: Commands) {
         .insert(SimulationCamera);
 }
 
-fn display_error(commands: &mut Commands, next_state: &mut ResMut<NextState<GameState>>, error: &str) {
-    commands.insert_resource(GameError(error.to_string()));
+fn display_error(commands: &mut Commands, next_state: &mut ResMut<NextState<GameState>>, error: impl Into<ClientError>) {
+    let error = error.into();
+    warn!("{error}");
+    commands.insert_resource(GameError(error));
     next_state.set(GameState::Error)
 }
 
@@ -44,7 +57,7 @@ fn set_window_icon(
     windows: NonSend<WinitWindows>,
 ) {
     let (icon_rgba, icon_width, icon_height) = {
-        let image = image::open("assets/textures/icon.png")
+        let image = image::open(config::assets_dir().join("textures/icon.png"))
             .expect("Failed to open icon path")
             .into_rgba8();
         let (width, height) = image.dimensions();
@@ -58,17 +71,52 @@ fn set_window_icon(
     }
 }
 
+fn chrome_layer(_: &mut App) -> Option<BoxedLayer> {
+    ChromeLayer::from_env().map(|layer| Box::new(layer) as BoxedLayer)
+}
+
 fn main() {
+    let launch = match LaunchArgs::parse(std::env::args().skip(1)) {
+        Ok(launch) => launch,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    let mut preferences = Preferences::load();
+    if let Some(name) = &launch.name {
+        preferences.nickname = name.clone();
+    }
+    if let Some(password) = &launch.password {
+        preferences.password = password.clone();
+    }
+    let mut window = preferences.window();
+    if launch.fullscreen {
+        window.mode = WindowMode::BorderlessFullscreen;
+    }
+
     App::new()
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                title: "SMOG".to_string(),
-                ..default()
-            }),
-            ..default()
-        }))
-        .add_plugins(RenderSimulationPlugin)
-        .add_plugins((MainMenuPlugin, LobbyPlugin, GamePlugin, WinScreenPlugin))
+        .add_plugins(
+            DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: Some(window),
+                    ..default()
+                })
+                .set(AssetPlugin {
+                    file_path: config::assets_dir().to_string_lossy().to_string(),
+                    ..default()
+                })
+                .set(LogPlugin {
+                    custom_layer: chrome_layer,
+                    ..default()
+                }),
+        )
+        .add_plugins(PreferencesPlugin(preferences))
+        .add_plugins(LaunchPlugin(launch))
+        .add_plugins(LocalePlugin)
+        .add_plugins(CapturePlugin)
+        .add_plugins((RenderSimulationPlugin, SolverDebugPlugin))
+        .add_plugins((MainMenuPlugin, LobbyPlugin, GamePlugin, WinScreenPlugin, TutorialPlugin, ReplayPlugin, MapSelectPlugin, SettingsPlugin, ErrorScreenPlugin))
         .add_systems(Startup, (setup, set_window_icon))
         .insert_state(GameState::Menu)
         .run();
@@ -1,2 +1,3 @@
 pub mod client;
-pub mod error;
\ No newline at end of file
+pub mod error;
+pub mod query;
\ No newline at end of file
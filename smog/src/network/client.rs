@@ -1,28 +1,64 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
-use common::RELATIVE_MAPS_PATH;
-use map_editor::map::MapLoader;
+use common::{chassis::{Chassis, Garage}, config, mode::MatchSettings, PHYSICS_TICK};
 use tokio::{
-    io::AsyncWriteExt,
-    net::{TcpStream, ToSocketAddrs},
+    io::{AsyncSeekExt, AsyncWriteExt},
+    net::{TcpStream, ToSocketAddrs, UdpSocket},
     runtime::Runtime,
+    sync::{
+        mpsc::{self, error::TrySendError},
+        watch,
+    },
     task::JoinHandle,
 };
 
 use crossbeam_channel::{unbounded, Receiver, Sender};
+use tracing::{info_span, trace_span, warn, Instrument};
 
 use packet_tools::{
-    client_packets::ClientPacket, server_packets::ServerPacket, IndexedPacket, Packet,
-    UnsizedPacketRead, UnsizedPacketWrite,
+    client_packets::ClientPacket,
+    compression,
+    files,
+    server_packets::ServerPacket,
+    udp::{Datagram, ReliableReceiver, MAX_DATAGRAM},
+    IndexedPacket, Packet, StreamItem, UnsizedPacket, UnsizedPacketRead, UnsizedPacketWrite, INPUTS_END_MARKER,
 };
 
 use crate::network::error::ClientError;
 
+const SEND_BUFFER: usize = 256; // inputs waiting to be sent, newer ones are dropped when it's full
+const HELLO_INTERVAL: Duration = Duration::from_millis(100);
+const STREAM_TIMEOUT: Duration = Duration::from_secs(2); // silence after which the UDP game stream is given up
+const RECONNECT_ATTEMPTS: u32 = 10;
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
+const PART_EXTENSION: &str = ".part"; // of a map file that is still being downloaded
+
 pub struct LobbyInfo {
     pub id: u8,
     pub map: String,
+    pub settings: MatchSettings,
     pub players: Vec<(u8, String)>,
+    pub garage: Garage, // picked by the players, the ones missing drive the tank
+    pub udp_port: Option<u16>, // game port of a server that plays over UDP
+    pub session: Option<u64>, // lets the client reconnect if the stream drops mid-game
+}
+
+/// Map file being downloaded in the lobby.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Download {
+    pub file: String,
+    pub received: u64,
+    pub total: u64,
 }
 
 pub struct GameClient<P, const SIZE: usize>
@@ -31,13 +67,19 @@ where
 {
     pub name: String,
     pub lobby: LobbyInfo,
+    lobby_code: Option<String>, // lobby of a hub, selected again on reconnect
     runtime: Runtime,
     lobby_channel: Receiver<ServerPacket>,
     lobby_task: Option<JoinHandle<Result<(LobbyInfo, TcpStream)>>>,
-    send_channel: Option<Sender<P>>,
+    requests: Option<mpsc::UnboundedSender<ClientPacket>>, // votes and picks, written to the server by the lobby task
+    send_channel: Option<mpsc::Sender<P>>,
     send_task: Option<JoinHandle<Result<()>>>,
-    receive_channel: Option<Receiver<Vec<IndexedPacket<P, SIZE>>>>,
-    receive_task: Option<JoinHandle<Result<()>>>,
+    dropped_inputs: AtomicU32,
+    receive_channel: Option<Receiver<StreamItem<P, SIZE>>>,
+    state_sync: Mutex<Option<Vec<u8>>>, // state from a simulating server, to apply before the next tick
+    map_vote: Mutex<Option<Vec<String>>>, // maps offered for the next round once the match is over
+    download: Arc<Mutex<Option<Download>>>, // written by the lobby task
+    receive_task: Option<JoinHandle<Result<Option<Arc<TcpStream>>>>>, // gives the stream back for the next round
     stop_channel: Option<Sender<()>>,
 }
 
@@ -46,6 +88,57 @@ where
     P: Packet<SIZE> + std::fmt::Debug,
 {
     pub fn new<A>(addr: A, name: String) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        Self::join(addr, None, ClientPacket::SetName(name.clone()), name, None)
+    }
+
+    /// Joins without a tank, the client only receives the match.
+    pub fn spectate<A>(addr: A, name: String) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        Self::join(addr, None, ClientPacket::JoinAsSpectator(name.clone()), name, None)
+    }
+
+    /// Joins the lobby with the code on a server that hosts several of them.
+    pub fn join_lobby<A>(addr: A, code: String, name: String, spectator: bool) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        let join_packet = match spectator {
+            true => ClientPacket::JoinAsSpectator(name.clone()),
+            false => ClientPacket::SetName(name.clone()),
+        };
+        Self::join(addr, Some(code), join_packet, name, None)
+    }
+
+    /// Joins a lobby protected with a password, the code is given for a lobby on a hub.
+    pub fn join_with_password<A>(
+        addr: A,
+        code: Option<String>,
+        name: String,
+        spectator: bool,
+        password: String,
+    ) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        let join_packet = match spectator {
+            true => ClientPacket::JoinAsSpectator(name.clone()),
+            false => ClientPacket::SetName(name.clone()),
+        };
+        Self::join(addr, code, join_packet, name, Some(password))
+    }
+
+    fn join<A>(
+        addr: A,
+        lobby_code: Option<String>,
+        join_packet: ClientPacket,
+        name: String,
+        password: Option<String>,
+    ) -> Result<Self>
     where
         A: ToSocketAddrs,
     {
@@ -53,74 +146,115 @@ where
             .enable_all()
             .build()?;
 
-        let (id, name, stream) = rt.block_on(async {
-            let mut stream = TcpStream::connect(addr).await?;
-            stream
-                .write_packet(&ClientPacket::SetName(name.clone()))
-                .await?;
-            let ServerPacket::SetId(id) = stream.read_packet().await? else {
-                return Result::Err(ClientError::AuthenticationError)?;
-            };
-
-            anyhow::Ok((id, name, stream))
-        })?;
-
-        let mut lobby_stream = stream;
-        let (_send_lobby, receive_lobby) = unbounded();
-        let lobby_task = rt.spawn(async move {
-            let mut id = id;
-            let mut map = String::new();
-            let mut players = Vec::new();
-            loop {
-                let packet = lobby_stream.read_packet().await?;
-                match packet {
-                    ServerPacket::StartGame => {
-                        let lobby = LobbyInfo { id, map, players };
-                        return anyhow::Ok((lobby, lobby_stream));
-                    }
-                    ServerPacket::SetId(new_id) => id = new_id,
-                    ServerPacket::SetMap(new_map) => {
-                        map = new_map;
-                        if !MapLoader::map_exists(&map, common::RELATIVE_MAPS_PATH) {
-                            lobby_stream.write_packet(&ClientPacket::RequestMap).await?
-                        } else {
-                            lobby_stream.write_packet(&ClientPacket::Ok).await?;
-                        }
-                    }
-                    ServerPacket::SetPlayers(new_players) => players = new_players,
-                    ServerPacket::CreateFile { name, contents } => {
-                        let mut file_path = PathBuf::from(RELATIVE_MAPS_PATH);
-                        file_path.push(&map);
-                        tokio::fs::create_dir_all(&file_path).await?;
-                        file_path.push(name);
-
-                        tokio::fs::File::create(&file_path)
-                            .await?
-                            .write_all(&contents)
-                            .await?
-                    }
+        let (id, name, stream) = rt.block_on(
+            async {
+                let mut stream = TcpStream::connect(addr).await?;
+                if let Some(code) = &lobby_code {
+                    stream.write_packet(&ClientPacket::SelectLobby(code.clone())).await?;
+                }
+                if let Some(password) = password {
+                    stream.write_packet(&ClientPacket::Password(password)).await?;
                 }
+                stream.write_packet(&ClientPacket::AcceptCompression).await?;
+                stream.write_packet(&join_packet).await?;
+                let id = match stream.read_packet().await? {
+                    ServerPacket::SetId(id) => id,
+                    ServerPacket::Kicked(reason) => return Result::Err(ClientError::Kicked(reason))?,
+                    ServerPacket::Rejected(reason) => return Result::Err(ClientError::Rejected(reason))?,
+                    _ => return Result::Err(ClientError::AuthenticationError)?,
+                };
+
+                anyhow::Ok((id, name, stream))
             }
-        });
+            .instrument(info_span!("handshake")),
+        )?;
+
+        let seed = LobbyInfo {
+            id,
+            map: String::new(),
+            settings: MatchSettings::default(),
+            players: vec![],
+            garage: Garage::default(),
+            udp_port: None,
+            session: None,
+        };
+        let download = Arc::new(Mutex::new(None));
+        let (lobby_task, lobby_channel, requests) = spawn_lobby(&rt, stream, seed, download.clone());
 
         Ok(Self {
             name,
+            lobby_code,
             lobby: LobbyInfo {
                 id,
                 map: "default".to_string(),
+                settings: MatchSettings::default(),
                 players: vec![],
+                garage: Garage::default(),
+                udp_port: None,
+                session: None,
             },
             runtime: rt,
-            lobby_channel: receive_lobby,
+            lobby_channel,
             lobby_task: Some(lobby_task),
+            requests: Some(requests),
             send_channel: None,
             send_task: None,
+            dropped_inputs: AtomicU32::new(0),
             receive_channel: None,
+            state_sync: Mutex::new(None),
+            map_vote: Mutex::new(None),
+            download,
             receive_task: None,
             stop_channel: None,
         })
     }
 
+    /// Client without a server: sent packets are looped back once per physics tick.
+    pub fn offline(name: String, lobby: LobbyInfo) -> Result<Self> {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+
+        let id = lobby.id;
+        let (stop_channel, stop_reader) = unbounded::<()>();
+        let (send_channel, mut r_channel) = mpsc::channel::<P>(SEND_BUFFER);
+        let (s_channel, receive_channel) = unbounded::<StreamItem<P, SIZE>>();
+        let send_task = rt.spawn(async move {
+            let mut interval = tokio::time::interval(PHYSICS_TICK);
+            loop {
+                interval.tick().await;
+                if !stop_reader.is_empty() {
+                    return anyhow::Ok(())
+                }
+                let mut packets = vec![];
+                while let Ok(packet) = r_channel.try_recv() {
+                    packets.push(IndexedPacket::new(id, packet));
+                }
+                s_channel.send(StreamItem::Tick(packets))?;
+            }
+        });
+
+        let (_, lobby_channel) = unbounded();
+        Ok(Self {
+            name,
+            lobby,
+            lobby_code: None,
+            runtime: rt,
+            lobby_channel,
+            lobby_task: None,
+            requests: None,
+            send_channel: Some(send_channel),
+            send_task: Some(send_task),
+            dropped_inputs: AtomicU32::new(0),
+            receive_channel: Some(receive_channel),
+            state_sync: Mutex::new(None),
+            map_vote: Mutex::new(None),
+            download: Arc::new(Mutex::new(None)),
+            receive_task: None,
+            stop_channel: Some(stop_channel),
+        })
+    }
+
     pub fn get_lobby_packets(&self) -> Vec<ServerPacket> {
         let mut packets = vec![];
         while let Ok(packet) = self.lobby_channel.try_recv() {
@@ -129,6 +263,11 @@ where
         packets
     }
 
+    /// Map file the lobby is receiving, `None` when nothing is being downloaded.
+    pub fn download(&self) -> Option<Download> {
+        self.download.lock().unwrap().clone()
+    }
+
     pub fn game_started(&self) -> bool {
         self.lobby_task
             .as_ref()
@@ -146,70 +285,168 @@ where
         let stream = Arc::new(stream);
         let (stop_channel, stop_reader) = unbounded();
 
-        // send task
-        let stop_sending = stop_reader.clone();
-        let (send_channel, r_channel) = unbounded::<P>();
-        let send_stream = Arc::clone(&stream);
+        let resume = Resume {
+            server: stream.peer_addr()?,
+            lobby_code: self.lobby_code.clone(),
+            session: lobby.session,
+        };
+        if let Some(port) = lobby.udp_port {
+            let server = SocketAddr::new(resume.server.ip(), port);
+            self.run_udp(server, lobby.id, stream, resume, stop_reader)?;
+            self.lobby = lobby;
+            self.stop_channel = Some(stop_channel);
+            return anyhow::Ok(());
+        }
+
+        // the receive task replaces the stream after reconnecting
+        let session = lobby.session;
+        let (streams, mut send_streams) = watch::channel(Arc::clone(&stream));
+
+        // send task, finishes once the client drops the channel
+        let (send_channel, mut r_channel) = mpsc::channel::<P>(SEND_BUFFER);
         let send_task = rt.spawn(async move {
-            loop {
-                if !stop_sending.is_empty() {
-                    return anyhow::Ok(())
+            while let Some(packet) = r_channel.recv().await {
+                let bytes = packet.to_bytes();
+                // the receive task drops the sender once it gives up reconnecting
+                if send_streams.has_changed().is_err() {
+                    return Err(ClientError::ServerClosedConnection)?;
                 }
-                match r_channel.try_recv() {
-                    Ok(packet) => {
-                        send_stream.writable().await?;
-                        send_stream.try_write(&packet.to_bytes())?;
+                let send_stream = send_streams.borrow_and_update().clone();
+                loop {
+                    send_stream.writable().await?;
+                    match send_stream.try_write(&bytes) {
+                        Ok(_) => break,
+                        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+                        // the input is lost while the receive task reconnects
+                        Err(_) if session.is_some() => break,
+                        Err(e) => return Err(e)?,
                     }
-                    Err(_e) => (),
                 }
             }
+            anyhow::Ok(())
         });
         // listen task
-        let stop_listening = stop_reader.clone();
-        let (s_channel, receive_channel) = unbounded::<Vec<IndexedPacket<P, SIZE>>>();
-        let receive_stream = Arc::clone(&stream);
-        let receive_task = rt.spawn(async move {
-            let mut buf_start = 0;
-            let mut buf = Vec::from([0; 4096]);
+        let (s_channel, receive_channel) = unbounded::<StreamItem<P, SIZE>>();
+        let receive_task = rt.spawn(receive_stream(stream, 0, s_channel, stop_reader, resume, move |stream| {
+            streams.send_replace(Arc::clone(stream));
+        }));
+
+        self.lobby = lobby;
+        self.send_channel = Some(send_channel);
+        self.send_task = Some(send_task);
+        self.receive_channel = Some(receive_channel);
+        self.receive_task = Some(receive_task);
+        self.stop_channel = Some(stop_channel);
+
+        anyhow::Ok(())
+    }
+
+    /// Inputs go to the server as they are, the game stream comes back in chunks through the ack/retransmit layer.
+    /// The TCP stream is only kept open so the server sees the player as connected. Once the UDP stream goes silent
+    /// the client reconnects and plays the rest of the game over TCP, the server gives up on a peer that doesn't
+    /// acknowledge the stream.
+    fn run_udp(
+        &mut self,
+        server: SocketAddr,
+        id: u8,
+        stream: Arc<TcpStream>,
+        resume: Resume,
+        stop_listening: Receiver<()>,
+    ) -> Result<()> {
+        let local: SocketAddr = match server {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let socket = Arc::new(self.runtime.block_on(async {
+            let socket = UdpSocket::bind(local).await?;
+            socket.connect(server).await?;
+            anyhow::Ok(socket)
+        })?);
+
+        // the inputs follow the game stream once it falls back to TCP
+        let (fallback, mut fallback_streams) = watch::channel::<Option<Arc<TcpStream>>>(None);
+        let (send_channel, mut r_channel) = mpsc::channel::<P>(SEND_BUFFER);
+        let send_socket = Arc::clone(&socket);
+        let send_task = self.runtime.spawn(async move {
+            while let Some(packet) = r_channel.recv().await {
+                let fallback_stream = fallback_streams.borrow_and_update().clone();
+                match fallback_stream {
+                    Some(stream) => {
+                        stream.writable().await?;
+                        // the input is lost if the stream is full or drops again
+                        let _ = stream.try_write(&packet.to_bytes());
+                    }
+                    None => {
+                        let datagram = Datagram::Unreliable(packet.to_bytes().to_vec());
+                        send_socket.send(&datagram.to_bytes()).await?;
+                    }
+                }
+            }
+            anyhow::Ok(())
+        });
+
+        let (s_channel, receive_channel) = unbounded::<StreamItem<P, SIZE>>();
+        let receive_task = self.runtime.spawn(async move {
+            let _stream = stream;
+            let mut receiver = ReliableReceiver::default();
+            let mut greeted = false;
+            let mut heard = Instant::now();
+            let mut received = 0; // ticks, the server resends the ones after them on reconnect
+            let mut buf = vec![0; MAX_DATAGRAM];
+            let mut pending = vec![]; // the stream comes in chunks, an item may be split between them
             loop {
                 if !stop_listening.is_empty() {
-                    return anyhow::Ok(())
+                    return anyhow::Ok(None);
                 }
-
-                receive_stream.readable().await?;
-                match receive_stream.try_read(&mut buf[buf_start..]) {
-                    Ok(0) => {
-                        return Err(ClientError::ServerClosedConnection)?;
+                // the hello may get lost too, repeat it until the server answers
+                if !greeted {
+                    socket.send(&Datagram::Hello(id).to_bytes()).await?;
+                }
+                let Ok(datagram) = tokio::time::timeout(HELLO_INTERVAL, socket.recv(&mut buf)).await else {
+                    if greeted && heard.elapsed() > STREAM_TIMEOUT {
+                        break;
                     }
-                    Ok(n) => {
-                        let (packets, res_len) =
-                            packet_tools::deserialize_queue(&mut buf[..buf_start + n]);
-                        buf_start = res_len;
-                        if buf_start > buf.len() / 2 {
-                            buf.extend((0..buf.len()).into_iter().map(|_| 0));
-                        }
+                    continue;
+                };
+                let Some(Datagram::Reliable { seq, bytes }) = Datagram::parse(&buf[..datagram?]) else {
+                    continue;
+                };
+                greeted = true;
+                heard = Instant::now();
+                socket.send(&Datagram::Ack(seq).to_bytes()).await?;
 
-                        for p in packets {
-                            s_channel.send(p)?;
+                for bytes in receiver.receive(seq, bytes) {
+                    let _span = trace_span!("receive_packets", bytes = bytes.len()).entered();
+                    pending.extend(bytes);
+                    let (items, res_len) = packet_tools::deserialize_stream(&mut pending)?;
+                    pending.truncate(res_len);
+                    for item in items {
+                        if let StreamItem::Tick(_) = item {
+                            received += 1;
                         }
-                    }
-                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                        continue;
-                    }
-                    Err(e) => {
-                        return Err(e)?;
+                        s_channel.send(item)?;
                     }
                 }
             }
+
+            let Some(session) = resume.session else {
+                return Err(ClientError::ServerClosedConnection)?;
+            };
+            warn!("The game stream over UDP went silent, reconnecting over TCP");
+            let Some(stream) = reconnect(resume.server, resume.lobby_code.as_deref(), session, received).await else {
+                return Err(ClientError::ServerClosedConnection)?;
+            };
+            fallback.send_replace(Some(Arc::clone(&stream)));
+            receive_stream(stream, received, s_channel, stop_listening, resume, move |stream| {
+                fallback.send_replace(Some(Arc::clone(stream)));
+            })
+            .await
         });
 
-        self.lobby = lobby;
         self.send_channel = Some(send_channel);
         self.send_task = Some(send_task);
         self.receive_channel = Some(receive_channel);
         self.receive_task = Some(receive_task);
-        self.stop_channel = Some(stop_channel);
-
         anyhow::Ok(())
     }
 
@@ -219,26 +456,111 @@ where
         self.receive_task.take().map(|task| task.abort());
     }
 
+    /// Ticks to simulate, stops early at a state sync so it can be applied on the tick it belongs to.
     pub fn get_packets(&self, limit: usize) -> Vec<Vec<IndexedPacket<P, SIZE>>> {
         let Some(channel) = self.receive_channel.as_ref() else {
             return vec![];
         };
         let mut v = Vec::new();
-        for _ in 0..limit {
-            if let Ok(packets) = channel.try_recv() {
-                v.push(packets);
-            } else {
-                break;
+        while v.len() < limit {
+            match channel.try_recv() {
+                Ok(StreamItem::Tick(packets)) => v.push(packets),
+                Ok(StreamItem::Server(ServerPacket::StateSync(state))) => {
+                    *self.state_sync.lock().unwrap() = Some(state);
+                    break;
+                }
+                Ok(StreamItem::Server(ServerPacket::MapVote(maps))) => {
+                    *self.map_vote.lock().unwrap() = Some(maps);
+                    break;
+                }
+                Ok(StreamItem::Server(_)) => continue,
+                Err(_) => break,
             }
         }
         v
     }
 
+    /// State the server sent after the last tick returned by [`GameClient::get_packets`].
+    pub fn take_state_sync(&self) -> Option<Vec<u8>> {
+        self.state_sync.lock().unwrap().take()
+    }
+
+    /// Maps the server offers for the next round, the match is over once they arrive.
+    pub fn take_map_vote(&self) -> Option<Vec<String>> {
+        self.map_vote.lock().unwrap().take()
+    }
+
+    /// Takes the stream back from the finished game and waits in the lobby on it again.
+    /// Only a game over TCP offers the next round.
+    pub fn return_to_lobby(&mut self) -> Result<()> {
+        // the send task finishes once its channel is dropped
+        self.send_channel = None;
+        let send_task = self.send_task.take();
+        let receive_task = self.receive_task.take().ok_or(ClientError::NoConnectionToServer)?;
+        let stream = self.runtime.block_on(async move {
+            if let Some(task) = send_task {
+                let _ = task.await;
+            }
+            receive_task.await?
+        })?;
+        let mut stream = stream
+            .and_then(|stream| Arc::try_unwrap(stream).ok())
+            .ok_or(ClientError::NoConnectionToServer)?;
+        // the server skips the inputs sent before the game ended up to here
+        self.runtime.block_on(stream.write_all(&[INPUTS_END_MARKER; SIZE]))?;
+        self.receive_channel = None;
+        self.stop_channel = None;
+
+        let seed = LobbyInfo {
+            id: self.lobby.id,
+            map: self.lobby.map.clone(),
+            settings: self.lobby.settings,
+            players: vec![],
+            garage: Garage::default(),
+            udp_port: None,
+            session: self.lobby.session,
+        };
+        let (lobby_task, lobby_channel, requests) = spawn_lobby(&self.runtime, stream, seed, self.download.clone());
+        self.lobby_task = Some(lobby_task);
+        self.lobby_channel = lobby_channel;
+        self.requests = Some(requests);
+        anyhow::Ok(())
+    }
+
+    /// Votes for the map of the next round, the server goes with the most voted one.
+    pub fn vote_map(&self, map: String) {
+        if let Some(requests) = self.requests.as_ref() {
+            let _ = requests.send(ClientPacket::VoteMap(map));
+        }
+    }
+
+    /// Picks the chassis the player drives in the next game, the last pick before it starts counts.
+    pub fn select_chassis(&self, chassis: Chassis) {
+        if let Some(requests) = self.requests.as_ref() {
+            let _ = requests.send(ClientPacket::SelectChassis(chassis));
+        }
+    }
+
+    /// Sends the exported vehicle the player drives with [`Chassis::Custom`], the server checks it before the game.
+    pub fn upload_vehicle(&self, vehicle: Vec<u8>) {
+        if let Some(requests) = self.requests.as_ref() {
+            let _ = requests.send(ClientPacket::UploadVehicle(vehicle));
+        }
+    }
+
     pub fn send_packet(&self, packet: P) -> Result<()> {
-        if let Some(channel) = self.send_channel.as_ref() {
-            channel.send(packet)?;
+        let Some(channel) = self.send_channel.as_ref() else {
+            return anyhow::Ok(());
+        };
+        match channel.try_send(packet) {
+            Ok(()) => anyhow::Ok(()),
+            // the connection stalls, losing an input is better than freezing the game
+            Err(TrySendError::Full(_)) => {
+                self.dropped_inputs.fetch_add(1, Ordering::Relaxed);
+                anyhow::Ok(())
+            }
+            Err(TrySendError::Closed(_)) => Err(ClientError::ServerClosedConnection)?,
         }
-        anyhow::Ok(())
     }
 
     pub fn send_packets(&self, packets: &[P]) -> Result<()> {
@@ -248,6 +570,23 @@ where
         anyhow::Ok(())
     }
 
+    /// Inputs lost because the send buffer was full.
+    pub fn dropped_inputs(&self) -> u32 {
+        self.dropped_inputs.load(Ordering::Relaxed)
+    }
+
+    /// Inputs waiting to be sent.
+    pub fn queued_inputs(&self) -> usize {
+        self.send_channel
+            .as_ref()
+            .map_or(0, |channel| channel.max_capacity() - channel.capacity())
+    }
+
+    /// Ticks received from the server but not simulated yet.
+    pub fn queued_ticks(&self) -> usize {
+        self.receive_channel.as_ref().map_or(0, |channel| channel.len())
+    }
+
     pub fn is_finished(&self) -> bool {
         self.send_task
             .as_ref()
@@ -259,6 +598,249 @@ where
     }
 }
 
+/// Waits for the game to start, writing the votes for the next map and the picks in between the lobby packets.
+fn spawn_lobby(
+    rt: &Runtime,
+    mut lobby_stream: TcpStream,
+    seed: LobbyInfo,
+    download: Arc<Mutex<Option<Download>>>,
+) -> (JoinHandle<Result<(LobbyInfo, TcpStream)>>, Receiver<ServerPacket>, mpsc::UnboundedSender<ClientPacket>) {
+    let (send_lobby, receive_lobby) = unbounded();
+    let (send_requests, mut requests) = mpsc::unbounded_channel();
+    let LobbyInfo { mut id, mut map, mut settings, mut players, mut garage, mut udp_port, mut session } = seed;
+    let lobby_task = rt.spawn(async move {
+        let mut compressed = false; // until the server enables it
+        let mut checksums = HashMap::new(); // of the map files announced with the map
+        // the server reads the answer to the map before anything else, the picks wait for it
+        let mut answered = false;
+        let mut picks = vec![];
+        loop {
+            if answered {
+                for pick in picks.drain(..) {
+                    lobby_stream.write_packet(&pick).await?;
+                }
+            }
+            // only waiting for the stream to be readable may be cancelled, a packet is read whole
+            tokio::select! {
+                Some(request) = requests.recv() => {
+                    match request {
+                        ClientPacket::SelectChassis(_) | ClientPacket::UploadVehicle(_) => picks.push(request),
+                        request => lobby_stream.write_packet(&request).await?,
+                    }
+                    continue;
+                }
+                readable = lobby_stream.readable() => readable?,
+            }
+            let packet = lobby_stream.read_packet().await?;
+            match packet {
+                ServerPacket::StartGame => {
+                    let lobby = LobbyInfo { id, map, settings, players, garage, udp_port, session };
+                    return anyhow::Ok((lobby, lobby_stream));
+                }
+                ServerPacket::UdpPort(port) => udp_port = Some(port),
+                ServerPacket::SetSession(token) => session = Some(token),
+                ServerPacket::SetId(new_id) => id = new_id,
+                ServerPacket::MatchSettings(new_settings) => {
+                    settings = new_settings;
+                    let _ = send_lobby.send(packet);
+                }
+                ServerPacket::SetMap { name, files: announced } => {
+                    map = name;
+                    let map_dir = config::maps_dir().join(&map);
+                    // a local map of the same name may be another version of it
+                    let parts: HashMap<String, u64> = partial_files(&map_dir).await.into_iter().collect();
+                    let mut requested = vec![];
+                    for (file, checksum) in announced.iter() {
+                        if files::checksum_file(map_dir.join(file)).await != Some(*checksum) {
+                            requested.push((file.clone(), parts.get(file).copied().unwrap_or(0)));
+                        }
+                    }
+                    checksums = announced.into_iter().collect();
+                    match requested.is_empty() {
+                        true => lobby_stream.write_packet(&ClientPacket::Ok).await?,
+                        false => lobby_stream.write_packet(&ClientPacket::RequestFiles(requested)).await?,
+                    }
+                    answered = true;
+                }
+                ServerPacket::SetPlayers(new_players) => players = new_players,
+                ServerPacket::SetChassis(chassis) => garage.chassis = chassis,
+                ServerPacket::SetVehicles(vehicles) => garage.vehicles = vehicles,
+                ServerPacket::StateSync(_) => (),
+                ServerPacket::Kicked(reason) => return Err(ClientError::Kicked(reason))?,
+                ServerPacket::Rejected(reason) => return Err(ClientError::Rejected(reason))?,
+                ServerPacket::EnableCompression => compressed = true,
+                ServerPacket::MapVote(_) | ServerPacket::MapVoteResult(_) => {
+                    let _ = send_lobby.send(packet);
+                }
+                ServerPacket::FileChunk { name, offset, total, data } => {
+                    let map_dir = config::maps_dir().join(&map);
+                    let received = offset + data.len() as u64;
+                    *download.lock().unwrap() = Some(Download { file: name.clone(), received, total });
+                    receive_chunk(&map_dir, &name, offset, &data).await?;
+                    if received >= total {
+                        finish_file(&map_dir, &name, total, compressed).await?;
+                        *download.lock().unwrap() = None;
+                        // a part left from another version of the file doesn't add up, it's downloaded again next time
+                        if files::checksum_file(map_dir.join(&name)).await != checksums.get(&name).copied() {
+                            let _ = tokio::fs::remove_file(map_dir.join(&name)).await;
+                            return Err(ClientError::MapCorrupt(map))?;
+                        }
+                    }
+                }
+            }
+        }
+    }.instrument(info_span!("lobby", id)));
+    (lobby_task, receive_lobby, send_requests)
+}
+
+/// Files of the map whose download broke off, with the bytes received of each.
+async fn partial_files(map_dir: &Path) -> Vec<(String, u64)> {
+    let mut parts = vec![];
+    let Ok(mut entries) = tokio::fs::read_dir(map_dir).await else {
+        return parts;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let file_name = entry.file_name();
+        let Some(name) = file_name.to_str().and_then(|name| name.strip_suffix(PART_EXTENSION)) else {
+            continue;
+        };
+        if let Ok(metadata) = entry.metadata().await {
+            parts.push((name.to_string(), metadata.len()));
+        }
+    }
+    parts
+}
+
+/// The server names the files, only plain names are taken so they stay in the map's directory.
+fn part_path(map_dir: &Path, name: &str) -> Result<PathBuf> {
+    if Path::new(name).file_name().and_then(|name| name.to_str()) != Some(name) {
+        return Err(ClientError::VersionMismatch)?;
+    }
+    Ok(map_dir.join(format!("{name}{PART_EXTENSION}")))
+}
+
+/// Writes the chunk where it belongs in the part file, the first chunk starts the file over.
+async fn receive_chunk(map_dir: &Path, name: &str, offset: u64, data: &[u8]) -> Result<()> {
+    tokio::fs::create_dir_all(map_dir).await?;
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(offset == 0)
+        .open(part_path(map_dir, name)?)
+        .await?;
+    file.seek(std::io::SeekFrom::Start(offset)).await?;
+    file.write_all(data).await?;
+    file.flush().await?;
+    Ok(())
+}
+
+/// Turns the complete part file into the map file, decompressing it if the server compressed it.
+async fn finish_file(map_dir: &Path, name: &str, total: u64, compressed: bool) -> Result<()> {
+    let part = part_path(map_dir, name)?;
+    let mut contents = tokio::fs::read(&part).await?;
+    contents.truncate(total as usize);
+    if compressed {
+        contents = compression::decompress(&contents)?;
+    }
+    tokio::fs::write(map_dir.join(name), contents).await?;
+    tokio::fs::remove_file(part).await?;
+    Ok(())
+}
+
+/// Where and as whom a client picks its game up again after the stream dropped.
+struct Resume {
+    server: SocketAddr, // of the TCP stream
+    lobby_code: Option<String>,
+    session: Option<u64>, // the server doesn't take players back without one
+}
+
+/// Reads the game stream over TCP after `received` ticks, reconnecting whenever it drops. `replaced` gets every new
+/// stream. Gives the stream back once the server offers the next round.
+async fn receive_stream<P: Packet<SIZE>, const SIZE: usize>(
+    mut stream: Arc<TcpStream>,
+    mut received: u64, // ticks, the server resends the ones after them on reconnect
+    channel: Sender<StreamItem<P, SIZE>>,
+    stop_listening: Receiver<()>,
+    resume: Resume,
+    replaced: impl Fn(&Arc<TcpStream>) + Send,
+) -> Result<Option<Arc<TcpStream>>> {
+    let mut buf_start = 0;
+    let mut buf = Vec::from([0; 4096]);
+    loop {
+        if !stop_listening.is_empty() {
+            return anyhow::Ok(None)
+        }
+
+        stream.readable().await?;
+        let error = match stream.try_read(&mut buf[buf_start..]) {
+            Ok(0) => anyhow::Error::from(ClientError::ServerClosedConnection),
+            Ok(n) => {
+                let _span = trace_span!("receive_packets", bytes = n).entered();
+                let (items, res_len) =
+                    packet_tools::deserialize_stream(&mut buf[..buf_start + n])?;
+                buf_start = res_len;
+                if buf_start > buf.len() / 2 {
+                    buf.extend((0..buf.len()).into_iter().map(|_| 0));
+                }
+
+                for item in items {
+                    match &item {
+                        StreamItem::Tick(_) => received += 1,
+                        // the lobby of the next round goes on over the same stream,
+                        // the server waits for the votes before sending anything after the offer
+                        StreamItem::Server(ServerPacket::MapVote(_)) => {
+                            channel.send(item)?;
+                            return anyhow::Ok(Some(stream));
+                        }
+                        _ => (),
+                    }
+                    channel.send(item)?;
+                }
+                continue;
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                continue;
+            }
+            Err(e) => e.into(),
+        };
+
+        let Some(session) = resume.session else {
+            return Err(error);
+        };
+        warn!("{error}, reconnecting");
+        let Some(new_stream) = reconnect(resume.server, resume.lobby_code.as_deref(), session, received).await else {
+            return Err(error);
+        };
+        // the tick cut off by the disconnect is resent whole
+        buf_start = 0;
+        stream = new_stream;
+        replaced(&stream);
+    }
+}
+
+/// Resumes the game on a new stream, the server resends the ticks after the `received` ones.
+async fn reconnect(server: SocketAddr, lobby_code: Option<&str>, session: u64, received: u64) -> Option<Arc<TcpStream>> {
+    for attempt in 1..=RECONNECT_ATTEMPTS {
+        tokio::time::sleep(RECONNECT_INTERVAL).await;
+        let stream = async {
+            let mut stream = TcpStream::connect(server).await?;
+            if let Some(code) = lobby_code {
+                stream.write_packet(&ClientPacket::SelectLobby(code.to_string())).await?;
+            }
+            stream.write_packet(&ClientPacket::Reconnect { session, received }).await?;
+            match stream.read_packet().await? {
+                ServerPacket::SetSession(accepted) if accepted == session => anyhow::Ok(stream),
+                _ => Err(ClientError::AuthenticationError)?,
+            }
+        };
+        match stream.await {
+            Ok(stream) => return Some(Arc::new(stream)),
+            Err(e) => warn!("Reconnect attempt {attempt} failed: {e}"),
+        }
+    }
+    None
+}
+
 impl<P, const SIZE: usize> Drop for GameClient<P, SIZE>
 where
     P: Packet<SIZE>,
@@ -267,3 +849,28 @@ where
         self.stop();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume_download_test() {
+        let map_dir = std::env::temp_dir().join(format!("smog-download-{}", std::process::id()));
+        let contents: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            // the download breaks off after the first chunk
+            receive_chunk(&map_dir, "texture0.png", 0, &contents[..400]).await.unwrap();
+            assert_eq!(partial_files(&map_dir).await, vec![("texture0.png".to_string(), 400)]);
+
+            receive_chunk(&map_dir, "texture0.png", 400, &contents[400..]).await.unwrap();
+            finish_file(&map_dir, "texture0.png", 1000, false).await.unwrap();
+            assert_eq!(tokio::fs::read(map_dir.join("texture0.png")).await.unwrap(), contents);
+            assert!(partial_files(&map_dir).await.is_empty());
+
+            assert!(receive_chunk(&map_dir, "../escape", 0, &[]).await.is_err());
+        });
+        let _ = std::fs::remove_dir_all(&map_dir);
+    }
+}
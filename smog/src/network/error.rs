@@ -1,8 +1,49 @@
-#[derive(Debug)]
+use std::io::ErrorKind;
+
+use map_editor::map::MapError;
+use packet_tools::error::PacketError;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum ClientError {
     AuthenticationError,
     NoConnectionToServer,
     ServerClosedConnection,
+    ConnectionRefused,
+    VersionMismatch,
+    MapMissing(String),
+    MapCorrupt(String),
+    Kicked(String),
+    Rejected(String),
+    Other(String),
+}
+
+impl ClientError {
+    /// Key of the locale string explaining what probably went wrong.
+    pub fn cause(&self) -> Option<&'static str> {
+        match self {
+            Self::AuthenticationError => Some("error.cause.authentication"),
+            Self::NoConnectionToServer | Self::ServerClosedConnection => Some("error.cause.connection_lost"),
+            Self::ConnectionRefused => Some("error.cause.connection_refused"),
+            Self::VersionMismatch => Some("error.cause.version_mismatch"),
+            Self::MapMissing(_) => Some("error.cause.map_missing"),
+            Self::MapCorrupt(_) => Some("error.cause.map_corrupt"),
+            Self::Kicked(_) => Some("error.cause.kicked"),
+            Self::Rejected(_) => Some("error.cause.rejected"),
+            Self::Other(_) => None,
+        }
+    }
+
+    /// Whether connecting to the same server again may help.
+    pub fn can_retry(&self) -> bool {
+        !matches!(self, Self::Kicked(_) | Self::Rejected(_) | Self::Other(_))
+    }
+
+    pub fn from_map_error(map: String, error: &MapError) -> Self {
+        match error {
+            MapError::NotFound(_) => Self::MapMissing(map),
+            _ => Self::MapCorrupt(map),
+        }
+    }
 }
 
 impl std::fmt::Display for ClientError {
@@ -11,8 +52,55 @@ impl std::fmt::Display for ClientError {
             Self::AuthenticationError => write!(f, "Server-side authentication error"),
             Self::NoConnectionToServer => write!(f, "No connection to server"),
             Self::ServerClosedConnection => write!(f, "Server closed connection"),
+            Self::ConnectionRefused => write!(f, "Connection refused"),
+            Self::VersionMismatch => write!(f, "Unexpected packet from the server"),
+            Self::MapMissing(map) => write!(f, "Failed to load the map \"{map}\""),
+            Self::MapCorrupt(map) => write!(f, "The map \"{map}\" is damaged"),
+            Self::Kicked(reason) => write!(f, "Kicked from the server: {reason}"),
+            Self::Rejected(reason) => write!(f, "The server refused to let you in: {reason}"),
+            Self::Other(error) => write!(f, "{error}"),
         }
     }
 }
 
-impl std::error::Error for ClientError {}
\ No newline at end of file
+impl std::error::Error for ClientError {}
+
+impl From<anyhow::Error> for ClientError {
+    fn from(error: anyhow::Error) -> Self {
+        if let Some(error) = error.downcast_ref::<ClientError>() {
+            return error.clone();
+        }
+        if error.downcast_ref::<PacketError>().is_some() {
+            return Self::VersionMismatch;
+        }
+        match error.downcast_ref::<std::io::Error>().map(|e| e.kind()) {
+            Some(ErrorKind::ConnectionRefused) => Self::ConnectionRefused,
+            Some(ErrorKind::InvalidData) => Self::VersionMismatch,
+            Some(ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted) => {
+                Self::ServerClosedConnection
+            }
+            _ => Self::Other(error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_test() {
+        let refused = anyhow::Error::from(std::io::Error::from(ErrorKind::ConnectionRefused));
+        assert_eq!(ClientError::from(refused), ClientError::ConnectionRefused);
+        let mismatch = anyhow::Error::from(std::io::Error::new(ErrorKind::InvalidData, "bad packet"));
+        assert_eq!(ClientError::from(mismatch), ClientError::VersionMismatch);
+        let corrupted = anyhow::Error::from(PacketError::UnknownKind(42));
+        assert_eq!(ClientError::from(corrupted), ClientError::VersionMismatch);
+        let typed = anyhow::Error::from(ClientError::AuthenticationError);
+        assert_eq!(ClientError::from(typed), ClientError::AuthenticationError);
+        let kicked = anyhow::Error::from(ClientError::Kicked("afk".to_string()));
+        assert_eq!(ClientError::from(kicked), ClientError::Kicked("afk".to_string()));
+        assert!(!ClientError::Kicked(String::new()).can_retry());
+        assert!(!ClientError::Rejected(String::new()).can_retry());
+    }
+}
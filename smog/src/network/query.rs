@@ -0,0 +1,73 @@
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use common::DISCOVERY_PORT;
+use crossbeam_channel::{unbounded, Receiver};
+use packet_tools::{
+    query_packets::{QueryPacket, ServerInfo},
+    UnsizedPacket,
+};
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Asks LAN servers and favorites for their info in the background.
+pub struct ServerQuery {
+    results: Receiver<(String, ServerInfo)>,
+    _task: JoinHandle<Result<()>>,
+}
+
+impl ServerQuery {
+    pub fn start(favorites: &[String]) -> Self {
+        let favorites = favorites.to_vec();
+        let (send_results, results) = unbounded();
+        let task = std::thread::spawn(move || {
+            let socket = UdpSocket::bind("0.0.0.0:0")?;
+            socket.set_broadcast(true)?;
+            socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+
+            let request = QueryPacket::Request.to_bytes();
+            let _ = socket.send_to(&request, ("255.255.255.255", DISCOVERY_PORT));
+
+            // answers from favorites are reported under the address the user typed
+            let mut known = HashMap::new();
+            for favorite in favorites.iter() {
+                let Some(addr) = favorite.to_socket_addrs().ok().and_then(|mut a| a.next()) else {
+                    continue;
+                };
+                known.insert(addr, favorite.clone());
+                let _ = socket.send_to(&request, addr);
+            }
+
+            let start = Instant::now();
+            let mut buf = [0; 1024];
+            while start.elapsed() < QUERY_TIMEOUT {
+                let Ok((len, peer)) = socket.recv_from(&mut buf) else {
+                    continue;
+                };
+                let Some(QueryPacket::Info(info)) = QueryPacket::parse(&buf[..len]) else {
+                    continue;
+                };
+                let addr = SocketAddr::new(peer.ip(), info.port);
+                let addr = known.get(&addr).cloned().unwrap_or(addr.to_string());
+                if send_results.send((addr, info)).is_err() {
+                    break;
+                }
+            }
+            anyhow::Ok(())
+        });
+
+        Self {
+            results,
+            _task: task,
+        }
+    }
+
+    pub fn get_results(&self) -> Vec<(String, ServerInfo)> {
+        self.results.try_iter().collect()
+    }
+}
@@ -0,0 +1,201 @@
+use anyhow::Result;
+use bevy::{
+    app::AppExit,
+    audio::Volume,
+    prelude::*,
+    window::{PrimaryWindow, WindowMode as BevyWindowMode},
+};
+use common::{
+    chassis::{Chassis, Garage},
+    config,
+    locale::DEFAULT_LANGUAGE,
+};
+use map_editor::vehicle::Vehicle;
+use render::{palette::TeamColors, settings::Settings};
+use serde::{Deserialize, Serialize};
+
+const PREFERENCES_FILE: &str = "preferences.ron";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum WindowMode {
+    #[default]
+    Windowed,
+    Fullscreen,
+}
+
+/// Hp of the local tank below which the danger warnings start, 0 turns a warning off.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LowHpWarning {
+    pub vignette: f32,
+    pub heartbeat: f32,
+}
+
+impl Default for LowHpWarning {
+    fn default() -> Self {
+        Self::PRESETS[0]
+    }
+}
+
+impl LowHpWarning {
+    pub const PRESETS: [LowHpWarning; 3] = [
+        LowHpWarning { vignette: 0.5, heartbeat: 0.3 },
+        LowHpWarning { vignette: 0.7, heartbeat: 0.5 },
+        LowHpWarning { vignette: 0., heartbeat: 0. },
+    ];
+
+    /// Preset after the current one, thresholds edited by hand go back to the first preset.
+    pub fn next(&self) -> Self {
+        let current = Self::PRESETS.iter().position(|preset| preset == self);
+        Self::PRESETS[current.map_or(0, |i| (i + 1) % Self::PRESETS.len())]
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.vignette > 0. || self.heartbeat > 0.
+    }
+}
+
+/// Client settings kept between sessions in the platform config directory.
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preferences {
+    pub nickname: String,
+    pub last_server: String,
+    pub favorites: Vec<String>,
+    pub window_size: (f32, f32),
+    pub window_mode: WindowMode,
+    pub tutorial_done: bool,
+    pub team_colors: TeamColors,
+    pub language: String,
+    pub low_hp_warning: LowHpWarning,
+    pub chassis: Chassis, // picked again in every lobby
+    pub vehicle: String,  // custom vehicle driven with `Chassis::Custom`, one of the exported ones
+    pub record_clips: bool, // keep the last seconds for a gif from the start, without LEFT CTRL + F12
+    #[serde(skip)]
+    pub password: String, // of the last lobby, kept for retries but never saved
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            nickname: String::new(),
+            last_server: String::new(),
+            favorites: vec![],
+            window_size: (1280., 720.),
+            window_mode: WindowMode::default(),
+            tutorial_done: false,
+            team_colors: TeamColors::default(),
+            language: DEFAULT_LANGUAGE.to_string(),
+            low_hp_warning: LowHpWarning::default(),
+            chassis: Chassis::default(),
+            vehicle: String::new(),
+            record_clips: false,
+            password: String::new(),
+        }
+    }
+}
+
+impl Preferences {
+    /// Falls back to the defaults if the file is missing or broken.
+    pub fn load() -> Self {
+        config::load(PREFERENCES_FILE)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        config::save(PREFERENCES_FILE, self)
+    }
+
+    /// Exported file of the custom vehicle, none while a built-in chassis is picked or if the file is gone.
+    pub fn vehicle_file(&self) -> Option<Vec<u8>> {
+        if self.chassis != Chassis::Custom {
+            return None;
+        }
+        std::fs::read(Vehicle::get_path(&self.vehicle, config::vehicles_dir())).ok()
+    }
+
+    /// What the local player drives in an offline game.
+    pub fn garage(&self, id: u8) -> Garage {
+        Garage {
+            chassis: vec![(id, self.chassis)],
+            vehicles: self.vehicle_file().map(|vehicle| vec![(id, vehicle)]).unwrap_or_default(),
+        }
+    }
+
+    pub fn window(&self) -> Window {
+        Window {
+            title: "SMOG".to_string(),
+            resolution: self.window_size.into(),
+            mode: match self.window_mode {
+                WindowMode::Windowed => BevyWindowMode::Windowed,
+                WindowMode::Fullscreen => BevyWindowMode::BorderlessFullscreen,
+            },
+            ..default()
+        }
+    }
+}
+
+fn track_window(
+    window: Query<&Window, (With<PrimaryWindow>, Changed<Window>)>,
+    mut preferences: ResMut<Preferences>,
+) {
+    let Ok(window) = window.get_single() else {
+        return;
+    };
+    let window_mode = match window.mode {
+        BevyWindowMode::Windowed => WindowMode::Windowed,
+        _ => WindowMode::Fullscreen,
+    };
+    // fullscreen size isn't worth remembering
+    if window_mode == WindowMode::Windowed {
+        preferences.window_size = (window.resolution.width(), window.resolution.height());
+    }
+    preferences.window_mode = window_mode;
+}
+
+fn sync_team_colors(preferences: Res<Preferences>, mut team_colors: ResMut<TeamColors>) {
+    if preferences.is_changed() && *team_colors != preferences.team_colors {
+        *team_colors = preferences.team_colors.clone();
+    }
+}
+
+fn sync_volume(settings: Res<Settings>, mut global_volume: ResMut<GlobalVolume>) {
+    if settings.is_changed() {
+        global_volume.volume = Volume::new(settings.volume.clamp(0., 1.));
+    }
+}
+
+fn save_on_exit(mut exit: EventReader<AppExit>, preferences: Res<Preferences>, settings: Res<Settings>) {
+    if exit.read().next().is_some() {
+        if let Err(e) = preferences.save() {
+            warn!("Failed to save preferences: {e}");
+        }
+        if let Err(e) = settings.save() {
+            warn!("Failed to save settings: {e}");
+        }
+    }
+}
+
+fn toggle_fullscreen(keyboard: Res<ButtonInput<KeyCode>>, mut window: Query<&mut Window, With<PrimaryWindow>>) {
+    if !keyboard.just_pressed(KeyCode::F11) {
+        return;
+    }
+    let Ok(mut window) = window.get_single_mut() else {
+        return;
+    };
+    window.mode = match window.mode {
+        BevyWindowMode::Windowed => BevyWindowMode::BorderlessFullscreen,
+        _ => BevyWindowMode::Windowed,
+    };
+}
+
+pub struct PreferencesPlugin(pub Preferences);
+
+impl Plugin for PreferencesPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.0.clone())
+            .insert_resource(self.0.team_colors.clone())
+            .insert_resource(Settings::load())
+            .add_systems(Update, (track_window, toggle_fullscreen, sync_team_colors, sync_volume))
+            .add_systems(Last, save_on_exit);
+    }
+}
@@ -1,4 +1,9 @@
 pub mod main_menu;
 pub mod game;
 pub mod lobby;
-pub mod over;
\ No newline at end of file
+pub mod over;
+pub mod tutorial;
+pub mod replay;
+pub mod error;
+pub mod maps;
+pub mod settings;
@@ -0,0 +1,168 @@
+use bevy::prelude::*;
+
+use crate::{locale::Strings, preferences::Preferences, GameError, GameState};
+
+use super::main_menu::connect;
+
+#[derive(Component)]
+struct ErrorScreen;
+
+#[derive(Component)]
+enum ErrorButton {
+    Retry,
+    Menu,
+}
+
+fn despawn(mut commands: Commands, error_screen: Query<Entity, With<ErrorScreen>>) {
+    if let Ok(error_screen) = error_screen.get_single() {
+        commands.entity(error_screen).despawn_recursive();
+    }
+    commands.remove_resource::<GameError>();
+}
+
+const BORDER_COLOR: Color = Color::srgb(0.25, 0.25, 0.25);
+const TEXT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
+const ERROR_COLOR: Color = Color::srgb(1., 0., 1.);
+const BACKGROUND_COLOR: Color = Color::srgb(0.15, 0.15, 0.15);
+
+fn build(commands: &mut Commands, error: &GameError, preferences: &Preferences, strings: &Strings) -> Entity {
+    let text_style = strings.text_style(40., TEXT_COLOR);
+
+    let node_bundle = NodeBundle {
+        style: Style {
+            width: Val::Px(800.0),
+            border: UiRect::all(Val::Px(5.0)),
+            padding: UiRect::all(Val::Px(5.0)),
+            flex_direction: FlexDirection::Column,
+            ..default()
+        },
+        border_color: BORDER_COLOR.into(),
+        background_color: BACKGROUND_COLOR.into(),
+        ..default()
+    };
+
+    let button_bundle = ButtonBundle {
+        style: Style {
+            width: Val::Px(200.),
+            border: UiRect::all(Val::Px(5.0)),
+            padding: UiRect::all(Val::Px(5.0)),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        border_color: BorderColor(BORDER_COLOR),
+        background_color: BACKGROUND_COLOR.into(),
+        ..default()
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(10.),
+                    ..default()
+                },
+                ..default()
+            },
+            ErrorScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn(node_bundle).with_children(|parent| {
+                parent.spawn(TextBundle::from_section(
+                    error.0.to_string(),
+                    strings.text_style(40., ERROR_COLOR),
+                ));
+                if let Some(cause) = error.0.cause() {
+                    parent.spawn(TextBundle::from_section(
+                        strings.get(cause),
+                        strings.text_style(30., TEXT_COLOR),
+                    ));
+                }
+            });
+
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        column_gap: Val::Px(10.),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    // retrying reconnects to the last server the user tried
+                    if error.0.can_retry() && !preferences.last_server.is_empty() {
+                        parent
+                            .spawn((button_bundle.clone(), ErrorButton::Retry))
+                            .with_children(|parent| {
+                                parent.spawn(TextBundle::from_section(strings.get("error.retry"), text_style.clone()));
+                            });
+                    }
+                    parent
+                        .spawn((button_bundle, ErrorButton::Menu))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section(strings.get("error.menu"), text_style));
+                        });
+                });
+        })
+        .id()
+}
+
+/// Builds the screen again whenever a new error comes in, e.g. after a failed retry.
+fn update_system(
+    mut commands: Commands,
+    error: Res<GameError>,
+    preferences: Res<Preferences>,
+    strings: Res<Strings>,
+    error_screen: Query<Entity, With<ErrorScreen>>,
+) {
+    if !error.is_changed() {
+        return;
+    }
+    if let Ok(error_screen) = error_screen.get_single() {
+        commands.entity(error_screen).despawn_recursive();
+    }
+    let _error_screen = build(&mut commands, &error, &preferences, &strings);
+}
+
+fn button_system(
+    mut commands: Commands,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut preferences: ResMut<Preferences>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    buttons: Query<(&Interaction, &ErrorButton), Changed<Interaction>>,
+) {
+    if keyboard.just_pressed(KeyCode::Escape) {
+        next_state.set(GameState::Menu);
+    }
+    for (interaction, button) in &buttons {
+        if !matches!(interaction, Interaction::Pressed) {
+            continue;
+        }
+        match button {
+            ErrorButton::Retry => {
+                let addr = preferences.last_server.clone();
+                let nick = preferences.nickname.clone();
+                connect(&mut commands, &mut next_state, &mut preferences, addr, nick, false);
+            }
+            ErrorButton::Menu => next_state.set(GameState::Menu),
+        }
+    }
+}
+
+pub struct ErrorScreenPlugin;
+
+impl Plugin for ErrorScreenPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnExit(GameState::Error), despawn).add_systems(
+            Update,
+            (update_system, button_system)
+                .chain()
+                .run_if(in_state(GameState::Error).and_then(resource_exists::<GameError>)),
+        );
+    }
+}
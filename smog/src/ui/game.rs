@@ -1,62 +1,116 @@
 use bevy::math::{vec2, vec3};
 use bevy::{ 
-    input::mouse::MouseWheel, prelude::*,
+    asset::LoadState, input::mouse::MouseWheel, prelude::*,
     render::camera::ScalingMode, window::PrimaryWindow,
 };
 
-use common::MAX_TEAMS;
+use chase::ChasePlugin;
 use interface::OverlayPlugin;
+use lighting::LightingPlugin;
+use shake::ShakePlugin;
+use sound::SoundPlugin;
+use warning::WarningPlugin;
 use map_editor::map::MapLoader;
-use render::{RenderedSimulation, SimulationCamera, SimulationTextures};
+use render::{
+    ambient::Ambient,
+    camera::ObserverCamera,
+    interpolation::TickPositions,
+    palette::TeamColors,
+    settings::{PadButton, Settings, Stick},
+    RenderedSimulation, SimulationCamera, SimulationTextures,
+};
 use packet_tools::game_packets::GamePacket;
-use crate::{display_error, Client, GameState};
-use crate::controller::{model::RawPlayerModel, Controller};
+use session::{controller::Controller, replay::Replay, GameSession, SUB_TICKS};
+use crate::{
+    display_error,
+    network::error::ClientError,
+    Client, GameState,
+};
 
-mod interface;
+use super::{replay::Recording, tutorial::{self, Tutorial}};
 
-const SUB_TICKS: usize = 8;
+mod chase;
+mod interface;
+mod lighting;
+mod shake;
+mod sound;
+mod warning;
 
 #[derive(Component)]
 pub struct GameController(pub Controller);
 
+/// Ambient light of the map, the simulation's [`Ambient`] goes back to it after a flash.
+#[derive(Component)]
+pub struct MapLight(pub Ambient);
+
 #[derive(Component)]
 struct PlayerBanner(u8);
 
+const PING_INTERVAL: f32 = 1.; // seconds between the probes
+const PING_TIMEOUT: f32 = 5.; // a probe dropped on the way is sent again
+const PROJECTILES: u8 = 6;
+
+/// Whether the gamepad moved the tank or the muzzle last frame, so letting go of a stick stops it once.
+#[derive(Default)]
+struct PadState {
+    driving: bool,
+    aiming: bool,
+}
+
+/// Ping packet in flight, the time it takes to come back in a tick is the round trip of the inputs.
+#[derive(Default)]
+struct PingProbe {
+    sent: Option<f32>,
+    last: f32,
+    ms: u16,
+}
+
 fn setup_simulation(
     mut commands: Commands,
     client: Res<Client>,
     asset_server: Res<AssetServer>,
-    mut camera: Query<&mut OrthographicProjection, With<SimulationCamera>>,
-    controller: Query<Entity, With<GameController>>,
+    mut camera: Query<(Entity, &mut OrthographicProjection), With<SimulationCamera>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    tutorial: Option<Res<Tutorial>>,
+    team_colors: Res<TeamColors>,
 ) {
-    // despawn old simulations
-    despawn(&mut commands, &controller);
-
     // setup simulation
-    let tank = RawPlayerModel::generate_tank();
     let lobby = &client.0.lobby;
-    let map_loader = MapLoader::init_from_file(&lobby.map, &asset_server).unwrap(); // TODO: error handling
+    let map_loader = match &tutorial {
+        Some(_) => tutorial::map_loader(&asset_server),
+        None => match MapLoader::init_from_file(&lobby.map, &asset_server) {
+            Ok(map_loader) => map_loader,
+            Err(e) => {
+                warn!("Failed to load the map: {e}");
+                display_error(&mut commands, &mut next_state, ClientError::from_map_error(lobby.map.clone(), &e));
+                return;
+            }
+        },
+    };
     commands.insert_resource(SimulationTextures {
         textures: map_loader.textures,
         background: map_loader.background,
+        parallax: map_loader.parallax,
+        atlas: map_loader.atlas,
     });
 
-    let mut solver = map_loader.map.solver();
-    let spawns = map_loader.map.spawns;
-    let mut player_model = None;
-    let mut players = Vec::new();
-    for (id, name) in lobby.players.iter() {
-        let model = RawPlayerModel::place_in_solver(
-            tank.clone(),
-            spawns[*id as usize].pos,
-            &mut solver,
-        );
-        if *id == lobby.id {
-            player_model = Some(model.clone());
-        }
-        players.push((*id, name.clone(), model));
+    // the tutorial isn't worth watching again
+    if tutorial.is_none() {
+        let replay = Replay::new(lobby.map.clone(), lobby.settings, lobby.players.clone(), lobby.garage.clone());
+        commands.insert_resource(Recording(replay));
     }
 
+    let GameSession { controller, solver } = GameSession::new(
+        lobby.id,
+        client.0.name.clone(),
+        &map_loader.map,
+        &lobby.players,
+        &lobby.garage,
+        &lobby.settings,
+        &team_colors,
+    );
+    let spawns = &map_loader.map.spawns;
+    let ambient = map_loader.map.ambient;
     let simulation = RenderedSimulation(solver);
 
     // setup camera
@@ -66,17 +120,21 @@ fn setup_simulation(
         scaling_mode: ScalingMode::FixedHorizontal(tr.x - bl.x),
         ..Default::default()
     };
-    let mut camera_projection = camera.single_mut();
+    let (camera, mut camera_projection) = camera.single_mut();
     *camera_projection = projection;
+    // spectators start with the free camera, as they have no tank to follow
+    if controller.spectating() {
+        commands.entity(camera).insert(ObserverCamera::default());
+    }
 
     // spawn player banners
-    for (id, name, _) in players.iter() {
+    for (id, name) in lobby.players.iter() {
         let team = spawns[*id as usize].team;
         commands
             .spawn(Text2dBundle {
                 text: Text::from_section(name.clone(), TextStyle {
                     font_size: 60., 
-                    color: Color::hsl(360. * team as f32 / MAX_TEAMS as f32, 1., 0.5),
+                    color: team_colors.color(team),
                     ..Default::default()
                 }),
                 ..Default::default()
@@ -92,38 +150,68 @@ fn setup_simulation(
             ..default()
         })
         .insert(simulation)
-        .insert(GameController(Controller::new(
-            lobby.id,
-            client.0.name.clone(),
-            player_model.unwrap(),
-            players,
-            &spawns,
-        )));
+        // the physics ticks in `FixedUpdate`, the frames in between are blended
+        .insert(TickPositions::default())
+        .insert((ambient, MapLight(ambient)))
+        .insert(GameController(controller));
 }
 
-fn despawn(commands: &mut Commands, controller: &Query<Entity, With<GameController>>) {
+/// Removes the simulation left from the previous game.
+fn despawn(mut commands: Commands, controller: Query<Entity, With<GameController>>) {
     if let Ok(controller) = controller.get_single() {
         commands.entity(controller).despawn_recursive();
     }
 }
 
 fn update_physics(
+    mut commands: Commands,
     client: Res<Client>,
+    time: Res<Time<Real>>,
+    mut probe: Local<PingProbe>,
     mut simulation: Query<(&mut RenderedSimulation, &mut GameController)>,
+    mut recording: Option<ResMut<Recording>>,
     mut next_state: ResMut<NextState<GameState>>,
 ) {
     let (mut simulation, mut controller) = simulation.single_mut();
     let packets = client.0.get_packets(1 * SUB_TICKS);
-    let dt = 1. / 60. / SUB_TICKS as f32;
+
+    let now = time.elapsed_seconds();
+    let id = controller.0.player.id;
+    if let Some(sent) = probe.sent {
+        let returned = packets
+            .iter()
+            .flatten()
+            .any(|p| p.id == id && matches!(p.contents, GamePacket::Ping(_)));
+        if returned {
+            probe.ms = ((now - sent) * 1000.).min(u16::MAX as f32) as u16;
+        }
+        if returned || now - sent > PING_TIMEOUT {
+            probe.sent = None;
+        }
+    }
+    if probe.sent.is_none() && now - probe.last > PING_INTERVAL && !controller.0.spectating() {
+        if let Err(e) = client.0.send_packet(GamePacket::Ping(probe.ms)) {
+            display_error(&mut commands, &mut next_state, e);
+            return;
+        }
+        probe.sent = Some(now);
+        probe.last = now;
+    }
 
     for p in packets {
-        controller.0.handle_packets(&mut simulation.0, &p);
-        simulation.0.solve(dt);
-        if controller.0.get_winners(&simulation.0).is_some() {
+        if let Some(recording) = recording.as_mut() {
+            recording.0.record(&p);
+        }
+        if GameSession::advance(&mut controller.0, &mut simulation.0, &p).is_some() {
             next_state.set(GameState::EndGame);
             return;
         }
     }
+    if let Some(state) = client.0.take_state_sync() {
+        if !GameSession::apply_state_sync(&mut controller.0, &mut simulation.0, &state) {
+            warn!("Ignoring the server's state of another tick");
+        }
+    }
 }
 
 fn update_banners(
@@ -138,6 +226,24 @@ fn update_banners(
     }
 }
 
+fn mode_gizmo_system(mut gizmos: Gizmos, controller: Query<&GameController>, team_colors: Res<TeamColors>) {
+    let Ok(controller) = controller.get_single() else {
+        return;
+    };
+    let mode = &controller.0.mode;
+    if let Some(hill) = &mode.hill {
+        let color = match mode.hill_owner {
+            Some(team) => team_colors.color(team),
+            None => Color::srgb(0.9, 0.9, 0.9),
+        };
+        gizmos.circle_2d(hill.pos, hill.radius, color);
+    }
+    for flag in mode.flags.iter() {
+        let color = team_colors.color(flag.team);
+        gizmos.rect_2d(flag.base, 0., vec2(3., 3.), color);
+    }
+}
+
 fn control_system(
     mut commands: Commands,
     mut evr_scroll: EventReader<MouseWheel>,
@@ -147,16 +253,25 @@ fn control_system(
     windows: Query<&Window, With<PrimaryWindow>>,
     client: Res<Client>,
     mut simulation: Query<(&mut RenderedSimulation, &mut GameController)>,
-    mut camera: Query<(&Camera, &mut OrthographicProjection, &mut Transform)>,
+    mut camera: Query<(&Camera, &mut OrthographicProjection, &mut Transform), Without<ObserverCamera>>,
     mut next_state: ResMut<NextState<GameState>>,
+    gamepads: Res<Gamepads>,
+    pad_buttons: Res<ButtonInput<GamepadButton>>,
+    pad_axes: Res<Axis<GamepadAxis>>,
+    settings: Res<Settings>,
+    mut pad: Local<PadState>,
 ) {
-    let (camera, mut projection, mut camera_transform) = camera.single_mut();
+    // spectators use the observer camera instead
+    let Ok((camera, mut projection, mut camera_transform)) = camera.get_single_mut() else {
+        return;
+    };
     let (simulation, mut controller) = simulation.single_mut();
     let window = windows.single();
+    let keys = &settings.game;
 
     // camera
     for ev in evr_scroll.read() {
-        projection.scale *= f32::powf(1.25, ev.y);
+        projection.scale = settings.zoom(projection.scale, ev.y);
     }
 
     let new_mouse_position = window.cursor_position().and_then(|cursor| {
@@ -173,81 +288,70 @@ fn control_system(
         *mouse_position = new_mouse_position;
     }
 
-    let mut factor: f32 = 1.;
-    let mut shift_pressed = false;
-    if keyboard.pressed(KeyCode::ShiftLeft) {
-        factor = 5.;
-        shift_pressed = true;
-    }
-    if keyboard.pressed(KeyCode::ArrowLeft) {
+    // holding the aim key also speeds the camera up
+    let aiming = keyboard.pressed(keys.aim);
+    let factor = settings.camera_sensitivity * if aiming { 5. } else { 1. };
+    if keyboard.pressed(keys.camera_left) {
         camera_transform.translation.x -= 0.1 * factor;
     }
-    if keyboard.pressed(KeyCode::ArrowRight) {
+    if keyboard.pressed(keys.camera_right) {
         camera_transform.translation.x += 0.1 * factor;
     }
-    if keyboard.pressed(KeyCode::ArrowDown) {
+    if keyboard.pressed(keys.camera_down) {
         camera_transform.translation.y -= 0.1 * factor;
     }
-    if keyboard.pressed(KeyCode::ArrowUp) {
+    if keyboard.pressed(keys.camera_up) {
         camera_transform.translation.y += 0.1 * factor;
     }
 
     let mut packets: Vec<GamePacket> = vec![];
     // player
-    if keyboard.pressed(KeyCode::KeyA) {
+    if keyboard.pressed(keys.drive_left) {
         packets.extend(&controller.0.move_tank(1.));
-    } else if keyboard.pressed(KeyCode::KeyD) {
+    } else if keyboard.pressed(keys.drive_right) {
         packets.extend(&controller.0.move_tank(-1.));
     } 
-    if keyboard.just_released(KeyCode::KeyA) || keyboard.just_released(KeyCode::KeyD) {
+    if keyboard.just_released(keys.drive_left) || keyboard.just_released(keys.drive_right) {
         packets.extend(&controller.0.move_tank(0.));
     }
-    if keyboard.just_released(KeyCode::KeyW) {
+    if keyboard.just_released(keys.gear_up) {
         controller.0.player.gear_up()
     }
-    if keyboard.just_released(KeyCode::KeyS) {
+    if keyboard.just_released(keys.gear_down) {
         controller.0.player.gear_down()
     }
     // rotation
     let hp = Controller::get_player_hp(&controller.0.player, &simulation.0);
-    if keyboard.pressed(KeyCode::KeyQ) {
+    if keyboard.pressed(keys.tilt_left) {
         packets.extend(&controller.0.rotate_tank(-0.1 * hp));
-    } else if keyboard.pressed(KeyCode::KeyE) {
+    } else if keyboard.pressed(keys.tilt_right) {
         packets.extend(&controller.0.rotate_tank(0.1 * hp));
     } 
-    if keyboard.just_released(KeyCode::KeyQ) || keyboard.just_released(KeyCode::KeyE) {
+    if keyboard.just_released(keys.tilt_left) || keyboard.just_released(keys.tilt_right) {
         packets.extend(&controller.0.rotate_tank(0.))
     }
     // dash
-    if keyboard.pressed(KeyCode::Space) {
+    if keyboard.pressed(keys.dash) {
         packets.extend(&controller.0.dash());
     }
+    if keyboard.just_pressed(keys.shield) {
+        packets.extend(&controller.0.shield());
+    }
 
     // shooting
     if let Some(cursor_world_position) = window.cursor_position().and_then(|cursor| {
         camera.viewport_to_world_2d(&GlobalTransform::from(camera_transform.clone()), cursor)
     }) {
-        let digits = vec![
-            KeyCode::Digit1,
-            KeyCode::Digit2,
-            KeyCode::Digit3,
-            KeyCode::Digit4,
-            KeyCode::Digit5,
-            KeyCode::Digit6,
-            KeyCode::Digit7,
-            KeyCode::Digit8,
-        ];
-
-        for (projectile, key) in digits.into_iter().enumerate() {
-            if keyboard.pressed(key) {
+        for (projectile, key) in keys.projectiles.iter().enumerate() {
+            if keyboard.pressed(*key) && controller.0.projectile_allowed(projectile as u8) {
                 controller.0.player.projectile = projectile as u8;
             }
         }
 
-        if shift_pressed {
+        if aiming {
             packets.extend(&controller.0.move_muzzle(cursor_world_position));
         } 
-        if keyboard.just_released(KeyCode::ShiftLeft){
+        if keyboard.just_released(keys.aim) {
             packets.extend(&controller.0.reset_muzzle());
         }
 
@@ -256,31 +360,141 @@ fn control_system(
         }
     }
 
+    // gamepad, only the first one connected plays
+    if let Some(gamepad) = gamepads.iter().next() {
+        let bindings = settings.gamepad;
+        let stick = |stick: Stick| {
+            let (x, y) = stick.axes();
+            let deflection = vec2(
+                pad_axes.get(GamepadAxis::new(gamepad, x)).unwrap_or(0.),
+                pad_axes.get(GamepadAxis::new(gamepad, y)).unwrap_or(0.),
+            );
+            match deflection.length() < bindings.dead_zone {
+                true => Vec2::ZERO,
+                false => deflection,
+            }
+        };
+        let button = |button: PadButton| GamepadButton::new(gamepad, button.button_type());
+
+        // the stick drives right, the tracks of a positive coefficient left
+        let drive = stick(bindings.drive).x;
+        if drive != 0. {
+            packets.extend(&controller.0.move_tank(-drive));
+        } else if pad.driving {
+            packets.extend(&controller.0.move_tank(0.));
+        }
+        pad.driving = drive != 0.;
+        if pad_buttons.just_pressed(button(bindings.gear_up)) {
+            controller.0.player.gear_up()
+        }
+        if pad_buttons.just_pressed(button(bindings.gear_down)) {
+            controller.0.player.gear_down()
+        }
+        if pad_buttons.pressed(button(bindings.dash)) {
+            packets.extend(&controller.0.dash());
+        }
+        if pad_buttons.just_pressed(button(bindings.shield)) {
+            packets.extend(&controller.0.shield());
+        }
+
+        let aim = stick(bindings.aim);
+        if aim != Vec2::ZERO {
+            let pos = Controller::get_player_pos(&controller.0.player, &simulation.0);
+            packets.extend(&controller.0.move_muzzle(pos + aim * bindings.aim_distance));
+        } else if pad.aiming {
+            packets.extend(&controller.0.reset_muzzle());
+        }
+        pad.aiming = aim != Vec2::ZERO;
+        if pad_buttons.just_pressed(button(bindings.next_projectile)) {
+            // the projectiles the rules forbid are skipped
+            let current = controller.0.player.projectile;
+            if let Some(next) = (1..=PROJECTILES)
+                .map(|i| (current + i) % PROJECTILES)
+                .find(|p| controller.0.projectile_allowed(*p))
+            {
+                controller.0.player.projectile = next;
+            }
+        }
+        if pad_buttons.pressed(button(bindings.fire)) {
+            packets.extend(&controller.0.fire());
+        }
+    }
+
     match client.0.send_packets(&packets) {
-        Err(e) => display_error(&mut commands, &mut next_state, &e.to_string()),
+        Err(e) => display_error(&mut commands, &mut next_state, e),
         _ => (),
     }
 }
 
-fn exit_system(mut commands: Commands, banners: Query<Entity, With<PlayerBanner>>) {
-    commands.remove_resource::<Client>();
+fn spectate_system(
+    mut commands: Commands,
+    simulation: Query<(&RenderedSimulation, &GameController)>,
+    camera: Query<Entity, (With<SimulationCamera>, Without<ObserverCamera>)>,
+) {
+    let Ok((simulation, controller)) = simulation.get_single() else {
+        return;
+    };
+    // keep following the tank while there are respawns left
+    if Controller::player_in_game(&controller.0.player, &simulation.0) {
+        return;
+    }
+    for camera in &camera {
+        commands.entity(camera).insert(ObserverCamera::default());
+    }
+}
+
+/// Textures of a damaged map only fail once the asset server gets to them.
+fn texture_check_system(
+    mut commands: Commands,
+    client: Res<Client>,
+    asset_server: Res<AssetServer>,
+    textures: Res<SimulationTextures>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let failed = textures
+        .textures
+        .iter()
+        .chain(textures.background.iter())
+        .chain(textures.parallax.iter().map(|layer| &layer.image))
+        .any(|handle| matches!(asset_server.get_load_state(handle), Some(LoadState::Failed(_))));
+    if failed {
+        display_error(&mut commands, &mut next_state, ClientError::MapCorrupt(client.0.lobby.map.clone()));
+    }
+}
+
+fn exit_system(
+    mut commands: Commands,
+    banners: Query<Entity, With<PlayerBanner>>,
+    camera: Query<Entity, With<ObserverCamera>>,
+) {
     for banner in &banners {
         commands.entity(banner).despawn_recursive();
     }
+    for camera in &camera {
+        commands.entity(camera).remove::<ObserverCamera>();
+    }
+}
+
+/// The client outlives the game so the win screen can vote for the next round, it's dropped on leaving.
+fn release_client(mut commands: Commands) {
+    commands.remove_resource::<Client>();
 }
 
 pub struct GamePlugin;
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(OverlayPlugin)
+        app.add_plugins((OverlayPlugin, WarningPlugin, SoundPlugin, ChasePlugin, LightingPlugin, ShakePlugin))
         .insert_resource(Time::<Fixed>::from_hz(64.0))
-            .add_systems(OnEnter(GameState::InGame), setup_simulation)
+            .add_systems(OnEnter(GameState::InGame), (despawn, setup_simulation).chain())
             .add_systems(OnExit(GameState::InGame), exit_system)
-            .add_systems(Update, (control_system, update_banners).run_if(in_state(GameState::InGame)))
+            .add_systems(OnEnter(GameState::Menu), release_client)
+            .add_systems(OnEnter(GameState::Error), release_client)
+            // the simulation is missing if the map failed to load
+            .add_systems(Update, (control_system, spectate_system, update_banners, mode_gizmo_system, texture_check_system).run_if(in_state(GameState::InGame).and_then(any_with_component::<GameController>)))
             .add_systems(
                 FixedUpdate,
-                (update_physics).run_if(in_state(GameState::InGame)),
+                (update_physics).run_if(in_state(GameState::InGame).and_then(any_with_component::<GameController>)),
             );
     }
 }
@@ -0,0 +1,71 @@
+use bevy::prelude::*;
+use render::{camera::ObserverCamera, settings::Settings, RenderedSimulation, SimulationCamera};
+use session::controller::Controller;
+
+use crate::GameState;
+
+use super::{control_system, GameController};
+
+const DEAD_ZONE: f32 = 0.3; // part of the half view the tank moves in before the camera follows
+const STIFFNESS: f32 = 4.; // how fast the camera catches up, per second
+const ZOOM_RANGE: (f32, f32) = (0.05, 1.); // scales of the camera while following, 1 shows the whole width of the map
+
+/// Whether the camera follows the tank of the player, toggled with the follow key.
+#[derive(Resource)]
+pub struct ChaseCamera(pub bool);
+
+impl Default for ChaseCamera {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+fn toggle_system(keyboard: Res<ButtonInput<KeyCode>>, settings: Res<Settings>, mut chase: ResMut<ChaseCamera>) {
+    if keyboard.just_pressed(settings.game.follow) {
+        chase.0 = !chase.0;
+        info!("Camera {}", if chase.0 { "follows the tank" } else { "is free" });
+    }
+}
+
+/// Moves the camera just enough to keep the tank inside the dead zone, easing toward it.
+fn chase_system(
+    time: Res<Time>,
+    chase: Res<ChaseCamera>,
+    simulation: Query<(&RenderedSimulation, &GameController)>,
+    mut camera: Query<(&mut OrthographicProjection, &mut Transform), (With<SimulationCamera>, Without<ObserverCamera>)>,
+) {
+    // spectators and dead players have the observer camera
+    let Ok((mut projection, mut transform)) = camera.get_single_mut() else {
+        return;
+    };
+    let Ok((simulation, controller)) = simulation.get_single() else {
+        return;
+    };
+    if !chase.0 || !Controller::player_alive(&controller.0.player, &simulation.0) {
+        return;
+    }
+
+    projection.scale = projection.scale.clamp(ZOOM_RANGE.0, ZOOM_RANGE.1);
+    let pos = Controller::get_player_pos(&controller.0.player, &simulation.0);
+    let center = transform.translation.truncate();
+    let dead_zone = projection.area.half_size() * DEAD_ZONE;
+    let offset = pos - center;
+    let outside = offset - offset.clamp(-dead_zone, dead_zone);
+    let step = 1. - (-STIFFNESS * time.delta_seconds()).exp();
+    transform.translation += (outside * step).extend(0.);
+}
+
+/// Chase camera of the player's tank, the manual panning still works inside the dead zone.
+pub struct ChasePlugin;
+
+impl Plugin for ChasePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChaseCamera>().add_systems(
+            Update,
+            (toggle_system, chase_system)
+                .chain()
+                .after(control_system)
+                .run_if(in_state(GameState::InGame).and_then(any_with_component::<GameController>)),
+        );
+    }
+}
@@ -1,6 +1,11 @@
+use std::collections::BTreeSet;
+
 use bevy::prelude::*;
+use common::mode::GameMode;
+use render::{palette::TeamColors, RenderedSimulation, SimulationCamera};
+use session::controller::{stats::KILL_TICKS, Controller, Player, HIT_TICKS};
 
-use crate::GameState;
+use crate::{locale::Strings, Client, GameState};
 
 use super::GameController;
 
@@ -13,10 +18,30 @@ enum OverlayTexture {
     Gear(Vec<Handle<Image>>),
 }
 
+#[derive(Component)]
+struct OverlayScore;
+
+#[derive(Component)]
+struct OverlayTimer;
+
+/// Connection stats, toggled with F3.
+#[derive(Component)]
+struct OverlayNetwork;
+
+/// Every player of the match, shown while Tab is held.
+#[derive(Component)]
+struct OverlayScoreboard;
+
+/// Recent kills of the match, in the corner of the screen.
+#[derive(Component)]
+struct OverlayKillFeed;
+
 #[derive(Component)]
 enum OverlayProgress {
-    DashProgress,
-    ReloadProgress,
+    Dash,
+    Shield,
+    Energy,
+    Reload,
 }
 
 fn spawn(mut commands: Commands, asset_server: Res<AssetServer>) {
@@ -32,6 +57,12 @@ fn despawn(mut commands: Commands, lobby: Query<Entity, With<Overlay>>) {
 const BORDER_COLOR: Color = Color::srgb(0.25, 0.25, 0.25);
 const BACKGROUND_COLOR: Color = Color::srgba(0., 0., 0., 0.9);
 
+const HIT_ARC: f32 = std::f32::consts::PI / 4.;
+const HIT_LINES: usize = 6; // gizmo lines are thin, a few of them make one thick arc
+const HIT_FULL_DAMAGE: f32 = 1.; // damage of a hit shown at full brightness
+const KILL_FADE: u128 = 400; // last ticks of a kill in the feed, it fades out over them
+const FORBIDDEN_ALPHA: f32 = 0.2; // of the icons of the projectiles the rules forbid
+
 fn build(commands: &mut Commands, asset_server: &Res<AssetServer>) -> Entity {
     let projectile_node = NodeBundle {
         style: Style {
@@ -73,7 +104,35 @@ fn build(commands: &mut Commands, asset_server: &Res<AssetServer>) -> Entity {
                     ..Default::default()
                 })
                 .insert(UiImage::new(progress_texture.clone()).with_color(Color::srgba(0., 0.7, 0., 0.9)))
-                .insert(OverlayProgress::DashProgress);
+                .insert(OverlayProgress::Dash);
+
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.),
+                        height: Val::Px(7.5),
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::Center,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(UiImage::new(progress_texture.clone()).with_color(Color::srgba(0.6, 0.9, 1., 0.9)))
+                .insert(OverlayProgress::Shield);
+
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.),
+                        height: Val::Px(7.5),
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::Center,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(UiImage::new(progress_texture.clone()).with_color(Color::srgba(0.1, 0.5, 1., 0.9)))
+                .insert(OverlayProgress::Energy);
 
             parent
                 .spawn(NodeBundle {
@@ -87,7 +146,7 @@ fn build(commands: &mut Commands, asset_server: &Res<AssetServer>) -> Entity {
                     ..Default::default()
                 })
                 .insert(UiImage::new(progress_texture.clone()).with_color(Color::srgba(1., 0., 0., 0.9)))
-                .insert(OverlayProgress::ReloadProgress);
+                .insert(OverlayProgress::Reload);
 
             parent
                 .spawn(NodeBundle {
@@ -117,7 +176,7 @@ fn build(commands: &mut Commands, asset_server: &Res<AssetServer>) -> Entity {
                             ..default()
                         })
                         .with_children(|parent| {
-                            for i in 0..3 {
+                            for i in 0..6 {
                                 let off =
                                     asset_server.load(format!("textures/projectiles/{}.png", i));
                                 let on = asset_server
@@ -151,6 +210,54 @@ fn build(commands: &mut Commands, asset_server: &Res<AssetServer>) -> Entity {
                         })
                         .insert(OverlayTexture::Gear(digits));
                 });
+
+            parent
+                .spawn(TextBundle::default())
+                .insert(OverlayTimer);
+
+            parent
+                .spawn(TextBundle::default())
+                .insert(OverlayScore);
+
+            parent
+                .spawn(TextBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        right: Val::Px(10.),
+                        bottom: Val::Px(10.),
+                        ..default()
+                    },
+                    visibility: Visibility::Hidden,
+                    ..default()
+                })
+                .insert(OverlayNetwork);
+
+            parent
+                .spawn(TextBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        top: Val::Percent(25.),
+                        padding: UiRect::all(Val::Px(15.)),
+                        ..default()
+                    },
+                    background_color: BACKGROUND_COLOR.into(),
+                    visibility: Visibility::Hidden,
+                    ..default()
+                })
+                .insert(OverlayScoreboard);
+
+            parent
+                .spawn(TextBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        right: Val::Px(10.),
+                        top: Val::Px(10.),
+                        ..default()
+                    },
+                    text: Text::default().with_justify(JustifyText::Right),
+                    ..default()
+                })
+                .insert(OverlayKillFeed);
         })
         .id()
 }
@@ -170,6 +277,11 @@ fn update_overlay_textures(
                 } else {
                     ui_image.texture = off.clone();
                 }
+                // the projectiles the rules of the match forbid are dimmed
+                let alpha = if controller.0.projectile_allowed(*id as u8) { 1. } else { FORBIDDEN_ALPHA };
+                if ui_image.color.alpha() != alpha {
+                    ui_image.color.set_alpha(alpha);
+                }
             }
             OverlayTexture::Gear(digits) => {
                 ui_image.texture = digits[controller.0.player.gear].clone();
@@ -186,18 +298,207 @@ fn update_overlay_progress(
 
     for (mut style, overlay) in &mut overlays {
         match overlay {
-            OverlayProgress::ReloadProgress => {
+            OverlayProgress::Reload => {
                 let progress = controller.0.player.reload_timer.progress() * 100.;
                 style.width = Val::Percent(progress);
             },
-            OverlayProgress::DashProgress => {
+            OverlayProgress::Dash => {
                 let progress = controller.0.player.dash_timer.progress() * 100.;
                 style.width = Val::Percent(progress);
             }
+            OverlayProgress::Shield => {
+                let progress = controller.0.player.shield_timer.progress() * 100.;
+                style.width = Val::Percent(progress);
+            }
+            OverlayProgress::Energy => {
+                let progress = controller.0.player.energy / Player::MAX_ENERGY * 100.;
+                style.width = Val::Percent(progress);
+            }
+        }
+    }
+}
+
+fn update_overlay_score(
+    mut texts: Query<&mut Text, With<OverlayScore>>,
+    controller: Query<&GameController>,
+    team_colors: Res<TeamColors>,
+    strings: Res<Strings>,
+) {
+    let controller = &controller.single().0;
+    let mut text = texts.single_mut();
+    text.sections.clear();
+
+    let (GameMode::KingOfTheHill { .. } | GameMode::CaptureTheFlag { .. }) = controller.mode.mode else {
+        return;
+    };
+    let teams: BTreeSet<usize> = controller.players.iter().map(|p| p.team).collect();
+    for team in teams {
+        text.sections.push(TextSection::new(
+            format!(" {} ", controller.mode.score(team)),
+            strings.text_style(60., team_colors.color(team)),
+        ));
+    }
+    if controller.carried_flag(controller.player.id).is_some() {
+        text.sections.push(TextSection::new(
+            strings.get("game.flag"),
+            strings.text_style(60., Color::WHITE),
+        ));
+    }
+}
+
+fn update_overlay_network(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    client: Res<Client>,
+    strings: Res<Strings>,
+    mut texts: Query<(&mut Text, &mut Visibility), With<OverlayNetwork>>,
+) {
+    let Ok((mut text, mut visibility)) = texts.get_single_mut() else {
+        return;
+    };
+    if keyboard.just_pressed(KeyCode::F3) {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Inherited,
+            _ => Visibility::Hidden,
+        };
+    }
+    if *visibility == Visibility::Hidden {
+        return;
+    }
+
+    let client = &client.0;
+    let stats = strings.format(
+        "game.network",
+        &[
+            ("ticks", &client.queued_ticks().to_string()),
+            ("inputs", &client.queued_inputs().to_string()),
+            ("dropped", &client.dropped_inputs().to_string()),
+        ],
+    );
+    // dropped inputs mean the connection can't keep up
+    let color = match client.dropped_inputs() {
+        0 => Color::WHITE,
+        _ => Color::srgb(1., 0.6, 0.),
+    };
+    *text = Text::from_section(stats, strings.text_style(24., color));
+}
+
+fn update_overlay_scoreboard(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    simulation: Query<(&RenderedSimulation, &GameController)>,
+    mut texts: Query<(&mut Text, &mut Visibility), With<OverlayScoreboard>>,
+    team_colors: Res<TeamColors>,
+    strings: Res<Strings>,
+) {
+    let Ok((mut text, mut visibility)) = texts.get_single_mut() else {
+        return;
+    };
+    *visibility = match keyboard.pressed(KeyCode::Tab) {
+        true => Visibility::Inherited,
+        false => Visibility::Hidden,
+    };
+    if *visibility == Visibility::Hidden {
+        return;
+    }
+
+    let (simulation, controller) = simulation.single();
+    // teams go one after another, the players who did the most damage first
+    let mut players: Vec<_> = controller.0.players.iter().collect();
+    players.sort_by(|p1, p2| p1.team.cmp(&p2.team).then(p2.stats.damage.total_cmp(&p1.stats.damage)));
+
+    text.sections.clear();
+    for player in players {
+        let hp = Controller::get_player_hp(player, &simulation.0);
+        let row = strings.format(
+            "game.scoreboard",
+            &[
+                ("name", &player.name),
+                ("team", &(player.team + 1).to_string()),
+                ("hp", &format!("{:.0}", hp * 100.)),
+                ("damage", &format!("{:.0}", player.stats.damage * 100.)),
+                ("ping", &player.stats.ping.to_string()),
+            ],
+        );
+        text.sections.push(TextSection::new(row + "\n", strings.text_style(30., team_colors.color(player.team))));
+    }
+}
+
+/// Kills of the last seconds, the killer's team colors each one.
+fn update_overlay_kill_feed(
+    controller: Query<&GameController>,
+    mut texts: Query<&mut Text, With<OverlayKillFeed>>,
+    team_colors: Res<TeamColors>,
+    strings: Res<Strings>,
+) {
+    let controller = &controller.single().0;
+    let Ok(mut text) = texts.get_single_mut() else {
+        return;
+    };
+    let name = |id: u8| controller.get_player(id).map_or(String::new(), |player| player.name.clone());
+
+    text.sections.clear();
+    for kill in controller.kills.iter() {
+        let left = KILL_TICKS.saturating_sub(controller.tick.saturating_sub(kill.tick));
+        let alpha = (left as f32 / KILL_FADE as f32).min(1.);
+        let row = match kill.killer {
+            Some(killer) => strings.format("game.kill", &[("killer", &name(killer)), ("victim", &name(kill.victim))]),
+            None => strings.format("game.death", &[("victim", &name(kill.victim))]),
+        };
+        let team = controller.get_player(kill.killer.unwrap_or(kill.victim)).map_or(0, |player| player.team);
+        let color = team_colors.color(team).with_alpha(alpha);
+        text.sections.push(TextSection::new(row + "\n", strings.text_style(30., color)));
+    }
+}
+
+/// Arcs at the edge of the screen pointing to where the recent hits came from.
+fn update_damage_indicators(
+    mut gizmos: Gizmos,
+    controller: Query<&GameController>,
+    camera: Query<(&OrthographicProjection, &GlobalTransform), With<SimulationCamera>>,
+) {
+    let controller = &controller.single().0;
+    let Ok((projection, transform)) = camera.get_single() else {
+        return;
+    };
+    let center = transform.translation().truncate();
+    let radius = 0.45 * projection.area.width().min(projection.area.height());
+    for hit in controller.hits.iter() {
+        let fade = (1. - (controller.tick - hit.tick) as f32 / HIT_TICKS as f32).max(0.);
+        let strength = (hit.damage / HIT_FULL_DAMAGE).clamp(0.4, 1.);
+        let color = Color::srgba(0.9, 0., 0., fade * strength);
+        let angle = Vec2::Y.angle_between(hit.direction);
+        for i in 0..HIT_LINES {
+            gizmos.arc_2d(center, angle, HIT_ARC, radius * (1. - 0.005 * i as f32), color);
         }
     }
 }
 
+fn update_overlay_timer(
+    mut texts: Query<&mut Text, With<OverlayTimer>>,
+    controller: Query<&GameController>,
+    strings: Res<Strings>,
+) {
+    let controller = &controller.single().0;
+    let mut text = texts.single_mut();
+    text.sections.clear();
+
+    let Some(time_left) = controller.mode.time_left(controller.tick) else {
+        return;
+    };
+    let section = if controller.mode.sudden_death(controller.tick) {
+        TextSection::new(
+            strings.get("game.sudden_death"),
+            strings.text_style(60., Color::srgb(0.9, 0., 0.)),
+        )
+    } else {
+        let secs = time_left.as_secs();
+        TextSection::new(
+            format!("{}:{:02}", secs / 60, secs % 60),
+            strings.text_style(60., Color::WHITE),
+        )
+    };
+    text.sections.push(section);
+}
+
 pub struct OverlayPlugin;
 
 impl Plugin for OverlayPlugin {
@@ -206,7 +507,8 @@ impl Plugin for OverlayPlugin {
             .add_systems(OnExit(GameState::InGame), despawn)
             .add_systems(
                 Update,
-                (update_overlay_textures, update_overlay_progress).run_if(in_state(GameState::InGame)),
+                (update_overlay_textures, update_overlay_progress, update_overlay_timer, update_overlay_score, update_damage_indicators, update_overlay_network, update_overlay_scoreboard, update_overlay_kill_feed)
+                    .run_if(in_state(GameState::InGame).and_then(any_with_component::<GameController>)),
             );
     }
 }
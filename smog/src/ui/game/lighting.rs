@@ -0,0 +1,43 @@
+use bevy::prelude::*;
+use render::ambient::Ambient;
+use session::controller::event::GameEvent;
+
+use crate::GameState;
+
+use super::{GameController, MapLight};
+
+const FLASH: Ambient = Ambient { color: Vec3::new(1., 0.85, 0.6), brightness: 1.6 };
+const FLASH_STRENGTH: f32 = 0.5; // of the way from the light of the map to the flash, right after the explosion
+const FLASH_TICKS: u128 = 120; // ticks the flash of an explosion takes to fade out
+
+/// Lights the scene up for a moment after every explosion, the light of the map comes back as it fades.
+fn update_lighting(mut game: Query<(&GameController, &MapLight, &mut Ambient)>) {
+    for (controller, light, mut ambient) in &mut game {
+        let flash = controller
+            .0
+            .events
+            .iter()
+            .filter(|(_, event)| matches!(event, GameEvent::Explosion { .. }))
+            .map(|(tick, _)| 1. - controller.0.tick.saturating_sub(*tick) as f32 / FLASH_TICKS as f32)
+            .fold(0f32, f32::max);
+        let lit = light.0.lerp(&FLASH, flash * FLASH_STRENGTH);
+        if *ambient != lit {
+            *ambient = lit;
+        }
+    }
+}
+
+pub struct LightingPlugin;
+
+impl Plugin for LightingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            update_lighting.run_if(
+                in_state(GameState::InGame)
+                    .or_else(in_state(GameState::Replay))
+                    .and_then(any_with_component::<GameController>),
+            ),
+        );
+    }
+}
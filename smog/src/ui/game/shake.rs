@@ -0,0 +1,141 @@
+use bevy::{math::vec2, prelude::*, transform::TransformSystem};
+use render::{RenderedSimulation, SimulationCamera};
+use session::controller::{event::GameEvent, Controller};
+
+use crate::GameState;
+
+use super::GameController;
+
+const EXPLOSION_TRAUMA: f32 = 0.6; // of an explosion in the middle of the view, none a view's width away
+const HIT_TRAUMA: f32 = 2.; // per hp the tank lost, a hit taking half of it shakes as hard as it gets
+const DECAY: f32 = 1.2; // trauma lost per second
+const SHAKE_SIZE: f32 = 0.03; // largest offset of the camera, part of the height of the view
+const SHAKE_SPEED: f32 = 30.; // radians per second of the waves the camera follows
+
+/// The local tank lost a part of its hp since the last frame, all that was left if it died.
+#[derive(Event)]
+pub struct Hit {
+    pub damage: f32,
+}
+
+/// Shaking of the camera, its offset is added after everything else moved the camera and taken back every frame.
+#[derive(Resource, Default)]
+pub struct CameraShake {
+    pub trauma: f32, // from 0 to 1, the camera shakes by its square
+    offset: Vec2,
+}
+
+impl CameraShake {
+    pub fn add(&mut self, trauma: f32) {
+        self.trauma = (self.trauma + trauma).min(1.);
+    }
+}
+
+/// Hp of the local tank in the last frame, none while it's dead or the player is spectating.
+#[derive(Default)]
+struct LastHp(Option<f32>);
+
+fn hit_system(mut last: Local<LastHp>, game: Query<(&GameController, &RenderedSimulation)>, mut hits: EventWriter<Hit>) {
+    let (controller, simulation) = game.single();
+    let player = &controller.0.player;
+    let hp = Controller::player_alive(player, &simulation.0).then(|| Controller::get_player_hp(player, &simulation.0));
+    // a respawned tank starts over, the repairs don't count
+    let damage = match (last.0, hp) {
+        (Some(last), Some(hp)) => last - hp,
+        (Some(last), None) => last,
+        (None, _) => 0.,
+    };
+    if damage > 0. {
+        hits.send(Hit { damage });
+    }
+    last.0 = hp;
+}
+
+/// Adds the trauma of the hits and the explosions since the last frame, the rest of it wears off.
+fn trauma_system(
+    time: Res<Time>,
+    mut shake: ResMut<CameraShake>,
+    mut seen: Local<u128>,
+    mut hits: EventReader<Hit>,
+    game: Query<&GameController>,
+    camera: Query<(&Transform, &OrthographicProjection), With<SimulationCamera>>,
+) {
+    shake.trauma = (shake.trauma - DECAY * time.delta_seconds()).max(0.);
+    for hit in hits.read() {
+        shake.add(hit.damage * HIT_TRAUMA);
+    }
+
+    let controller = &game.single().0;
+    // the tick starts over with every game
+    let last = (*seen).min(controller.tick.saturating_sub(1));
+    *seen = controller.tick;
+    let Ok((transform, projection)) = camera.get_single() else {
+        return;
+    };
+    let center = transform.translation.truncate();
+    let range = projection.area.width().max(1.);
+    for (tick, event) in &controller.events {
+        if let GameEvent::Explosion { pos } = event {
+            if *tick > last {
+                shake.add(EXPLOSION_TRAUMA * (1. - pos.distance(center) / range).max(0.));
+            }
+        }
+    }
+}
+
+/// Takes the offset of the last frame back, so the camera is moved from where it really is.
+fn unshake_system(mut shake: ResMut<CameraShake>, mut camera: Query<&mut Transform, With<SimulationCamera>>) {
+    let offset = std::mem::take(&mut shake.offset);
+    if offset == Vec2::ZERO {
+        return;
+    }
+    for mut transform in &mut camera {
+        transform.translation -= offset.extend(0.);
+    }
+}
+
+fn shake_system(
+    time: Res<Time>,
+    mut shake: ResMut<CameraShake>,
+    mut camera: Query<(&mut Transform, &OrthographicProjection), With<SimulationCamera>>,
+) {
+    if shake.trauma <= 0. {
+        return;
+    }
+    let Ok((mut transform, projection)) = camera.get_single_mut() else {
+        return;
+    };
+    // waves of unrelated periods, so the camera wanders without a pattern
+    let t = time.elapsed_seconds() * SHAKE_SPEED;
+    let direction = vec2(t.sin() + (t * 2.3).sin() * 0.5, (t * 1.7).cos() + (t * 3.1).cos() * 0.5) / 1.5;
+    shake.offset = direction * shake.trauma.powi(2) * SHAKE_SIZE * projection.area.height();
+    transform.translation += shake.offset.extend(0.);
+}
+
+fn calm_system(mut shake: ResMut<CameraShake>) {
+    shake.trauma = 0.;
+}
+
+/// Shakes the camera when the local tank is hit or something explodes nearby.
+pub struct ShakePlugin;
+
+impl Plugin for ShakePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CameraShake>()
+            .add_event::<Hit>()
+            .add_systems(OnExit(GameState::InGame), calm_system)
+            .add_systems(PreUpdate, unshake_system)
+            .add_systems(
+                Update,
+                (hit_system, trauma_system)
+                    .chain()
+                    .run_if(in_state(GameState::InGame).and_then(any_with_component::<GameController>)),
+            )
+            .add_systems(
+                PostUpdate,
+                shake_system
+                    .before(TransformSystem::TransformPropagate)
+                    .run_if(in_state(GameState::InGame).and_then(any_with_component::<GameController>)),
+            );
+    }
+}
@@ -0,0 +1,119 @@
+use std::{collections::HashMap, time::Duration};
+
+use bevy::{
+    audio::{PitchBundle, Volume},
+    prelude::*,
+};
+use render::SimulationCamera;
+use session::{controller::event::GameEvent, SUB_TICKS};
+
+use crate::GameState;
+
+use super::GameController;
+
+const MAX_DELAY: u128 = 4 * SUB_TICKS as u128; // older events are skipped, e.g. after seeking a replay
+const MAX_SOUNDS: usize = 8; // sounds started per frame, an explosion breaks lots of links at once
+const MOTOR_INTERVAL: f32 = 0.12; // seconds between the hums of one tank
+const IMPACT_STRENGTH: f32 = 0.6; // impact heard at full volume
+
+/// Procedural sounds of the match, one tone per kind of event.
+#[derive(Resource)]
+struct SoundAssets {
+    fire: Handle<Pitch>,
+    impact: Handle<Pitch>,
+    motor: Handle<Pitch>,
+    link_break: Handle<Pitch>,
+    explosion: Handle<Pitch>,
+    hazard: Handle<Pitch>,
+}
+
+impl FromWorld for SoundAssets {
+    fn from_world(world: &mut World) -> Self {
+        let mut pitches = world.resource_mut::<Assets<Pitch>>();
+        Self {
+            fire: pitches.add(Pitch::new(180., Duration::from_millis(60))),
+            impact: pitches.add(Pitch::new(75., Duration::from_millis(50))),
+            motor: pitches.add(Pitch::new(50., Duration::from_millis(120))),
+            link_break: pitches.add(Pitch::new(520., Duration::from_millis(25))),
+            explosion: pitches.add(Pitch::new(40., Duration::from_millis(400))),
+            hazard: pitches.add(Pitch::new(300., Duration::from_millis(150))),
+        }
+    }
+}
+
+/// Tick of the last event played and when each tank hummed last.
+#[derive(Default)]
+struct Played {
+    tick: u128,
+    motors: HashMap<u8, f32>,
+}
+
+fn play_events(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut played: Local<Played>,
+    assets: Res<SoundAssets>,
+    controller: Query<&GameController>,
+    camera: Query<(&Transform, &OrthographicProjection), With<SimulationCamera>>,
+) {
+    let controller = &controller.single().0;
+    let Ok((camera, projection)) = camera.get_single() else {
+        return;
+    };
+    // sounds fade out a screen away from the middle of the view
+    let center = camera.translation.truncate();
+    let range = projection.area.width().max(1.);
+
+    let now = time.elapsed_seconds();
+    // the tick goes back when a new match starts or a replay is rewound
+    let last = played.tick.min(controller.tick.saturating_sub(1)).max(controller.tick.saturating_sub(MAX_DELAY));
+    let mut sounds = 0;
+    for (tick, event) in &controller.events {
+        if sounds == MAX_SOUNDS {
+            break;
+        }
+        if *tick <= last {
+            continue;
+        }
+        let (source, pos, volume) = match *event {
+            GameEvent::Fire { pos, .. } => (&assets.fire, pos, 0.5),
+            GameEvent::Impact { pos, strength } => (&assets.impact, pos, (strength / IMPACT_STRENGTH).min(1.) * 0.6),
+            GameEvent::Motor { player, pos } => {
+                let hummed = played.motors.entry(player).or_insert(f32::NEG_INFINITY);
+                if now - *hummed < MOTOR_INTERVAL {
+                    continue;
+                }
+                *hummed = now;
+                (&assets.motor, pos, 0.2)
+            }
+            GameEvent::LinkBreak { pos } => (&assets.link_break, pos, 0.15),
+            GameEvent::Explosion { pos } => (&assets.explosion, pos, 1.),
+            GameEvent::Hazard { pos, .. } => (&assets.hazard, pos, 0.3),
+        };
+        let volume = volume * (1. - pos.distance(center) / range).max(0.);
+        if volume <= 0. {
+            continue;
+        }
+        commands.spawn(PitchBundle {
+            source: source.clone(),
+            settings: PlaybackSettings::DESPAWN.with_volume(Volume::new(volume)),
+        });
+        sounds += 1;
+    }
+    played.tick = controller.tick;
+}
+
+pub struct SoundPlugin;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SoundAssets>().add_systems(
+            Update,
+            play_events.run_if(
+                in_state(GameState::InGame)
+                    .or_else(in_state(GameState::Replay))
+                    .and_then(any_with_component::<GameController>),
+            ),
+        );
+    }
+}
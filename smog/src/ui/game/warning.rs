@@ -0,0 +1,182 @@
+use std::time::Duration;
+
+use bevy::{
+    audio::{PitchBundle, Volume},
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+use render::RenderedSimulation;
+use session::controller::{event::GameEvent, Controller};
+
+use crate::{preferences::Preferences, GameState};
+
+use super::{shake::Hit, GameController};
+
+const VIGNETTE_SIZE: u32 = 128;
+const VIGNETTE_COLOR: Color = Color::srgb(0.7, 0., 0.);
+const HAZARD_COLOR: Color = Color::srgb(1., 0.45, 0.);
+const HAZARD_TICKS: u128 = 240; // ticks the screen glows after the tank took damage in a hazard
+const BEAT_INTERVAL: (f32, f32) = (0.35, 1.); // seconds between beats at 0 hp and at the threshold
+const DUB_DELAY: f32 = 0.18; // the second beat follows the first one
+const BEAT_VOLUME: f32 = 0.6;
+const HIT_FLASH: f32 = 3.; // alpha of the edges per hp the tank lost
+const HIT_FADE: f32 = 2.; // alpha the flash of a hit loses per second
+
+#[derive(Component)]
+struct Vignette;
+
+/// Red screen edges and the heartbeat, both get stronger as the local tank loses hp. Every hit flashes the edges.
+/// The edges glow orange while the tank wears out in a hazard of the map.
+#[derive(Resource)]
+struct WarningAssets {
+    vignette: Handle<Image>,
+    lub: Handle<Pitch>,
+    dub: Handle<Pitch>,
+}
+
+impl FromWorld for WarningAssets {
+    fn from_world(world: &mut World) -> Self {
+        // transparent in the middle, opaque in the corners
+        let half = VIGNETTE_SIZE as f32 / 2.;
+        let data = (0..VIGNETTE_SIZE * VIGNETTE_SIZE)
+            .flat_map(|i| {
+                let pos = Vec2::new((i % VIGNETTE_SIZE) as f32, (i / VIGNETTE_SIZE) as f32);
+                let distance = pos.distance(Vec2::splat(half)) / half;
+                let alpha = ((distance - 0.6) / 0.8).clamp(0., 1.);
+                [255, 255, 255, (alpha * 255.) as u8]
+            })
+            .collect();
+        let image = Image::new(
+            Extent3d {
+                width: VIGNETTE_SIZE,
+                height: VIGNETTE_SIZE,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::RENDER_WORLD,
+        );
+        let vignette = world.resource_mut::<Assets<Image>>().add(image);
+
+        let mut pitches = world.resource_mut::<Assets<Pitch>>();
+        Self {
+            vignette,
+            lub: pitches.add(Pitch::new(110., Duration::from_millis(90))),
+            dub: pitches.add(Pitch::new(90., Duration::from_millis(70))),
+        }
+    }
+}
+
+/// Seconds since the last heartbeat.
+#[derive(Default)]
+struct Heartbeat(f32);
+
+/// Alpha the edges flash with after a hit, fading out.
+#[derive(Default)]
+struct HitFlash(f32);
+
+fn spawn(mut commands: Commands, assets: Res<WarningAssets>) {
+    commands.spawn((
+        ImageBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                ..default()
+            },
+            image: UiImage::new(assets.vignette.clone()).with_color(VIGNETTE_COLOR.with_alpha(0.)),
+            z_index: ZIndex::Global(-1), // under the rest of the interface
+            ..default()
+        },
+        Vignette,
+    ));
+}
+
+fn despawn(mut commands: Commands, vignette: Query<Entity, With<Vignette>>) {
+    if let Ok(vignette) = vignette.get_single() {
+        commands.entity(vignette).despawn_recursive();
+    }
+}
+
+/// How far below the threshold the hp is, from 0 at the threshold to 1 at no hp.
+fn danger(hp: f32, threshold: f32) -> f32 {
+    if threshold <= 0. || hp >= threshold {
+        return 0.;
+    }
+    1. - hp / threshold
+}
+
+fn update_warning(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut heartbeat: Local<Heartbeat>,
+    mut flash: Local<HitFlash>,
+    mut hits: EventReader<Hit>,
+    assets: Res<WarningAssets>,
+    preferences: Res<Preferences>,
+    game: Query<(&GameController, &RenderedSimulation)>,
+    mut vignette: Query<&mut UiImage, With<Vignette>>,
+) {
+    let (controller, simulation) = game.single();
+    let player = &controller.0.player;
+    let hp = match Controller::player_alive(player, &simulation.0) {
+        true => Controller::get_player_hp(player, &simulation.0),
+        false => 1., // nothing to warn about when spectating
+    };
+    let warning = preferences.low_hp_warning;
+
+    let last_beat = heartbeat.0;
+    heartbeat.0 += time.delta_seconds();
+    let beating = danger(hp, warning.heartbeat);
+    let mut pulse = 0.;
+    if beating > 0. {
+        let interval = BEAT_INTERVAL.1 + (BEAT_INTERVAL.0 - BEAT_INTERVAL.1) * beating;
+        let settings = PlaybackSettings::DESPAWN.with_volume(Volume::new(BEAT_VOLUME));
+        if heartbeat.0 >= interval {
+            heartbeat.0 = 0.;
+            commands.spawn(PitchBundle { source: assets.lub.clone(), settings });
+        } else if last_beat < DUB_DELAY && heartbeat.0 >= DUB_DELAY {
+            commands.spawn(PitchBundle { source: assets.dub.clone(), settings });
+        }
+        pulse = 0.2 * (1. - heartbeat.0 / interval).max(0.);
+    }
+
+    flash.0 = (flash.0 - HIT_FADE * time.delta_seconds()).max(0.);
+    for hit in hits.read() {
+        flash.0 = (flash.0 + hit.damage * HIT_FLASH).min(1.);
+    }
+
+    let hazard = controller
+        .0
+        .events
+        .iter()
+        .filter(|(_, event)| matches!(event, GameEvent::Hazard { player: p, .. } if *p == player.id))
+        .map(|(tick, _)| 1. - controller.0.tick.saturating_sub(*tick) as f32 / HAZARD_TICKS as f32)
+        .fold(0f32, f32::max);
+
+    if let Ok(mut image) = vignette.get_single_mut() {
+        let alpha = danger(hp, warning.vignette) * 0.8 + pulse + flash.0 * 0.6;
+        image.color = match hazard > 0. {
+            true => HAZARD_COLOR.with_alpha(alpha.max(hazard * 0.6).min(1.)),
+            false => VIGNETTE_COLOR.with_alpha(alpha.min(1.)),
+        };
+    }
+}
+
+pub struct WarningPlugin;
+
+impl Plugin for WarningPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WarningAssets>()
+            .add_systems(OnEnter(GameState::InGame), spawn)
+            .add_systems(OnExit(GameState::InGame), despawn)
+            .add_systems(
+                Update,
+                update_warning.run_if(in_state(GameState::InGame).and_then(any_with_component::<GameController>)),
+            );
+    }
+}
@@ -1,12 +1,81 @@
 use bevy::prelude::*;
+use common::{chassis::Chassis, config, mode::MatchSettings};
+use map_editor::vehicle::Vehicle;
+use packet_tools::server_packets::ServerPacket;
 
-use crate::{display_error, Client, GameState};
+use crate::{display_error, locale::Strings, preferences::Preferences, Client, GameState};
 
 #[derive(Component)]
 struct Lobby;
 
-fn spawn(mut commands: Commands) {
-    let _lobby = build(&mut commands);
+/// Match settings announced by the server.
+#[derive(Component)]
+struct LobbySettings(MatchSettings);
+
+/// Shown while the map is being downloaded.
+#[derive(Component)]
+struct DownloadProgress;
+
+#[derive(Component)]
+struct DownloadBar;
+
+/// Picks the chassis for the next game, the picked one keeps its border lit.
+#[derive(Component)]
+struct ChassisButton(Chassis);
+
+/// Name of the custom vehicle on its button.
+#[derive(Component)]
+struct VehicleName;
+
+impl LobbySettings {
+    fn describe(&self, strings: &Strings) -> String {
+        let settings = &self.0;
+        let round_time = match settings.round_time {
+            0 => strings.get("lobby.no_time_limit").to_string(),
+            secs => strings.format(
+                "lobby.until_sudden_death",
+                &[("time", &format!("{}:{:02}", secs / 60, secs % 60))],
+            ),
+        };
+        let friendly_fire = strings.get(if settings.friendly_fire { "lobby.on" } else { "lobby.off" });
+        strings.format(
+            "lobby.settings",
+            &[
+                ("mode", settings.mode.name()),
+                ("round", &round_time),
+                ("friendly_fire", friendly_fire),
+                ("respawns", &settings.respawns.to_string()),
+                ("projectiles", &settings.projectiles.count_ones().to_string()),
+            ],
+        )
+    }
+}
+
+fn spawn(mut commands: Commands, client: Option<Res<Client>>, strings: Res<Strings>, mut preferences: ResMut<Preferences>) {
+    // a vehicle whose file is gone is replaced by the first exported one, the tank if there are none
+    let vehicles = Vehicle::list(config::vehicles_dir());
+    if !vehicles.contains(&preferences.vehicle) {
+        preferences.vehicle = vehicles.first().cloned().unwrap_or_default();
+    }
+    if vehicles.is_empty() && preferences.chassis == Chassis::Custom {
+        preferences.chassis = Chassis::default();
+    }
+    // every lobby starts from the chassis picked last time
+    if let Some(client) = client.as_ref() {
+        pick(client, &preferences);
+    }
+    // after a match the settings of the next round may have come already
+    let settings = client.map_or(MatchSettings::default(), |client| client.0.lobby.settings);
+    let vehicle = (!vehicles.is_empty()).then_some(preferences.vehicle.as_str());
+    let _lobby = build(&mut commands, settings, preferences.chassis, vehicle, &strings);
+}
+
+/// Sends the picked chassis to the server, with the vehicle if it's a custom one.
+fn pick(client: &Client, preferences: &Preferences) {
+    if let Some(vehicle) = preferences.vehicle_file() {
+        client.0.upload_vehicle(vehicle);
+    }
+    client.0.select_chassis(preferences.chassis);
 }
 
 fn despawn(mut commands: Commands, lobby: Query<Entity, With<Lobby>>) {
@@ -16,15 +85,14 @@ fn despawn(mut commands: Commands, lobby: Query<Entity, With<Lobby>>) {
 }
 
 const BORDER_COLOR: Color = Color::srgb(0.25, 0.25, 0.25);
+const BORDER_COLOR_ACTIVE: Color = Color::srgb(0.75, 0.52, 0.99);
+const BAR_COLOR: Color = Color::srgb(0.75, 0.52, 0.99);
 const TEXT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
 const BACKGROUND_COLOR: Color = Color::srgb(0.15, 0.15, 0.15);
 
-fn build(commands: &mut Commands) -> Entity {
-    let text_style = TextStyle {
-        font_size: 40.,
-        color: TEXT_COLOR,
-        ..default()
-    };
+/// `vehicle` is the custom vehicle to offer next to the built-in chassis, none if nothing was exported.
+fn build(commands: &mut Commands, settings: MatchSettings, chassis: Chassis, vehicle: Option<&str>, strings: &Strings) -> Entity {
+    let text_style = strings.text_style(40., TEXT_COLOR);
 
     let node_bundle = NodeBundle {
         style: Style {
@@ -54,30 +122,168 @@ fn build(commands: &mut Commands) -> Entity {
             Lobby,
         ))
         .with_children(|parent| {
-            parent.spawn(node_bundle).with_children(|parent| {
+            parent.spawn(node_bundle.clone()).with_children(|parent| {
                 parent.spawn(TextBundle::from_section(
-                    "Waiting for the host to start the game...",
-                    text_style,
+                    strings.get("lobby.waiting"),
+                    text_style.clone(),
+                ));
+            });
+
+            let settings = LobbySettings(settings);
+            parent.spawn(node_bundle.clone()).with_children(|parent| {
+                parent
+                    .spawn(TextBundle::from_section(settings.describe(strings), text_style.clone()))
+                    .insert(settings);
+            });
+
+            let mut picks = node_bundle.clone();
+            picks.style.justify_content = JustifyContent::SpaceBetween;
+            picks.style.align_items = AlignItems::Center;
+            parent.spawn(picks).with_children(|parent| {
+                parent.spawn(TextBundle::from_section(strings.get("lobby.chassis"), text_style.clone()));
+                let custom = vehicle.map(|_| Chassis::Custom);
+                for option in Chassis::ALL.into_iter().chain(custom) {
+                    parent
+                        .spawn((
+                            ButtonBundle {
+                                style: Style {
+                                    border: UiRect::all(Val::Px(5.0)),
+                                    padding: UiRect::all(Val::Px(5.0)),
+                                    ..default()
+                                },
+                                border_color: if option == chassis { BORDER_COLOR_ACTIVE } else { BORDER_COLOR }.into(),
+                                background_color: BACKGROUND_COLOR.into(),
+                                ..default()
+                            },
+                            ChassisButton(option),
+                        ))
+                        .with_children(|parent| {
+                            let style = strings.text_style(30., TEXT_COLOR);
+                            match (option, vehicle) {
+                                (Chassis::Custom, Some(name)) => {
+                                    parent.spawn((TextBundle::from_section(name, style), VehicleName));
+                                }
+                                _ => {
+                                    let key = format!("chassis.{}", option.name());
+                                    parent.spawn(TextBundle::from_section(strings.get(&key), style));
+                                }
+                            }
+                        });
+                }
+            });
+
+            let mut progress = node_bundle;
+            progress.style.flex_direction = FlexDirection::Column;
+            progress.style.display = Display::None;
+            parent.spawn((progress, DownloadProgress)).with_children(|parent| {
+                parent.spawn(TextBundle::from_section("", text_style));
+                parent.spawn((
+                    NodeBundle {
+                        style: Style {
+                            width: Val::Percent(0.),
+                            height: Val::Px(10.),
+                            ..default()
+                        },
+                        background_color: BAR_COLOR.into(),
+                        ..default()
+                    },
+                    DownloadBar,
                 ));
             });
         })
         .id()
 }
 
-fn lobby_system(mut commands: Commands, mut client: ResMut<Client>, mut next_state: ResMut<NextState<GameState>>) {
+fn lobby_system(
+    mut commands: Commands,
+    mut client: ResMut<Client>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut settings: Query<(&mut Text, &mut LobbySettings)>,
+    strings: Res<Strings>,
+) {
+    if let Ok((mut text, mut settings)) = settings.get_single_mut() {
+        for packet in client.0.get_lobby_packets() {
+            let ServerPacket::MatchSettings(new_settings) = packet else {
+                continue;
+            };
+            settings.0 = new_settings;
+            text.sections[0].value = settings.describe(&strings);
+        }
+    }
+
     if client.0.game_started() {
         match client.0.run() {
             Ok(_) => next_state.set(GameState::InGame), 
-            Err(e) => display_error(&mut commands, &mut next_state, &e.to_string())
+            Err(e) => display_error(&mut commands, &mut next_state, e)
+        }
+    }
+}
+
+/// Picking the custom vehicle again switches to the next exported one.
+fn chassis_system(
+    client: Res<Client>,
+    mut preferences: ResMut<Preferences>,
+    pressed: Query<(&Interaction, &ChassisButton), Changed<Interaction>>,
+    mut buttons: Query<(&ChassisButton, &mut BorderColor)>,
+    mut names: Query<&mut Text, With<VehicleName>>,
+) {
+    for (interaction, picked) in &pressed {
+        if !matches!(interaction, Interaction::Pressed) {
+            continue;
+        }
+        if picked.0 == Chassis::Custom && preferences.chassis == Chassis::Custom {
+            let vehicles = Vehicle::list(config::vehicles_dir());
+            let next = vehicles.iter().position(|name| *name == preferences.vehicle).map_or(0, |i| i + 1);
+            if let Some(name) = vehicles.get(next % vehicles.len().max(1)) {
+                preferences.vehicle = name.clone();
+                for mut text in &mut names {
+                    text.sections[0].value = name.clone();
+                }
+            }
+        }
+        preferences.chassis = picked.0;
+        pick(&client, &preferences);
+        for (button, mut border) in &mut buttons {
+            *border = if button.0 == picked.0 { BORDER_COLOR_ACTIVE } else { BORDER_COLOR }.into();
         }
     }
 }
+
+fn download_system(
+    client: Res<Client>,
+    mut progress: Query<(&mut Style, &Children), (With<DownloadProgress>, Without<DownloadBar>)>,
+    mut text: Query<&mut Text>,
+    mut bar: Query<&mut Style, With<DownloadBar>>,
+    strings: Res<Strings>,
+) {
+    let Ok((mut style, children)) = progress.get_single_mut() else {
+        return;
+    };
+    let Some(download) = client.0.download() else {
+        style.display = Display::None;
+        return;
+    };
+    style.display = Display::Flex;
+    let percent = download.received as f32 / download.total.max(1) as f32 * 100.;
+    for child in children {
+        if let Ok(mut text) = text.get_mut(*child) {
+            text.sections[0].value = strings.format(
+                "lobby.downloading",
+                &[("file", &download.file), ("percent", &format!("{percent:.0}"))],
+            );
+        }
+    }
+    if let Ok(mut bar) = bar.get_single_mut() {
+        bar.width = Val::Percent(percent);
+    }
+}
+
 pub struct LobbyPlugin;
 
 impl Plugin for LobbyPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(OnEnter(GameState::InLobby), spawn)
             .add_systems(OnExit(GameState::InLobby), despawn)
-            .add_systems(Update, lobby_system.run_if(in_state(GameState::InLobby)));
+            .add_systems(Update, (lobby_system, chassis_system, download_system).run_if(in_state(GameState::InLobby)));
     }
 }
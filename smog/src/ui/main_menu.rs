@@ -3,23 +3,88 @@ use bevy_simple_text_input::{
     TextInputBundle, TextInputInactive, TextInputPlugin, TextInputSystem, TextInputValue,
 };
 use clipboard::{ClipboardContext, ClipboardProvider};
-use packet_tools::game_packets::GamePacket;
+use packet_tools::{game_packets::GamePacket, query_packets::ServerInfo};
+use common::locale::Locale;
+use render::palette::Palette;
 
 use crate::{
-    display_error, network::client::GameClient, Client, GameError, GameState, PACKET_SIZE,
+    display_error,
+    network::{
+        client::GameClient,
+        error::ClientError,
+        query::ServerQuery,
+    },
+    locale::Strings,
+    preferences::{LowHpWarning, Preferences},
+    ui::tutorial::start_tutorial,
+    Client, GameState, PACKET_SIZE,
 };
 
 #[derive(Component)]
 struct MainMenu;
 
-fn spawn(mut commands: Commands, error: Option<Res<GameError>>) {
-    let _menu = build(&mut commands, &error);
+/// Servers found by the last query together with the user's favorites.
+#[derive(Resource)]
+struct ServerBrowser {
+    query: ServerQuery,
+    favorites: Vec<String>,
+    servers: Vec<(String, ServerInfo)>,
+    changed: bool,
+}
+
+impl ServerBrowser {
+    fn new(favorites: Vec<String>) -> Self {
+        Self {
+            query: ServerQuery::start(&favorites),
+            favorites,
+            servers: vec![],
+            changed: true,
+        }
+    }
+
+    fn refresh(&mut self) {
+        self.query = ServerQuery::start(&self.favorites);
+        self.servers.clear();
+        self.changed = true;
+    }
+
+    /// Rows of the list: responding servers first, then silent favorites.
+    fn rows(&self, strings: &Strings) -> Vec<(String, String)> {
+        let mut rows: Vec<_> = self
+            .servers
+            .iter()
+            .map(|(addr, info)| {
+                let star = if self.favorites.contains(addr) { "* " } else { "" };
+                let label = format!(
+                    "{star}{} | {} | {} | {}/{}",
+                    info.name,
+                    info.map,
+                    info.mode.name(),
+                    info.players,
+                    info.max_players
+                );
+                (addr.clone(), label)
+            })
+            .collect();
+        for favorite in self.favorites.iter() {
+            if !self.servers.iter().any(|(addr, _)| addr == favorite) {
+                rows.push((favorite.clone(), format!("* {favorite} | {}", strings.get("menu.no_response"))));
+            }
+        }
+        rows
+    }
+}
+
+fn spawn(mut commands: Commands, preferences: Res<Preferences>, strings: Res<Strings>) {
+    let _menu = build(&mut commands, &preferences, &strings);
+    commands.insert_resource(ServerBrowser::new(preferences.favorites.clone()));
 }
 
 fn despawn(mut commands: Commands, main_menu: Query<Entity, With<MainMenu>>) {
     if let Ok(main_menu) = main_menu.get_single() {
         commands.entity(main_menu).despawn_recursive();
     }
+    commands.remove_resource::<ServerBrowser>();
 }
 
 const BORDER_COLOR_ACTIVE: Color = Color::srgb(0.75, 0.52, 0.99);
@@ -27,15 +92,8 @@ const BORDER_COLOR_INACTIVE: Color = Color::srgb(0.25, 0.25, 0.25);
 const TEXT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
 const BACKGROUND_COLOR: Color = Color::srgb(0.15, 0.15, 0.15);
 
-fn build(
-    commands: &mut Commands,
-    error: &Option<Res<GameError>>,
-) -> Entity {
-    let text_style = TextStyle {
-        font_size: 40.,
-        color: TEXT_COLOR,
-        ..default()
-    };
+fn build(commands: &mut Commands, preferences: &Preferences, strings: &Strings) -> Entity {
+    let text_style = strings.text_style(40., TEXT_COLOR);
 
     let node_style = Style {
         width: Val::Px(600.0),
@@ -56,8 +114,6 @@ fn build(
         ..default()
     };
 
-    commands.remove_resource::<GameError>();
-
     commands
         .spawn((
             NodeBundle {
@@ -80,8 +136,9 @@ fn build(
             parent.spawn((
                 node_bundle.clone(),
                 TextInputBundle::default()
+                    .with_value(preferences.nickname.clone())
                     .with_text_style(text_style.clone())
-                    .with_placeholder("nickname", None)
+                    .with_placeholder(strings.get("menu.nickname"), None)
                     .with_inactive(true),
                 NicknameInput,
             ));
@@ -101,12 +158,13 @@ fn build(
                     parent.spawn((
                         NodeBundle {
                             style: Style {
-                                width: Val::Percent(80.),
+                                width: Val::Percent(60.),
                                 ..node_style.clone()
                             },
                             ..node_bundle.clone()
                         },
                         TextInputBundle::default()
+                            .with_value(preferences.last_server.clone())
                             .with_text_style(text_style.clone())
                             .with_placeholder("127.0.0.1:8080", None)
                             .with_inactive(true),
@@ -126,41 +184,145 @@ fn build(
                             PasteButton,
                         ))
                         .with_children(|parent| {
-                            parent.spawn(TextBundle::from_section("Paste", text_style.clone()));
+                            parent.spawn(TextBundle::from_section(strings.get("menu.paste"), text_style.clone()));
+                        });
+                    parent
+                        .spawn((
+                            ButtonBundle {
+                                style: Style {
+                                    width: Val::Percent(20.),
+                                    ..node_style.clone()
+                                },
+                                border_color: BorderColor(BORDER_COLOR_INACTIVE),
+                                background_color: BACKGROUND_COLOR.into(),
+                                ..default()
+                            },
+                            FavoriteButton,
+                        ))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section(strings.get("menu.favorite"), text_style.clone()));
                         });
                 });
 
+            parent.spawn((
+                node_bundle.clone(),
+                TextInputBundle::default()
+                    .with_value(preferences.password.clone())
+                    .with_text_style(text_style.clone())
+                    .with_placeholder(strings.get("menu.password"), None)
+                    .with_inactive(true),
+                PasswordInput,
+            ));
+
+            let button_bundle = ButtonBundle {
+                style: Style {
+                    width: Val::Px(142.5), // four buttons and their gaps take the width of the wide ones
+                    border: UiRect::all(Val::Px(5.0)),
+                    padding: UiRect::all(Val::Px(5.0)),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                border_color: BorderColor(BORDER_COLOR_INACTIVE),
+                background_color: BACKGROUND_COLOR.into(),
+                ..default()
+            };
+
             parent
-                .spawn((
-                    ButtonBundle {
-                        style: Style {
-                            width: Val::Px(200.),
-                            border: UiRect::all(Val::Px(5.0)),
-                            padding: UiRect::all(Val::Px(5.0)),
-                            justify_content: JustifyContent::Center,
-                            ..default()
-                        },
-                        border_color: BorderColor(BORDER_COLOR_INACTIVE),
-                        background_color: BACKGROUND_COLOR.into(),
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        column_gap: Val::Px(10.),
                         ..default()
                     },
-                    ConnectButton,
-                ))
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent
+                        .spawn((button_bundle.clone(), ConnectButton))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section(strings.get("menu.connect"), text_style.clone()));
+                        });
+                    parent
+                        .spawn((button_bundle.clone(), SpectateButton))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section(strings.get("menu.spectate"), text_style.clone()));
+                        });
+                    parent
+                        .spawn((button_bundle.clone(), RefreshButton))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section(strings.get("menu.refresh"), text_style.clone()));
+                        });
+                    parent
+                        .spawn((button_bundle.clone(), TutorialButton))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section(strings.get("menu.tutorial"), text_style.clone()));
+                        });
+                });
+
+            let wide_button = ButtonBundle {
+                style: Style {
+                    width: Val::Px(600.),
+                    ..button_bundle.style.clone()
+                },
+                ..button_bundle
+            };
+
+            parent
+                .spawn((wide_button.clone(), MapsButton))
+                .with_children(|parent| {
+                    parent.spawn(TextBundle::from_section(strings.get("menu.maps"), text_style.clone()));
+                });
+
+            parent
+                .spawn((wide_button.clone(), PaletteButton))
                 .with_children(|parent| {
-                    parent.spawn(TextBundle::from_section("Connect", text_style.clone()));
+                    parent.spawn((
+                        TextBundle::from_section(
+                            palette_label(preferences.team_colors.palette, strings),
+                            text_style.clone(),
+                        ),
+                        PaletteLabel,
+                    ));
                 });
 
-            if let Some(error) = error {
-                parent.spawn(node_bundle.clone()).with_children(|parent| {
+            parent
+                .spawn((wide_button.clone(), LowHpButton))
+                .with_children(|parent| {
+                    parent.spawn((
+                        TextBundle::from_section(
+                            low_hp_label(preferences.low_hp_warning, strings),
+                            text_style.clone(),
+                        ),
+                        LowHpLabel,
+                    ));
+                });
+
+            parent
+                .spawn((wide_button.clone(), SettingsButton))
+                .with_children(|parent| {
+                    parent.spawn(TextBundle::from_section(strings.get("menu.settings"), text_style.clone()));
+                });
+
+            parent
+                .spawn((wide_button, LanguageButton))
+                .with_children(|parent| {
                     parent.spawn(TextBundle::from_section(
-                        error.0.clone(),
-                        TextStyle {
-                            color: Color::srgb(1., 0., 1.),
-                            ..text_style
-                        },
+                        strings.format("menu.language", &[("language", strings.get("language.name"))]),
+                        text_style.clone(),
                     ));
                 });
-            }
+
+            parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        width: Val::Px(600.0),
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ServerList,
+            ));
         })
         .id()
 }
@@ -184,29 +346,310 @@ fn focus(
     }
 }
 
+pub fn connect(
+    commands: &mut Commands,
+    next_state: &mut ResMut<NextState<GameState>>,
+    preferences: &mut Preferences,
+    addr: String,
+    nick: String,
+    spectator: bool,
+) {
+    preferences.nickname = nick.clone();
+    preferences.last_server = addr.clone();
+    if let Err(e) = preferences.save() {
+        warn!("Failed to save preferences: {e}");
+    }
+    // a server hosting several lobbies is given as address/code
+    let lobby = addr.split_once('/');
+    let client = match (lobby, spectator) {
+        _ if !preferences.password.is_empty() => {
+            let (addr, code) = lobby.map_or((addr.as_str(), None), |(addr, code)| (addr, Some(code.to_string())));
+            let password = preferences.password.clone();
+            GameClient::<GamePacket, PACKET_SIZE>::join_with_password(addr, code, nick, spectator, password)
+        }
+        (Some((addr, code)), _) => GameClient::<GamePacket, PACKET_SIZE>::join_lobby(addr, code.to_string(), nick, spectator),
+        (None, true) => GameClient::<GamePacket, PACKET_SIZE>::spectate(addr, nick),
+        (None, false) => GameClient::<GamePacket, PACKET_SIZE>::new(addr, nick),
+    };
+    match client {
+        Ok(client) => {
+            commands.insert_resource(Client(client));
+            next_state.set(GameState::InLobby);
+        }
+        Err(e) => display_error(commands, next_state, e),
+    }
+}
+
 fn connect_system(
     mut commands: Commands,
     nick: Query<&TextInputValue, With<NicknameInput>>,
     addr: Query<&TextInputValue, With<AddrInput>>,
+    password: Query<&TextInputValue, With<PasswordInput>>,
     mut next_state: ResMut<NextState<GameState>>,
+    mut preferences: ResMut<Preferences>,
     connect_button: Query<&Interaction, (With<ConnectButton>, Changed<Interaction>)>,
+    spectate_button: Query<&Interaction, (With<SpectateButton>, Changed<Interaction>)>,
 ) {
-    for interaction in &connect_button {
+    let pressed = connect_button.iter().map(|i| (i, false)).chain(spectate_button.iter().map(|i| (i, true)));
+    for (interaction, spectator) in pressed {
         if matches!(interaction, Interaction::Pressed) {
             let nick = nick.single().0.clone();
             let addr = addr.single().0.clone();
+            preferences.password = password.single().0.clone();
+            connect(&mut commands, &mut next_state, &mut preferences, addr, nick, spectator);
+        }
+    }
+}
 
-            match GameClient::<GamePacket, PACKET_SIZE>::new(addr, nick) {
-                Ok(client) => {
-                    commands.insert_resource(Client(client));
-                    next_state.set(GameState::InLobby);
+fn join_system(
+    mut commands: Commands,
+    nick: Query<&TextInputValue, With<NicknameInput>>,
+    password: Query<&TextInputValue, With<PasswordInput>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut preferences: ResMut<Preferences>,
+    join_buttons: Query<(&Interaction, &JoinButton), Changed<Interaction>>,
+) {
+    for (interaction, join) in &join_buttons {
+        if matches!(interaction, Interaction::Pressed) {
+            let nick = nick.single().0.clone();
+            preferences.password = password.single().0.clone();
+            connect(&mut commands, &mut next_state, &mut preferences, join.0.clone(), nick, false);
+        }
+    }
+}
+
+fn palette_label(palette: Palette, strings: &Strings) -> String {
+    let name = strings.get(&format!("palette.{palette:?}")).to_string();
+    strings.format("menu.team_colors", &[("palette", &name)])
+}
+
+fn palette_system(
+    mut preferences: ResMut<Preferences>,
+    strings: Res<Strings>,
+    palette_button: Query<&Interaction, (With<PaletteButton>, Changed<Interaction>)>,
+    mut label: Query<&mut Text, With<PaletteLabel>>,
+) {
+    for interaction in &palette_button {
+        if !matches!(interaction, Interaction::Pressed) {
+            continue;
+        }
+        let palette = preferences.team_colors.palette.next();
+        preferences.team_colors.palette = palette;
+        if let Err(e) = preferences.save() {
+            warn!("Failed to save preferences: {e}");
+        }
+        if let Ok(mut text) = label.get_single_mut() {
+            text.sections[0].value = palette_label(palette, &strings);
+        }
+    }
+}
+
+fn low_hp_label(warning: LowHpWarning, strings: &Strings) -> String {
+    let thresholds = match warning.enabled() {
+        true => format!("{:.0}% / {:.0}%", warning.vignette * 100., warning.heartbeat * 100.),
+        false => strings.get("menu.off").to_string(),
+    };
+    strings.format("menu.low_hp", &[("thresholds", &thresholds)])
+}
+
+fn low_hp_system(
+    mut preferences: ResMut<Preferences>,
+    strings: Res<Strings>,
+    low_hp_button: Query<&Interaction, (With<LowHpButton>, Changed<Interaction>)>,
+    mut label: Query<&mut Text, With<LowHpLabel>>,
+) {
+    for interaction in &low_hp_button {
+        if !matches!(interaction, Interaction::Pressed) {
+            continue;
+        }
+        let warning = preferences.low_hp_warning.next();
+        preferences.low_hp_warning = warning;
+        if let Err(e) = preferences.save() {
+            warn!("Failed to save preferences: {e}");
+        }
+        if let Ok(mut text) = label.get_single_mut() {
+            text.sections[0].value = low_hp_label(warning, &strings);
+        }
+    }
+}
+
+fn settings_system(
+    mut next_state: ResMut<NextState<GameState>>,
+    settings_button: Query<&Interaction, (With<SettingsButton>, Changed<Interaction>)>,
+) {
+    for interaction in &settings_button {
+        if matches!(interaction, Interaction::Pressed) {
+            next_state.set(GameState::Settings);
+        }
+    }
+}
+
+fn language_system(
+    mut preferences: ResMut<Preferences>,
+    language_button: Query<&Interaction, (With<LanguageButton>, Changed<Interaction>)>,
+    inputs: Query<(&TextInputValue, Has<NicknameInput>, Has<PasswordInput>)>,
+) {
+    for interaction in &language_button {
+        if !matches!(interaction, Interaction::Pressed) {
+            continue;
+        }
+        let languages = Locale::languages();
+        let current = languages.iter().position(|l| *l == preferences.language);
+        let Some(language) = current.map_or(languages.first(), |i| languages.get((i + 1) % languages.len())) else {
+            continue;
+        };
+        preferences.language = language.clone();
+
+        // the menu is built again in the new language, keep what the user typed so far
+        for (value, is_nickname, is_password) in &inputs {
+            match (is_nickname, is_password) {
+                (true, _) => preferences.nickname = value.0.clone(),
+                (_, true) => preferences.password = value.0.clone(),
+                _ => preferences.last_server = value.0.clone(),
+            }
+        }
+        if let Err(e) = preferences.save() {
+            warn!("Failed to save preferences: {e}");
+        }
+    }
+}
+
+fn rebuild_system(
+    mut commands: Commands,
+    strings: Res<Strings>,
+    preferences: Res<Preferences>,
+    mut browser: ResMut<ServerBrowser>,
+    main_menu: Query<Entity, With<MainMenu>>,
+) {
+    if !strings.is_changed() || strings.is_added() {
+        return;
+    }
+    if let Ok(main_menu) = main_menu.get_single() {
+        commands.entity(main_menu).despawn_recursive();
+    }
+    build(&mut commands, &preferences, &strings);
+    browser.changed = true;
+}
+
+fn tutorial_system(
+    mut commands: Commands,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut preferences: ResMut<Preferences>,
+    strings: Res<Strings>,
+    tutorial_button: Query<&Interaction, (With<TutorialButton>, Changed<Interaction>)>,
+) {
+    for interaction in &tutorial_button {
+        if matches!(interaction, Interaction::Pressed) {
+            start_tutorial(&mut commands, &mut next_state, &mut preferences, &strings);
+        }
+    }
+}
+
+fn maps_system(
+    mut next_state: ResMut<NextState<GameState>>,
+    maps_button: Query<&Interaction, (With<MapsButton>, Changed<Interaction>)>,
+) {
+    for interaction in &maps_button {
+        if matches!(interaction, Interaction::Pressed) {
+            next_state.set(GameState::Maps);
+        }
+    }
+}
+
+fn favorite_system(
+    mut browser: ResMut<ServerBrowser>,
+    mut preferences: ResMut<Preferences>,
+    addr: Query<&TextInputValue, With<AddrInput>>,
+    favorite_button: Query<&Interaction, (With<FavoriteButton>, Changed<Interaction>)>,
+) {
+    for interaction in &favorite_button {
+        if matches!(interaction, Interaction::Pressed) {
+            let addr = addr.single().0.trim().to_string();
+            if addr.is_empty() {
+                continue;
+            }
+            match browser.favorites.iter().position(|f| *f == addr) {
+                Some(i) => {
+                    browser.favorites.remove(i);
                 }
-                Err(e) => display_error(&mut commands, &mut next_state, &e.to_string()),
+                None => browser.favorites.push(addr),
             }
+            preferences.favorites = browser.favorites.clone();
+            if let Err(e) = preferences.save() {
+                warn!("Failed to save preferences: {e}");
+            }
+            browser.refresh();
         }
     }
 }
 
+fn browser_system(
+    mut commands: Commands,
+    mut browser: ResMut<ServerBrowser>,
+    strings: Res<Strings>,
+    server_list: Query<Entity, With<ServerList>>,
+    refresh_button: Query<&Interaction, (With<RefreshButton>, Changed<Interaction>)>,
+) {
+    for interaction in &refresh_button {
+        if matches!(interaction, Interaction::Pressed) {
+            browser.refresh();
+        }
+    }
+
+    for (addr, info) in browser.query.get_results() {
+        browser.servers.retain(|(a, _)| *a != addr);
+        browser.servers.push((addr, info));
+        browser.changed = true;
+    }
+    if !browser.changed {
+        return;
+    }
+    browser.changed = false;
+
+    let Ok(server_list) = server_list.get_single() else {
+        return;
+    };
+    let text_style = strings.text_style(30., TEXT_COLOR);
+    commands.entity(server_list).despawn_descendants();
+    commands.entity(server_list).with_children(|parent| {
+        for (addr, label) in browser.rows(&strings) {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        border: UiRect::all(Val::Px(2.0)),
+                        padding: UiRect::all(Val::Px(5.0)),
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::SpaceBetween,
+                        ..default()
+                    },
+                    border_color: BORDER_COLOR_INACTIVE.into(),
+                    background_color: BACKGROUND_COLOR.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn(TextBundle::from_section(label, text_style.clone()));
+                    parent
+                        .spawn((
+                            ButtonBundle {
+                                style: Style {
+                                    border: UiRect::all(Val::Px(2.0)),
+                                    padding: UiRect::horizontal(Val::Px(5.0)),
+                                    ..default()
+                                },
+                                border_color: BorderColor(BORDER_COLOR_INACTIVE),
+                                background_color: BACKGROUND_COLOR.into(),
+                                ..default()
+                            },
+                            JoinButton(addr),
+                        ))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section(strings.get("menu.join"), text_style.clone()));
+                        });
+                });
+        }
+    });
+}
+
 fn paste_system(
     mut commands: Commands,
     mut addr: Query<&mut TextInputValue, With<AddrInput>>,
@@ -223,7 +666,7 @@ fn paste_system(
 
             match result {
                 Ok(paste_addr) => addr.0 = paste_addr,
-                Err(e) => display_error(&mut commands, &mut next_state, &e.to_string())
+                Err(e) => display_error(&mut commands, &mut next_state, ClientError::Other(e.to_string()))
             }
         }
     }
@@ -235,12 +678,54 @@ struct NicknameInput;
 #[derive(Component)]
 struct AddrInput;
 
+#[derive(Component)]
+struct PasswordInput;
+
 #[derive(Component)]
 struct ConnectButton;
 
+#[derive(Component)]
+struct SpectateButton;
+
 #[derive(Component)]
 struct PasteButton;
 
+#[derive(Component)]
+struct FavoriteButton;
+
+#[derive(Component)]
+struct RefreshButton;
+
+#[derive(Component)]
+struct TutorialButton;
+
+#[derive(Component)]
+struct MapsButton;
+
+#[derive(Component)]
+struct PaletteButton;
+
+#[derive(Component)]
+struct PaletteLabel;
+
+#[derive(Component)]
+struct LowHpButton;
+
+#[derive(Component)]
+struct LowHpLabel;
+
+#[derive(Component)]
+struct SettingsButton;
+
+#[derive(Component)]
+struct LanguageButton;
+
+#[derive(Component)]
+struct ServerList;
+
+#[derive(Component)]
+struct JoinButton(String);
+
 pub struct MainMenuPlugin;
 
 impl Plugin for MainMenuPlugin {
@@ -250,12 +735,7 @@ impl Plugin for MainMenuPlugin {
             .add_systems(OnExit(GameState::Menu), despawn)
             .add_systems(
                 Update,
-                (focus.before(TextInputSystem), connect_system, paste_system).run_if(in_state(GameState::Menu)),
-            )
-            .add_systems(
-                Update,
-                (|mut next_state: ResMut<NextState<GameState>>| next_state.set(GameState::Menu))
-                    .run_if(in_state(GameState::Error)),
+                (focus.before(TextInputSystem), connect_system, join_system, favorite_system, rebuild_system.before(browser_system), browser_system, tutorial_system, maps_system, palette_system, low_hp_system, settings_system, language_system, paste_system).run_if(in_state(GameState::Menu)),
             );
     }
 }
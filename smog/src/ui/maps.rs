@@ -0,0 +1,181 @@
+use bevy::prelude::*;
+use common::{config, mode::GameMode};
+use map_editor::map::{Map, MapLoader};
+
+use crate::{launch::start_offline, locale::Strings, preferences::Preferences, Client, GameState};
+
+use super::over::esc_system;
+
+#[derive(Component)]
+struct MapSelect;
+
+#[derive(Component)]
+enum MapButton {
+    Play(String),
+    Back,
+}
+
+fn spawn(mut commands: Commands, asset_server: Res<AssetServer>, strings: Res<Strings>) {
+    let _map_select = build(&mut commands, &asset_server, &strings);
+}
+
+fn despawn(mut commands: Commands, map_select: Query<Entity, With<MapSelect>>) {
+    if let Ok(map_select) = map_select.get_single() {
+        commands.entity(map_select).despawn_recursive();
+    }
+}
+
+const BORDER_COLOR: Color = Color::srgb(0.25, 0.25, 0.25);
+const TEXT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
+const BACKGROUND_COLOR: Color = Color::srgb(0.15, 0.15, 0.15);
+const THUMBNAIL_WIDTH: f32 = 240.;
+
+fn build(commands: &mut Commands, asset_server: &AssetServer, strings: &Strings) -> Entity {
+    let text_style = strings.text_style(30., TEXT_COLOR);
+    let maps = Map::list(config::maps_dir());
+
+    let card = ButtonBundle {
+        style: Style {
+            width: Val::Px(THUMBNAIL_WIDTH + 20.),
+            border: UiRect::all(Val::Px(5.0)),
+            padding: UiRect::all(Val::Px(5.0)),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            row_gap: Val::Px(5.),
+            ..default()
+        },
+        border_color: BorderColor(BORDER_COLOR),
+        background_color: BACKGROUND_COLOR.into(),
+        ..default()
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(10.),
+                    ..default()
+                },
+                ..default()
+            },
+            MapSelect,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(strings.get("maps.title"), strings.text_style(40., TEXT_COLOR)));
+
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Percent(80.),
+                        flex_wrap: FlexWrap::Wrap,
+                        justify_content: JustifyContent::Center,
+                        gap: Val::Px(10.),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    if maps.is_empty() {
+                        parent.spawn(TextBundle::from_section(strings.get("maps.empty"), text_style.clone()));
+                    }
+                    for map in maps {
+                        parent
+                            .spawn((card.clone(), MapButton::Play(map.clone())))
+                            .with_children(|parent| {
+                                // maps saved before thumbnails existed get an empty frame of the same width
+                                match MapLoader::thumbnail(&map, asset_server) {
+                                    Some(thumbnail) => parent.spawn(ImageBundle {
+                                        style: Style {
+                                            width: Val::Px(THUMBNAIL_WIDTH),
+                                            ..default()
+                                        },
+                                        image: UiImage::new(thumbnail),
+                                        ..default()
+                                    }),
+                                    None => parent.spawn(NodeBundle {
+                                        style: Style {
+                                            width: Val::Px(THUMBNAIL_WIDTH),
+                                            height: Val::Px(THUMBNAIL_WIDTH / 2.),
+                                            ..default()
+                                        },
+                                        background_color: Color::BLACK.into(),
+                                        ..default()
+                                    }),
+                                };
+                                parent.spawn(TextBundle::from_section(map, text_style.clone()));
+                            });
+                    }
+                });
+
+            parent
+                .spawn((
+                    ButtonBundle {
+                        style: Style {
+                            width: Val::Px(200.),
+                            ..card.style.clone()
+                        },
+                        ..card.clone()
+                    },
+                    MapButton::Back,
+                ))
+                .with_children(|parent| {
+                    parent.spawn(TextBundle::from_section(strings.get("maps.back"), text_style.clone()));
+                });
+        })
+        .id()
+}
+
+fn button_system(
+    mut commands: Commands,
+    mut next_state: ResMut<NextState<GameState>>,
+    preferences: Res<Preferences>,
+    buttons: Query<(&Interaction, &MapButton), Changed<Interaction>>,
+) {
+    for (interaction, button) in &buttons {
+        if !matches!(interaction, Interaction::Pressed) {
+            continue;
+        }
+        match button {
+            MapButton::Play(map) => {
+                start_offline(
+                    &mut commands,
+                    &mut next_state,
+                    preferences.nickname.clone(),
+                    preferences.garage(0),
+                    map.clone(),
+                    true,
+                )
+            }
+            MapButton::Back => next_state.set(GameState::Menu),
+        }
+    }
+}
+
+/// There's no winner to end a sandbox, so ESC leaves it.
+fn sandbox_exit_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    client: Option<Res<Client>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let sandbox = client.is_some_and(|client| client.0.lobby.settings.mode == GameMode::Sandbox);
+    if sandbox && keyboard.just_pressed(KeyCode::Escape) {
+        next_state.set(GameState::Menu);
+    }
+}
+
+/// Lists the installed maps with their thumbnails, picking one plays it alone in a sandbox.
+pub struct MapSelectPlugin;
+
+impl Plugin for MapSelectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Maps), spawn)
+            .add_systems(OnExit(GameState::Maps), despawn)
+            .add_systems(Update, (button_system, esc_system).run_if(in_state(GameState::Maps)))
+            .add_systems(Update, sandbox_exit_system.run_if(in_state(GameState::InGame)));
+    }
+}
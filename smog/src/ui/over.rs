@@ -1,15 +1,28 @@
 use bevy::{input::{keyboard::{Key, KeyboardInput}, ButtonState}, prelude::*};
+use map_editor::map::MapLoader;
+use packet_tools::server_packets::ServerPacket;
 use render::RenderedSimulation;
 
-use crate::GameState;
+use crate::{display_error, locale::Strings, Client, GameState};
 
 use super::game::GameController;
 
 #[derive(Component)]
 struct WinScreen;
 
-fn spawn(mut commands: Commands, controller: Query<(&GameController, &RenderedSimulation)>) {
-    let _winscreen = build(&mut commands, &controller);
+/// Holds the buttons of the maps offered for the next round.
+#[derive(Component)]
+struct MapVotePanel;
+
+#[derive(Component)]
+struct MapVoteButton(String);
+
+fn spawn(
+    mut commands: Commands,
+    controller: Query<(&GameController, &RenderedSimulation)>,
+    strings: Res<Strings>,
+) {
+    let _winscreen = build(&mut commands, &controller, &strings);
 }
 
 fn despawn(mut commands: Commands, win_screen: Query<Entity, With<WinScreen>>) {
@@ -18,29 +31,30 @@ fn despawn(mut commands: Commands, win_screen: Query<Entity, With<WinScreen>>) {
     }
 }
 
-const _BORDER_COLOR_ACTIVE: Color = Color::srgb(0.75, 0.52, 0.99);
+const BORDER_COLOR_ACTIVE: Color = Color::srgb(0.75, 0.52, 0.99);
 const BORDER_COLOR_INACTIVE: Color = Color::srgb(0.25, 0.25, 0.25);
 const TEXT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
 const BACKGROUND_COLOR: Color = Color::srgb(0.15, 0.15, 0.15);
 
-fn build(commands: &mut Commands, game: &Query<(&GameController, &RenderedSimulation)>) -> Entity {
-    let text_style = TextStyle {
-        font_size: 160.,
-        color: TEXT_COLOR,
-        ..default()
-    };
+fn build(
+    commands: &mut Commands,
+    game: &Query<(&GameController, &RenderedSimulation)>,
+    strings: &Strings,
+) -> Entity {
+    let text_style = strings.text_style(160., TEXT_COLOR);
+    let stats_style = strings.text_style(30., TEXT_COLOR);
 
     let (controller, simulation) = game.single();
-    let (team, _) = controller.0.get_winners(&simulation.0).unwrap();
+    let (_, winners) = controller.0.get_winners(&simulation.0).unwrap();
 
-    let text = if team == controller.0.player.team {
+    let text = if winners.iter().any(|p| p.id == controller.0.player.id) {
         TextBundle::from_section(
-            "VICTORY",
+            strings.get("over.victory"),
             text_style,
         )
     } else {
         TextBundle::from_section(
-            "DEFEAT",
+            strings.get("over.defeat"),
             TextStyle {
                 color: Color::srgb(0.9, 0., 0.,),
                 ..text_style
@@ -48,9 +62,34 @@ fn build(commands: &mut Commands, game: &Query<(&GameController, &RenderedSimula
         )
     };
 
+    // the players who did the most damage go first
+    let mut players: Vec<_> = controller.0.players.iter().collect();
+    players.sort_by(|p1, p2| p2.stats.damage.total_cmp(&p1.stats.damage));
+    let rows: Vec<String> = players
+        .into_iter()
+        .map(|player| {
+            let stats = &player.stats;
+            let percent = |damage: f32| format!("{:.0}", damage * 100.);
+            let mut row = strings.format(
+                "over.stats",
+                &[
+                    ("name", &player.name),
+                    ("damage", &percent(stats.damage)),
+                    ("kills", &stats.kills.to_string()),
+                    ("deaths", &stats.deaths.to_string()),
+                ],
+            );
+            if stats.friendly_damage > 0. {
+                row += &strings.format("over.friendly", &[("damage", &percent(stats.friendly_damage))]);
+            }
+            row
+        })
+        .collect();
+
     let node_bundle = NodeBundle {
         style: Style {
             width: Val::Percent(80.),
+            flex_direction: FlexDirection::Column,
             border: UiRect::all(Val::Px(5.0)),
             padding: UiRect::all(Val::Px(5.0)),
             align_items: AlignItems::Center,
@@ -79,11 +118,114 @@ fn build(commands: &mut Commands, game: &Query<(&GameController, &RenderedSimula
         .with_children(|parent| {
             parent.spawn(node_bundle).with_children(|parent| {
                 parent.spawn(text);
+                for row in rows {
+                    parent.spawn(TextBundle::from_section(row, stats_style.clone()));
+                }
+                parent.spawn((
+                    NodeBundle {
+                        style: Style {
+                            flex_direction: FlexDirection::Column,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        },
+                        ..default()
+                    },
+                    MapVotePanel,
+                ));
             });
         })
         .id()
 }
 
+/// Once the server offers the next round the players vote for its map here, the lobby follows the result.
+fn map_vote_system(
+    mut commands: Commands,
+    client: Option<ResMut<Client>>,
+    panel: Query<Entity, With<MapVotePanel>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    strings: Res<Strings>,
+    asset_server: Res<AssetServer>,
+) {
+    let Some(mut client) = client else {
+        return;
+    };
+    // the ticks after the winning one aren't simulated
+    client.0.get_packets(usize::MAX);
+    if let Some(maps) = client.0.take_map_vote() {
+        if let Err(e) = client.0.return_to_lobby() {
+            display_error(&mut commands, &mut next_state, e);
+            return;
+        }
+        if let Ok(panel) = panel.get_single() {
+            commands.entity(panel).with_children(|parent| {
+                let text_style = strings.text_style(30., TEXT_COLOR);
+                parent.spawn(TextBundle::from_section(strings.get("over.vote"), text_style.clone()));
+                for map in maps {
+                    parent
+                        .spawn((
+                            ButtonBundle {
+                                style: Style {
+                                    width: Val::Px(300.),
+                                    border: UiRect::all(Val::Px(5.0)),
+                                    padding: UiRect::all(Val::Px(5.0)),
+                                    flex_direction: FlexDirection::Column,
+                                    align_items: AlignItems::Center,
+                                    ..default()
+                                },
+                                border_color: BORDER_COLOR_INACTIVE.into(),
+                                background_color: BACKGROUND_COLOR.into(),
+                                ..default()
+                            },
+                            MapVoteButton(map.clone()),
+                        ))
+                        .with_children(|parent| {
+                            // only the maps the player has played on are on the disk
+                            if let Some(thumbnail) = MapLoader::thumbnail(&map, &asset_server) {
+                                parent.spawn(ImageBundle {
+                                    style: Style {
+                                        width: Val::Px(280.),
+                                        ..default()
+                                    },
+                                    image: UiImage::new(thumbnail),
+                                    ..default()
+                                });
+                            }
+                            parent.spawn(TextBundle::from_section(map, text_style.clone()));
+                        });
+                }
+            });
+        }
+    }
+
+    for packet in client.0.get_lobby_packets() {
+        match packet {
+            ServerPacket::MatchSettings(settings) => client.0.lobby.settings = settings,
+            ServerPacket::MapVoteResult(_) => next_state.set(GameState::InLobby),
+            _ => (),
+        }
+    }
+}
+
+/// The last vote counts, the chosen map keeps its border lit.
+fn vote_button_system(
+    client: Option<Res<Client>>,
+    pressed: Query<(&Interaction, &MapVoteButton), Changed<Interaction>>,
+    mut buttons: Query<(&MapVoteButton, &mut BorderColor)>,
+) {
+    let Some(client) = client else {
+        return;
+    };
+    for (interaction, vote) in &pressed {
+        if !matches!(interaction, Interaction::Pressed) {
+            continue;
+        }
+        client.0.vote_map(vote.0.clone());
+        for (button, mut border) in &mut buttons {
+            *border = if button.0 == vote.0 { BORDER_COLOR_ACTIVE } else { BORDER_COLOR_INACTIVE }.into();
+        }
+    }
+}
+
 pub fn esc_system(mut keyboard: EventReader<KeyboardInput>, mut next_state: ResMut<NextState<GameState>>) {
     for ev in keyboard.read() {
         if ev.state == ButtonState::Released {
@@ -103,6 +245,9 @@ impl Plugin for WinScreenPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(OnEnter(GameState::EndGame), spawn)
             .add_systems(OnExit(GameState::EndGame), despawn)
-            .add_systems(Update, esc_system.run_if(in_state(GameState::EndGame)));
+            .add_systems(
+                Update,
+                (esc_system, map_vote_system, vote_button_system).run_if(in_state(GameState::EndGame)),
+            );
     }
 }
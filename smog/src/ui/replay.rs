@@ -0,0 +1,308 @@
+use std::{path::Path, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use bevy::{prelude::*, render::camera::ScalingMode};
+use common::{config, PHYSICS_TICK, REPLAY_EXTENSION};
+use map_editor::map::MapLoader;
+use render::{
+    ambient::Ambient, camera::ObserverCamera, interpolation::TickPositions, palette::TeamColors, RenderedSimulation, SimulationCamera,
+    SimulationTextures,
+};
+use session::{replay::Replay, GameSession, SUB_TICKS};
+
+use crate::{display_error, locale::Strings, network::error::ClientError, GameState};
+
+use super::game::{GameController, MapLight};
+
+const KEYFRAME_TICKS: u128 = 4096; // ticks between the sessions kept to seek back from
+const SEEK_STEP_TICKS: u128 = 32 * SUB_TICKS as u128; // ticks simulated per fixed step while seeking
+const SEEK_TIME: Duration = Duration::from_secs(5);
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 4.;
+
+const TEXT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
+
+/// Packets of the match being played, saved to the replays directory once it's over.
+#[derive(Resource)]
+pub struct Recording(pub Replay);
+
+/// Replay being watched, the simulation follows it tick by tick.
+#[derive(Resource)]
+pub struct Playback {
+    replay: Replay,
+    keyframes: Vec<GameSession>, // one every `KEYFRAME_TICKS` ticks, starting at the first one
+    target: Option<u128>,        // tick being seeked to
+    paused: bool,
+    speed: f32,
+    carry: f32, // part of a tick left over at low speeds
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            replay,
+            keyframes: vec![],
+            target: None,
+            paused: false,
+            speed: 1.,
+            carry: 0.,
+        }
+    }
+}
+
+#[derive(Component)]
+struct ReplayOverlay;
+
+fn seek_ticks() -> u128 {
+    SEEK_TIME.as_nanos() / PHYSICS_TICK.as_nanos()
+}
+
+fn format_ticks(ticks: u128) -> String {
+    let secs = (PHYSICS_TICK * ticks as u32).as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// Opens the replay file and starts watching it.
+pub fn start_replay<P: AsRef<Path>>(commands: &mut Commands, next_state: &mut ResMut<NextState<GameState>>, path: P) {
+    match Replay::load(&path) {
+        Ok(replay) => {
+            commands.insert_resource(Playback::new(replay));
+            next_state.set(GameState::Replay);
+        }
+        Err(e) => display_error(
+            commands,
+            next_state,
+            ClientError::Other(format!("Failed to open the replay {}: {e}", path.as_ref().display())),
+        ),
+    }
+}
+
+/// Writes the recording of the match that just ended, named after the map and the time it ended.
+fn save_recording(mut commands: Commands, recording: Option<Res<Recording>>) {
+    let Some(recording) = recording else {
+        return;
+    };
+    commands.remove_resource::<Recording>();
+    if recording.0.is_empty() {
+        return;
+    }
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let path = config::replays_dir().join(format!("{}-{time}.{REPLAY_EXTENSION}", recording.0.map));
+    match recording.0.save(&path) {
+        Ok(()) => info!("Replay saved to {}", path.display()),
+        Err(e) => warn!("Failed to save the replay: {e}"),
+    }
+}
+
+fn setup_replay(
+    mut commands: Commands,
+    mut playback: ResMut<Playback>,
+    asset_server: Res<AssetServer>,
+    mut camera: Query<(Entity, &mut OrthographicProjection), With<SimulationCamera>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    team_colors: Res<TeamColors>,
+    strings: Res<Strings>,
+) {
+    let replay = &playback.replay;
+    let map_loader = match MapLoader::init_from_file(&replay.map, &asset_server) {
+        Ok(map_loader) => map_loader,
+        Err(e) => {
+            warn!("Failed to load the map of the replay: {e}");
+            display_error(&mut commands, &mut next_state, ClientError::from_map_error(replay.map.clone(), &e));
+            return;
+        }
+    };
+    commands.insert_resource(SimulationTextures {
+        textures: map_loader.textures,
+        background: map_loader.background,
+        parallax: map_loader.parallax,
+        atlas: map_loader.atlas,
+    });
+
+    // the viewer has no tank, so it watches as a spectator
+    let session = GameSession::new(
+        u8::MAX,
+        String::new(),
+        &map_loader.map,
+        &replay.players,
+        &replay.garage,
+        &replay.settings,
+        &team_colors,
+    );
+    playback.keyframes = vec![session.clone()];
+    let ambient = map_loader.map.ambient;
+
+    let (bl, tr) = session.solver.constraint.bounds();
+    let (camera, mut projection) = camera.single_mut();
+    *projection = OrthographicProjection {
+        scale: 1.0,
+        scaling_mode: ScalingMode::FixedHorizontal(tr.x - bl.x),
+        ..Default::default()
+    };
+    commands.entity(camera).insert(ObserverCamera::default());
+
+    let GameSession { controller, solver } = session;
+    commands
+        .spawn(SpatialBundle {
+            visibility: Visibility::Visible,
+            transform: Transform::IDENTITY,
+            ..default()
+        })
+        .insert(RenderedSimulation(solver))
+        .insert(TickPositions::default())
+        .insert((ambient, MapLight(ambient)))
+        .insert(GameController(controller));
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    padding: UiRect::all(Val::Px(10.)),
+                    align_items: AlignItems::End,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            ReplayOverlay,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section("", strings.text_style(30., TEXT_COLOR)));
+        });
+}
+
+fn playback_system(
+    mut playback: ResMut<Playback>,
+    mut simulation: Query<(&mut RenderedSimulation, &mut GameController)>,
+) {
+    let (mut simulation, mut controller) = simulation.single_mut();
+    let playback = &mut *playback;
+
+    let ticks = match playback.target {
+        Some(target) => {
+            if target < controller.0.tick {
+                // simulate again from the last keyframe before the target
+                let keyframe = &playback.keyframes[(target / KEYFRAME_TICKS) as usize];
+                controller.0 = keyframe.controller.clone();
+                simulation.0 = keyframe.solver.clone();
+            }
+            target.saturating_sub(controller.0.tick).min(SEEK_STEP_TICKS)
+        }
+        None if playback.paused => 0,
+        None => {
+            let ticks = playback.speed * SUB_TICKS as f32 + playback.carry;
+            playback.carry = ticks.fract();
+            ticks as u128
+        }
+    };
+
+    for _ in 0..ticks {
+        let tick = controller.0.tick;
+        let Some(packets) = playback.replay.tick(tick) else {
+            playback.paused = true;
+            playback.target = None;
+            return;
+        };
+        if tick % KEYFRAME_TICKS == 0 && playback.keyframes.len() as u128 == tick / KEYFRAME_TICKS {
+            playback.keyframes.push(GameSession {
+                controller: controller.0.clone(),
+                solver: simulation.0.clone(),
+            });
+        }
+        GameSession::advance(&mut controller.0, &mut simulation.0, &packets);
+    }
+    if playback.target == Some(controller.0.tick) {
+        playback.target = None;
+    }
+}
+
+fn control_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut playback: ResMut<Playback>,
+    controller: Query<&GameController>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let tick = playback.target.unwrap_or(controller.single().0.tick);
+    if keyboard.just_pressed(KeyCode::Space) {
+        playback.paused = !playback.paused;
+    }
+    if keyboard.just_pressed(KeyCode::ArrowLeft) {
+        playback.target = Some(tick.saturating_sub(seek_ticks()));
+    }
+    if keyboard.just_pressed(KeyCode::ArrowRight) {
+        playback.target = Some((tick + seek_ticks()).min(playback.replay.len()));
+    }
+    if keyboard.just_pressed(KeyCode::ArrowUp) {
+        playback.speed = (playback.speed * 2.).min(MAX_SPEED);
+    }
+    if keyboard.just_pressed(KeyCode::ArrowDown) {
+        playback.speed = (playback.speed / 2.).max(MIN_SPEED);
+    }
+    if keyboard.just_pressed(KeyCode::Escape) {
+        next_state.set(GameState::Menu);
+    }
+}
+
+fn update_overlay(
+    playback: Res<Playback>,
+    controller: Query<&GameController>,
+    mut texts: Query<&mut Text>,
+    overlay: Query<&Children, With<ReplayOverlay>>,
+    strings: Res<Strings>,
+) {
+    let Ok(children) = overlay.get_single() else {
+        return;
+    };
+    let Ok(mut text) = texts.get_mut(children[0]) else {
+        return;
+    };
+    let tick = controller.single().0.tick;
+    let status = match playback.paused {
+        true => strings.get("replay.paused").to_string(),
+        false => format!("x{}", playback.speed),
+    };
+    text.sections[0].value = strings.format(
+        "replay.status",
+        &[
+            ("time", &format_ticks(tick)),
+            ("length", &format_ticks(playback.replay.len())),
+            ("status", &status),
+        ],
+    );
+}
+
+fn exit_replay(
+    mut commands: Commands,
+    simulation: Query<Entity, With<GameController>>,
+    overlay: Query<Entity, With<ReplayOverlay>>,
+    camera: Query<Entity, With<ObserverCamera>>,
+) {
+    commands.remove_resource::<Playback>();
+    for entity in simulation.iter().chain(overlay.iter()) {
+        commands.entity(entity).despawn_recursive();
+    }
+    for camera in &camera {
+        commands.entity(camera).remove::<ObserverCamera>();
+    }
+}
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnExit(GameState::InGame), save_recording)
+            .add_systems(OnEnter(GameState::Replay), setup_replay)
+            .add_systems(OnExit(GameState::Replay), exit_replay)
+            // the simulation is missing if the map failed to load
+            .add_systems(
+                Update,
+                (control_system, update_overlay)
+                    .run_if(in_state(GameState::Replay).and_then(any_with_component::<GameController>)),
+            )
+            .add_systems(
+                FixedUpdate,
+                playback_system.run_if(in_state(GameState::Replay).and_then(any_with_component::<GameController>)),
+            );
+    }
+}
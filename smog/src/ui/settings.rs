@@ -0,0 +1,233 @@
+use bevy::prelude::*;
+use render::settings::{key_name, Settings};
+
+use crate::{locale::Strings, GameState};
+
+#[derive(Component)]
+struct SettingsScreen;
+
+#[derive(Component, Clone, Copy, PartialEq)]
+enum SettingsAction {
+    Volume,
+    Sensitivity,
+    Trails,
+    Bind(&'static str), // action of the game keys
+    Reset,
+    Back,
+}
+
+/// Action waiting for its new key, the next key pressed is bound to it.
+#[derive(Resource, Default)]
+struct Rebinding(Option<&'static str>);
+
+fn spawn(mut commands: Commands, settings: Res<Settings>, strings: Res<Strings>) {
+    let _settings_screen = build(&mut commands, &settings, &strings);
+}
+
+fn despawn(mut commands: Commands, settings_screen: Query<Entity, With<SettingsScreen>>, mut rebinding: ResMut<Rebinding>) {
+    if let Ok(settings_screen) = settings_screen.get_single() {
+        commands.entity(settings_screen).despawn_recursive();
+    }
+    rebinding.0 = None;
+}
+
+const BORDER_COLOR: Color = Color::srgb(0.25, 0.25, 0.25);
+const TEXT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
+const BACKGROUND_COLOR: Color = Color::srgb(0.15, 0.15, 0.15);
+
+fn build(commands: &mut Commands, settings: &Settings, strings: &Strings) -> Entity {
+    let text_style = strings.text_style(30., TEXT_COLOR);
+
+    let button = ButtonBundle {
+        style: Style {
+            width: Val::Px(450.),
+            border: UiRect::all(Val::Px(5.0)),
+            padding: UiRect::all(Val::Px(5.0)),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        border_color: BorderColor(BORDER_COLOR),
+        background_color: BACKGROUND_COLOR.into(),
+        ..default()
+    };
+
+    let mut actions = vec![SettingsAction::Volume, SettingsAction::Sensitivity, SettingsAction::Trails];
+    actions.extend(settings.game.actions().map(|(action, _)| SettingsAction::Bind(action)));
+    actions.extend([SettingsAction::Reset, SettingsAction::Back]);
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(10.),
+                    ..default()
+                },
+                ..default()
+            },
+            SettingsScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(strings.get("settings.title"), strings.text_style(40., TEXT_COLOR)));
+
+            // two columns, so every binding fits on the screen
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(920.),
+                        flex_wrap: FlexWrap::Wrap,
+                        justify_content: JustifyContent::Center,
+                        gap: Val::Px(10.),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for action in actions {
+                        parent.spawn((button.clone(), action)).with_children(|parent| {
+                            parent.spawn(TextBundle::from_section(label(action, settings, None, strings), text_style.clone()));
+                        });
+                    }
+                });
+        })
+        .id()
+}
+
+fn volume_label(volume: f32, strings: &Strings) -> String {
+    let volume = match volume > 0. {
+        true => format!("{:.0}%", volume * 100.),
+        false => strings.get("menu.off").to_string(),
+    };
+    strings.format("settings.volume", &[("volume", &volume)])
+}
+
+fn label(action: SettingsAction, settings: &Settings, rebinding: Option<&str>, strings: &Strings) -> String {
+    match action {
+        SettingsAction::Volume => volume_label(settings.volume, strings),
+        SettingsAction::Sensitivity => strings.format(
+            "settings.sensitivity",
+            &[("sensitivity", &format!("{:.0}%", settings.camera_sensitivity * 100.))],
+        ),
+        SettingsAction::Trails => {
+            let state = strings.get(if settings.trails.enabled { "menu.on" } else { "menu.off" }).to_string();
+            strings.format("settings.trails", &[("state", &state)])
+        }
+        SettingsAction::Bind(name) => {
+            let key = match rebinding == Some(name) {
+                true => strings.get("settings.press_key").to_string(),
+                false => settings
+                    .game
+                    .actions()
+                    .iter()
+                    .find(|(action, _)| *action == name)
+                    .map_or(String::new(), |(_, key)| key_name(*key)),
+            };
+            let action = strings.get(&format!("settings.action.{name}")).to_string();
+            strings.format("settings.binding", &[("action", &action), ("key", &key)])
+        }
+        SettingsAction::Reset => strings.get("settings.reset").to_string(),
+        SettingsAction::Back => strings.get("settings.back").to_string(),
+    }
+}
+
+fn save(settings: &Settings) {
+    if let Err(e) = settings.save() {
+        warn!("Failed to save settings: {e}");
+    }
+}
+
+fn button_system(
+    mut settings: ResMut<Settings>,
+    mut rebinding: ResMut<Rebinding>,
+    mut next_state: ResMut<NextState<GameState>>,
+    buttons: Query<(&Interaction, &SettingsAction), Changed<Interaction>>,
+) {
+    for (interaction, action) in &buttons {
+        if !matches!(interaction, Interaction::Pressed) {
+            continue;
+        }
+        match action {
+            SettingsAction::Volume => {
+                settings.volume = settings.next_volume();
+                save(&settings);
+            }
+            SettingsAction::Sensitivity => {
+                settings.camera_sensitivity = settings.next_sensitivity();
+                save(&settings);
+            }
+            SettingsAction::Trails => {
+                settings.trails.enabled = !settings.trails.enabled;
+                save(&settings);
+            }
+            SettingsAction::Bind(name) => rebinding.0 = Some(*name),
+            SettingsAction::Reset => {
+                // only the keys of the game are shown here, the editor's stay as they are
+                settings.game = default();
+                settings.camera_sensitivity = 1.;
+                settings.volume = 1.;
+                settings.trails = default();
+                save(&settings);
+            }
+            SettingsAction::Back => next_state.set(GameState::Menu),
+        }
+    }
+}
+
+/// Binds the next key pressed while an action waits for one, ESC cancels it and otherwise leaves the screen.
+fn key_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut settings: ResMut<Settings>,
+    mut rebinding: ResMut<Rebinding>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let Some(&key) = keyboard.get_just_pressed().next() else {
+        return;
+    };
+    match (rebinding.0.take(), key) {
+        (Some(_), KeyCode::Escape) => (),
+        (Some(action), key) => {
+            settings.game.bind(action, key);
+            save(&settings);
+        }
+        (None, KeyCode::Escape) => next_state.set(GameState::Menu),
+        (None, _) => (),
+    }
+}
+
+fn label_system(
+    settings: Res<Settings>,
+    rebinding: Res<Rebinding>,
+    strings: Res<Strings>,
+    buttons: Query<(&SettingsAction, &Children)>,
+    mut texts: Query<&mut Text>,
+) {
+    if !settings.is_changed() && !rebinding.is_changed() {
+        return;
+    }
+    for (action, children) in &buttons {
+        for child in children.iter() {
+            if let Ok(mut text) = texts.get_mut(*child) {
+                text.sections[0].value = label(*action, &settings, rebinding.0, &strings);
+            }
+        }
+    }
+}
+
+/// Volume, camera sensitivity, trails and the keys of the tank, saved as soon as they change.
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Rebinding>()
+            .add_systems(OnEnter(GameState::Settings), spawn)
+            .add_systems(OnExit(GameState::Settings), despawn)
+            .add_systems(
+                Update,
+                (button_system, key_system, label_system).chain().run_if(in_state(GameState::Settings)),
+            );
+    }
+}
@@ -0,0 +1,286 @@
+use bevy::{math::vec2, prelude::*};
+use common::{
+    chassis::Garage,
+    mode::{GameMode, MatchSettings},
+    DEFAULT_PROJECTILES,
+};
+use image::Rgba;
+use map_editor::{
+    constructor::MapConstructor,
+    map::{Map, MapLoader, Spawn},
+};
+use packet_tools::game_packets::GamePacket;
+use render::{
+    settings::{key_name, Settings},
+    RenderedSimulation, SimulationTextures,
+};
+use session::controller::Controller;
+use solver::{particle::GROUND, Constraint, Link};
+
+use crate::{
+    display_error,
+    launch::LaunchArgs,
+    locale::Strings,
+    network::client::{GameClient, LobbyInfo},
+    preferences::Preferences,
+    Client, GameState, PACKET_SIZE,
+};
+
+use super::game::GameController;
+
+const TUTORIAL_MAP: &str = "tutorial";
+const TARGET_ID: u8 = 1;
+const MOVE_DISTANCE: f32 = 15.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum Step {
+    #[default]
+    Move,
+    Gears,
+    Aim,
+    Projectiles,
+    Dash,
+    Destroy,
+}
+
+impl Step {
+    const COUNT: usize = 6;
+
+    /// Key of the prompt in the locale files.
+    fn prompt(&self) -> &'static str {
+        match self {
+            Step::Move => "tutorial.move",
+            Step::Gears => "tutorial.gears",
+            Step::Aim => "tutorial.aim",
+            Step::Projectiles => "tutorial.projectiles",
+            Step::Dash => "tutorial.dash",
+            Step::Destroy => "tutorial.destroy",
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            Step::Move => Step::Gears,
+            Step::Gears => Step::Aim,
+            Step::Aim => Step::Projectiles,
+            Step::Projectiles => Step::Dash,
+            Step::Dash | Step::Destroy => Step::Destroy,
+        }
+    }
+}
+
+/// Present while the tutorial is played, the game itself runs on an offline client.
+#[derive(Resource, Default)]
+pub struct Tutorial {
+    step: Step,
+    start: Option<Vec2>, // position of the tank when the step began
+}
+
+#[derive(Component)]
+struct TutorialPrompt;
+
+/// Small flat map with a bump in the middle, the player spawns on the left and the target on the right.
+fn map() -> Map {
+    let mut constructor = MapConstructor::new(
+        TUTORIAL_MAP.to_string(),
+        Constraint::Box(vec2(-120., -30.), vec2(120., 90.)),
+    );
+    constructor.add_layer();
+    let layer = constructor.layers.last_mut().unwrap();
+    layer.base_particle = GROUND;
+    layer.init_from_fn(|pos| {
+        let ground = -10. + 8. * f32::exp(-(pos.x / 20.).powi(2));
+        (pos.y < ground).then_some(Rgba([194, 178, 128, 255]))
+    });
+    layer.link = Some(Link::Rigid {
+        length: 1.,
+        durability: 1.,
+        elasticity: 5.,
+    });
+    layer.strength = 0.5;
+    constructor.spawns = vec![
+        Spawn { pos: vec2(-70., 5.), team: 0 },
+        Spawn { pos: vec2(70., 5.), team: 1 },
+    ];
+    constructor.map()
+}
+
+pub fn map_loader(asset_server: &AssetServer) -> MapLoader {
+    MapLoader {
+        map: map(),
+        textures: SimulationTextures::SIMULATION_TEXTURES
+            .iter()
+            .map(|&name| asset_server.load(name))
+            .collect(),
+        background: None,
+        parallax: vec![],
+        atlas: None,
+    }
+}
+
+pub fn start_tutorial(
+    commands: &mut Commands,
+    next_state: &mut ResMut<NextState<GameState>>,
+    preferences: &mut Preferences,
+    strings: &Strings,
+) {
+    preferences.tutorial_done = true;
+    if let Err(e) = preferences.save() {
+        warn!("Failed to save preferences: {e}");
+    }
+
+    let name = match preferences.nickname.as_str() {
+        "" => strings.get("tutorial.you").to_string(),
+        nickname => nickname.to_string(),
+    };
+    let lobby = LobbyInfo {
+        id: 0,
+        map: TUTORIAL_MAP.to_string(),
+        settings: MatchSettings {
+            mode: GameMode::TeamDeathmatch,
+            round_time: 0,
+            friendly_fire: true,
+            respawns: 0,
+            projectiles: DEFAULT_PROJECTILES,
+        },
+        players: vec![(0, name.clone()), (TARGET_ID, strings.get("tutorial.target").to_string())],
+        garage: Garage::default(),
+        udp_port: None,
+        session: None,
+    };
+    match GameClient::<GamePacket, PACKET_SIZE>::offline(name, lobby) {
+        Ok(client) => {
+            commands.insert_resource(Client(client));
+            commands.insert_resource(Tutorial::default());
+            next_state.set(GameState::InGame);
+        }
+        Err(e) => display_error(commands, next_state, e),
+    }
+}
+
+fn first_run_system(
+    mut commands: Commands,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut preferences: ResMut<Preferences>,
+    strings: Res<Strings>,
+    launch: Res<LaunchArgs>,
+) {
+    // scripted launches go straight into the match
+    if !preferences.tutorial_done && !launch.has_target() {
+        start_tutorial(&mut commands, &mut next_state, &mut preferences, &strings);
+    }
+}
+
+fn spawn(mut commands: Commands, tutorial: Option<Res<Tutorial>>) {
+    if tutorial.is_none() {
+        return;
+    }
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::End,
+                    justify_content: JustifyContent::Center,
+                    padding: UiRect::bottom(Val::Px(40.)),
+                    ..default()
+                },
+                ..default()
+            },
+            TutorialPrompt,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::default());
+        });
+}
+
+fn despawn(mut commands: Commands, prompt: Query<Entity, With<TutorialPrompt>>) {
+    if let Ok(prompt) = prompt.get_single() {
+        commands.entity(prompt).despawn_recursive();
+    }
+}
+
+fn tutorial_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    mut tutorial: ResMut<Tutorial>,
+    mut next_state: ResMut<NextState<GameState>>,
+    settings: Res<Settings>,
+    simulation: Query<(&RenderedSimulation, &GameController)>,
+) {
+    if keyboard.just_pressed(KeyCode::Escape) {
+        next_state.set(GameState::Menu);
+        return;
+    }
+    let Ok((simulation, controller)) = simulation.get_single() else {
+        return;
+    };
+    let player = &controller.0.player;
+    let pos = Controller::get_player_pos(player, &simulation.0);
+    let start = *tutorial.start.get_or_insert(pos);
+
+    let done = match tutorial.step {
+        Step::Move => pos.distance(start) > MOVE_DISTANCE,
+        Step::Gears => player.gear >= 2,
+        Step::Aim => keyboard.pressed(settings.game.aim) && mouse.just_pressed(MouseButton::Left),
+        Step::Projectiles => player.projectile != 0 && player.reload_timer.not_ready(),
+        Step::Dash => player.dash_timer.not_ready(),
+        Step::Destroy => false,
+    };
+    if done {
+        tutorial.step = tutorial.step.next();
+        tutorial.start = None;
+    }
+}
+
+fn prompt_system(
+    tutorial: Res<Tutorial>,
+    strings: Res<Strings>,
+    settings: Res<Settings>,
+    prompt: Query<&Children, With<TutorialPrompt>>,
+    mut texts: Query<&mut Text>,
+) {
+    let Ok(children) = prompt.get_single() else {
+        return;
+    };
+    // the prompts name the keys the player bound
+    let names = settings.game.actions().map(|(action, key)| (action, key_name(key)));
+    let keys: Vec<_> = names.iter().map(|(action, name)| (*action, name.as_str())).collect();
+    let prompt = strings.format(tutorial.step.prompt(), &keys);
+    for child in children.iter() {
+        if let Ok(mut text) = texts.get_mut(*child) {
+            *text = Text::from_section(
+                strings.format(
+                    "tutorial.step",
+                    &[
+                        ("step", &(tutorial.step as usize + 1).to_string()),
+                        ("count", &Step::COUNT.to_string()),
+                        ("prompt", &prompt),
+                    ],
+                ),
+                strings.text_style(40., Color::WHITE),
+            );
+        }
+    }
+}
+
+fn exit_system(mut commands: Commands) {
+    commands.remove_resource::<Tutorial>();
+}
+
+pub struct TutorialPlugin;
+
+impl Plugin for TutorialPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Menu), (exit_system, first_run_system).chain())
+            .add_systems(OnEnter(GameState::InGame), spawn)
+            .add_systems(OnExit(GameState::InGame), despawn)
+            .add_systems(
+                Update,
+                (tutorial_system, prompt_system)
+                    .chain()
+                    .run_if(in_state(GameState::InGame).and_then(resource_exists::<Tutorial>)),
+            );
+    }
+}
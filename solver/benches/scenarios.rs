@@ -0,0 +1,150 @@
+//! Times the ticks of typical scenes, run with `cargo bench -p solver --bench scenarios`. Every scene is built
+//! and settled into a checkpoint once, each batch starts from a copy of it so the runs measure the same ticks.
+
+use bevy::math::{vec2, Vec2};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use solver::{
+    chain_model, model,
+    model::{SHIFT_X, SHIFT_Y},
+    particle::{Kind, Particle, GROUND, METAL, MOTOR, SPIKE},
+    CollisionKernel, Constraint, Link, Solver,
+};
+
+const DT: f32 = 1. / 480.;
+const SETTLE_TICKS: usize = 200;
+const TICKS: usize = 8; // one frame of the client
+const MOTOR_POWER: f32 = 32.; // a tank in the second gear
+
+const SAND: (usize, usize) = (100, 100); // columns and rows, 10k particles
+const PILE: (usize, usize) = (200, 50);
+const BRIDGE: (usize, usize) = (300, 4);
+
+fn rigid(durability: f32, elasticity: f32) -> Link {
+    Link::Rigid { length: 1., durability, elasticity }
+}
+
+/// Solves the scene until it settles, the benchmarks start from here.
+fn checkpoint(mut solver: Solver, ticks: usize) -> Solver {
+    for _ in 0..ticks {
+        solver.solve(DT);
+    }
+    solver
+}
+
+/// Particles of a hexagonal grid with its lower left corner at `origin`.
+fn hex(columns: usize, rows: usize, origin: Vec2, p: Particle) -> Vec<Particle> {
+    (0..rows)
+        .flat_map(|y| (0..columns).map(move |x| p.with_position(origin + SHIFT_X * x as f32 + SHIFT_Y * y as f32)))
+        .collect()
+}
+
+/// Sand falling through the air, no particle touches another one.
+fn sand() -> Solver {
+    let (columns, rows) = SAND;
+    let constraint = Constraint::Box(vec2(-5., -100.), vec2(2. * columns as f32 + 5., 2. * rows as f32 + 5.));
+    let particles: Vec<_> = (0..rows)
+        .flat_map(|y| (0..columns).map(move |x| GROUND.with_position(vec2(x as f32, y as f32) * 2.)))
+        .collect();
+    Solver::new(constraint, &particles, &[]) // settling would land it
+}
+
+/// Settled pile, every particle touches its neighbours.
+fn pile() -> Solver {
+    let (columns, rows) = PILE;
+    let constraint = Constraint::Box(vec2(-5., -5.), vec2((columns + rows / 2) as f32 + 5., rows as f32 + 5.));
+    let particles = hex(columns, rows, Vec2::ZERO, METAL);
+    checkpoint(Solver::new(constraint, &particles, &[]), SETTLE_TICKS)
+}
+
+/// The same pile with the neighbours of a cell checked in SIMD batches.
+fn pile_batched() -> Solver {
+    let mut solver = pile();
+    solver.kernel = CollisionKernel::Batched;
+    solver
+}
+
+/// Strip of linked particles hanging between two fixed ends, sagging under its own weight.
+fn bridge() -> Solver {
+    let (columns, rows) = BRIDGE;
+    let constraint = Constraint::Box(vec2(-5., -50.), vec2((columns + rows / 2) as f32 + 5., 20.));
+    let mut particles = hex(columns, rows, Vec2::ZERO, METAL);
+    for (i, p) in particles.iter_mut().enumerate() {
+        if i % columns < 2 || i % columns >= columns - 2 {
+            p.set_kind(Kind::Static);
+        }
+    }
+    // every particle is linked to the neighbours to the right and above
+    let index = |x: usize, y: usize| y * columns + x;
+    let link = rigid(f32::INFINITY, 10.);
+    let mut connections = vec![];
+    for y in 0..rows {
+        for x in 0..columns {
+            if x + 1 < columns {
+                connections.push((index(x, y), index(x + 1, y), link));
+            }
+            if y + 1 < rows {
+                connections.push((index(x, y), index(x, y + 1), link));
+                if x > 0 {
+                    connections.push((index(x, y), index(x - 1, y + 1), link));
+                }
+            }
+        }
+    }
+    checkpoint(Solver::new(constraint, &particles, &connections), SETTLE_TICKS)
+}
+
+/// Tank driving its treads over the ground, the same build as the players' one without the muzzle.
+#[allow(unused_mut, unused_assignments)]
+fn tank() -> Solver {
+    let link = rigid(f32::INFINITY, 10.);
+    let hull = model! {
+        METAL; link => .hex:false [
+            -4,0; -3,-0.5; -3,0.5; -2,0; -1,-0.5; -1,0.5; 0,0; 0,1; 1,-0.5; 1,0.5; 2,0; 3,-0.5; 3,0.5; 4,0
+        ] + [0=>1,2; 1,2=>3; 3=>4,5; 4,5=>6,7; 6,7=>8,9; 8,9=>10; 10=>11,12; 11,12=>13; 0=>13]
+
+        MOTOR.with_kind(Kind::Motor(MOTOR_POWER)); link => .offset:vec2(0., -3.), .hex:true [
+            -7.5,2; -5.5,0; -2,0; 2,0; 5.5,0; 5.5,2
+        ] + [
+            0 => 1; 1 => 2; 2 => 3; 3 => 4; 4 => 5; 0 => 5; 1 => 4; 0 => 4;
+            .global:true 0 => 0,1; .global:true 7 => 2,3; .global:true 13 => 4,5
+        ]
+    };
+    let tread = chain_model! [
+        METAL; link.with_elasticity(30.); 2=>SPIKE; link.with_elasticity(100.) => .start:vec2(-6., -3. - SHIFT_Y.y);
+        r:12, ur:3, ul:1, l:1, dl:2, l:10, ul:2, l:1, dl:1, dr:3
+    ];
+
+    let constraint = Constraint::Box(vec2(-20., -10.), vec2(180., 30.));
+    let ground = hex(200, 4, vec2(-20., -10.), GROUND.with_kind(Kind::Static));
+    let mut solver = Solver::new(constraint, &ground, &[]);
+    solver.add_model(&(hull + tread), vec2(0., 0.));
+    checkpoint(solver, SETTLE_TICKS)
+}
+
+fn scenarios(c: &mut Criterion) {
+    let scenes: [(&str, fn() -> Solver); 5] = [
+        ("sand", sand),
+        ("tank", tank),
+        ("pile", pile),
+        ("pile_batched", pile_batched),
+        ("bridge", bridge),
+    ];
+    for (name, scene) in scenes {
+        let checkpoint = scene();
+        c.bench_function(name, |b| {
+            b.iter_batched(
+                || checkpoint.clone(),
+                |mut solver| {
+                    for _ in 0..TICKS {
+                        solver.solve(DT);
+                    }
+                    solver
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+}
+
+criterion_group!(benches, scenarios);
+criterion_main!(benches);
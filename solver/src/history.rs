@@ -0,0 +1,69 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{model::PressureBody, particle::Particle, Connection};
+
+/// Everything a solver changes while solving, the constraint and the settings are left out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SolverState {
+    pub(crate) particles: Vec<Particle>,
+    pub(crate) connections: Vec<Connection>,
+    pub(crate) bodies: Vec<PressureBody>,
+    pub(crate) special: Vec<usize>,
+}
+
+/// Ring buffer of the solver states of the last ticks, used to roll back and resimulate.
+#[derive(Debug, Clone)]
+pub struct History {
+    states: VecDeque<(u128, SolverState)>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            states: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Saves the state of the tick, dropping the oldest one when full.
+    /// States of the same or later ticks are dropped too, as they belong to an outdated timeline.
+    pub fn push(&mut self, tick: u128, state: SolverState) {
+        self.states.retain(|(t, _)| *t < tick);
+        if self.states.len() == self.capacity {
+            self.states.pop_front();
+        }
+        self.states.push_back((tick, state));
+    }
+
+    pub fn get(&self, tick: u128) -> Option<&SolverState> {
+        self.states.iter().find(|(t, _)| *t == tick).map(|(_, state)| state)
+    }
+
+    /// Latest saved state no newer than the tick, the one to resimulate from.
+    pub fn latest_at(&self, tick: u128) -> Option<(u128, &SolverState)> {
+        self.states
+            .iter()
+            .rev()
+            .find(|(t, _)| *t <= tick)
+            .map(|(t, state)| (*t, state))
+    }
+
+    pub fn oldest(&self) -> Option<u128> {
+        self.states.front().map(|(t, _)| *t)
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.states.clear();
+    }
+}
@@ -1,20 +1,32 @@
 use std::{
     borrow::{Borrow, BorrowMut},
+    collections::HashSet,
+    f32::consts::{PI, TAU},
     ops::Range,
+    sync::Mutex,
 };
 
-use bevy::math::{vec4, Vec2};
-use particle::IMPULSE_VELOCITY;
+use bevy::math::{vec2, vec4, Vec2};
+use particle::{
+    BURN_TEMPERATURE, EXPLOSIVE_IMPULSE, EXPLOSIVE_RADIUS, IMPULSE_VELOCITY, INCENDIARY_HEAT, MINE_ARM_TICKS,
+    MINE_IMPULSE, MINE_RADIUS,
+};
 use rand::Rng;
-use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use tracing::{instrument, trace_span};
 
+pub mod history;
 pub mod model;
 mod multithreaded;
 pub mod particle;
-pub use model::Model;
+pub use history::{History, SolverState};
+pub use model::{Model, PressureBody};
 mod utils;
-use self::{multithreaded::UnsafeMultithreadedArray, utils::Grid};
+pub use utils::CELL_MAX;
+use self::{
+    multithreaded::UnsafeMultithreadedArray,
+    utils::{Batch, Grid, LANES},
+};
 
 use self::particle::{Kind, Particle};
 pub const MAX: u32 = 200000;
@@ -26,12 +38,28 @@ pub struct Solver {
     pub constraint: Constraint,
     pub particles: Vec<Particle>,
     pub connections: Vec<Connection>,
+    pub bodies: Vec<PressureBody>,
     pub cell_size: f32,
+    pub teams: Vec<usize>, // team of every particle owner
+    pub friendly_fire: bool,
+    pub forces: Forces,
+    pub fields: Vec<ForceField>,
+    pub hazards: Vec<Hazard>,
+    pub kernel: CollisionKernel,
+    pub broken: Vec<BrokenLink>, // links that broke during the last solve
+    events: Vec<SolverEvent>,    // what happened during the last solve, see `drain_events`
     special: Vec<usize>, // list of special particles' indexes
     grid: Grid<usize>,
 }
 
 impl Solver {
+    const BURN_DAMAGE: f32 = 0.002;
+    const FLUID_PRESSURE: f32 = 0.2; // part of the overlap a fluid corrects per tick
+    const FLUID_VISCOSITY: f32 = 0.05; // part of the relative velocity a fluid evens out per tick
+    const QUERY_MARGIN: f32 = Particle::MAX_SPEED + PARTICLE_RADIUS; // how far a particle is from its cell in the grid
+    const HIT_IMPULSE: f32 = 0.5; // impulse of a collision worth an event, a projectile hitting the ground is well above
+    const WAKE_MARGIN: f32 = PARTICLE_RADIUS / 2.; // gap between particles that still count as resting on each other
+
     pub fn new(constraint: Constraint, particles: &[Particle], connections: &[Connection]) -> Self {
         let cell_size = 2. * PARTICLE_RADIUS;
         let bounds = constraint.bounds();
@@ -42,12 +70,28 @@ impl Solver {
             constraint,
             particles: Vec::from(particles),
             connections: Vec::from(connections),
+            bodies: vec![],
             cell_size,
+            teams: vec![],
+            friendly_fire: true,
+            forces: Forces::default(),
+            fields: vec![],
+            hazards: vec![],
+            kernel: CollisionKernel::default(),
+            broken: vec![],
+            events: vec![],
             grid: Grid::new(width, height),
             special: vec![],
         }
+        .with_grid()
     }
 
+    fn with_grid(mut self) -> Self {
+        self.populate_grid();
+        self
+    }
+
+    #[instrument(level = "trace", skip_all)]
     fn populate_grid(&mut self) {
         self.grid.clear();
         for (i, particle) in self.particles.iter().enumerate() {
@@ -56,7 +100,8 @@ impl Solver {
         }
     }
 
-    fn get_cell(&self, pos: Vec2) -> (usize, usize) {
+    /// Column and row of the grid cell the position falls into, positions out of the bounds go to the edge cells.
+    pub fn get_cell(&self, pos: Vec2) -> (usize, usize) {
         let bounds = self.constraint.bounds().0;
         (
             (((pos.x - bounds.x) / self.cell_size).max(0.) as usize + 1).min(self.grid.width - 1),
@@ -64,23 +109,49 @@ impl Solver {
         )
     }
 
+    /// Columns and rows of the grid, the edge cells take the positions out of the bounds.
+    pub fn grid_size(&self) -> (usize, usize) {
+        (self.grid.width, self.grid.height)
+    }
+
+    #[instrument(level = "trace", skip_all, fields(particles = self.particles.len()))]
     pub fn solve(&mut self, dt: f32) {
         // populate the grid with indexes of particles
         // FIXME: biggest bottleneck
         self.populate_grid(); // ISSUE: for some reason it's slow in debug mode
+        self.broken.clear();
+        self.events.clear();
 
         self.resolve_collisions();
         self.resolve_connections();
+        self.resolve_bodies();
         self.resolve_special();
 
-        self.particles.par_iter_mut().for_each(|p| {
-            p.apply_gravity();
+        let _span = trace_span!("update_particles").entered();
+        let constraint = &self.constraint;
+        let forces = &self.forces;
+        let fields = &self.fields;
+        multithreaded::for_each_mut(&mut self.particles, |p| {
+            p.cool_down();
+            p.burn();
+            p.age();
+            if p.rest() {
+                return;
+            }
+            p.apply_forces(forces);
+            for field in fields {
+                if let Some(force) = field.force(p.pos) {
+                    p.accelerate(force / p.mass);
+                }
+            }
             p.update(dt);
-            p.apply_constraint(self.constraint);
+            p.apply_constraint(constraint);
+            p.update_sleep();
         });
     }
 
     // FIXME: this seems messy
+    #[instrument(level = "trace", skip_all)]
     fn resolve_collisions(&mut self) {
         let even: Vec<Range<usize>> = (1..self.grid.width - 1)
             .filter(|i| i % 4 == 1)
@@ -95,51 +166,166 @@ impl Solver {
 
         let particles = UnsafeMultithreadedArray::new(&mut self.particles); // create unsafe array that can be manipulated in threads
         let grid: &Grid<usize> = self.grid.borrow();
+        let teams = (!self.friendly_fire).then_some(self.teams.as_slice());
+        let kernel = self.kernel;
+        // every range records its own events, they're put in the order of the columns afterwards
+        let events = Mutex::new(vec![]);
 
-
-        // WOW THIS IS SOME MESS
         for group in groups {
-            group.par_iter().for_each(|range| {
+            multithreaded::for_each(group, |range| {
+                let mut batch = Batch::default();
+                let mut range_events = vec![];
                 for col in range.clone() {
                     for row in 1..grid.height - 1 {
-                        let c = (col, row);
-                        for &i in grid[c].iter() {
-                            for dc in -1..=1 {
-                                for dr in -1..=1 {
-                                    let adj = (
-                                        (col as isize + dc) as usize,
-                                        (row as isize + dr) as usize,
-                                    );
-                                    for &j in grid[adj].iter() {
-                                        if i == j {
-                                            continue;
-                                        }
-                                        Solver::resolve_collision(
-                                            &mut particles.clone()[i],
-                                            &mut particles.clone()[j],
-                                            i,
-                                            j,
-                                        );
-                                    }
-                                }
+                        let cell = (col, row);
+                        match kernel {
+                            CollisionKernel::Scalar => Solver::collide_cell(particles, grid, teams, cell, &mut range_events),
+                            CollisionKernel::Batched => {
+                                Solver::collide_cell_batched(particles, grid, teams, cell, &mut batch, &mut range_events)
                             }
                         }
                     }
                 }
+                if !range_events.is_empty() {
+                    events.lock().unwrap().push((range.start, range_events));
+                }
             })
         }
+        let mut events = events.into_inner().unwrap();
+        events.sort_by_key(|(col, _)| *col);
+        self.events.extend(events.into_iter().flat_map(|(_, events)| events));
     }
 
+    /// Collides the particles of the cell with the ones of the cell and its neighbours, one pair at a time.
+    fn collide_cell(
+        particles: UnsafeMultithreadedArray<Particle>,
+        grid: &Grid<usize>,
+        teams: Option<&[usize]>,
+        (col, row): (usize, usize),
+        events: &mut Vec<SolverEvent>,
+    ) {
+        for &i in grid[(col, row)].iter() {
+            for dc in -1..=1 {
+                for dr in -1..=1 {
+                    let adj = ((col as isize + dc) as usize, (row as isize + dr) as usize);
+                    for &j in grid[adj].iter() {
+                        Solver::collide_pair(particles, teams, i, j, events);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Same as [`Solver::collide_cell`], but the neighbours are checked [`LANES`] at a time and only the ones
+    /// that overlap go on to the pair. The pairs are visited in the same order.
+    ///
+    /// The neighbours are gathered once per cell, a particle pushed into another one by a collision of the same
+    /// cell is caught on the next tick instead.
+    fn collide_cell_batched(
+        particles: UnsafeMultithreadedArray<Particle>,
+        grid: &Grid<usize>,
+        teams: Option<&[usize]>,
+        cell: (usize, usize),
+        batch: &mut Batch,
+        events: &mut Vec<SolverEvent>,
+    ) {
+        if grid[cell].len == 0 {
+            return;
+        }
+        batch.gather(grid, cell, |j| (particles[j].pos, particles[j].radius));
+        for &i in grid[cell].iter() {
+            for chunk in 0..batch.chunks() {
+                let hits = batch.overlaps(chunk, particles[i].pos, particles[i].radius);
+                for (lane, _) in hits.iter().enumerate().filter(|(_, hit)| **hit) {
+                    Solver::collide_pair(particles, teams, i, batch.index[chunk * LANES + lane], events);
+                }
+            }
+        }
+    }
+
+    /// Collides the two particles unless they're the same one, both sleep or they're on the same team.
+    fn collide_pair(
+        particles: UnsafeMultithreadedArray<Particle>,
+        teams: Option<&[usize]>,
+        i: usize,
+        j: usize,
+        events: &mut Vec<SolverEvent>,
+    ) {
+        if i == j || (particles[i].asleep() && particles[j].asleep()) {
+            return;
+        }
+        if teams.is_some_and(|teams| Solver::friendly(teams, &particles[i], &particles[j])) {
+            return;
+        }
+        let (mut p1, mut p2) = (particles, particles); // copies of the pointer, one for each particle
+        Solver::resolve_collision(&mut p1[i], &mut p2[j], i, j, events);
+    }
+
+    #[instrument(level = "trace", skip_all)]
     fn resolve_connections(&mut self) {
-        for (i, j, link) in self.connections.iter_mut() {
+        let mut loosened = vec![]; // sleeping ends of the broken links
+        for (k, (i, j, link)) in self.connections.iter_mut().enumerate() {
+            if let Link::Angle { pivot, target, stiffness } = *link {
+                Solver::resolve_angle(&mut self.particles, (*i, pivot, *j), target, stiffness);
+                continue;
+            }
             let (i, j) = (usize::min(*i, *j), usize::max(*i, *j));
             let (head, tail) = self.particles.split_at_mut(i + 1);
-            Solver::resolve_connection(&mut head[i], &mut tail[j - i - 1], link);
+            let (p1, p2) = (&mut head[i], &mut tail[j - i - 1]);
+            if p1.asleep() && p2.asleep() {
+                continue;
+            }
+            Solver::wake_pair(p1, p2);
+            let intact = link.durability() >= 0.;
+            Solver::resolve_connection(p1, p2, link);
+            if intact && link.durability() < 0. {
+                let pos = (p1.pos + p2.pos) / 2.;
+                self.broken.push(BrokenLink { index: k, pos });
+                self.events.push(SolverEvent::LinkBreak { index: k, pos });
+                loosened.extend([p1, p2].into_iter().filter(|p| p.asleep()).map(|p| (p.pos, p.radius)));
+            }
+        }
+        self.wake_around(loosened);
+    }
+
+    /// Wakes the sleeping particles touching the circles, then the ones touching those and so on. Whatever rested
+    /// on a broken link or a removed particle falls instead of hanging in the air. Relies on the last populated grid.
+    fn wake_around(&mut self, mut circles: Vec<(Vec2, f32)>) {
+        while let Some((pos, radius)) = circles.pop() {
+            for i in self.particles_in_radius(pos, radius + PARTICLE_RADIUS + Self::WAKE_MARGIN) {
+                let p = &mut self.particles[i];
+                if p.asleep() && !p.is_static() && p.pos.distance(pos) <= radius + p.radius + Self::WAKE_MARGIN {
+                    p.wake();
+                    circles.push((p.pos, p.radius));
+                }
+            }
+        }
+    }
+
+    #[instrument(level = "trace", skip_all)]
+    fn resolve_bodies(&mut self) {
+        for body in self.bodies.iter() {
+            body.resolve(&mut self.particles);
+        }
+    }
+
+    /// Whether one of the particles is a projectile fired by a teammate of the other's owner.
+    fn friendly(teams: &[usize], p1: &Particle, p2: &Particle) -> bool {
+        if !p1.projectile && !p2.projectile {
+            return false;
+        }
+        match (p1.owner, p2.owner) {
+            (Some(o1), Some(o2)) => {
+                let t1 = teams.get(o1 as usize);
+                t1.is_some() && t1 == teams.get(o2 as usize)
+            }
+            _ => false,
         }
     }
 
-    pub fn resolve_collision(p1: &mut Particle, p2: &mut Particle, i: usize, j: usize) {
-        if !p1.kind.can_collide_with(&p2.kind) {
+    /// Pushes the touching particles apart, a hard hit and what their kinds do to each other go to the `events`.
+    pub fn resolve_collision(p1: &mut Particle, p2: &mut Particle, i: usize, j: usize, events: &mut Vec<SolverEvent>) {
+        if !p1.kind.can_collide_with(&p2.kind) || !p1.collides_with(p2) {
             return;
         };
 
@@ -147,24 +333,94 @@ impl Solver {
         let length = v.length();
         let min_length = p1.radius + p2.radius;
         if length < min_length && length > 0.0001 {
-            let overlap = min_length - length;
-            let c1 = p2.mass / (p1.mass + p2.mass);
-            let c2 = 1. - c1;
-            v = v / length * overlap;
-            p1.set_position(p1.pos + v * c1, true);
-            p2.set_position(p2.pos - v * c2, true);
+            Solver::wake_pair(p1, p2);
+            p1.conduct(p2);
+
+            // fluids push back in `resolve_interaction` instead
+            if !p1.is_fluid() && !p2.is_fluid() {
+                let overlap = min_length - length;
+                let shares = Solver::shares(p1, p2, p2.mass / (p1.mass + p2.mass));
+                let (c1, c2) = shares;
+                let normal = v / length;
+                let relative = p1.velocity() - p2.velocity(); // the correction below stops the particles
+                v = normal * overlap;
+                p1.set_position(p1.pos + v * c1, true);
+                p2.set_position(p2.pos - v * c2, true);
+                Solver::resolve_contact(p1, p2, normal, relative, shares);
+
+                let impulse = -relative.dot(normal) * p1.mass * p2.mass / (p1.mass + p2.mass);
+                if impulse > Self::HIT_IMPULSE {
+                    let pos = p2.pos + normal * p2.radius;
+                    events.push(SolverEvent::Collision { i, j, pos, impulse });
+                }
+            }
 
             if !p1.kind.none() {
-                Solver::resolve_interaction(p1, p2, i, j);
+                Solver::resolve_interaction(p1, p2, i, j, events);
             }
             if !p2.kind.none() {
-                Solver::resolve_interaction(p2, p1, j, i);
+                Solver::resolve_interaction(p2, p1, j, i, events);
             }
         }
     }
 
-    pub fn resolve_interaction(p1: &mut Particle, p2: &mut Particle, _i: usize, j: usize) {
+    /// Bounce and friction of two touching particles, the `normal` points from the second one to the first
+    /// and `relative` is the velocity of the first one relative to the second before they were pushed apart.
+    fn resolve_contact(p1: &mut Particle, p2: &mut Particle, normal: Vec2, relative: Vec2, (c1, c2): (f32, f32)) {
+        let approach = relative.dot(normal);
+        let sliding = relative - normal * approach;
+
+        let mut dv = -sliding * (p1.grip() * p2.grip()).sqrt();
+        if approach < 0. {
+            dv -= normal * approach * p1.restitution.max(p2.restitution).clamp(0., 1.);
+        }
+        if dv != Vec2::ZERO {
+            p1.add_velocity(dv * c1);
+            p2.add_velocity(-dv * c2);
+        }
+    }
+
+    /// A particle that hits or pulls a sleeping one hard enough wakes it, otherwise the sleeping one holds still.
+    fn wake_pair(p1: &mut Particle, p2: &mut Particle) {
+        match (p1.asleep(), p2.asleep()) {
+            (true, false) => p1.wake_by(p2),
+            (false, true) => p2.wake_by(p1),
+            _ => (),
+        }
+    }
+
+    /// Parts of a correction taken by each particle, `c1` is the part of the first one if both can move.
+    /// Sleeping particles don't move, like the static ones.
+    fn shares(p1: &Particle, p2: &Particle, c1: f32) -> (f32, f32) {
+        match (p1.asleep(), p2.asleep()) {
+            (true, true) => (0., 0.),
+            (true, false) => (0., 1.),
+            (false, true) => (1., 0.),
+            (false, false) => (c1, 1. - c1),
+        }
+    }
+
+    pub fn resolve_interaction(p1: &mut Particle, p2: &mut Particle, i: usize, j: usize, events: &mut Vec<SolverEvent>) {
         match p1.kind.borrow_mut() {
+            Kind::Fluid => {
+                // the deeper the particles overlap the denser the fluid gets and the harder it pushes,
+                // a fraction of the overlap per tick lets heavy particles sink before they are pushed out
+                let v = p1.pos - p2.pos;
+                let overlap = p1.radius + p2.radius - v.length();
+                let (c1, c2) = Solver::shares(p1, p2, p2.mass / (p1.mass + p2.mass));
+                let v = v.normalize_or_zero() * overlap * Self::FLUID_PRESSURE;
+                p1.set_position(p1.pos + v * c1, true);
+                p2.set_position(p2.pos - v * c2, true);
+
+                let drag = (p2.velocity() - p1.velocity()) * Self::FLUID_VISCOSITY;
+                p1.add_velocity(drag * c1);
+                p2.add_velocity(-drag * c2);
+
+                // the fluid itself doesn't float in the fluid
+                if !p2.is_fluid() {
+                    p2.float_in(p1);
+                }
+            }
             Kind::Motor(acc) => {
                 let v = (p2.pos - p1.pos).normalize_or_zero();
                 let acceleration = v.perp() * *acc;
@@ -179,10 +435,27 @@ impl Solver {
                 p2.set_velocity(v*IMPULSE_VELOCITY);
                 *imp -= IMPULSE_VELOCITY;
                 p1.color *= vec4(0.95, 0.95, 0.95, 1.);
+                events.push(SolverEvent::Impulse { i, j, pos: p1.pos });
             }
             Kind::Sticky(state, con) if *state > 0 && con.is_none() => {
                 *state -= 1;
                 *con = Some(j);
+                events.push(SolverEvent::Sticky { i, j, pos: p1.pos });
+            }
+            Kind::Mine { anchor, .. } if anchor.is_none() && p2.owner.is_none() => {
+                *anchor = Some(j);
+            }
+            Kind::Explosive(hit) => *hit = true,
+            Kind::Pickup { active: true, taker, .. } if taker.is_none() && !p2.projectile => {
+                *taker = p2.owner;
+            }
+            Kind::Incendiary(fuel) if *fuel > 0. => {
+                let heat = INCENDIARY_HEAT.min(*fuel);
+                p2.temperature += heat;
+                *fuel -= heat;
+                if *fuel <= 0. {
+                    p1.kind = Kind::None;
+                }
             }
             _ => (),
         }
@@ -204,20 +477,65 @@ impl Solver {
                     return;
                 };
                 let mut v = p1.pos - p2.pos;
-                let overlap = (*length - v.length()) / 2.;
+                let overlap = *length - v.length();
+                let (c1, c2) = Solver::shares(p1, p2, 0.5);
                 v = overlap * v.normalize_or_zero();
-                p1.set_position(p1.pos + v, true);
-                p2.set_position(p2.pos - v, true);
+                p1.set_position(p1.pos + v * c1, true);
+                p2.set_position(p2.pos - v * c2, true);
 
                 let max_length = *elasticity / 100.;
-                if 2. * overlap.abs() > max_length {
-                    *durability -= 2. * overlap.abs() - max_length; // substract the amount of units max_length was exceeded
+                if overlap.abs() > max_length {
+                    *durability -= overlap.abs() - max_length; // substract the amount of units max_length was exceeded
+                }
+
+                // burning particles slowly eat the link
+                let temperature = p1.temperature.max(p2.temperature);
+                if temperature > BURN_TEMPERATURE {
+                    *durability -= (temperature - BURN_TEMPERATURE) * Self::BURN_DAMAGE;
                 }
             }
+            Link::Rope { length, durability } => {
+                if *durability < 0. {
+                    return;
+                };
+                let mut v = p1.pos - p2.pos;
+                let overlap = *length - v.length();
+                if overlap < 0. {
+                    let (c1, c2) = Solver::shares(p1, p2, 0.5);
+                    v = overlap * v.normalize_or_zero();
+                    p1.set_position(p1.pos + v * c1, true);
+                    p2.set_position(p2.pos - v * c2, true);
+                }
+            }
+            // resolved with the three particles in resolve_angle
+            Link::Angle { .. } => (),
         }
     }
 
+    /// Turns both arms around the pivot toward the target angle, the pivot stays where it is.
+    fn resolve_angle(particles: &mut [Particle], (i, pivot, j): (usize, usize, usize), target: f32, stiffness: f32) {
+        let (p1, center, p2) = (particles[i], particles[pivot], particles[j]);
+        if p1.asleep() && center.asleep() && p2.asleep() {
+            return;
+        }
+        for (k, other) in [(i, center), (pivot, p1), (j, center)] {
+            if particles[k].asleep() {
+                particles[k].wake_by(&other);
+            }
+        }
+        let (arm1, arm2) = (p1.pos - center.pos, p2.pos - center.pos);
+        if arm1.length() < f32::EPSILON || arm2.length() < f32::EPSILON {
+            return;
+        }
+        let error = Link::angle_error(Link::angle(p1.pos, center.pos, p2.pos), target) * stiffness.clamp(0., 1.);
+        let (c1, c2) = Solver::shares(&particles[i], &particles[j], 0.5);
+        particles[i].set_position(center.pos + Vec2::from_angle(error * c1).rotate(arm1), true);
+        particles[j].set_position(center.pos + Vec2::from_angle(-error * c2).rotate(arm2), true);
+    }
+
+    #[instrument(level = "trace", skip_all)]
     pub fn resolve_special(&mut self) {
+        let mut detonated = vec![];
         for i in &self.special {
             let p = &mut self.particles[*i];
             match &mut p.kind {
@@ -233,15 +551,394 @@ impl Solver {
                     ));
                     *con = None;
                 }
+                Kind::Mine { anchor: Some(anchor), arm, .. } if *arm == MINE_ARM_TICKS => {
+                    // bond to the terrain once, then start arming
+                    self.connections.push((
+                        *i,
+                        *anchor,
+                        Link::Rigid {
+                            length: 1.,
+                            durability: 3.,
+                            elasticity: 5.,
+                        },
+                    ));
+                    *arm -= 1;
+                }
+                Kind::Mine { anchor: Some(_), arm, .. } if *arm > 0 => {
+                    *arm -= 1;
+                    if *arm == 0 {
+                        p.color = vec4(1., 0., 0., 1.);
+                    }
+                }
+                Kind::Mine { anchor: Some(_), owner, .. } => {
+                    let (owner, pos) = (*owner, p.pos);
+                    let triggered = self
+                        .particles_in_radius(pos, MINE_RADIUS)
+                        .into_iter()
+                        .any(|j| self.particles[j].owner.is_some_and(|o| self.hostile(owner, o)));
+                    if triggered {
+                        detonated.push((*i, MINE_RADIUS, MINE_IMPULSE));
+                    }
+                }
+                Kind::Explosive(true) => detonated.push((*i, EXPLOSIVE_RADIUS, EXPLOSIVE_IMPULSE)),
                 _ => (),
             }
         }
+
+        for (i, radius, impulse) in detonated {
+            let p = &mut self.particles[i];
+            p.kind = Kind::None;
+            p.color = vec4(0.2, 0.2, 0.2, 1.);
+            let pos = p.pos;
+            self.events.push(SolverEvent::Explosion { index: i, pos, radius });
+            self.apply_explosion(pos, radius, impulse);
+        }
+    }
+
+    /// Whether particles of the owner `o2` can set off traps of the owner `o1`.
+    fn hostile(&self, o1: u8, o2: u8) -> bool {
+        if o1 == o2 {
+            return false;
+        }
+        let t1 = self.teams.get(o1 as usize);
+        self.friendly_fire || t1.is_none() || t1 != self.teams.get(o2 as usize)
+    }
+
+    /// Push particles away from the `center` and damage the links within the `radius`.
+    /// Both the impulse and the damage fall off linearly with the distance.
+    pub fn apply_explosion(&mut self, center: Vec2, radius: f32, impulse: f32) {
+        let falloff = |pos: Vec2| (1. - pos.distance(center) / radius).max(0.);
+        for i in self.particles_in_radius(center, radius) {
+            let p = &mut self.particles[i];
+            let v = (p.pos - center).normalize_or_zero();
+            let vel = v * impulse * falloff(p.pos) / p.mass;
+            p.add_velocity(vel);
+        }
+
+        for k in 0..self.connections.len() {
+            let (i, j, _) = self.connections[k];
+            let damage = impulse * f32::max(falloff(self.particles[i].pos), falloff(self.particles[j].pos));
+            if damage > 0. {
+                self.damage_link(k, damage);
+            }
+        }
+    }
+
+    /// Wears the link out, a link that breaks goes to the broken ones of this tick.
+    pub fn damage_link(&mut self, k: usize, damage: f32) {
+        let (i, j, link) = &mut self.connections[k];
+        let intact = link.durability() >= 0.;
+        *link = link.with_durability(link.durability() - damage);
+        if intact && link.durability() < 0. {
+            let (p1, p2) = (self.particles[*i], self.particles[*j]);
+            let pos = (p1.pos + p2.pos) / 2.;
+            self.broken.push(BrokenLink { index: k, pos });
+            self.events.push(SolverEvent::LinkBreak { index: k, pos });
+            let loosened = [p1, p2].into_iter().filter(|p| p.asleep()).map(|p| (p.pos, p.radius)).collect();
+            self.wake_around(loosened);
+        }
+    }
+
+    /// Wears out the links of the owned particles in the hazards, the strongest hazard a link is in counts.
+    /// Called every few ticks by the game, every owner in a hazard gets one [`SolverEvent::Hazard`].
+    pub fn apply_hazards(&mut self) {
+        if self.hazards.is_empty() {
+            return;
+        }
+        let damage = |p: &Particle| match p.owner {
+            Some(_) => self
+                .hazards
+                .iter()
+                .filter(|hazard| hazard.region.contains(p.pos))
+                .fold(0f32, |damage, hazard| damage.max(hazard.damage)),
+            None => 0.,
+        };
+        let damaged: Vec<(usize, f32, usize)> = self
+            .connections
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, _, link))| link.durability() >= 0.)
+            .filter_map(|(k, (i, j, _))| {
+                let (d1, d2) = (damage(&self.particles[*i]), damage(&self.particles[*j]));
+                (d1.max(d2) > 0.).then_some((k, d1.max(d2), if d1 >= d2 { *i } else { *j }))
+            })
+            .collect();
+
+        let mut owners = vec![];
+        for (k, damage, i) in damaged {
+            self.damage_link(k, damage);
+            let p = &self.particles[i];
+            if !owners.contains(&p.owner) {
+                owners.push(p.owner);
+                self.events.push(SolverEvent::Hazard { index: i, pos: p.pos });
+            }
+        }
+    }
+
+    /// Removes the broken links except the ones the `keep` asks for, returns the new index of every link,
+    /// `None` for the removed ones. Indexes held elsewhere have to be moved, the broken ones are forgotten.
+    pub fn prune_connections(&mut self, keep: impl Fn(usize) -> bool) -> Vec<Option<usize>> {
+        let mut next = 0;
+        let moved: Vec<Option<usize>> = self
+            .connections
+            .iter()
+            .enumerate()
+            .map(|(k, (_, _, link))| match link.durability() < 0. && !keep(k) {
+                true => None,
+                false => {
+                    next += 1;
+                    Some(next - 1)
+                }
+            })
+            .collect();
+        let mut k = 0;
+        self.connections.retain(|_| {
+            k += 1;
+            moved[k - 1].is_some()
+        });
+        self.broken.clear();
+        moved
+    }
+
+    /// Removes the particles the `remove` asks for together with their links, a pressure body goes with any of
+    /// its particles. Returns the new index of every
+    /// particle and of every link, `None` for the removed ones. Indexes held elsewhere have to be moved,
+    /// as after [`Solver::prune_connections`].
+    pub fn remove_particles(&mut self, remove: impl Fn(usize, &Particle) -> bool) -> Removed {
+        let mut next = 0;
+        let particles: Vec<Option<usize>> = self
+            .particles
+            .iter()
+            .enumerate()
+            .map(|(i, p)| match remove(i, p) {
+                true => None,
+                false => {
+                    next += 1;
+                    Some(next - 1)
+                }
+            })
+            .collect();
+        if next == self.particles.len() {
+            return Removed {
+                particles,
+                links: (0..self.connections.len()).map(Some).collect(),
+            };
+        }
+        let gone: Vec<_> = self
+            .particles
+            .iter()
+            .zip(particles.iter())
+            .filter(|(_, moved)| moved.is_none())
+            .map(|(p, _)| (p.pos, p.radius))
+            .collect();
+        let mut i = 0;
+        self.particles.retain(|_| {
+            i += 1;
+            particles[i - 1].is_some()
+        });
+        for p in self.particles.iter_mut() {
+            match &mut p.kind {
+                Kind::Sticky(_, con) => *con = con.and_then(|j| particles[j]),
+                Kind::Mine { anchor, .. } => *anchor = anchor.and_then(|j| particles[j]),
+                _ => (),
+            }
+        }
+        self.special = self.special.iter().filter_map(|i| particles[*i]).collect();
+        self.bodies = self.bodies.iter().filter_map(|body| body.move_particles(|i| particles[i])).collect();
+
+        // links lose their index along with one of their ends
+        let mut next = 0;
+        let links: Vec<Option<usize>> = self
+            .connections
+            .iter()
+            .map(|(i, j, link)| match (particles[*i], particles[*j], link.pivot().map(|k| particles[k])) {
+                (Some(_), Some(_), None | Some(Some(_))) => {
+                    next += 1;
+                    Some(next - 1)
+                }
+                _ => None,
+            })
+            .collect();
+        let mut k = 0;
+        self.connections.retain_mut(|(i, j, link)| {
+            k += 1;
+            let kept = links[k - 1].is_some();
+            if kept {
+                (*i, *j) = (particles[*i].unwrap(), particles[*j].unwrap());
+                *link = link.move_pivot(|pivot| particles[pivot].unwrap());
+            }
+            kept
+        });
+        self.broken = self
+            .broken
+            .iter()
+            .filter_map(|link| links[link.index].map(|index| BrokenLink { index, ..*link }))
+            .collect();
+        self.populate_grid();
+        self.wake_around(gone);
+        Removed { particles, links }
+    }
+
+    /// Indexes of the particles within the `radius` from the `center`, based on the last populated grid.
+    fn particles_in_radius(&self, center: Vec2, radius: f32) -> Vec<usize> {
+        self.grid_particles(center - Vec2::splat(radius), center + Vec2::splat(radius))
+            .filter(|&i| self.particles[i].pos.distance(center) <= radius)
+            .collect()
+    }
+
+    /// Particles in the cells from `min` to `max`, indexes past the last particle are left out.
+    fn grid_particles(&self, min: Vec2, max: Vec2) -> impl Iterator<Item = usize> + '_ {
+        let (bl, tr) = (self.get_cell(min), self.get_cell(max));
+        (bl.0..=tr.0)
+            .flat_map(move |col| (bl.1..=tr.1).map(move |row| (col, row)))
+            .flat_map(|cell| self.grid[cell].iter().copied())
+            .filter(|&i| i < self.particles.len())
+    }
+
+    /// Indexes of the visible particles that overlap the circle, in the order of the grid.
+    ///
+    /// The grid is filled at the start of every solve, so it looks a bit around the circle for the particles
+    /// that moved since. Particles added after the last solve aren't in it yet.
+    pub fn query_circle(&self, center: Vec2, radius: f32) -> Vec<usize> {
+        let reach = Vec2::splat(radius + Self::QUERY_MARGIN);
+        self.grid_particles(center - reach, center + reach)
+            .filter(|&i| {
+                let p = &self.particles[i];
+                !p.kind.hidden() && p.pos.distance(center) <= radius + p.radius
+            })
+            .collect()
+    }
+
+    /// The first visible particle along the ray from `origin` in the direction `dir`, up to `max_dist` away.
+    /// A ray starting inside a particle hits it at its origin. Like [`Solver::query_circle`] it relies on the grid.
+    pub fn raycast(&self, origin: Vec2, dir: Vec2, max_dist: f32) -> Option<Hit> {
+        let dir = dir.try_normalize()?;
+        let reach = Vec2::splat(Self::QUERY_MARGIN);
+        let mut visited = HashSet::new();
+        let mut nearest: Option<Hit> = None;
+        // no particle is further than the corners of the bounds, even for an endless ray
+        let (bl, tr) = self.constraint.bounds();
+        let corners = [bl, tr, vec2(bl.x, tr.y), vec2(tr.x, bl.y)];
+        let span = corners.iter().map(|corner| origin.distance(*corner)).fold(0., f32::max) + Self::QUERY_MARGIN;
+        let mut along = 0.;
+        while along <= max_dist.min(span) + self.cell_size {
+            // particles further along can't come before the nearest hit
+            if nearest.is_some_and(|hit| hit.distance < along - Self::QUERY_MARGIN - self.cell_size) {
+                break;
+            }
+            let point = origin + dir * along.min(max_dist);
+            let (bl, tr) = (self.get_cell(point - reach), self.get_cell(point + reach));
+            for col in bl.0..=tr.0 {
+                for row in bl.1..=tr.1 {
+                    if !visited.insert((col, row)) {
+                        continue;
+                    }
+                    for &i in self.grid[(col, row)].iter().filter(|&&i| i < self.particles.len()) {
+                        let Some(hit) = Self::ray_hit(&self.particles[i], i, origin, dir) else {
+                            continue;
+                        };
+                        if hit.distance <= max_dist && nearest.map_or(true, |nearest| hit.distance < nearest.distance) {
+                            nearest = Some(hit);
+                        }
+                    }
+                }
+            }
+            along += self.cell_size;
+        }
+        nearest
+    }
+
+    /// Where the ray enters the particle, `dir` is normalized.
+    fn ray_hit(p: &Particle, index: usize, origin: Vec2, dir: Vec2) -> Option<Hit> {
+        if p.kind.hidden() {
+            return None;
+        }
+        let offset = origin - p.pos;
+        let b = offset.dot(dir);
+        let c = offset.length_squared() - p.radius * p.radius;
+        if c > 0. && b > 0. {
+            return None; // outside and pointing away
+        }
+        let discriminant = b * b - c;
+        if discriminant < 0. {
+            return None;
+        }
+        let distance = (-b - discriminant.sqrt()).max(0.);
+        let pos = origin + dir * distance;
+        Some(Hit {
+            index,
+            pos,
+            normal: (pos - p.pos).try_normalize().unwrap_or(-dir),
+            distance,
+        })
     }
 
     pub fn size(&self) -> usize {
         self.particles.len()
     }
 
+    /// What happened during the last solve, without taking it.
+    pub fn events(&self) -> &[SolverEvent] {
+        &self.events
+    }
+
+    /// Takes what happened during the last solve, in the order it happened. The next solve starts over, so
+    /// events nobody drains don't pile up.
+    pub fn drain_events(&mut self) -> Vec<SolverEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn snapshot(&self) -> SolverState {
+        SolverState {
+            particles: self.particles.clone(),
+            connections: self.connections.clone(),
+            bodies: self.bodies.clone(),
+            special: self.special.clone(),
+        }
+    }
+
+    /// Brings the solver back to the snapshot, the grid is rebuilt on the next solve.
+    pub fn restore(&mut self, state: &SolverState) {
+        self.particles.clone_from(&state.particles);
+        self.connections.clone_from(&state.connections);
+        self.bodies.clone_from(&state.bodies);
+        self.special.clone_from(&state.special);
+    }
+
+    /// Same as [`Solver::restore`], but the particles keep their colors. Every client picks those for itself,
+    /// e.g. from its team palette, only a particle that turned into another one takes the color of the snapshot.
+    pub fn restore_physics(&mut self, state: &SolverState) {
+        let colors: Vec<_> = self.particles.iter().map(|p| (p.texture, p.owner, p.color)).collect();
+        self.restore(state);
+        for (p, (texture, owner, color)) in self.particles.iter_mut().zip(colors) {
+            if p.texture == texture && p.owner == owner {
+                p.color = color;
+            }
+        }
+    }
+
+    /// FNV-1a hash of the particles and the links, clients running the same simulation must get equal checksums.
+    pub fn checksum(&self) -> u64 {
+        const PRIME: u64 = 0x100000001b3;
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut write = |value: u32| {
+            for byte in value.to_le_bytes() {
+                hash = (hash ^ byte as u64).wrapping_mul(PRIME);
+            }
+        };
+        for p in self.particles.iter() {
+            for value in [p.pos.x, p.pos.y, p.pos_old.x, p.pos_old.y, p.temperature] {
+                write(value.to_bits());
+            }
+        }
+        for (i, j, link) in self.connections.iter() {
+            write(*i as u32);
+            write(*j as u32);
+            write(link.durability().to_bits());
+        }
+        hash
+    }
+
     pub fn add_particle(&mut self, particle: Particle) {
         let ind = self.particles.len();
         self.particles.push(particle);
@@ -264,6 +961,10 @@ impl Solver {
         ))
     }
 
+    pub fn add_rope(&mut self, i: usize, j: usize, length: f32, durability: f32) {
+        self.connections.push((i, j, Link::Rope { length, durability }))
+    }
+
     pub fn add_spring(&mut self, i: usize, j: usize, force: f32) {
         self.connections.push((i, j, Link::Force(force)))
     }
@@ -281,7 +982,13 @@ impl Solver {
             model
                 .connections
                 .iter()
-                .map(|(i, j, link)| (*i + particles_num, *j + particles_num, *link)),
+                .map(|(i, j, link)| (*i + particles_num, *j + particles_num, link.move_pivot(|k| k + particles_num))),
+        );
+        self.bodies.extend(
+            model
+                .bodies
+                .iter()
+                .filter_map(|body| body.move_particles(|i| Some(i + particles_num))),
         );
 
         // add special particles
@@ -300,6 +1007,130 @@ pub fn rnd_in_bounds(bounds: (Vec2, Vec2), margin: f32) -> Vec2 {
     )
 }
 
+/// A link that broke, its index is valid until the links are pruned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BrokenLink {
+    pub index: usize,
+    pub pos: Vec2, // middle of the link when it broke
+}
+
+/// Something worth a sound, an effect or a score that happened during a solve, see [`Solver::drain_events`].
+/// The indexes are the ones of that solve.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SolverEvent {
+    Collision { i: usize, j: usize, pos: Vec2, impulse: f32 }, // a hard hit, the impulse is the momentum it took away
+    LinkBreak { index: usize, pos: Vec2 },
+    Impulse { i: usize, j: usize, pos: Vec2 }, // the impulse particle `i` pushed `j`
+    Sticky { i: usize, j: usize, pos: Vec2 },  // the sticky particle `i` stuck to `j`
+    Explosion { index: usize, pos: Vec2, radius: f32 },
+    Hazard { index: usize, pos: Vec2 }, // particle `i` of an owner wore its links out in a hazard
+}
+
+/// New indexes after [`Solver::remove_particles`], `None` for the removed particles and links.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Removed {
+    pub particles: Vec<Option<usize>>,
+    pub links: Vec<Option<usize>>,
+}
+
+/// Where a ray first touched a particle, see [`Solver::raycast`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    pub index: usize,
+    pub pos: Vec2,
+    pub normal: Vec2, // pointing out of the particle
+    pub distance: f32, // from the origin of the ray
+}
+
+/// How [`Solver::solve`] finds the touching particles. Both resolve the pairs in the same order, the batched one
+/// skips the pairs that don't touch [`LANES`] at a time. It reads the positions once per cell though, so a pair
+/// pushed into touching by the same cell collides a tick later than with the scalar one and the results drift
+/// apart. Clients of one game have to use the same kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollisionKernel {
+    #[default]
+    Scalar,
+    Batched,
+}
+
+/// Forces acting on every particle of the map.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Forces {
+    pub gravity: Vec2, // acceleration, the same for any mass
+    pub wind: Vec2,    // force, light particles drift further
+}
+
+impl Default for Forces {
+    fn default() -> Self {
+        Self {
+            gravity: vec2(0., -70.),
+            wind: Vec2::ZERO,
+        }
+    }
+}
+
+/// Area of a [`ForceField`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Region {
+    Rect { min: Vec2, max: Vec2 },
+    Circle { center: Vec2, radius: f32 },
+}
+
+impl Region {
+    pub fn contains(&self, pos: Vec2) -> bool {
+        match *self {
+            Self::Rect { min, max } => pos.cmpge(min).all() && pos.cmple(max).all(),
+            Self::Circle { center, radius } => pos.distance_squared(center) <= radius * radius,
+        }
+    }
+
+    pub fn center(&self) -> Vec2 {
+        match *self {
+            Self::Rect { min, max } => (min + max) / 2.,
+            Self::Circle { center, .. } => center,
+        }
+    }
+}
+
+/// How a [`ForceField`] pushes the particles inside it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Push {
+    Direction(Vec2), // the same force everywhere in the region
+    Radial(f32),     // force away from the center of the region, toward it when negative
+}
+
+/// Force acting on the particles in a region of the map, like a fan, an updraft or a conveyor.
+/// Just like the wind it's a force, light particles are pushed further.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ForceField {
+    pub region: Region,
+    pub push: Push,
+}
+
+impl ForceField {
+    /// Force on a particle at `pos`, `None` outside of the region.
+    pub fn force(&self, pos: Vec2) -> Option<Vec2> {
+        if !self.region.contains(pos) {
+            return None;
+        }
+        Some(match self.push {
+            Push::Direction(force) => force,
+            Push::Radial(strength) => (pos - self.region.center()).normalize_or_zero() * strength,
+        })
+    }
+}
+
+/// Region of the map wearing out the links of the models that enter it, like water or lava pits.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Hazard {
+    pub region: Region,
+    pub damage: f32, // durability taken from every link each time the hazards are applied
+}
+
+impl Hazard {
+    pub const DEFAULT_DAMAGE: f32 = 0.5;
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum Link {
     Force(f32), // force
@@ -308,12 +1139,23 @@ pub enum Link {
         durability: f32,
         elasticity: f32,
     },
+    Rope {
+        length: f32, // max length, the rope doesn't push
+        durability: f32,
+    },
+    /// Holds the angle at the `pivot` between the arms to the two particles of the connection, counterclockwise
+    /// from the first arm in radians. The `stiffness` from 0 to 1 is the part of the error corrected every tick.
+    Angle {
+        pivot: usize,
+        target: f32,
+        stiffness: f32,
+    },
 }
 
 impl Link {
     pub fn with_length(&self, length: f32) -> Self {
         match self {
-            Self::Force(_) => *self,
+            Self::Force(_) | Self::Angle { .. } => *self,
             Self::Rigid {
                 length: _,
                 durability,
@@ -323,12 +1165,16 @@ impl Link {
                 durability: *durability,
                 elasticity: *elasticity,
             },
+            Self::Rope { durability, .. } => Self::Rope {
+                length,
+                durability: *durability,
+            },
         }
     }
 
     pub fn with_durability(&self, durability: f32) -> Self {
         match self {
-            Self::Force(_) => *self,
+            Self::Force(_) | Self::Angle { .. } => *self,
             Self::Rigid {
                 length,
                 durability: _,
@@ -338,12 +1184,16 @@ impl Link {
                 durability,
                 elasticity: *elasticity,
             },
+            Self::Rope { length, .. } => Self::Rope {
+                length: *length,
+                durability,
+            },
         }
     }
 
     pub fn with_elasticity(&self, elasticity: f32) -> Self {
         match self {
-            Self::Force(_) => *self,
+            Self::Force(_) | Self::Rope { .. } | Self::Angle { .. } => *self,
             Self::Rigid {
                 length,
                 durability,
@@ -363,6 +1213,7 @@ impl Link {
                 durability,
                 elasticity: _,
             } => *durability,
+            Self::Rope { durability, .. } => *durability,
             _ => 1.,
         }
     }
@@ -377,17 +1228,754 @@ impl Link {
             _ => 100.,
         }
     }
+
+    /// The third particle of an angle link.
+    pub fn pivot(&self) -> Option<usize> {
+        match self {
+            Self::Angle { pivot, .. } => Some(*pivot),
+            _ => None,
+        }
+    }
+
+    /// The link with its pivot moved to another index, for links of models placed in a solver or particles removed.
+    pub fn move_pivot(&self, f: impl FnOnce(usize) -> usize) -> Self {
+        match self {
+            Self::Angle { pivot, target, stiffness } => Self::Angle {
+                pivot: f(*pivot),
+                target: *target,
+                stiffness: *stiffness,
+            },
+            _ => *self,
+        }
+    }
+
+    /// Angle at the `pivot` from the arm to `p1` to the arm to `p2`, counterclockwise from -PI to PI.
+    pub fn angle(p1: Vec2, pivot: Vec2, p2: Vec2) -> f32 {
+        (p1 - pivot).angle_between(p2 - pivot)
+    }
+
+    /// How far the angle has turned past the target, the shortest way around.
+    pub fn angle_error(angle: f32, target: f32) -> f32 {
+        (angle - target + PI).rem_euclid(TAU) - PI
+    }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Constraint {
     Box(Vec2, Vec2), // Rectangle, bottom-left and top-right corners
+    Circle { center: Vec2, radius: f32 },
+    Polygon(Polygon),
+}
+
+/// Convex polygon, the vertices may go in either direction. Its edges are worked out once here
+/// rather than for every particle on every substep, only the vertices are saved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<Vec2>", into = "Vec<Vec2>")]
+pub struct Polygon {
+    vertices: Vec<Vec2>,
+    edges: Vec<(Vec2, Vec2)>, // a point on the edge and the normal pointing inside
+}
+
+impl Polygon {
+    pub fn new(vertices: Vec<Vec2>) -> Self {
+        let area: f32 = (0..vertices.len())
+            .map(|i| vertices[i].perp_dot(vertices[(i + 1) % vertices.len()]))
+            .sum();
+        let winding = area.signum();
+        let edges = (0..vertices.len())
+            .map(|i| {
+                let (a, b) = (vertices[i], vertices[(i + 1) % vertices.len()]);
+                (a, winding * (b - a).perp().normalize_or_zero())
+            })
+            .collect();
+        Self { vertices, edges }
+    }
+
+    pub fn vertices(&self) -> &[Vec2] {
+        &self.vertices
+    }
+}
+
+impl From<Vec<Vec2>> for Polygon {
+    fn from(vertices: Vec<Vec2>) -> Self {
+        Self::new(vertices)
+    }
+}
+
+impl From<Polygon> for Vec<Vec2> {
+    fn from(polygon: Polygon) -> Self {
+        polygon.vertices
+    }
 }
 
 impl Constraint {
-    pub const fn bounds(&self) -> (Vec2, Vec2) {
+    pub fn bounds(&self) -> (Vec2, Vec2) {
+        match self {
+            Constraint::Box(bl, tr) => (*bl, *tr),
+            Constraint::Circle { center, radius } => (*center - *radius, *center + *radius),
+            Constraint::Polygon(polygon) => polygon.vertices.iter().fold(
+                (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+                |(bl, tr), v| (bl.min(*v), tr.max(*v)),
+            ),
+        }
+    }
+
+    /// Polygons need at least three vertices, circles and boxes a positive size.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Constraint::Box(bl, tr) => bl.x < tr.x && bl.y < tr.y,
+            Constraint::Circle { radius, .. } => *radius > 0.,
+            Constraint::Polygon(polygon) => polygon.vertices.len() >= 3,
+        }
+    }
+
+    /// Whether a particle of the radius fits at the position.
+    pub fn contains(&self, pos: Vec2, radius: f32) -> bool {
         match self {
-            &Constraint::Box(bl, tr) => (bl, tr),
+            Constraint::Box(bl, tr) => {
+                pos.cmpge(*bl + radius).all() && pos.cmple(*tr - radius).all()
+            }
+            Constraint::Circle { center, radius: r } => pos.distance(*center) <= r - radius,
+            Constraint::Polygon(polygon) => {
+                polygon.edges.iter().all(|(a, normal)| (pos - *a).dot(*normal) >= radius)
+            }
+        }
+    }
+
+    /// Closest position of a particle of the radius inside the constraint.
+    pub fn clamp(&self, pos: Vec2, radius: f32) -> Vec2 {
+        match self {
+            Constraint::Box(bl, tr) => vec2(
+                pos.x.max(bl.x + radius).min(tr.x - radius),
+                pos.y.max(bl.y + radius).min(tr.y - radius),
+            ),
+            Constraint::Circle { center, radius: r } => {
+                let offset = pos - *center;
+                let max = (r - radius).max(0.);
+                match offset.length() > max {
+                    true => *center + offset.normalize_or_zero() * max,
+                    false => pos,
+                }
+            }
+            Constraint::Polygon(polygon) => polygon.edges.iter().fold(pos, |pos, (a, normal)| {
+                let depth = radius - (pos - *a).dot(*normal);
+                match depth > 0. {
+                    true => pos + *normal * depth,
+                    false => pos,
+                }
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::vec2;
+
+    use super::*;
+    use crate::particle::{
+        team_group, DEFAULT_GROUP, GROUND, METAL, PROJECTILE_EXPLOSIVE, PROJECTILE_HEAVY, PROJECTILE_INCENDIARY,
+        PROJECTILE_MINE, SLEEP_TICKS, WATER, WOOD,
+    };
+
+    #[test]
+    fn explosion_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let particles = [METAL.with_position(vec2(1., 0.)), METAL.with_position(vec2(2., 0.))];
+        let link = Link::Rigid { length: 1., durability: 5., elasticity: 10. };
+        let mut solver = Solver::new(constraint, &particles, &[(0, 1, link)]);
+        solver.populate_grid();
+        solver.apply_explosion(vec2(0., 0.), 4., 2.);
+
+        assert!(solver.particles[0].velocity().x > solver.particles[1].velocity().x);
+        assert!(solver.particles[1].velocity().x > 0.);
+        assert!(solver.connections[0].2.durability() < 5.);
+    }
+
+    #[test]
+    fn mine_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let mut solver = Solver::new(constraint, &[GROUND.with_position(vec2(0., -19.5))], &[]);
+        solver.add_particle(PROJECTILE_MINE.with_position(vec2(0., -18.5)));
+        for _ in 0..MINE_ARM_TICKS + 2 {
+            solver.solve(1. / 480.);
+        }
+        assert_eq!(solver.connections.len(), 1);
+        let Kind::Mine { arm, anchor, .. } = solver.particles[1].kind else {
+            panic!("mine detonated without a target");
+        };
+        assert_eq!((arm, anchor), (0, Some(0)));
+
+        solver.add_particle(METAL.with_position(vec2(2., -19.)).with_owner(1));
+        solver.solve(1. / 480.);
+        solver.solve(1. / 480.);
+        assert!(solver.particles[1].kind.none());
+    }
+
+    #[test]
+    fn explosive_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let particles = [GROUND.with_position(vec2(0., -19.5)), GROUND.with_position(vec2(1., -19.5))];
+        let link = Link::Rigid { length: 1., durability: 5., elasticity: 10. };
+        let mut solver = Solver::new(constraint, &particles, &[(0, 1, link)]);
+        solver.add_particle(PROJECTILE_EXPLOSIVE.with_position(vec2(0., -18.4)));
+        solver.solve(1. / 480.);
+        assert_eq!(solver.particles[2].kind, Kind::Explosive(false));
+
+        for _ in 0..64 {
+            solver.solve(1. / 480.);
+        }
+        assert!(solver.particles[2].kind.none());
+        assert!(solver.connections[0].2.durability() < 5.);
+    }
+
+    #[test]
+    fn incendiary_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let particles = [GROUND.with_position(vec2(0., -19.5)), GROUND.with_position(vec2(1., -19.5))];
+        let link = Link::Rigid { length: 1., durability: 1., elasticity: 10. };
+        let mut solver = Solver::new(constraint, &particles, &[(0, 1, link)]);
+        solver.add_particle(PROJECTILE_INCENDIARY.with_position(vec2(0., -18.6)));
+        for _ in 0..64 {
+            solver.solve(1. / 480.);
+        }
+
+        assert!(solver.particles[0].is_burning());
+        assert!(solver.connections[0].2.durability() < 1.);
+    }
+
+    #[test]
+    fn wood_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let mut wood = WOOD.with_position(vec2(0., -19.5));
+        wood.temperature = 2. * BURN_TEMPERATURE;
+
+        // heat flows into the colder particle
+        let (mut hot, mut cold) = (wood, WOOD);
+        hot.conduct(&mut cold);
+        assert!(cold.temperature > 0. && hot.temperature < wood.temperature);
+
+        // the fire keeps itself going on the fuel of the wood, then the wood is left charred
+        let mut solver = Solver::new(constraint, &[wood], &[]);
+        for _ in 0..1000 {
+            solver.solve(1. / 480.);
+        }
+        assert!(solver.particles[0].is_burning());
+        for _ in 0..3000 {
+            solver.solve(1. / 480.);
+        }
+        assert!(solver.particles[0].kind.none());
+    }
+
+    #[test]
+    fn friendly_fire_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let mut solver = Solver::new(constraint, &[METAL.with_position(vec2(0., 0.)).with_owner(0)], &[]);
+        solver.add_particle(PROJECTILE_HEAVY.with_position(vec2(0.5, 0.)).with_owner(1));
+        solver.teams = vec![0, 0];
+        solver.friendly_fire = false;
+        solver.solve(1. / 480.);
+        assert_eq!(solver.particles[0].pos.x, 0.);
+
+        solver.teams = vec![0, 1];
+        solver.solve(1. / 480.);
+        assert!(solver.particles[0].pos.x < 0.);
+    }
+
+    #[test]
+    fn constraint_test() {
+        let circle = Constraint::Circle { center: vec2(0., 0.), radius: 10. };
+        // clockwise, the winding should not matter
+        let triangle = Constraint::Polygon(Polygon::new(vec![vec2(-10., 0.), vec2(0., 10.), vec2(10., 0.)]));
+        assert!(circle.contains(vec2(0., 9.), 0.5) && !circle.contains(vec2(0., 9.8), 0.5));
+        assert!(triangle.contains(vec2(0., 1.), 0.5) && !triangle.contains(vec2(0., -1.), 0.5));
+        assert!(!Constraint::Polygon(Polygon::new(vec![vec2(0., 0.), vec2(1., 1.)])).is_valid());
+
+        for constraint in [circle, triangle] {
+            let particles = [METAL.with_position(vec2(-30., 5.)), METAL.with_position(vec2(0., -30.))];
+            let mut solver = Solver::new(constraint.clone(), &particles, &[]);
+            for _ in 0..8 {
+                solver.solve(1. / 480.);
+            }
+            assert!(solver.particles.iter().all(|p| constraint.contains(p.pos, p.radius - 1e-3)));
+        }
+    }
+
+    #[test]
+    fn checksum_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let particles: Vec<_> = (0..40).map(|i| METAL.with_position(vec2(i as f32 % 8., (i / 8) as f32))).collect();
+        let mut first = Solver::new(constraint, &particles, &[]);
+        let mut second = first.clone();
+        assert_eq!(first.checksum(), second.checksum());
+
+        for _ in 0..64 {
+            first.solve(1. / 480.);
+            second.solve(1. / 480.);
+        }
+        assert_eq!(first.checksum(), second.checksum());
+
+        second.particles[0].pos.x += 0.001;
+        assert_ne!(first.checksum(), second.checksum());
+    }
+
+    #[test]
+    fn rollback_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let mut solver = Solver::new(constraint, &[GROUND.with_position(vec2(0., -19.5))], &[]);
+        solver.add_particle(PROJECTILE_MINE.with_position(vec2(0., -18.5)));
+        let mut history = History::new(4);
+        for tick in 0..8 {
+            history.push(tick, solver.snapshot());
+            solver.solve(1. / 480.);
+        }
+        let checksum = solver.checksum();
+        assert_eq!((history.len(), history.oldest()), (4, Some(4)));
+
+        // a late packet for the tick 5 arrives, resimulate from there
+        let (tick, state) = history.latest_at(5).unwrap();
+        solver.restore(&state.clone());
+        for tick in tick..8 {
+            history.push(tick, solver.snapshot());
+            solver.solve(1. / 480.);
+        }
+        assert_eq!(solver.checksum(), checksum);
+        assert_eq!(history.len(), 4);
+        assert!(history.get(3).is_none() && history.get(7).is_some());
+    }
+
+    #[test]
+    fn static_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let anchor = GROUND.with_position(vec2(0., 0.)).with_kind(Kind::Static);
+        let particles = [anchor, METAL.with_position(vec2(0.6, 0.)), METAL.with_position(vec2(0., -3.))];
+        let link = Link::Rigid { length: 2., durability: 5., elasticity: 10. };
+        let mut solver = Solver::new(constraint, &particles, &[(0, 2, link)]);
+        solver.populate_grid();
+        solver.apply_explosion(vec2(-1., 0.), 4., 2.);
+        for _ in 0..64 {
+            solver.solve(1. / 480.);
+        }
+
+        assert_eq!(solver.particles[0].pos, vec2(0., 0.));
+        assert!(solver.particles[1].pos.distance(vec2(0., 0.)) >= 1. - 1e-3);
+        assert!(solver.particles[2].pos.distance(vec2(0., 0.)) < 2.1);
+    }
+
+    #[test]
+    fn fluid_test() {
+        // a pool four particles deep with room to flow aside, and a metal particle dropped on it
+        let settle = |base: Particle| {
+            let constraint = Constraint::Box(vec2(-6., -10.), vec2(6., 10.));
+            let mut particles: Vec<_> = (0..32)
+                .map(|i| base.with_position(vec2(-3.5 + (i % 8) as f32, -9.5 + (i / 8) as f32)))
+                .collect();
+            particles.push(METAL.with_position(vec2(0., 0.)));
+            let mut solver = Solver::new(constraint, &particles, &[]);
+            for _ in 0..4000 {
+                solver.solve(1. / 480.);
+            }
+            solver.particles[32].pos.y
+        };
+
+        // the metal stays on the ground but sinks to the bottom of the water
+        assert!(settle(GROUND) > -8.5);
+        assert!(settle(WATER) < -9.);
+    }
+
+    #[test]
+    fn buoyancy_test() {
+        let constraint = Constraint::Box(vec2(-6., -10.), vec2(6., 10.));
+        let mut particles: Vec<_> = (0..32)
+            .map(|i| WATER.with_position(vec2(-3.5 + (i % 8) as f32, -9.5 + (i / 8) as f32)))
+            .collect();
+        // wood at the bottom of the pool, the water it takes the place of is dropped on top
+        particles[3] = WOOD.with_position(vec2(-0.5, -9.5));
+        particles.push(WATER.with_position(vec2(0., 0.)));
+        let mut solver = Solver::new(constraint, &particles, &[]);
+
+        let mut wood = WOOD;
+        wood.float_in(&WATER);
+        assert!(wood.buoyancy > 0. && wood.buoyancy < WATER.density() / WOOD.density());
+
+        for _ in 0..4000 {
+            solver.solve(1. / 480.);
+        }
+        // it comes up to the surface of the water, four particles deep
+        assert!(solver.particles[3].pos.y > -7., "{}", solver.particles[3].pos.y);
+        assert_eq!(solver.particles[3].buoyancy, 0.);
+    }
+
+    #[test]
+    fn sleep_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let mut solver = Solver::new(constraint, &[GROUND.with_position(vec2(0., -19.5))], &[]);
+        for _ in 0..SLEEP_TICKS + 1 {
+            solver.solve(1. / 480.);
+        }
+        assert!(solver.particles[0].asleep());
+
+        // a slow touch doesn't move it, a hit wakes it up
+        solver.add_particle(METAL.with_position(vec2(-0.95, -19.5)));
+        solver.solve(1. / 480.);
+        assert!(solver.particles[0].asleep());
+        assert_eq!(solver.particles[0].pos, vec2(0., -19.5));
+
+        solver.particles[1].set_velocity(vec2(0.1, 0.));
+        for _ in 0..8 {
+            solver.solve(1. / 480.);
+        }
+        assert!(!solver.particles[0].asleep());
+        assert!(solver.particles[0].pos.x > 0.);
+    }
+
+    #[test]
+    fn wake_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let floor = GROUND.with_position(vec2(0., -10.)).with_kind(Kind::Static);
+        let stack: Vec<Particle> = (0..4).map(|y| GROUND.with_position(vec2(0., -9. + y as f32))).collect();
+        let mut solver = Solver::new(constraint.clone(), &[&[floor][..], &stack].concat(), &[]);
+        solver.particles.iter_mut().for_each(|p| p.sleep = SLEEP_TICKS);
+
+        // the stack falls once the particle at its bottom is gone
+        let top = solver.particles[4].pos.y;
+        solver.remove_particles(|i, _| i == 1);
+        assert!(solver.particles[1..].iter().all(|p| !p.asleep()));
+        for _ in 0..480 {
+            solver.solve(1. / 480.);
+        }
+        assert!(solver.particles[3].pos.y < top - 0.5, "{}", solver.particles[3].pos.y);
+
+        // the ends of a broken link wake up
+        let link = Link::Rigid { length: 1., durability: 1., elasticity: 10. };
+        let mut solver = Solver::new(constraint, &stack[..2], &[(0, 1, link)]);
+        solver.particles.iter_mut().for_each(|p| p.sleep = SLEEP_TICKS);
+        solver.populate_grid();
+        solver.damage_link(0, 2.);
+        assert!(solver.particles.iter().all(|p| !p.asleep()));
+    }
+
+    #[test]
+    fn contact_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let floor: Vec<Particle> = (-10..=10)
+            .map(|x| GROUND.with_position(vec2(x as f32, -10.)).with_kind(Kind::Static))
+            .collect();
+
+        // a particle pushed along the floor slides further without friction
+        let slide = |friction: f32| {
+            let slider = Particle { friction, ..GROUND }.with_position(vec2(-5., -9.)).with_velocity(vec2(0.05, 0.));
+            let mut solver = Solver::new(constraint.clone(), &[&floor[..], &[slider]].concat(), &[]);
+            for _ in 0..200 {
+                solver.solve(1. / 480.);
+            }
+            solver.particles[floor.len()].pos.x
+        };
+        assert!(slide(0.) > slide(1.) + 1.);
+
+        // and bounces off it only with restitution
+        let bounce = |restitution: f32| {
+            let ball = Particle { restitution, ..METAL }.with_position(vec2(0., -8.9)).with_velocity(vec2(0., -0.1));
+            let mut solver = Solver::new(constraint.clone(), &[&floor[..], &[ball]].concat(), &[]);
+            for _ in 0..8 {
+                solver.solve(1. / 480.);
+            }
+            solver.particles[floor.len()].velocity().y
+        };
+        assert!(bounce(1.) > bounce(0.) + 0.05);
+    }
+
+    #[test]
+    fn forces_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let particles = [GROUND.with_position(vec2(-5., 0.)), METAL.with_position(vec2(5., 0.))];
+        let mut solver = Solver::new(constraint, &particles, &[]);
+        solver.forces = Forces { gravity: Vec2::ZERO, wind: vec2(10., 0.) };
+        for _ in 0..100 {
+            solver.solve(1. / 480.);
         }
+
+        // nothing falls and the lighter particle drifts further
+        assert!(solver.particles.iter().all(|p| p.pos.y == 0.));
+        assert!(solver.particles[0].pos.x + 5. > solver.particles[1].pos.x - 5.);
+        assert!(solver.particles[1].pos.x > 5.);
+    }
+
+    #[test]
+    fn fields_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let particles = [
+            GROUND.with_position(vec2(-5., 0.)),
+            GROUND.with_position(vec2(5., 0.)),
+            GROUND.with_position(vec2(12., -10.)),
+        ];
+        let mut solver = Solver::new(constraint, &particles, &[]);
+        solver.forces.gravity = Vec2::ZERO;
+        solver.fields = vec![
+            ForceField {
+                region: Region::Rect { min: vec2(-10., -10.), max: vec2(0., 10.) },
+                push: Push::Direction(vec2(0., 50.)),
+            },
+            ForceField {
+                region: Region::Circle { center: vec2(10., -10.), radius: 5. },
+                push: Push::Radial(50.),
+            },
+        ];
+        for _ in 0..100 {
+            solver.solve(1. / 480.);
+        }
+
+        // the updraft lifts the first one, the second is outside of both fields
+        assert!(solver.particles[0].pos.y > 0.);
+        assert_eq!(solver.particles[1].pos, vec2(5., 0.));
+        // and the third is pushed away from the center of the circle
+        assert!(solver.particles[2].pos.x > 12.);
+        assert_eq!(solver.particles[2].pos.y, -10.);
+    }
+
+    #[test]
+    fn hazards_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let owned = |pos: Vec2| Particle { owner: Some(0), ..METAL.with_position(pos) };
+        let particles = [
+            owned(vec2(-5., 0.)),
+            owned(vec2(-4., 0.)),
+            owned(vec2(5., 0.)),
+            owned(vec2(6., 0.)),
+            GROUND.with_position(vec2(-5., 5.)),
+            GROUND.with_position(vec2(-4., 5.)),
+        ];
+        let link = Link::Rigid { length: 1., durability: 1., elasticity: 100. };
+        let connections = [(0, 1, link), (2, 3, link), (4, 5, link)];
+        let mut solver = Solver::new(constraint, &particles, &connections);
+        solver.hazards = vec![Hazard {
+            region: Region::Rect { min: vec2(-10., -10.), max: vec2(0., 10.) },
+            damage: 0.75,
+        }];
+
+        // only the owned particles in the hazard lose their link, and their owner is reported once
+        solver.apply_hazards();
+        assert_eq!(solver.connections[0].2.durability(), 0.25);
+        assert_eq!(solver.connections[1].2.durability(), 1.);
+        assert_eq!(solver.connections[2].2.durability(), 1.);
+        assert_eq!(solver.events().len(), 1);
+        solver.apply_hazards();
+        assert!(solver.connections[0].2.durability() < 0.);
+        assert!(solver.events().iter().any(|event| matches!(event, SolverEvent::LinkBreak { index: 0, .. })));
+    }
+
+    #[test]
+    fn query_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let particles: Vec<Particle> = (0..5).map(|i| METAL.with_position(vec2(i as f32 * 3., 0.))).collect();
+        let solver = Solver::new(constraint, &particles, &[]);
+
+        let mut found = solver.query_circle(vec2(4.5, 0.), 1.2);
+        found.sort();
+        assert_eq!(found, vec![1, 2]);
+        assert!(solver.query_circle(vec2(0., 10.), 1.).is_empty());
+
+        // the first particle along the ray, from the side facing it
+        let hit = solver.raycast(vec2(-10., 0.), vec2(1., 0.), 100.).unwrap();
+        assert_eq!(hit.index, 0);
+        assert!((hit.distance - (10. - METAL.radius)).abs() < 1e-4);
+        assert!(hit.normal.abs_diff_eq(vec2(-1., 0.), 1e-4));
+        let hit = solver.raycast(vec2(30., 0.), vec2(-1., 0.), f32::INFINITY).unwrap();
+        assert_eq!(hit.index, 4);
+
+        // too short, pointing away or passing by
+        assert!(solver.raycast(vec2(-10., 0.), vec2(1., 0.), 5.).is_none());
+        assert!(solver.raycast(vec2(-10., 0.), vec2(-1., 0.), 100.).is_none());
+        assert!(solver.raycast(vec2(-10., 2.), vec2(1., 0.), 100.).is_none());
+    }
+
+    #[test]
+    fn remove_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let mut particles: Vec<Particle> = (0..4).map(|i| METAL.with_position(vec2(i as f32, 0.))).collect();
+        particles[1] = particles[1].with_lifetime(2);
+        let link = Link::Rigid { length: 1., durability: 1., elasticity: 10. };
+        let connections = [(0, 1, link), (2, 3, link)];
+        let mut solver = Solver::new(constraint, &particles, &connections);
+        solver.add_particle(PROJECTILE_MINE.with_position(vec2(3., 1.)).with_kind(Kind::Mine {
+            owner: 0,
+            arm: MINE_ARM_TICKS - 1, // already bonded, so it doesn't link to the anchor
+            anchor: Some(3),
+        }));
+
+        solver.solve(1. / 480.);
+        assert!(!solver.particles[1].expired());
+        solver.solve(1. / 480.);
+        assert!(solver.particles[1].expired());
+
+        // the expired particle leaves with its link, the rest move down
+        let removed = solver.remove_particles(|_, p| p.expired());
+        assert_eq!(removed.particles, vec![Some(0), None, Some(1), Some(2), Some(3)]);
+        assert_eq!(removed.links, vec![None, Some(0)]);
+        assert_eq!(solver.particles.len(), 4);
+        assert_eq!((solver.connections[0].0, solver.connections[0].1), (1, 2));
+        assert!(matches!(solver.particles[3].kind, Kind::Mine { anchor: Some(2), .. }));
+    }
+
+    #[test]
+    fn angle_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let particles = [vec2(2., 0.), vec2(0., 0.), vec2(0., 2.)].map(|pos| METAL.with_position(pos));
+        let rib = Link::Rigid { length: 2., durability: 1., elasticity: 10. };
+        let angle = Link::Angle { pivot: 1, target: 3. * PI / 4., stiffness: 0.5 };
+        let connections = [(0, 1, rib), (1, 2, rib), (0, 2, angle)];
+        let mut solver = Solver::new(constraint, &particles, &connections);
+        assert!((Link::angle(particles[0].pos, particles[1].pos, particles[2].pos) - PI / 2.).abs() < 0.001);
+
+        for _ in 0..100 {
+            solver.solve(1. / 480.);
+        }
+        let (p1, pivot, p2) = (solver.particles[0].pos, solver.particles[1].pos, solver.particles[2].pos);
+        assert!(Link::angle_error(Link::angle(p1, pivot, p2), 3. * PI / 4.).abs() < 0.05);
+        assert!((p1.distance(pivot) - 2.).abs() < 0.1);
+
+        // the angle link goes along with its pivot
+        let removed = solver.remove_particles(|i, _| i == 1);
+        assert_eq!(removed.links, vec![None, None, None]);
+        assert!(solver.connections.is_empty());
+        assert!((Link::angle_error(-PI + 0.1, PI - 0.1) - 0.2).abs() < 0.001);
+    }
+
+    #[test]
+    fn pressure_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let mut solver = Solver::new(constraint, &[], &[]);
+        // ropes don't push, only the pressure brings the squeezed ring back
+        let ring = crate::ring_model![METAL; Link::Rope { length: 1., durability: 1. }; .radius: 4., .count: 16, .stiffness: 0.5];
+        solver.add_model(&ring, vec2(0., 0.));
+        let area = solver.bodies[0].area;
+        for p in solver.particles.iter_mut() {
+            *p = p.with_position(p.pos * 0.8);
+        }
+        assert!(solver.bodies[0].current_area(&solver.particles) < 0.7 * area);
+
+        for _ in 0..100 {
+            solver.solve(1. / 480.);
+        }
+        assert!((solver.bodies[0].current_area(&solver.particles) - area).abs() < 0.05 * area);
+
+        let removed = solver.remove_particles(|i, _| i == 3);
+        assert_eq!(removed.particles.len(), 16);
+        assert!(solver.bodies.is_empty());
+    }
+
+    #[test]
+    fn kernel_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let pairs = [(vec2(0., 0.), vec2(0.6, 0.)), (vec2(5., 5.), vec2(5., 5.7)), (vec2(-5., 3.), vec2(-4.5, 3.2))];
+        let particles: Vec<Particle> = pairs.iter().flat_map(|(a, b)| [METAL.with_position(*a), METAL.with_position(*b)]).collect();
+        let mut scalar = Solver::new(constraint.clone(), &particles, &[]);
+        scalar.kernel = CollisionKernel::Scalar;
+        let mut batched = Solver::new(constraint, &particles, &[]);
+        batched.kernel = CollisionKernel::Batched;
+
+        for _ in 0..10 {
+            scalar.solve(1. / 480.);
+            batched.solve(1. / 480.);
+        }
+        // the pairs are pushed apart the same way
+        assert!(scalar.particles[0].pos.distance(scalar.particles[1].pos) > 0.9);
+        assert_eq!(scalar.checksum(), batched.checksum());
+    }
+
+    #[test]
+    fn overflow_test() {
+        let constraint = Constraint::Box(vec2(-5., -5.), vec2(5., 5.));
+        // a clump twice as dense as a cell holds inline
+        let particles: Vec<Particle> = (0..2 * CELL_MAX)
+            .map(|i| METAL.with_position(Vec2::from_angle(i as f32) * 0.1 + 0.5))
+            .collect();
+        let mut solver = Solver::new(constraint, &particles, &[]);
+        solver.forces.gravity = Vec2::ZERO;
+        assert_eq!(solver.grid[solver.get_cell(vec2(0.5, 0.5))].iter().count(), 2 * CELL_MAX);
+
+        for _ in 0..200 {
+            solver.solve(1. / 480.);
+        }
+        // every particle collided its way out of the clump
+        for (i, p1) in solver.particles.iter().enumerate() {
+            for p2 in solver.particles.iter().skip(i + 1) {
+                assert!(p1.pos.distance(p2.pos) > 0.9, "{} {}", p1.pos, p2.pos);
+            }
+        }
+    }
+
+    #[test]
+    fn events_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        // a fast particle about to hit a resting one, and a link that's about to break
+        let mut bullet = METAL.with_position(vec2(0., 0.));
+        bullet.set_velocity(vec2(1., 0.));
+        let particles = [bullet, METAL.with_position(vec2(0.95, 0.)), METAL.with_position(vec2(0., 5.)), METAL.with_position(vec2(1., 5.))];
+        let link = Link::Rigid { length: 1., durability: 1., elasticity: 10. };
+        let mut solver = Solver::new(constraint, &particles, &[(2, 3, link)]);
+        solver.damage_link(0, 0.5);
+        assert!(solver.events().is_empty());
+
+        solver.solve(1. / 480.);
+        solver.damage_link(0, 1.);
+        let events = solver.drain_events();
+        assert!(matches!(events[0], SolverEvent::Collision { i: 0, j: 1, impulse, .. } if impulse > 1.));
+        assert_eq!(events.last(), Some(&SolverEvent::LinkBreak { index: 0, pos: solver.broken[0].pos }));
+        assert!(solver.events().is_empty());
+
+        // the next solve starts over
+        solver.damage_link(0, 1.);
+        solver.solve(1. / 480.);
+        assert!(!solver.events().iter().any(|e| matches!(e, SolverEvent::LinkBreak { .. })));
+    }
+
+    #[test]
+    fn groups_test() {
+        // a barrier the tanks of the first team drive through
+        let mut barrier = METAL.with_position(vec2(0., 0.));
+        barrier.collision_mask = !team_group(0);
+        let mut tank = METAL.with_position(vec2(0.5, 0.));
+        tank.collision_group = team_group(0);
+        let mut enemy = tank;
+        enemy.collision_group = team_group(1);
+        assert!(!barrier.collides_with(&tank) && !tank.collides_with(&barrier));
+        assert!(barrier.collides_with(&enemy));
+
+        let mut events = vec![];
+        let (mut b, mut t) = (barrier, tank);
+        Solver::resolve_collision(&mut b, &mut t, 0, 1, &mut events);
+        assert_eq!((b.pos, t.pos), (barrier.pos, tank.pos));
+        let (mut b, mut e) = (barrier, enemy);
+        Solver::resolve_collision(&mut b, &mut e, 0, 1, &mut events);
+        assert!(b.pos.distance(e.pos) > 0.99);
+
+        // decorations only collide with the map and the projectiles, the tanks drive through them
+        let mut decoration = GROUND;
+        decoration.collision_mask = DEFAULT_GROUP;
+        assert!(decoration.collides_with(&GROUND));
+        assert!(!decoration.collides_with(&tank));
+        assert!(decoration.collides_with(&PROJECTILE_HEAVY));
+    }
+
+    #[test]
+    fn prune_test() {
+        let constraint = Constraint::Box(vec2(-20., -20.), vec2(20., 20.));
+        let particles: Vec<Particle> = (0..4).map(|i| METAL.with_position(vec2(i as f32, 0.))).collect();
+        let link = Link::Rigid { length: 1., durability: 1., elasticity: 10. };
+        let connections = [(0, 1, link), (1, 2, link), (2, 3, link)];
+        let mut solver = Solver::new(constraint, &particles, &connections);
+        solver.damage_link(0, 2.);
+        solver.damage_link(1, 2.);
+        solver.damage_link(1, 2.);
+        assert_eq!(solver.broken.iter().map(|b| b.index).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(solver.broken[0].pos, vec2(0.5, 0.));
+
+        // the kept broken link and the intact one move to the front
+        let moved = solver.prune_connections(|k| k == 1);
+        assert_eq!(moved, vec![None, Some(0), Some(1)]);
+        assert_eq!(solver.connections.len(), 2);
+        assert_eq!((solver.connections[1].0, solver.connections[1].1), (2, 3));
+        assert!(solver.broken.is_empty());
     }
 }
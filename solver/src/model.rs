@@ -1,6 +1,7 @@
 use std::ops::Add;
 
 use bevy::math::{vec2, Vec2};
+use serde::{Deserialize, Serialize};
 
 use crate::{particle::Particle, Connection};
 
@@ -9,6 +10,76 @@ pub struct Model {
     pub center: Vec2,
     pub particles: Vec<Particle>,
     pub connections: Vec<Connection>,
+    pub bodies: Vec<PressureBody>,
+}
+
+/// Closed loop of particles pushed out from the inside to keep the area it encloses, for tires, balloons and
+/// squishy hulls. The links of the loop hold it together, the pressure only keeps it from caving in or bulging.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PressureBody {
+    pub particles: Vec<usize>, // the loop in order, the last particle joins the first
+    pub area: f32,             // signed, positive when the loop goes counterclockwise
+    pub stiffness: f32,        // part of the lost or gained area restored every tick, from 0 to 1
+}
+
+impl PressureBody {
+    /// Body keeping the area its particles enclose at their current positions.
+    pub fn new(indexes: Vec<usize>, particles: &[Particle], stiffness: f32) -> Self {
+        let mut body = Self {
+            particles: indexes,
+            area: 0.,
+            stiffness,
+        };
+        body.area = body.current_area(particles);
+        body
+    }
+
+    /// Signed area of the loop as the particles are now.
+    pub fn current_area(&self, particles: &[Particle]) -> f32 {
+        self.edges(particles).map(|(a, b)| a.perp_dot(b)).sum::<f32>() / 2.
+    }
+
+    fn edges<'a>(&'a self, particles: &'a [Particle]) -> impl Iterator<Item = (Vec2, Vec2)> + 'a {
+        let n = self.particles.len();
+        (0..n).map(move |k| (particles[self.particles[k]].pos, particles[self.particles[(k + 1) % n]].pos))
+    }
+
+    /// Moves the particles of the loop along the gradient of its area until the area is back, in proportion to
+    /// the stiffness. Sleeping and static particles stay where they are.
+    pub fn resolve(&self, particles: &mut [Particle]) {
+        let n = self.particles.len();
+        if n < 3 || self.particles.iter().all(|i| particles[*i].asleep()) {
+            return;
+        }
+        // how fast the area grows as each particle moves, half the perpendicular of the line between its neighbours
+        let gradients: Vec<Vec2> = (0..n)
+            .map(|k| {
+                let (prev, next) = (self.particles[(k + n - 1) % n], self.particles[(k + 1) % n]);
+                let d = particles[next].pos - particles[prev].pos;
+                match particles[self.particles[k]].asleep() {
+                    true => Vec2::ZERO,
+                    false => vec2(d.y, -d.x) / 2.,
+                }
+            })
+            .collect();
+        let weight: f32 = gradients.iter().map(|g| g.length_squared()).sum();
+        if weight < f32::EPSILON {
+            return;
+        }
+        let lambda = (self.current_area(particles) - self.area) / weight * self.stiffness.clamp(0., 1.);
+        for (i, g) in self.particles.iter().zip(gradients) {
+            let p = &mut particles[*i];
+            p.set_position(p.pos - g * lambda, true);
+        }
+    }
+
+    /// The body with its particles at their new indexes, `None` once one of them is gone.
+    pub fn move_particles(&self, f: impl Fn(usize) -> Option<usize>) -> Option<Self> {
+        Some(Self {
+            particles: self.particles.iter().map(|i| f(*i)).collect::<Option<_>>()?,
+            ..self.clone()
+        })
+    }
 }
 
 impl Add for Model {
@@ -26,7 +97,12 @@ impl Add for Model {
         output.connections.extend(
             rhs.connections
                 .into_iter()
-                .map(|(i, j, link)| (i + particles_num, j + particles_num, link)),
+                .map(|(i, j, link)| (i + particles_num, j + particles_num, link.move_pivot(|k| k + particles_num))),
+        );
+        output.bodies.extend(
+            rhs.bodies
+                .iter()
+                .filter_map(|body| body.move_particles(|i| Some(i + particles_num))),
         );
 
         output
@@ -195,6 +271,42 @@ macro_rules! chain_model {
     }
 }
 
+/// Macro to create ring models filled with pressure (i.e. tires, balloons), the particles go counterclockwise
+/// around the center and each one is linked to the next.
+#[macro_export]
+macro_rules! ring_model {
+    ($p:expr; $l:expr; .radius:$radius:expr, .count:$count:expr $(, .stiffness:$stiffness:expr)?) => {
+        {
+            use $crate::model::{Model, PressureBody};
+            use $crate::particle::Particle;
+            use bevy::math::Vec2;
+
+            let count: usize = $count;
+            let particles: Vec<Particle> = (0..count)
+                .map(|i| $p.with_position(Vec2::from_angle(i as f32 * std::f32::consts::TAU / count as f32) * $radius as f32))
+                .collect();
+            let connections = (0..count)
+                .map(|i| {
+                    let j = (i + 1) % count;
+                    (i, j, $l.with_length(particles[i].pos.distance(particles[j].pos)))
+                })
+                .collect();
+
+            let mut _stiffness = 1.;
+            $(
+                _stiffness = $stiffness;
+            )?
+            let bodies = vec![PressureBody::new((0..count).collect(), &particles, _stiffness)];
+
+            Model {
+                particles,
+                connections,
+                bodies,
+                ..Default::default()
+            }
+        }
+    }
+}
 
 #[allow(unused_mut)]
 #[cfg(test)]
@@ -227,4 +339,21 @@ mod tests {
         assert_eq!(chain.connections.len(), 12);
         dbg!(chain);
     }
+
+    #[test]
+    fn ring_model_test() {
+        let ring = ring_model![METAL; Link::Rope { length: 1., durability: 1. }; .radius: 2., .count: 8, .stiffness: 0.5];
+        assert_eq!(ring.particles.len(), 8);
+        assert_eq!(ring.connections.len(), 8);
+        assert_eq!(ring.connections[7].1, 0);
+
+        // a regular octagon, counterclockwise
+        let body = &ring.bodies[0];
+        assert!((body.area - 2. * 2f32.sqrt() * 4.).abs() < 1e-4);
+        assert_eq!(body.current_area(&ring.particles), body.area);
+
+        let doubled = ring_model![METAL; Link::Force(1.); .radius: 1., .count: 4] + ring_model![METAL; Link::Force(1.); .radius: 1., .count: 4];
+        assert_eq!(doubled.bodies[1].particles, vec![4, 5, 6, 7]);
+        assert_eq!(doubled.bodies[1].stiffness, 1.);
+    }
 }
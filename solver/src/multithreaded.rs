@@ -1,5 +1,8 @@
 use std::ops::{Index, IndexMut};
 
+#[cfg(not(feature = "deterministic"))]
+use rayon::prelude::*;
+
 /// Unsafe array that can be sent between threads.
 /// It allows for independent read/write in threads but is vulnerable to race conditions, use-after-free and etc.
 #[derive(Clone, Copy)]
@@ -33,4 +36,20 @@ impl<T> IndexMut<usize> for UnsafeMultithreadedArray<T> {
 }
 
 unsafe impl<T> Send for UnsafeMultithreadedArray<T> {}
-unsafe impl<T> Sync for UnsafeMultithreadedArray<T> {}
\ No newline at end of file
+unsafe impl<T> Sync for UnsafeMultithreadedArray<T> {}
+
+/// Calls `f` for every item, in parallel unless the `deterministic` feature is on.
+pub fn for_each<T: Sync, F: Fn(&T) + Sync + Send>(items: &[T], f: F) {
+    #[cfg(feature = "deterministic")]
+    items.iter().for_each(f);
+    #[cfg(not(feature = "deterministic"))]
+    items.par_iter().for_each(f);
+}
+
+/// Same as [`for_each`] for mutable items.
+pub fn for_each_mut<T: Send, F: Fn(&mut T) + Sync + Send>(items: &mut [T], f: F) {
+    #[cfg(feature = "deterministic")]
+    items.iter_mut().for_each(f);
+    #[cfg(not(feature = "deterministic"))]
+    items.par_iter_mut().for_each(f);
+}
@@ -1,11 +1,14 @@
-use bevy::math::{vec2, vec4, Vec2, Vec4};
+use std::f32::consts::PI;
+
+use bevy::math::{vec4, Vec2, Vec4};
 use serde::{Deserialize, Serialize};
 
-use crate::{Constraint, PARTICLE_RADIUS};
+use crate::{Constraint, Forces, PARTICLE_RADIUS};
 
 pub const GROUND: Particle = Particle {
     mass: 1.,
     texture: 1,
+    friction: 0.5,
     ..Particle::null()
 };
 
@@ -19,6 +22,7 @@ pub const MOTOR: Particle = Particle {
     mass: 3.,
     texture: 3,
     kind: Kind::Motor(0.),
+    friction: 0.5, // idle treads hold the tank on slopes
     ..Particle::null()
 };
 
@@ -34,6 +38,7 @@ pub const PROJECTILE_HEAVY: Particle = Particle {
     mass: 10.,
     texture: 4,
     color: vec4(1., 0., 0., 1.),
+    projectile: true,
     ..Particle::null()
 };
 
@@ -43,6 +48,7 @@ pub const PROJECTILE_IMPULSE: Particle = Particle {
     texture: 0,
     color: vec4(0., 1., 0., 1.),
     kind: Kind::Impulse(20.),
+    projectile: true,
     ..Particle::null()
 };
 
@@ -51,9 +57,96 @@ pub const PROJECTILE_STICKY: Particle = Particle {
     texture: 0,
     color: vec4(0.5, 0.5, 0.5, 1.),
     kind: Kind::Sticky(6, None),
+    projectile: true,
+    ..Particle::null()
+};
+
+pub const BURN_TEMPERATURE: f32 = 1.;
+pub const INCENDIARY_HEAT: f32 = 2.5;
+pub const PROJECTILE_INCENDIARY: Particle = Particle {
+    mass: 1.,
+    texture: 0,
+    color: vec4(1., 0.4, 0., 1.),
+    kind: Kind::Incendiary(20.),
+    temperature: 4.,
+    projectile: true,
+    ..Particle::null()
+};
+
+pub const WOOD_FUEL: f32 = 30.; // heat a wooden particle gives off before it's burnt out (~6 seconds)
+const BURN_HEAT: f32 = 0.01; // heat a burning flammable particle gives off every tick
+const CHARRED_COLOR: Vec4 = vec4(0.15, 0.12, 0.1, 1.);
+pub const WOOD: Particle = Particle {
+    mass: 0.3, // lighter than water, so it floats
+    texture: 0,
+    color: vec4(0.55, 0.35, 0.2, 1.),
+    kind: Kind::Flammable(WOOD_FUEL),
+    friction: 0.5,
     ..Particle::null()
 };
 
+pub const DEFAULT_GROUP: u16 = 1; // collision group of the map and the projectiles, the tanks are in the ones of their teams
+
+/// Collision group of the tanks of the team, a barrier without it in the mask lets them through.
+/// Teams past the 15th share the groups of the first ones.
+pub const fn team_group(team: usize) -> u16 {
+    1 << (1 + team % 15)
+}
+
+pub const MINE_ARM_TICKS: u16 = 640;
+pub const SLEEP_TICKS: u16 = 480; // ticks a particle stays still before it falls asleep (~1 second)
+const SLEEP_SPEED: f32 = 0.001; // particles slower than this count as still
+const WAKE_SPEED: f32 = 0.005; // a sleeping particle wakes when it's hit or pushed faster than this
+pub const MINE_RADIUS: f32 = 4.;
+pub const MINE_IMPULSE: f32 = 1.5;
+pub const PROJECTILE_MINE: Particle = Particle {
+    mass: 0.5,
+    texture: 0,
+    color: vec4(1., 0.8, 0., 1.),
+    kind: Kind::Mine {
+        owner: 0,
+        arm: MINE_ARM_TICKS,
+        anchor: None,
+    },
+    projectile: true,
+    ..Particle::null()
+};
+
+pub const WATER: Particle = Particle {
+    mass: 0.5,
+    texture: 0,
+    color: vec4(0.2, 0.45, 0.9, 0.8),
+    kind: Kind::Fluid,
+    ..Particle::null()
+};
+
+pub const EXPLOSIVE_RADIUS: f32 = 6.;
+pub const EXPLOSIVE_IMPULSE: f32 = 2.;
+pub const PROJECTILE_EXPLOSIVE: Particle = Particle {
+    mass: 2.,
+    texture: 0,
+    color: vec4(1., 0.9, 0.2, 1.),
+    kind: Kind::Explosive(false),
+    projectile: true,
+    ..Particle::null()
+};
+
+pub const PICKUP: Particle = Particle {
+    mass: 1.,
+    texture: 0,
+    kind: Kind::Pickup {
+        item: Item::Repair,
+        active: true,
+        taker: None,
+    },
+    ..Particle::null()
+};
+
+pub const FLAG: Particle = Particle {
+    mass: 0.5,
+    texture: 0,
+    ..Particle::null()
+};
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Particle {
@@ -65,6 +158,17 @@ pub struct Particle {
     pub texture: u32,
     pub kind: Kind,
     pub color: Vec4,
+    pub owner: Option<u8>, // id of the player this particle belongs to
+    pub projectile: bool,
+    pub temperature: f32,
+    pub friction: f32,    // part of the sliding between touching particles damped per tick, from 0 to 1
+    pub restitution: f32, // part of the approaching velocity given back as a bounce, from 0 to 1
+    pub sleep: u16, // ticks the particle has stayed still, up to `SLEEP_TICKS`
+    pub lifetime: Option<u32>, // ticks left until the particle expires, `None` keeps it for good
+    pub collision_group: u16,  // groups the particle belongs to, one bit per group
+    pub collision_mask: u16,   // groups the particle collides with
+    #[serde(skip)]
+    pub buoyancy: f32, // part of the gravity the fluids around cancel this tick, from the contacts of the tick
 }
 
 impl Default for Particle {
@@ -80,6 +184,43 @@ pub enum Kind {
     Motor(f32), // motor with acc
     Impulse(f32),
     Sticky(u8, Option<usize>), // active state + unhandled connection
+    Mine {
+        owner: u8,             // id of the player who fired the mine
+        arm: u16,              // ticks left until the mine is armed
+        anchor: Option<usize>, // particle the mine is bonded to
+    },
+    Incendiary(f32), // heat left to spread
+    Pickup {
+        item: Item,
+        active: bool,      // inactive pickups don't collide with anything
+        taker: Option<u8>, // owner of the first particle that touched the pickup
+    },
+    Static, // never moves, used for fixed terrain and mount points
+    Fluid,  // pushes others out softly, so heavy particles sink into it
+    Explosive(bool), // set on the first collision, detonates right after
+    Flammable(f32),  // fuel left, burns on its own once it's hot enough
+}
+
+/// Effect a pickup gives to the tank that touches it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Item {
+    Repair,
+    Ammo,
+    DamageBoost,
+    SpeedBoost,
+}
+
+impl Item {
+    pub const ALL: [Item; 4] = [Item::Repair, Item::Ammo, Item::DamageBoost, Item::SpeedBoost];
+
+    pub fn color(&self) -> Vec4 {
+        match self {
+            Item::Repair => vec4(0.2, 1., 0.4, 1.),
+            Item::Ammo => vec4(1., 0.9, 0.3, 1.),
+            Item::DamageBoost => vec4(1., 0.2, 0.6, 1.),
+            Item::SpeedBoost => vec4(0.3, 0.8, 1., 1.),
+        }
+    }
 }
 
 impl Kind {
@@ -96,12 +237,27 @@ impl Kind {
 
     pub fn is_special(&self) -> bool {
         match self {
-            &Kind::Sticky(_, _) => true,
+            &Kind::Sticky(_, _) | &Kind::Mine { .. } | &Kind::Explosive(_) => true,
             _ => false
         }
     }
 
+    pub fn hidden(&self) -> bool {
+        matches!(self, Kind::Pickup { active: false, .. })
+    }
+
+    pub fn is_static(&self) -> bool {
+        self == &Kind::Static
+    }
+
+    pub fn is_fluid(&self) -> bool {
+        self == &Kind::Fluid
+    }
+
     pub fn can_collide_with(&self, kind: &Kind) -> bool {
+        if self.hidden() || kind.hidden() {
+            return false;
+        }
         match self {
             &Self::Motor(_) => *kind != Self::Spike,
             &Self::Spike => !kind.is_motor(),
@@ -111,9 +267,11 @@ impl Kind {
 }
 
 impl Particle {
-    const GRAVITY: Vec2 = vec2(0., -70.);
     const SLOWDOWN: f32 = 100.;
-    const MAX_SPEED: f32 = 3.;
+    pub const MAX_SPEED: f32 = 3.; // distance per substep
+    const COOLING: f32 = 0.002;
+    const CONDUCTION: f32 = 0.01; // part of the difference in temperature that flows per contact and tick
+    const SUBMERGED_CONTACTS: f32 = 12.; // fluid contacts of a particle deep in a fluid, every pair meets twice a tick
 
     pub const fn null() -> Self {
         Self {
@@ -125,6 +283,16 @@ impl Particle {
             acc: Vec2::ZERO,
             kind: Kind::None,
             color: Vec4::ONE,
+            owner: None,
+            projectile: false,
+            temperature: 0.,
+            friction: 0.,
+            restitution: 0.,
+            sleep: 0,
+            lifetime: None,
+            collision_group: DEFAULT_GROUP,
+            collision_mask: u16::MAX,
+            buoyancy: 0.,
         }
     }
 
@@ -144,6 +312,20 @@ impl Particle {
         Particle { color, ..self }
     }
 
+    pub fn with_owner(self, owner: u8) -> Self {
+        Particle {
+            owner: Some(owner),
+            ..self
+        }
+    }
+
+    pub fn with_lifetime(self, ticks: u32) -> Self {
+        Particle {
+            lifetime: Some(ticks),
+            ..self
+        }
+    }
+
     pub fn with_velocity(self, velocity: Vec2) -> Self {
         Particle {
             pos_old: self.pos - velocity,
@@ -160,11 +342,34 @@ impl Particle {
             acc: Vec2::ZERO,
             texture,
             kind,
-            color
+            color,
+            owner: None,
+            projectile: false,
+            temperature: 0.,
+            friction: 0.,
+            restitution: 0.,
+            sleep: 0,
+            lifetime: None,
+            collision_group: DEFAULT_GROUP,
+            collision_mask: u16::MAX,
+            buoyancy: 0.,
+        }
+    }
+
+    /// Friction of the particle in a contact, driving treads don't hold on to what they push.
+    pub fn grip(&self) -> f32 {
+        match self.kind {
+            Kind::Motor(acc) if acc != 0. => 0.,
+            _ => self.friction.clamp(0., 1.),
         }
     }
 
     pub fn update(&mut self, dt: f32) {
+        if self.is_static() {
+            self.pos_old = self.pos;
+            self.acc = Vec2::ZERO;
+            return;
+        }
         let vel = (self.pos - self.pos_old).clamp_length(0., Self::MAX_SPEED);
         let new_pos = self.pos + vel + (self.acc - vel * Particle::SLOWDOWN) * dt * dt;
         self.pos_old = self.pos;
@@ -172,8 +377,120 @@ impl Particle {
         self.acc = Vec2::ZERO;
     }
 
-    pub fn apply_gravity(&mut self) {
-        self.accelerate(Particle::GRAVITY);
+    /// Sleeping particles are skipped by the solver, static ones never wake up.
+    pub fn asleep(&self) -> bool {
+        self.is_static() || self.sleep >= SLEEP_TICKS
+    }
+
+    pub fn wake(&mut self) {
+        self.sleep = 0;
+    }
+
+    /// Wakes the particle if the other one comes at it fast enough.
+    pub fn wake_by(&mut self, other: &Particle) {
+        if other.velocity().distance(self.velocity()) > WAKE_SPEED {
+            self.wake();
+        }
+    }
+
+    /// Only loose map geometry sleeps, tanks, projectiles and anything with a behaviour stay awake.
+    fn can_sleep(&self) -> bool {
+        self.owner.is_none()
+            && !self.projectile
+            && !self.is_burning()
+            && matches!(self.kind, Kind::None | Kind::Spike | Kind::Flammable(_))
+    }
+
+    /// Keeps a sleeping particle in place, returns `false` once an impulse or heat woke it up.
+    pub fn rest(&mut self) -> bool {
+        if !self.asleep() || self.is_static() {
+            return false;
+        }
+        if self.velocity().length() > WAKE_SPEED || !self.can_sleep() {
+            self.wake();
+            return false;
+        }
+        self.pos_old = self.pos;
+        self.acc = Vec2::ZERO;
+        self.buoyancy = 0.;
+        true
+    }
+
+    /// Counts the ticks the particle stays still, called after it moves.
+    pub fn update_sleep(&mut self) {
+        self.sleep = match self.can_sleep() && self.velocity().length() < SLEEP_SPEED {
+            true => (self.sleep + 1).min(SLEEP_TICKS),
+            false => 0,
+        };
+    }
+
+    pub fn cool_down(&mut self) {
+        self.temperature *= 1. - Self::COOLING;
+    }
+
+    /// Whether each particle is in a group the other one collides with.
+    pub fn collides_with(&self, other: &Particle) -> bool {
+        self.collision_group & other.collision_mask != 0 && other.collision_group & self.collision_mask != 0
+    }
+
+    /// A flammable particle on fire keeps heating up until its fuel runs out, then it's left charred.
+    pub fn burn(&mut self) {
+        let burning = self.is_burning();
+        let Kind::Flammable(fuel) = &mut self.kind else {
+            return;
+        };
+        if !burning {
+            return;
+        }
+        let heat = BURN_HEAT.min(*fuel);
+        self.temperature += heat;
+        *fuel -= heat;
+        if *fuel <= 0. {
+            self.kind = Kind::None;
+            self.color = CHARRED_COLOR;
+        }
+    }
+
+    /// Evens out a part of the difference in temperature of two touching particles.
+    pub fn conduct(&mut self, other: &mut Particle) {
+        let flow = (self.temperature - other.temperature) * Self::CONDUCTION;
+        self.temperature -= flow;
+        other.temperature += flow;
+    }
+
+    /// Counts the lifetime down, sleeping particles age too.
+    pub fn age(&mut self) {
+        if let Some(lifetime) = &mut self.lifetime {
+            *lifetime = lifetime.saturating_sub(1);
+        }
+    }
+
+    /// Expired particles are left for whoever holds their indexes to remove, see [`Solver::remove_particles`].
+    ///
+    /// [`Solver::remove_particles`]: crate::Solver::remove_particles
+    pub fn expired(&self) -> bool {
+        self.lifetime == Some(0)
+    }
+
+    pub fn is_burning(&self) -> bool {
+        self.temperature > BURN_TEMPERATURE
+    }
+
+    /// Mass over area, a particle lighter than the fluid it's in floats.
+    pub fn density(&self) -> f32 {
+        self.mass / (PI * self.radius * self.radius)
+    }
+
+    /// Lifts the particle out of the fluid it touches, see [`Particle::buoyancy`]. A fully submerged particle is
+    /// pushed up as hard as its weight times the fluid's density over its own, so heavier ones still sink.
+    pub fn float_in(&mut self, fluid: &Particle) {
+        let ratio = fluid.density() / self.density();
+        self.buoyancy = (self.buoyancy + ratio / Self::SUBMERGED_CONTACTS).min(ratio);
+    }
+
+    pub fn apply_forces(&mut self, forces: &Forces) {
+        let gravity = forces.gravity * (1. - std::mem::take(&mut self.buoyancy));
+        self.accelerate(gravity + forces.wind / self.mass);
     }
 
     pub fn accelerate(&mut self, acceleration: Vec2) {
@@ -200,17 +517,13 @@ impl Particle {
 
     pub fn set_kind(&mut self, kind: Kind) {
         self.kind = kind;
+        self.wake();
     }
 
-    pub fn apply_constraint(&mut self, constraint: Constraint) {
-        match constraint {
-            Constraint::Box(bl, tr) => {
-                let new_x = self.pos.x.max(bl.x + self.radius).min(tr.x - self.radius);
-                let new_y = self.pos.y.max(bl.y + self.radius).min(tr.y - self.radius);
-                if (new_x, new_y) != (self.pos.x, self.pos.y) {
-                    self.set_position(vec2(new_x, new_y), false);
-                }
-            }
+    pub fn apply_constraint(&mut self, constraint: &Constraint) {
+        let pos = constraint.clamp(self.pos, self.radius);
+        if pos != self.pos {
+            self.set_position(pos, false);
         }
     }
 
@@ -221,4 +534,12 @@ impl Particle {
     pub fn is_special(&self) -> bool {
         self.kind.is_special()
     }
+
+    pub fn is_static(&self) -> bool {
+        self.kind.is_static()
+    }
+
+    pub fn is_fluid(&self) -> bool {
+        self.kind.is_fluid()
+    }
 }
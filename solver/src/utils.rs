@@ -1,7 +1,12 @@
 use std::ops::{Index, IndexMut};
 
-const CELL_MAX: usize = 4;
+use bevy::math::Vec2;
 
+pub const CELL_MAX: usize = 4; // particles a cell holds inline, the rest of them spill over to the heap
+pub const LANES: usize = 8; // particles a batch checks at once, two vector registers of 4 f32
+
+/// Cell of the grid, the first [`CELL_MAX`] elements stay inline and a dense clump spills the rest over to
+/// the heap. The overflow keeps its capacity when cleared, so a clump that stays only allocates once.
 #[derive(Default, Clone)]
 pub struct GridCell<T>
 where
@@ -9,6 +14,7 @@ where
 {
     pub len: usize,
     pub elements: [T; CELL_MAX],
+    overflow: Vec<T>,
 }
 
 impl<T> GridCell<T>
@@ -16,22 +22,24 @@ where
     T: Clone + Copy + Default,
 {
     pub fn push(&mut self, elem: T) {
-        if self.len < CELL_MAX {
-            self.elements[self.len] = elem;
-            self.len += 1;
+        match self.len < CELL_MAX {
+            true => self.elements[self.len] = elem,
+            false => self.overflow.push(elem),
         }
+        self.len += 1;
     }
 
     pub fn clear(&mut self) {
         self.len = 0;
+        self.overflow.clear();
     }
 
-    pub fn iter(&self) -> std::slice::Iter<T> {
-        self.elements[0..self.len].iter()
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.elements[0..self.len.min(CELL_MAX)].iter().chain(self.overflow.iter())
     }
 
-    pub fn iter_mut(&mut self) -> std::slice::IterMut<T> {
-        self.elements[0..self.len].iter_mut()
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.elements[0..self.len.min(CELL_MAX)].iter_mut().chain(self.overflow.iter_mut())
     }
 }
 
@@ -87,4 +95,129 @@ where
     pub fn push(&mut self, ind: (usize, usize), value: T) {
         self[ind].push(value);
     }
-}
\ No newline at end of file
+}
+
+/// Particles of a cell and its neighbours laid out lane by lane, so the distance checks of a chunk run on SIMD lanes.
+#[derive(Default)]
+pub struct Batch {
+    pub index: Vec<usize>,
+    x: Vec<f32>,
+    y: Vec<f32>,
+    radius: Vec<f32>,
+}
+
+impl Batch {
+    /// Fills the batch with the particles of the cell and its neighbours, in the order of the grid.
+    /// The last chunk is padded with lanes infinitely far away.
+    pub fn gather(&mut self, grid: &Grid<usize>, (col, row): (usize, usize), particle: impl Fn(usize) -> (Vec2, f32)) {
+        self.index.clear();
+        self.x.clear();
+        self.y.clear();
+        self.radius.clear();
+        for col in col - 1..=col + 1 {
+            for row in row - 1..=row + 1 {
+                for &j in grid[(col, row)].iter() {
+                    let (pos, radius) = particle(j);
+                    self.push(j, pos, radius);
+                }
+            }
+        }
+        while self.index.len() % LANES != 0 {
+            self.push(usize::MAX, Vec2::splat(f32::INFINITY), 0.);
+        }
+    }
+
+    fn push(&mut self, index: usize, pos: Vec2, radius: f32) {
+        self.index.push(index);
+        self.x.push(pos.x);
+        self.y.push(pos.y);
+        self.radius.push(radius);
+    }
+
+    pub fn chunks(&self) -> usize {
+        self.index.len() / LANES
+    }
+
+    /// Lanes of the chunk that overlap the circle. The distance is taken the way `Solver::resolve_collision`
+    /// takes it, so a lane left out here wouldn't collide there either.
+    pub fn overlaps(&self, chunk: usize, pos: Vec2, radius: f32) -> [bool; LANES] {
+        let lanes = chunk * LANES..(chunk + 1) * LANES;
+        let (x, y, r) = (&self.x[lanes.clone()], &self.y[lanes.clone()], &self.radius[lanes]);
+        let mut hits = [false; LANES];
+        for lane in (0..LANES).step_by(4) {
+            let quad = lane..lane + 4;
+            hits[quad.clone()].copy_from_slice(&overlaps_quad(&x[quad.clone()], &y[quad.clone()], &r[quad], pos, radius));
+        }
+        hits
+    }
+}
+
+// The checks only subtract, multiply, add, take square roots and compare, which IEEE 754 rounds the same on the
+// vector registers as on the scalar ones, so every target gets the bits of `overlaps_scalar`.
+
+/// Four lanes of [`Batch::overlaps`] on SSE2, which every x86_64 cpu has.
+#[cfg(target_arch = "x86_64")]
+fn overlaps_quad(x: &[f32], y: &[f32], r: &[f32], pos: Vec2, radius: f32) -> [bool; 4] {
+    use std::arch::x86_64::*;
+    assert!(x.len() >= 4 && y.len() >= 4 && r.len() >= 4);
+    // SAFETY: sse2 is enabled on every x86_64 target and the loads are unaligned reads of 4 floats in bounds
+    let mask = unsafe {
+        let dx = _mm_sub_ps(_mm_set1_ps(pos.x), _mm_loadu_ps(x.as_ptr()));
+        let dy = _mm_sub_ps(_mm_set1_ps(pos.y), _mm_loadu_ps(y.as_ptr()));
+        let distance = _mm_sqrt_ps(_mm_add_ps(_mm_mul_ps(dx, dx), _mm_mul_ps(dy, dy)));
+        let reach = _mm_add_ps(_mm_set1_ps(radius), _mm_loadu_ps(r.as_ptr()));
+        _mm_movemask_ps(_mm_cmplt_ps(distance, reach))
+    };
+    std::array::from_fn(|lane| mask & (1 << lane) != 0)
+}
+
+/// Four lanes of [`Batch::overlaps`] on NEON, which every aarch64 cpu has.
+#[cfg(target_arch = "aarch64")]
+fn overlaps_quad(x: &[f32], y: &[f32], r: &[f32], pos: Vec2, radius: f32) -> [bool; 4] {
+    use std::arch::aarch64::*;
+    assert!(x.len() >= 4 && y.len() >= 4 && r.len() >= 4);
+    let mut mask = [0u32; 4];
+    // SAFETY: neon is enabled on every aarch64 target and the loads and the store stay within 4 elements
+    unsafe {
+        let dx = vsubq_f32(vdupq_n_f32(pos.x), vld1q_f32(x.as_ptr()));
+        let dy = vsubq_f32(vdupq_n_f32(pos.y), vld1q_f32(y.as_ptr()));
+        let distance = vsqrtq_f32(vaddq_f32(vmulq_f32(dx, dx), vmulq_f32(dy, dy)));
+        let reach = vaddq_f32(vdupq_n_f32(radius), vld1q_f32(r.as_ptr()));
+        vst1q_u32(mask.as_mut_ptr(), vcltq_f32(distance, reach));
+    }
+    mask.map(|lane| lane != 0)
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn overlaps_quad(x: &[f32], y: &[f32], r: &[f32], pos: Vec2, radius: f32) -> [bool; 4] {
+    overlaps_scalar(x, y, r, pos, radius)
+}
+
+/// Lanes the particle at `pos` overlaps, the same check as `Solver::resolve_collision` makes for one pair.
+#[cfg(any(test, not(any(target_arch = "x86_64", target_arch = "aarch64"))))]
+fn overlaps_scalar<const N: usize>(x: &[f32], y: &[f32], r: &[f32], pos: Vec2, radius: f32) -> [bool; N] {
+    std::array::from_fn(|lane| {
+        let (dx, dy) = (pos.x - x[lane], pos.y - y[lane]);
+        (dx * dx + dy * dy).sqrt() < radius + r[lane]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlaps_test() {
+        // touching, just apart, padding, a lost particle and radii the float steps of a distance decide
+        let x = [0.5, 1.0, f32::INFINITY, f32::NAN, 0.999_999_9, -0.7, 3., 0.1];
+        let y = [0., 0., f32::INFINITY, 0., 0., 0.7, -4., 1e-7];
+        let r = [0.5, 0.5, 0., 0.5, 0.5, 0.5, 4.5, 0.3];
+        for (pos, radius) in [(Vec2::ZERO, 0.5), (Vec2::new(0.25, -0.1), 0.6), (Vec2::splat(f32::INFINITY), 0.5)] {
+            for quad in [0..4, 4..8] {
+                let (x, y, r) = (&x[quad.clone()], &y[quad.clone()], &r[quad]);
+                assert_eq!(overlaps_quad(x, y, r, pos, radius), overlaps_scalar::<4>(x, y, r, pos, radius));
+            }
+        }
+        assert_eq!(overlaps_scalar::<4>(&x, &y, &r, Vec2::ZERO, 0.5), [true, false, false, false]);
+    }
+}